@@ -0,0 +1,61 @@
+//! Canonical table schemas and test data shared across the mutation crates.
+
+use arrow::datatypes::{DataType, Field, Schema};
+
+/// Directory of the bundled test dataset.
+pub const DATA_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/data");
+/// A tiny nextclade TSV with two samples.
+pub const TEST_NEXTCLADE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/data/nextclade.tsv");
+/// GFF3 gene annotations matching the test nextclade TSV.
+pub const TEST_GFF: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/data/genome_annotation.gff3");
+/// Mutation annotations matching the test nextclade TSV.
+pub const TEST_ANNOTATIONS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/data/annotations.tsv");
+
+/// The long mutations table produced by `extract`, one row per sample mutation.
+pub fn mutations_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("sample",    DataType::Utf8,   false),
+        Field::new("mutation",  DataType::Utf8,   true),
+        Field::new("column",    DataType::Utf8,   false),
+        Field::new("type",      DataType::Utf8,   false),
+        Field::new("gene",      DataType::Utf8,   true),
+        Field::new("nuc_start", DataType::UInt32, true),
+        Field::new("nuc_end",   DataType::UInt32, true),
+        Field::new("aa_start",  DataType::UInt32, true),
+        Field::new("aa_end",    DataType::UInt32, true),
+        Field::new("hgvs_c",    DataType::Utf8,   true),
+        Field::new("hgvs_p",    DataType::Utf8,   true),
+    ])
+}
+
+/// The required columns of the annotations table consumed by `annotate`.
+///
+/// The optional `column` and `is_gene` columns refine matching, and any other
+/// columns are carried through to the output.
+pub fn annotations_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("mutation", DataType::Utf8, false),
+    ])
+}
+
+/// The annotated table produced by `annotate`, one row per sample and annotation.
+pub fn annotated_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("sample",   DataType::Utf8, false),
+        Field::new("mutation", DataType::Utf8, true),
+        Field::new("column",   DataType::Utf8, true),
+        Field::new("status",   DataType::Utf8, false),
+    ])
+}
+
+/// Names of the columns in `expected` that are not in `schema`.
+///
+/// Column types aren't compared, because delimited text inputs infer their own types.
+pub fn missing_columns(schema: &Schema, expected: &Schema) -> Vec<String> {
+    expected
+        .fields()
+        .iter()
+        .filter(|f| schema.column_with_name(f.name()).is_none())
+        .map(|f| f.name().to_string())
+        .collect()
+}
@@ -1,4 +1,4 @@
-use clap::{Parser, Subcommand };
+use clap::{ArgGroup, Parser, Subcommand };
 use crate::Verbosity;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
@@ -49,35 +49,1040 @@ pub enum Command {
     #[clap(about = "Extract mutations.")]
     Extract(ExtractArgs),
 
+    #[clap(about = "Annotate samples with catalogued mutations.")]
+    Annotate(AnnotateArgs),
+
     #[clap(about = "Plot mutations.")]
     Plot(PlotArgs),
+
+    #[clap(about = "Export mutations to other file formats.")]
+    Export(ExportArgs),
+
+    #[clap(about = "Compute per-site diversity (entropy).")]
+    Diversity(DiversityArgs),
+
+    #[clap(about = "Compute pairwise SNP distances between samples.")]
+    Distances(DistancesArgs),
+
+    #[clap(about = "Reconstruct which mutations arose on which branches of a tree.")]
+    Tree(TreeArgs),
+
+    #[clap(about = "Write the gene model parsed from the gene annotations.")]
+    Genes(GenesArgs),
+
+    #[clap(about = "Compute per-gene amino acid coverage of each sample.")]
+    Coverage(CoverageArgs),
+
+    #[clap(about = "Select columns from the mutations table.")]
+    Query(QueryArgs),
+
+    #[clap(about = "Compute the abundance of lineage-defining mutations from Freyja output.")]
+    Freyja(FreyjaArgs),
+
+    #[clap(about = "Flag mutations inside primer binding sites.")]
+    Primers(PrimersArgs),
+
+    #[clap(about = "Summarize nonsynonymous and synonymous substitutions per gene (pN/pS).")]
+    Selection(SelectionArgs),
+
+    #[clap(about = "Count the annotated mutations present per sample across allele frequency thresholds.")]
+    Sweep(SweepArgs),
 }
 
 /// Detect recombination in a dataset population and/or input alignment.
 #[derive(Clone, Debug, Deserialize, Serialize, Parser)]
+#[clap(group = ArgGroup::new("input").required(true).args(["nextclade", "ivar", "vcf", "sample_paths", "auspice", "alignment", "maf", "translations", "mutation_list"]))]
 pub struct ExtractArgs {
 
-    /// Input nextclade tsv.
-    #[clap(help = "This is created by the command nextclade run ... --output-tsv")]
+    /// Input nextclade tsv, json, or ndjson.
+    #[clap(help = "This is created by the command nextclade run ... --output-tsv (or --output-json, --output-ndjson). Use '-' to read standard input, or a https://, s3://, or gs:// url for a tsv. Multiple files or a quoted glob (ex. 'runs/*.tsv') are combined, with a run column named by each file.")]
     #[clap(long)]
-    #[clap(required = true)]
-    pub nextclade: PathBuf,
+    #[clap(num_args = 1..)]
+    pub nextclade: Vec<PathBuf>,
+
+    /// Input ivar variants tables.
+    #[clap(help = "One or more ivar variants tsv files, one per sample (named by the file stem), instead of nextclade output.")]
+    #[clap(long)]
+    #[clap(num_args = 1..)]
+    pub ivar: Vec<PathBuf>,
+
+    /// Input minor variants.
+    #[clap(help = "One or more ivar variants tsv files, one per sample, whose minor variants are merged into the input mutations (ex. nextclade consensus). Each row records its source, and minor variants missing from a sample's consensus are drawn as conflicts.")]
+    #[clap(long)]
+    #[clap(num_args = 1..)]
+    #[clap(conflicts_with = "ivar")]
+    pub minor_variants: Vec<PathBuf>,
+
+    /// Input vcf.
+    #[clap(help = "A single or multi-sample VCF (optionally bgzipped) or BCF, instead of nextclade output. Samples without genotypes are named by the file stem.")]
+    #[clap(long)]
+    pub vcf: Option<PathBuf>,
+
+    /// Vcf region.
+    #[clap(help = "Only read the vcf records in a region (ex. MN908947.3:21563-25384), using the tabix (.tbi) or CSI (.csi) index of a bgzipped VCF or BCF.")]
+    #[clap(long)]
+    #[clap(requires = "vcf")]
+    pub region: Option<String>,
+
+    /// Vcf preset.
+    #[clap(help = "Filter the vcf for the quirks of a variant caller: 'ont' for long-read callers (medaka, longshot, clair3), which drops failed filters, low quality records, and methylation calls, and trims padded multi-allelic alleles.")]
+    #[clap(long)]
+    #[clap(requires = "vcf")]
+    #[clap(value_parser = ["ont"])]
+    pub vcf_preset: Option<String>,
+
+    /// Input UShER sample paths.
+    #[clap(help = "Per-sample mutation paths from an UShER tree (matUtils extract --sample-paths), instead of nextclade output.")]
+    #[clap(long)]
+    pub sample_paths: Option<PathBuf>,
+
+    /// Input auspice json.
+    #[clap(help = "An Auspice v2 JSON tree (ex. from a Nextstrain build), whose branch mutations are accumulated for each tip, instead of nextclade output.")]
+    #[clap(long)]
+    pub auspice: Option<PathBuf>,
+
+    /// Input alignment.
+    #[clap(help = "A multi-FASTA alignment to the --reference, whose substitutions and deletions are called column by column, instead of nextclade output.")]
+    #[clap(long)]
+    #[clap(requires = "reference")]
+    pub alignment: Option<PathBuf>,
+
+    /// Input maf.
+    #[clap(help = "A Mutation Annotation Format file (ex. TCGA), whose protein changes (HGVSp_Short) of each tumor sample (Tumor_Sample_Barcode) are extracted, instead of nextclade output.")]
+    #[clap(long)]
+    pub maf: Option<PathBuf>,
+
+    /// Input protein alignments.
+    #[clap(help = "One or more per-gene protein alignments (ex. nextalign --output-translations), named by the gene (ex. nextalign.gene.S.fasta), whose amino acid substitutions and deletions are called against the --reference translated with the --gff, instead of nextclade output.")]
+    #[clap(long)]
+    #[clap(num_args = 1..)]
+    #[clap(requires = "reference")]
+    pub translations: Vec<PathBuf>,
+
+    /// Input mutation list.
+    #[clap(help = "A plain-text list of sample<TAB>mutation lines, with mutations in nextclade notation (ex. S:E484K, C913T), instead of nextclade output.")]
+    #[clap(long)]
+    pub mutation_list: Option<PathBuf>,
+
+    /// Minimum ivar variant depth.
+    #[clap(help = "Minimum total depth for an ivar variant to be extracted.")]
+    #[clap(long)]
+    #[clap(default_value = "10")]
+    pub min_depth: u32,
+
+    /// Minimum ivar variant frequency.
+    #[clap(help = "Minimum alternate allele frequency for an ivar variant to be extracted. Variants below 0.5 are low-frequency.")]
+    #[clap(long)]
+    #[clap(default_value = "0.03")]
+    pub min_freq: f64,
+
+    /// Minimum nextclade genome coverage.
+    #[clap(help = "Drop nextclade samples whose genome coverage (0-1) is below this minimum (ex. 0.9). Uses the 'coverage' column, or 'totalMissing' and the --reference length.")]
+    #[clap(long)]
+    #[clap(requires = "nextclade")]
+    pub min_coverage: Option<f64>,
 
     /// Input annotations gff from nextclade dataset.
-    #[clap(help = "This is the genome_annotations.gff3 that is provided with nextclade datasets.")]
+    #[clap(help = "This is the genome_annotations.gff3 that is provided with nextclade datasets. GTF, GFF2, and GenBank (.gb, .gbk) annotations are also accepted.")]
     #[clap(long)]
     #[clap(required = true)]
-    pub gff: PathBuf
+    pub gff: PathBuf,
+
+    /// Annotations format.
+    #[clap(help = "Format of the --gff annotations, detected from the extension or first line when not provided.")]
+    #[clap(long)]
+    #[clap(value_parser = ["gff3", "gtf", "genbank"])]
+    pub annotation_format: Option<String>,
+
+    /// Input regions of interest.
+    #[clap(help = "One or more BED files of regions of interest (ex. primer schemes, antigenic sites). Each adds a column, named region_ and the file stem (ex. region_primers), of the regions that a mutation falls inside. A BED file with several chromosomes only uses the regions on the --reference sequence.")]
+    #[clap(long)]
+    #[clap(num_args = 1..)]
+    pub bed: Vec<PathBuf>,
+
+    /// Input reference genome fasta.
+    #[clap(help = "Reference genome fasta, used to left-align indels, describe amino acid insertions and frameshifts in HGVS notation, and classify substitutions as synonymous or nonsynonymous (effect and codon_pos columns).")]
+    #[clap(long)]
+    pub reference: Option<PathBuf>,
+
+    /// Only coding substitutions.
+    #[clap(help = "Drop nucleotide substitutions outside of genes. Other mutations are kept.")]
+    #[clap(long)]
+    #[clap(requires = "reference")]
+    pub coding_only: bool,
+
+    /// Only nonsynonymous substitutions.
+    #[clap(help = "Drop synonymous and noncoding nucleotide substitutions, classified within the codon of each sample. Other mutations are kept.")]
+    #[clap(long)]
+    #[clap(requires = "reference")]
+    pub nonsyn_only: bool,
+
+    /// Gene model cache directory.
+    #[clap(help = "Directory to cache parsed gene models in (keyed by the hash of the annotations), so repeated runs with the same annotations skip parsing.")]
+    #[clap(long)]
+    pub gene_cache: Option<PathBuf>,
+
+    /// Intergenic mutations.
+    #[clap(help = "What to do with nucleotide mutations outside of every gene: leave the gene 'null', 'label' it by region (ex. 5'UTR, intergenic:28260-28273), or 'drop' them.")]
+    #[clap(long)]
+    #[clap(default_value = "null")]
+    #[clap(value_parser = ["null", "label", "drop"])]
+    pub intergenic: String,
+
+    /// Frameshifts.
+    #[clap(help = "Where to write frameshifts: keep them in the 'mutations' table, or move them to a separate 'qc' table (frameshifts.tsv) that is excluded from the heatmap.")]
+    #[clap(long)]
+    #[clap(default_value = "mutations")]
+    #[clap(value_parser = ["mutations", "qc"])]
+    pub frameshifts: String,
+
+    /// Nucleotide mutation columns.
+    #[clap(help = "Comma-separated nextclade columns of nucleotide mutations to extract.")]
+    #[clap(long)]
+    #[clap(value_delimiter = ',')]
+    #[clap(default_value = "substitutions,deletions,insertions")]
+    pub nucleotide_columns: Vec<String>,
+
+    /// Amino acid mutation columns.
+    #[clap(help = "Comma-separated nextclade columns of amino acid mutations to extract, in GENE:MUTATION notation.")]
+    #[clap(long)]
+    #[clap(value_delimiter = ',')]
+    #[clap(default_value = "frameShifts,aaSubstitutions,aaDeletions,aaInsertions")]
+    pub amino_acid_columns: Vec<String>,
+
+    /// Output QC report html.
+    #[clap(help = "Write an html page of extraction statistics (rows per stage, samples without mutations, unmatched genes, warnings), for pipeline debugging.")]
+    #[clap(long)]
+    pub qc: Option<PathBuf>,
+
+    /// Number precision.
+    #[clap(help = "Digits after the decimal separator of the QC report fractions.")]
+    #[clap(long)]
+    #[clap(default_value = "2")]
+    pub precision: usize,
+
+    /// Decimal separator.
+    #[clap(help = "Decimal separator of the QC report fractions (ex. ',').")]
+    #[clap(long)]
+    #[clap(default_value = ".")]
+    pub decimal_separator: char,
+
+    /// Percent formatting.
+    #[clap(help = "Show the QC report fractions as percentages.")]
+    #[clap(long)]
+    pub percent: bool,
+
+    /// Output formats.
+    #[clap(help = "Comma-separated formats to write the mutations table in (mutations.tsv, mutations.parquet, a mutations table in mutations.sqlite, appended to the mutations.delta table, Arrow IPC mutations.arrow, a json array mutations.json for web dashboards, or newline-delimited mutations.ndjson). The sqlite and delta formats require the features of the same name.")]
+    #[clap(long)]
+    #[clap(alias = "format")]
+    #[clap(value_delimiter = ',')]
+    #[clap(default_value = "tsv,parquet")]
+    #[clap(value_parser = ["tsv", "parquet", "sqlite", "delta", "arrow", "json", "ndjson"])]
+    pub to: Vec<String>,
+
+    /// Output prefix.
+    #[clap(help = "Prefix of every output table (ex. results/run1. writes results/run1.mutations.tsv), the directory is created if needed.")]
+    #[clap(long)]
+    #[clap(default_value = "")]
+    pub prefix: String,
+
+    /// Output mutations table.
+    #[clap(help = "Write the mutations table to this path instead, in the format of its extension (ex. results/mutations.parquet).")]
+    #[clap(long)]
+    #[clap(conflicts_with = "to")]
+    pub output: Option<PathBuf>,
+
+    /// Assign integer sample and mutation IDs.
+    #[clap(help = "Add integer sample_id and mutation_id columns, and write the sample_ids and mutation_ids lookup tables. The IDs are derived from the names, so they're the same across runs and cohorts.")]
+    #[clap(long)]
+    pub ids: bool,
+
+    /// Write a per-sample summary table.
+    #[clap(help = "Also write a per-sample table (samples.tsv), with the number of mutations of each column and in each --bed's regions, and the nextclade clade and QC status when present.")]
+    #[clap(long)]
+    pub emit_sample_table: bool,
+
+    /// Output column case.
+    #[clap(help = "Case of the output column names: original (ex. seqName, qc.overallStatus), snake (seq_name, qc_overall_status), or camel (seqName, qcOverallStatus). The other commands expect the original names.")]
+    #[clap(long)]
+    #[clap(default_value = "original")]
+    #[clap(value_parser = ["original", "snake", "camel"])]
+    pub column_case: String,
+
+    /// Output column renames.
+    #[clap(help = "Comma-separated renames of output columns as new=old, like --column-map of annotate, which take precedence over --column-case (ex. qc_status=qc.overallStatus,sample_name=seqName).")]
+    #[clap(long)]
+    #[clap(default_value = "")]
+    pub rename_columns: String,
 }
 
 
+/// Annotate samples with the presence or absence of catalogued mutations.
+#[derive(Clone, Debug, Deserialize, Serialize, Parser)]
+#[clap(group = ArgGroup::new("input").required(true).args(["mutations", "ivar"]))]
+pub struct AnnotateArgs {
+
+    /// Input mutations table.
+    #[clap(help = "This is the mutations.tsv, mutations.parquet, or mutations.sqlite created by the extract command.")]
+    #[clap(long)]
+    pub mutations: Option<PathBuf>,
+
+    /// Input ivar variants tables.
+    #[clap(help = "One or more ivar variants tsv files, one per sample (named by the file stem).")]
+    #[clap(long)]
+    #[clap(num_args = 1..)]
+    pub ivar: Vec<PathBuf>,
+
+    /// Minimum ivar variant depth.
+    #[clap(help = "Minimum total depth for an ivar variant to be called.")]
+    #[clap(long)]
+    #[clap(default_value = "10")]
+    pub min_depth: u32,
+
+    /// Minimum ivar variant frequency.
+    #[clap(help = "Minimum alternate allele frequency for an ivar variant to be called. Variants below 0.5 are low-frequency.")]
+    #[clap(long)]
+    #[clap(default_value = "0.03")]
+    pub min_freq: f64,
+
+    /// Input annotations table.
+    #[clap(help = "Annotations table (tsv, csv, or the first sheet of an xlsx workbook, with the xlsx feature) with a 'mutation' column, and optional 'column' and 'is_gene' columns. Use '-' to read standard input, or a https://, s3://, or gs:// url.")]
+    #[clap(long)]
+    #[clap(required = true)]
+    pub annotations: PathBuf,
+
+    /// Input annotations gff from nextclade dataset.
+    #[clap(help = "Gene coordinates used to convert HGVS coding (c.) mutations in the annotations.")]
+    #[clap(long)]
+    pub gff: Option<PathBuf>,
+
+    /// Input reference genome fasta.
+    #[clap(help = "Reference genome fasta, used to left-align the indels of the mutations and annotations before matching. With --gff, nucleotide substitutions in the same codon are also merged into one amino acid substitution before matching.")]
+    #[clap(long)]
+    pub reference: Option<PathBuf>,
+
+    /// Gene model cache directory.
+    #[clap(help = "Directory to cache parsed gene models in (keyed by the hash of the annotations), so repeated runs with the same annotations skip parsing.")]
+    #[clap(long)]
+    #[clap(requires = "gff")]
+    pub gene_cache: Option<PathBuf>,
+
+    /// Deletion range matching.
+    #[clap(help = "How amino acid deletion ranges in the annotations (ex. S:del69-70) match the codon deletions of a sample: 'overlap' if any codon is deleted, or 'exact' if every codon is deleted.")]
+    #[clap(long)]
+    #[clap(default_value = "overlap")]
+    #[clap(value_parser = ["overlap", "exact"])]
+    pub deletion_match: String,
+
+    /// Mutations column mapping.
+    #[clap(help = "Comma-separated renames of the columns of another pipeline's variant table onto the expected mutations columns, as new=old (ex. sample=SequenceName,mutation=AAChange).")]
+    #[clap(long)]
+    #[clap(default_value = "")]
+    pub column_map: String,
+
+    /// Input sample metadata.
+    #[clap(help = "Per-sample metadata (tsv or csv) joined onto the annotated table by sample (ex. collection date, location, host), for plot labels and grouping.")]
+    #[clap(long)]
+    pub metadata: Option<PathBuf>,
+
+    /// Metadata sample column.
+    #[clap(help = "Metadata column with the sample names to join on. The first column by default. Each sample can only have one row.")]
+    #[clap(long)]
+    #[clap(requires = "metadata")]
+    pub metadata_key: Option<String>,
+
+    /// Output annotated table.
+    #[clap(help = "Output annotated table (tsv, csv, parquet, sqlite with an annotated table and the sqlite feature, a json array, or ndjson).")]
+    #[clap(long)]
+    #[clap(default_value = "annotated.tsv")]
+    pub output: PathBuf,
+
+    /// Output column case.
+    #[clap(help = "Case of the output column names: original (ex. seqName, qc.overallStatus), snake (seq_name, qc_overall_status), or camel (seqName, qcOverallStatus). The other commands expect the original names.")]
+    #[clap(long)]
+    #[clap(default_value = "original")]
+    #[clap(value_parser = ["original", "snake", "camel"])]
+    pub column_case: String,
+
+    /// Output column renames.
+    #[clap(help = "Comma-separated renames of output columns as new=old, like --column-map of annotate, which take precedence over --column-case (ex. qc_status=qc.overallStatus,sample_name=seqName).")]
+    #[clap(long)]
+    #[clap(default_value = "")]
+    pub rename_columns: String,
+}
+
 /// Detect recombination in a dataset population and/or input alignment.
 #[derive(Clone, Debug, Deserialize, Serialize, Parser)]
 pub struct PlotArgs {
 
+    /// Input annotated or mutations table.
+    #[clap(help = "This is the annotated table created by the annotate command, or the mutations table created by the extract command. Cells are colored by mutation status.")]
+    #[clap(long)]
+    pub annotated: Option<PathBuf>,
+
+    /// Three-letter amino acid labels.
+    #[clap(help = "Write amino acid mutations with three-letter residues (ex. S:Phe456Leu instead of S:F456L).")]
+    #[clap(long)]
+    pub three_letter: bool,
+
+    /// Deletion labels with del.
+    #[clap(help = "Write amino acid deletions with del (ex. S:del69 instead of S:H69-).")]
+    #[clap(long)]
+    pub del_notation: bool,
+
+    /// Number precision.
+    #[clap(help = "Digits after the decimal separator of the aggregate frequencies in the cell tooltips.")]
+    #[clap(long)]
+    #[clap(default_value = "2")]
+    pub precision: usize,
+
+    /// Decimal separator.
+    #[clap(help = "Decimal separator of the aggregate frequencies in the cell tooltips (ex. ',').")]
+    #[clap(long)]
+    #[clap(default_value = ".")]
+    pub decimal_separator: char,
+
+    /// Percent formatting.
+    #[clap(help = "Show the aggregate frequencies in the cell tooltips as percentages.")]
+    #[clap(long)]
+    pub percent: bool,
+
+    /// Samples to include.
+    #[clap(help = "File of sample names to plot, one per line. All samples are plotted by default.")]
+    #[clap(long)]
+    pub include_samples: Option<PathBuf>,
+
+    /// Samples to exclude.
+    #[clap(help = "File of sample names to leave out of the plot, one per line.")]
+    #[clap(long)]
+    pub exclude_samples: Option<PathBuf>,
+
+    /// Previous annotated table.
+    #[clap(help = "Annotated table of a previous run. Cells are colored by change (gained, lost, present, absent) instead of status.")]
+    #[clap(long)]
+    #[clap(requires = "annotated")]
+    #[clap(conflicts_with = "aggregate")]
+    pub previous: Option<PathBuf>,
+
     /// Output file prefix.
     #[clap(help = "Output file prefix.")]
     #[clap(long)]
     pub prefix: String,
 
-}
\ No newline at end of file
+    /// Mutation label template.
+    #[clap(help = "Template for the mutation labels, filled in from the annotated table columns (ex. '{mutation} | {drug}').")]
+    #[clap(long)]
+    pub label: Option<String>,
+
+    /// Mutation label rotation.
+    #[clap(help = "Rotation of the mutation labels, in degrees (ex. 90, 45, 0).")]
+    #[clap(long)]
+    #[clap(value_parser = clap::value_parser!(u32).range(0..=90))]
+    #[clap(default_value = "90")]
+    pub label_rotation: u32,
+
+    /// Mutation label placement.
+    #[clap(help = "Side of the heatmap to draw the mutation labels on.")]
+    #[clap(long)]
+    #[clap(value_parser = ["top", "bottom"])]
+    #[clap(default_value = "top")]
+    pub mutation_labels: String,
+
+    /// Sample label placement.
+    #[clap(help = "Side of the heatmap to draw the sample labels on.")]
+    #[clap(long)]
+    #[clap(value_parser = ["left", "right"])]
+    #[clap(default_value = "left")]
+    pub sample_labels: String,
+
+    /// Repeat the mutation labels.
+    #[clap(help = "Repeat the mutation labels on the opposite side of the heatmap, for figures with many samples.")]
+    #[clap(long)]
+    pub repeat_mutation_labels: bool,
+
+    /// Repeat the sample labels.
+    #[clap(help = "Repeat the sample labels on the opposite side of the heatmap, for figures with many mutations.")]
+    #[clap(long)]
+    pub repeat_sample_labels: bool,
+
+    /// Compact barcode mode.
+    #[clap(help = "Render a compact barcode with 1-2 px rows and no sample labels, for thousands of samples. The sample of each row is written to PREFIX.index.tsv.")]
+    #[clap(long)]
+    pub barcode: bool,
+
+    /// Aggregate samples by group.
+    #[clap(help = "Plot groups (--group-by) by mutations, colored by the frequency of each mutation within the group.")]
+    #[clap(long)]
+    #[clap(requires = "group_by")]
+    pub aggregate: bool,
+
+    /// Input sample metadata.
+    #[clap(help = "Per-sample metadata (tsv or csv), used to group the rows into labelled blocks, the barcode rows into bands, or aggregate samples. The first column is the sample name.")]
+    #[clap(long)]
+    pub metadata: Option<PathBuf>,
+
+    /// Metadata column to group samples by.
+    #[clap(help = "Metadata column to group samples by (ex. lineage, facility, week).")]
+    #[clap(long)]
+    #[clap(requires = "metadata")]
+    pub group_by: Option<String>,
+
+    /// Render cache directory.
+    #[clap(help = "Directory to cache rendered pngs in, so re-plotting unchanged data and style skips rasterizing.")]
+    #[clap(long)]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Skip font embedding.
+    #[clap(help = "Don't embed the font in the svg. The embedded font is subset to the characters of the labels, but still adds tens of KB. Viewers will fall back to a local font.")]
+    #[clap(long)]
+    pub no_embed_font: bool,
+
+    /// Skip the png.
+    #[clap(help = "Only write the svg, without rendering a png. A png left by a previous run with the same prefix is removed.")]
+    #[clap(long)]
+    pub no_png: bool,
+
+    /// Largest png.
+    #[clap(help = "The most cells of a heatmap rendered to a png. Larger heatmaps are an error, unless the png is skipped with --no-png.")]
+    #[clap(long)]
+    #[clap(default_value = "250000")]
+    pub max_png_cells: usize,
+
+    /// Embed the plot data.
+    #[clap(help = "Embed the plotted matrix, options, and SHA-256 of the inputs in the svg <metadata> (gzipped, base64 JSON), so the figure can be traced and re-plotted.")]
+    #[clap(long)]
+    pub embed_data: bool,
+
+    /// Gene products band.
+    #[clap(help = "Draw a band of gene products (ex. the nsps of ORF1ab) under the mutations, from a tsv of gene, product, start, end (amino acid coordinates) or annotations with mature peptide features.")]
+    #[clap(long)]
+    pub products: Option<PathBuf>,
+
+    /// Input mutation scores.
+    #[clap(help = "Table of per-mutation scores (ex. DMS antibody escape), with a 'mutation' column written as in the annotated table (ex. S:E484K). Scores are shown in the cell tooltips.")]
+    #[clap(long)]
+    pub scores: Option<PathBuf>,
+
+    /// Score column.
+    #[clap(help = "Column of the --scores table to read the scores from.")]
+    #[clap(long)]
+    #[clap(default_value = "score")]
+    pub score_column: String,
+
+    /// Color cells by score.
+    #[clap(help = "Shade the observed mutations by their score (relative to the largest absolute score), rather than their status.")]
+    #[clap(long)]
+    #[clap(requires = "scores")]
+    pub color_by_score: bool,
+
+    /// Sort mutations by score.
+    #[clap(help = "Sort the mutation columns by descending score, with unscored mutations last.")]
+    #[clap(long)]
+    #[clap(requires = "scores")]
+    pub sort_by_score: bool,
+
+    /// Per-sample sparklines.
+    #[clap(help = "Draw a sparkline right of each sample row, a tiny histogram of where its mutations fall along the genome.")]
+    #[clap(long)]
+    pub sparkline: bool,
+
+    /// Input gene annotations.
+    #[clap(help = "Gene annotations (GFF3, GTF, or GenBank), used to place amino acid mutations along the genome in the sparklines.")]
+    #[clap(long)]
+    #[clap(requires = "sparkline")]
+    pub gff: Option<PathBuf>,
+
+    /// Gene model cache directory.
+    #[clap(help = "Directory to cache parsed gene models in (keyed by the hash of the annotations), so repeated runs with the same annotations skip parsing.")]
+    #[clap(long)]
+    #[clap(requires = "gff")]
+    pub gene_cache: Option<PathBuf>,
+
+    /// Input nextclade genome coverage.
+    #[clap(help = "Nextclade output (TSV or JSON) with a 'coverage' column. Draws a bar of each sample's genome coverage next to its label, to judge its calls in context.")]
+    #[clap(long)]
+    pub coverage: Option<PathBuf>,
+
+    /// Sample link template.
+    #[clap(help = "URL template to link each sample label to (ex. 'https://lims.example.org/samples/{sample}').")]
+    #[clap(long)]
+    pub sample_url: Option<String>,
+
+    /// Mutation link template.
+    #[clap(help = "URL template to link each mutation label to (ex. 'https://cov-spectrum.org/explore/World/AllSamples/Past6M/variants?aaMutations={mutation}').")]
+    #[clap(long)]
+    pub mutation_url: Option<String>,
+
+    /// Cell link template.
+    #[clap(help = "URL template to link each cell to, using {sample} and {mutation}. The sample and cell links are not drawn with --aggregate.")]
+    #[clap(long)]
+    pub cell_url: Option<String>,
+
+}
+
+/// Compute per-site diversity (entropy) across samples.
+#[derive(Clone, Debug, Deserialize, Serialize, Parser)]
+pub struct DiversityArgs {
+
+    /// Input mutations table.
+    #[clap(help = "This is the mutations.tsv or mutations.parquet created by the extract command.")]
+    #[clap(long)]
+    #[clap(required = true)]
+    pub mutations: PathBuf,
+
+    /// Output diversity table.
+    #[clap(help = "Output per-site diversity table.")]
+    #[clap(long)]
+    #[clap(default_value = "diversity.tsv")]
+    pub output: PathBuf,
+
+    /// Output heat track prefix.
+    #[clap(help = "Render the nucleotide entropy as a genome heat track with this output prefix.")]
+    #[clap(long)]
+    pub track: Option<String>,
+}
+
+/// Compute pairwise SNP distances between samples.
+#[derive(Clone, Debug, Deserialize, Serialize, Parser)]
+pub struct DistancesArgs {
+
+    /// Input mutations table.
+    #[clap(help = "This is the mutations.tsv or mutations.parquet created by the extract command.")]
+    #[clap(long)]
+    #[clap(required = true)]
+    pub mutations: PathBuf,
+
+    /// Input nextclade tsv.
+    #[clap(help = "Ignore positions within each sample's missing ranges from this nextclade tsv.")]
+    #[clap(long)]
+    pub nextclade: Option<PathBuf>,
+
+    /// Position ranges to mask.
+    #[clap(help = "Comma-separated position ranges to ignore in all samples (ex. 1-54,29837-29903).")]
+    #[clap(long)]
+    #[clap(default_value = "")]
+    pub mask: String,
+
+    /// Output square distance matrix.
+    #[clap(help = "Output square distance matrix.")]
+    #[clap(long)]
+    #[clap(default_value = "distances.tsv")]
+    pub output_matrix: PathBuf,
+
+    /// Output long-format distance pairs.
+    #[clap(help = "Output long-format distance pairs.")]
+    #[clap(long)]
+    #[clap(default_value = "distances_pairs.tsv")]
+    pub output_pairs: PathBuf,
+
+    /// SNP threshold for single-linkage clustering.
+    #[clap(help = "Cluster samples within this many SNPs of each other (single-linkage).")]
+    #[clap(long)]
+    pub cluster_threshold: Option<u32>,
+
+    /// Output per-sample cluster IDs.
+    #[clap(help = "Output per-sample cluster IDs, written when --cluster-threshold is used.")]
+    #[clap(long)]
+    #[clap(default_value = "clusters.tsv")]
+    pub output_clusters: PathBuf,
+
+    /// Output sample network.
+    #[clap(help = "Output sample network, as DOT (.dot) or GraphML (.graphml).")]
+    #[clap(long)]
+    pub output_graph: Option<PathBuf>,
+
+    /// SNP threshold for the sample network.
+    #[clap(help = "Link all samples within this many SNPs, instead of drawing a minimum spanning tree.")]
+    #[clap(long)]
+    pub graph_threshold: Option<u32>,
+
+    /// Input sample metadata.
+    #[clap(help = "Per-sample metadata (tsv or csv) used as network node attributes. The first column is the sample name.")]
+    #[clap(long)]
+    pub metadata: Option<PathBuf>,
+}
+
+/// Reconstruct which mutations arose on which branches of a tree.
+#[derive(Clone, Debug, Deserialize, Serialize, Parser)]
+pub struct TreeArgs {
+
+    /// Input mutations table.
+    #[clap(help = "This is the mutations.tsv or mutations.parquet created by the extract command.")]
+    #[clap(long)]
+    #[clap(required = true)]
+    pub mutations: PathBuf,
+
+    /// Input Newick tree.
+    #[clap(help = "Newick tree whose tip names match the sample names.")]
+    #[clap(long)]
+    #[clap(required = true)]
+    pub tree: PathBuf,
+
+    /// Output branch mutations table.
+    #[clap(help = "Output table of mutations gained or lost on each branch.")]
+    #[clap(long)]
+    #[clap(default_value = "branches.tsv")]
+    pub output: PathBuf,
+}
+
+/// Export the mutations table to other file formats.
+#[derive(Clone, Debug, Deserialize, Serialize, Parser)]
+pub struct ExportArgs {
+
+    /// Pass CLI arguments to a particular [ExportCommand].
+    #[clap(subcommand)]
+    #[clap(help = "Choose an export format.")]
+    pub command: ExportCommand,
+}
+
+/// Export [formats](#variants).
+#[derive(Clone, Debug, Deserialize, Serialize, Subcommand)]
+#[clap(arg_required_else_help = true)]
+pub enum ExportCommand {
+    #[clap(about = "Export mutations as GFF3 features.")]
+    Gff(ExportGffArgs),
+
+    #[clap(about = "Export nucleotide mutations as a multi-sample VCF.")]
+    Vcf(ExportVcfArgs),
+
+    #[clap(about = "Export per-residue mutation counts for protein structure coloring.")]
+    Residues(ExportResiduesArgs),
+
+    #[clap(about = "Export one line per sample with its comma-joined mutations.")]
+    Lists(ExportListsArgs),
+}
+
+/// Export mutations as GFF3 features.
+#[derive(Clone, Debug, Deserialize, Serialize, Parser)]
+pub struct ExportGffArgs {
+
+    /// Input mutations table.
+    #[clap(help = "This is the mutations.tsv or mutations.parquet created by the extract command, or the table created by the annotate command.")]
+    #[clap(long)]
+    #[clap(required = true)]
+    pub mutations: PathBuf,
+
+    /// Input extracted mutations table, for the coordinates of an annotated table.
+    #[clap(help = "The mutations table created by the extract command, whose coordinates are joined onto an annotated --mutations table. Required when --mutations is annotated.")]
+    #[clap(long)]
+    pub extract: Option<PathBuf>,
+
+    /// Output GFF3 file.
+    #[clap(help = "Output GFF3 file.")]
+    #[clap(long)]
+    #[clap(default_value = "mutations.gff3")]
+    pub output: PathBuf,
+
+    /// Sequence identifier of the reference genome.
+    #[clap(help = "Sequence identifier used in the first GFF3 column.")]
+    #[clap(long)]
+    #[clap(default_value = ".")]
+    pub seqid: String,
+
+    /// Aggregate mutations across samples.
+    #[clap(help = "Write one feature per mutation with count and frequency attributes, rather than one per sample.")]
+    #[clap(long)]
+    pub aggregate: bool,
+}
+
+/// Export nucleotide mutations as a multi-sample VCF.
+#[derive(Clone, Debug, Deserialize, Serialize, Parser)]
+pub struct ExportVcfArgs {
+
+    /// Input mutations table.
+    #[clap(help = "This is the mutations.tsv or mutations.parquet created by the extract command.")]
+    #[clap(long)]
+    #[clap(required = true)]
+    pub mutations: PathBuf,
+
+    /// Input reference genome fasta.
+    #[clap(help = "Reference genome fasta, used to synthesize REF and ALT alleles.")]
+    #[clap(long)]
+    #[clap(required = true)]
+    pub reference: PathBuf,
+
+    /// Output VCF file.
+    #[clap(help = "Output VCF file.")]
+    #[clap(long)]
+    #[clap(default_value = "mutations.vcf")]
+    pub output: PathBuf,
+}
+
+/// Export per-residue mutation counts for protein structure coloring.
+#[derive(Clone, Debug, Deserialize, Serialize, Parser)]
+pub struct ExportResiduesArgs {
+
+    /// Input mutations table.
+    #[clap(help = "This is the mutations.tsv or mutations.parquet created by the extract command.")]
+    #[clap(long)]
+    #[clap(required = true)]
+    pub mutations: PathBuf,
+
+    /// Gene to count residues of.
+    #[clap(help = "Gene whose residues will be counted (ex. S).")]
+    #[clap(long)]
+    #[clap(required = true)]
+    pub gene: String,
+
+    /// Structure chain identifier.
+    #[clap(help = "Chain identifier the gene is modelled as in the structure.")]
+    #[clap(long)]
+    #[clap(default_value = "A")]
+    pub chain: String,
+
+    /// Input PDB structure.
+    #[clap(help = "Rewrite the B-factor column of this PDB structure with the fraction of samples (0-1) mutated at each residue, instead of writing a ChimeraX defattr file of counts.")]
+    #[clap(long)]
+    pub pdb: Option<PathBuf>,
+
+    /// Output file.
+    #[clap(help = "Output defattr file, or PDB structure if --pdb is used.")]
+    #[clap(long)]
+    #[clap(default_value = "residues.defattr")]
+    pub output: PathBuf,
+}
+
+/// Export one line per sample with its comma-joined mutations.
+#[derive(Clone, Debug, Deserialize, Serialize, Parser)]
+pub struct ExportListsArgs {
+
+    /// Input mutations table.
+    #[clap(help = "This is the mutations.tsv or mutations.parquet created by the extract command.")]
+    #[clap(long)]
+    #[clap(required = true)]
+    pub mutations: PathBuf,
+
+    /// Output sample lists.
+    #[clap(help = "Output table of samples and their comma-joined mutations.")]
+    #[clap(long)]
+    #[clap(default_value = "mutation_lists.tsv")]
+    pub output: PathBuf,
+
+    /// Nextclade columns to export.
+    #[clap(help = "Only export mutations from these nextclade columns (ex. aaSubstitutions). All columns are exported by default.")]
+    #[clap(long)]
+    #[clap(value_delimiter = ',')]
+    pub columns: Vec<String>,
+
+    /// Genes to export.
+    #[clap(help = "Only export mutations in these genes (ex. S). All genes are exported by default.")]
+    #[clap(long)]
+    #[clap(value_delimiter = ',')]
+    pub genes: Vec<String>,
+
+    /// Three-letter amino acid labels.
+    #[clap(help = "Write amino acid mutations with three-letter residues (ex. S:Phe456Leu instead of S:F456L).")]
+    #[clap(long)]
+    pub three_letter: bool,
+
+    /// Deletion labels with del.
+    #[clap(help = "Write amino acid deletions with del (ex. S:del69 instead of S:H69-).")]
+    #[clap(long)]
+    pub del_notation: bool,
+}
+
+/// Write the gene model parsed from the gene annotations.
+#[derive(Clone, Debug, Deserialize, Serialize, Parser)]
+pub struct GenesArgs {
+
+    /// Input annotations gff.
+    #[clap(help = "This is the genome_annotations.gff3 that is provided with nextclade datasets.")]
+    #[clap(long)]
+    #[clap(required = true)]
+    pub gff: PathBuf,
+
+    /// Output gene model table.
+    #[clap(help = "Output gene model table (name, strand, CDS segments, and lengths).")]
+    #[clap(long)]
+    #[clap(default_value = "genes.tsv")]
+    pub output: PathBuf,
+}
+
+/// Compute the fraction of codons covered per sample per gene.
+#[derive(Clone, Debug, Deserialize, Serialize, Parser)]
+pub struct CoverageArgs {
+
+    /// Input nextclade tsv, json, or ndjson.
+    #[clap(help = "This is created by the command nextclade run ... --output-tsv (or --output-json, --output-ndjson)")]
+    #[clap(long)]
+    #[clap(required = true)]
+    pub nextclade: PathBuf,
+
+    /// Input annotations gff from nextclade dataset.
+    #[clap(help = "This is the genome_annotations.gff3 that is provided with nextclade datasets.")]
+    #[clap(long)]
+    #[clap(required = true)]
+    pub gff: PathBuf,
+
+    /// Output coverage table.
+    #[clap(help = "Output per sample per gene coverage table.")]
+    #[clap(long)]
+    #[clap(default_value = "coverage.tsv")]
+    pub output: PathBuf,
+
+    /// Output heatmap prefix.
+    #[clap(help = "Render the coverage as a sample by gene heatmap with this output prefix.")]
+    #[clap(long)]
+    pub heatmap: Option<String>,
+}
+
+/// Compute the abundance of lineage-defining mutations in wastewater samples, from Freyja output.
+#[derive(Clone, Debug, Deserialize, Serialize, Parser)]
+pub struct FreyjaArgs {
+
+    /// Input freyja demix output.
+    #[clap(help = "One or more freyja demix files (named by the file stem), or the output of freyja aggregate.")]
+    #[clap(long)]
+    #[clap(required = true)]
+    #[clap(num_args = 1..)]
+    pub demix: Vec<PathBuf>,
+
+    /// Input freyja barcodes.
+    #[clap(help = "The freyja barcodes csv that the samples were demixed with (ex. usher_barcodes.csv).")]
+    #[clap(long)]
+    #[clap(required = true)]
+    pub barcodes: PathBuf,
+
+    /// Output abundances table.
+    #[clap(help = "Output table of sample, mutation, and abundance.")]
+    #[clap(long)]
+    #[clap(default_value = "freyja_mutations.tsv")]
+    pub output: PathBuf,
+
+    /// Output heatmap prefix.
+    #[clap(help = "Render the abundances as a sample by mutation heatmap with this output prefix.")]
+    #[clap(long)]
+    pub heatmap: Option<String>,
+}
+
+/// Flag the mutations of each sample that fall inside primer binding sites.
+#[derive(Clone, Debug, Deserialize, Serialize, Parser)]
+pub struct PrimersArgs {
+
+    /// Input mutations table.
+    #[clap(help = "This is the mutations.tsv or mutations.parquet created by the extract command.")]
+    #[clap(long)]
+    #[clap(required = true)]
+    pub mutations: PathBuf,
+
+    /// Input primer scheme bed.
+    #[clap(help = "An ARTIC-style primer scheme BED, with LEFT and RIGHT primer names (ex. SARS-CoV-2_1_LEFT).")]
+    #[clap(long)]
+    #[clap(required = true)]
+    pub bed: PathBuf,
+
+    /// Output primer mutations table.
+    #[clap(help = "Output table of sample, mutation, primer, and distance from the primer's 3' end.")]
+    #[clap(long)]
+    #[clap(default_value = "primer_mutations.tsv")]
+    pub output: PathBuf,
+
+    /// Output heatmap prefix.
+    #[clap(help = "Render the primer mutations as a sample by mutation heatmap with this output prefix, with mutations near the 3' end in red.")]
+    #[clap(long)]
+    pub heatmap: Option<String>,
+}
+
+/// Summarize the nonsynonymous and synonymous substitutions of each gene.
+#[derive(Clone, Debug, Deserialize, Serialize, Parser)]
+pub struct SelectionArgs {
+
+    /// Input mutations table.
+    #[clap(help = "This is the mutations.tsv or mutations.parquet created by the extract command. The effect column from extract --reference is used when present.")]
+    #[clap(long)]
+    #[clap(required = true)]
+    pub mutations: PathBuf,
+
+    /// Input annotations gff.
+    #[clap(help = "Gene annotations (GFF3, GTF, or GenBank), to count the nonsynonymous and synonymous sites of each gene.")]
+    #[clap(long)]
+    #[clap(required = true)]
+    pub gff: PathBuf,
+
+    /// Input reference genome fasta.
+    #[clap(help = "Reference genome fasta, to count the nonsynonymous and synonymous sites of each gene.")]
+    #[clap(long)]
+    #[clap(required = true)]
+    pub reference: PathBuf,
+
+    /// Gene model cache directory.
+    #[clap(help = "Directory to cache parsed gene models in (keyed by the hash of the annotations), so repeated runs with the same annotations skip parsing.")]
+    #[clap(long)]
+    pub gene_cache: Option<PathBuf>,
+
+    /// Output selection table.
+    #[clap(help = "Output per-gene table of nonsynonymous and synonymous substitution and site counts, pN, pS, and pN/pS.")]
+    #[clap(long)]
+    #[clap(default_value = "selection.tsv")]
+    pub output: PathBuf,
+}
+
+/// Count the annotated mutations present per sample across allele frequency thresholds.
+#[derive(Clone, Debug, Deserialize, Serialize, Parser)]
+pub struct SweepArgs {
+
+    /// Input annotated table.
+    #[clap(help = "This is the annotated.tsv or annotated.parquet created by the annotate command from ivar variants. Annotate with a --min-freq at or below the lowest threshold.")]
+    #[clap(long)]
+    #[clap(required = true)]
+    pub annotated: PathBuf,
+
+    /// Allele frequency thresholds.
+    #[clap(help = "Comma-separated allele frequency thresholds to count the present mutations at.")]
+    #[clap(long)]
+    #[clap(value_delimiter = ',')]
+    #[clap(default_value = "0.01,0.02,0.03,0.05,0.1,0.15,0.2,0.25,0.5,0.75")]
+    pub thresholds: Vec<f64>,
+
+    /// Output threshold sweep table.
+    #[clap(help = "Output table of sample, threshold, and the number of annotated mutations present.")]
+    #[clap(long)]
+    #[clap(default_value = "threshold_sweep.tsv")]
+    pub output: PathBuf,
+
+    /// Output heatmap prefix.
+    #[clap(help = "Render the sweep as a sample by threshold heatmap with this output prefix, shaded by the number of present mutations.")]
+    #[clap(long)]
+    pub heatmap: Option<String>,
+}
+
+/// Select columns from the mutations table.
+#[derive(Clone, Debug, Deserialize, Serialize, Parser)]
+pub struct QueryArgs {
+
+    /// Input mutations table.
+    #[clap(help = "This is the mutations.tsv or mutations.parquet created by the extract or annotate command.")]
+    #[clap(long)]
+    #[clap(required = true)]
+    pub mutations: PathBuf,
+
+    /// Columns to select.
+    #[clap(help = "Only read these columns (ex. sample). All columns are selected by default.")]
+    #[clap(long)]
+    #[clap(value_delimiter = ',')]
+    pub columns: Vec<String>,
+
+    /// Remove duplicate rows.
+    #[clap(help = "Remove duplicate rows from the selection (ex. for a list of samples).")]
+    #[clap(long)]
+    pub distinct: bool,
+
+    /// Read-only SQL query.
+    #[clap(help = "Run a read-only SQL query of the 'mutations' table instead (ex. SELECT gene, COUNT(*) FROM mutations GROUP BY gene).")]
+    #[clap(long)]
+    #[clap(conflicts_with_all = ["columns", "distinct"])]
+    pub sql: Option<String>,
+
+    /// Table catalog.
+    #[clap(help = "A catalog (toml) of named tables, each with a path and optional format (tsv or csv), registered for the --sql query to join against (ex. gene aliases, lab mutation catalogs). Tables can't be named 'mutations'.")]
+    #[clap(long)]
+    #[clap(requires = "sql")]
+    pub catalog: Option<PathBuf>,
+
+    /// Output table.
+    #[clap(help = "Output table (tsv or parquet).")]
+    #[clap(long)]
+    #[clap(default_value = "query.tsv")]
+    pub output: PathBuf,
+}
@@ -2,38 +2,188 @@ use chrono::Local;
 use clap::Parser;
 use color_eyre::eyre::{Report, Result};
 use env_logger::Builder;
-use mutation_heatmap::{extract, plot};
+use mutation_heatmap::{ColumnNames, annotate, coverage, distances, diversity, expand_globs, export, extract, freyja, gene, parse_column_map, parse_ranges, plot, primers, query, read_metadata, read_sample_list, resolve_stdin, selection, sweep, tree};
+use mutation_heatmap::annotate::AnnotateOptions;
+use mutation_heatmap::extract::{ExtractOptions, Input};
+use mutation_heatmap::notation::LabelStyle;
+use mutation_heatmap::number::NumberFormat;
 use mutation_heatmap_cli::{Cli, Command};
+use mutation_heatmap_cli::cli::ExportCommand;
 use std::io::Write;
 
 #[tokio::main]
 async fn main() -> Result<(), Report> {
 
-    mutation_heatmap::convert().await?;
-    // // Parse arguments from the CLI
-    // let args = Cli::parse();
-    // // initialize color_eyre crate for colorized logs
-    // color_eyre::install()?;
+    // Parse arguments from the CLI
+    let args = Cli::parse();
+    // initialize color_eyre crate for colorized logs
+    color_eyre::install()?;
 
-    // // Customize logging message format
-    // Builder::new()
-    //     .format(|buf, record| {
-    //         writeln!(
-    //             buf, 
-    //             "{} [{}] - {}",
-    //             Local::now().format("%Y-%m-%dT%H:%M:%S"),
-    //             record.level(),
-    //             record.args()
-    //         )
-    //     })
-    //     .filter(None, args.verbosity.to_levelfilter())
-    //     .init();
+    // Customize logging message format
+    Builder::new()
+        .format(|buf, record| {
+            writeln!(
+                buf, 
+                "{} [{}] - {}",
+                Local::now().format("%Y-%m-%dT%H:%M:%S"),
+                record.level(),
+                record.args()
+            )
+        })
+        .filter(None, args.verbosity.to_levelfilter())
+        .init();
 
-    // // check which CLI command we're running (dataset, run, plot)
-    // match args.command {
-    //     Command::Extract(args) => extract(&args.nextclade, &args.gff).await?,
-    //     Command::Plot(args)    => plot(&args.prefix)?,
-    // }
+    // check which CLI command we're running (dataset, run, plot)
+    match args.command {
+        Command::Extract(args) => {
+            let options = ExtractOptions {
+                nucleotide_columns: args.nucleotide_columns,
+                amino_acid_columns: args.amino_acid_columns,
+                qc:                 args.qc,
+                ids:                args.ids,
+                min_depth:          args.min_depth,
+                min_freq:           args.min_freq,
+                annotation_format:  args.annotation_format.as_deref().map(str::parse).transpose()?,
+                number:             NumberFormat { precision: args.precision, decimal_separator: args.decimal_separator, percent: args.percent },
+                regions:            args.bed,
+                formats:            args.to.iter().map(|format| format.parse()).collect::<Result<Vec<_>, _>>()?,
+                coding_only:        args.coding_only,
+                nonsyn_only:        args.nonsyn_only,
+                intergenic:         args.intergenic.parse()?,
+                gene_cache:         args.gene_cache,
+                region:             args.region,
+                vcf_preset:         args.vcf_preset.as_deref().map(str::parse).transpose()?,
+                frameshifts:        args.frameshifts.parse()?,
+                minor_variants:     args.minor_variants,
+                sample_table:       args.emit_sample_table,
+                min_coverage:       args.min_coverage,
+                prefix:             args.prefix,
+                output:             args.output,
+                column_names:       ColumnNames { case: args.column_case.parse()?, rename: parse_column_map(&args.rename_columns)? },
+                ..Default::default()
+            };
+            // The temporary copy of standard input is kept until extraction is done
+            let (input, _stdin) = match (args.nextclade.is_empty(), args.vcf, args.sample_paths, args.auspice, args.alignment, args.maf) {
+                (false, _, _, _, _, _)                   => match args.nextclade.as_slice() {
+                    [path] if path.as_os_str() == "-" => { let (path, stdin) = resolve_stdin(path)?; (Input::Nextclade(vec![path]), stdin) },
+                    paths                             => (Input::Nextclade(expand_globs(paths)?), None),
+                },
+                (true, Some(vcf), _, _, _, _)            => (Input::Vcf(vcf), None),
+                (true, None, Some(p), _, _, _)           => (Input::SamplePaths(p), None),
+                (true, None, None, Some(a), _, _)        => (Input::Auspice(a), None),
+                (true, None, None, None, Some(a), _)     => (Input::Alignment(a), None),
+                (true, None, None, None, None, Some(m))  => (Input::Maf(m), None),
+                (true, None, None, None, None, None)     => match (args.translations.is_empty(), args.mutation_list) {
+                    (false, _)       => (Input::Translations(expand_globs(&args.translations)?), None),
+                    (true, Some(l))  => (Input::MutationList(l), None),
+                    (true, None)     => (Input::Ivar(args.ivar), None),
+                },
+            };
+            extract(input, args.gff, args.reference, &options).await?
+        },
+        Command::Annotate(args) => {
+            let (annotations, _stdin) = resolve_stdin(&args.annotations)?;
+            let options = AnnotateOptions {
+                min_depth:    args.min_depth,
+                min_freq:     args.min_freq,
+                deletions:    args.deletion_match.parse()?,
+                column_map:   parse_column_map(&args.column_map)?,
+                metadata:     args.metadata,
+                metadata_key: args.metadata_key,
+                column_names: ColumnNames { case: args.column_case.parse()?, rename: parse_column_map(&args.rename_columns)? },
+                gene_cache:   args.gene_cache,
+            };
+            annotate(args.mutations.as_ref(), &args.ivar.iter().collect::<Vec<_>>(), &annotations, args.gff.as_ref(), args.reference.as_ref(), &args.output, &options).await?
+        },
+        Command::Plot(args)    => {
+            let notation = LabelStyle { three_letter: args.three_letter, del: args.del_notation };
+            let options  = plot::PlotOptions {
+                rotation:               args.label_rotation,
+                mutation_labels:        args.mutation_labels.parse()?,
+                sample_labels:          args.sample_labels.parse()?,
+                repeat_mutation_labels: args.repeat_mutation_labels,
+                repeat_sample_labels:   args.repeat_sample_labels,
+                barcode:                args.barcode,
+                aggregate:              args.aggregate,
+                cache:                  args.cache_dir,
+                embed_font:             !args.no_embed_font,
+                png:                    !args.no_png,
+                max_png_cells:          args.max_png_cells,
+                sample_url:             args.sample_url,
+                mutation_url:           args.mutation_url,
+                cell_url:               args.cell_url,
+                include_samples:        args.include_samples.as_ref().map(read_sample_list).transpose()?,
+                exclude_samples:        args.exclude_samples.as_ref().map(read_sample_list).transpose()?.unwrap_or_default(),
+                notation,
+                number:                 NumberFormat { precision: args.precision, decimal_separator: args.decimal_separator, percent: args.percent },
+                embed_data:             args.embed_data,
+                color_by_score:         args.color_by_score,
+                sort_by_score:          args.sort_by_score,
+                sparkline:              args.sparkline,
+            };
+            let data = plot::PlotData {
+                groups:   match (&args.metadata, &args.group_by) {
+                    (Some(metadata), Some(group_by)) => {
+                        let metadata = read_metadata(metadata, None).await?;
+                        Some(metadata.into_iter().filter_map(|(sample, mut attributes)| Some((sample, attributes.remove(group_by)?))).collect())
+                    },
+                    _ => None,
+                },
+                products: args.products.as_ref().map(gene::products).transpose()?.unwrap_or_default(),
+                scores:   match &args.scores {
+                    Some(scores) => plot::read_scores(scores, &args.score_column).await?,
+                    None         => Default::default(),
+                },
+                genes:    args.gff.as_ref().map(|gff| gene::read_genes(gff, None, args.gene_cache.as_deref())).transpose()?.unwrap_or_default(),
+                coverage: match &args.coverage {
+                    Some(nextclade) => coverage::read_genome_coverage(nextclade, None).await?,
+                    None            => Default::default(),
+                },
+            };
+            match (&args.annotated, &args.previous) {
+                (Some(current), Some(previous)) => plot::diff(current, previous, &args.prefix, args.label.as_deref(), &options, &data).await?,
+                _                               => plot(args.annotated.as_ref(), &args.prefix, args.label.as_deref(), &options, &data).await?,
+            }
+        },
+        Command::Diversity(args) => { diversity(&args.mutations, &args.output, args.track.as_deref()).await?; },
+        Command::Distances(args) => {
+            let mask   = parse_ranges(&args.mask)?;
+            let matrix = distances(&args.mutations, args.nextclade.as_ref(), &mask).await?;
+            matrix.write_matrix(&args.output_matrix).await?;
+            matrix.write_pairs(&args.output_pairs).await?;
+            if let Some(threshold) = args.cluster_threshold {
+                matrix.write_clusters(threshold, &args.output_clusters).await?;
+            }
+            if let Some(output_graph) = &args.output_graph {
+                let metadata = match &args.metadata {
+                    Some(metadata) => read_metadata(metadata, None).await?,
+                    None           => Default::default(),
+                };
+                let edges = match args.graph_threshold {
+                    Some(threshold) => matrix.threshold_edges(threshold),
+                    None            => matrix.minimum_spanning_tree(),
+                };
+                matrix.write_graph(&edges, &metadata, output_graph)?;
+            }
+        },
+        Command::Tree(args) => { tree::branches(&args.mutations, &args.tree, &args.output).await?; },
+        Command::Genes(args) => gene::write_genes(&gene::genes(&args.gff)?, &args.output)?,
+        Command::Coverage(args) => { coverage::coverage(&args.nextclade, &args.gff, &args.output, args.heatmap.as_deref()).await?; },
+        Command::Export(args)  => match args.command {
+            ExportCommand::Gff(args) => export::gff(&args.mutations, &args.output, &args.seqid, args.aggregate, args.extract.as_ref()).await?,
+            ExportCommand::Vcf(args) => export::vcf(&args.mutations, &args.reference, &args.output).await?,
+            ExportCommand::Residues(args) => match &args.pdb {
+                Some(pdb) => export::bfactor(&args.mutations, &args.gene, &args.chain, pdb, &args.output).await?,
+                None      => export::defattr(&args.mutations, &args.gene, &args.chain, &args.output).await?,
+            },
+            ExportCommand::Lists(args) => export::lists(&args.mutations, &args.output, &args.columns, &args.genes, &LabelStyle { three_letter: args.three_letter, del: args.del_notation }).await?,
+        },
+        Command::Query(args) => query(&args.mutations, &args.columns, args.distinct, args.sql.as_deref(), args.catalog.as_deref(), &args.output).await?,
+        Command::Freyja(args) => { freyja::freyja(&args.demix, args.barcodes, args.output, args.heatmap.as_deref()).await?; },
+        Command::Primers(args) => { primers::primers(&args.mutations, &args.bed, &args.output, args.heatmap.as_deref()).await?; },
+        Command::Selection(args) => { selection::selection(&args.mutations, &args.gff, &args.reference, &args.output, args.gene_cache.as_deref()).await?; },
+        Command::Sweep(args) => { sweep::sweep(&args.annotated, &args.thresholds, &args.output, args.heatmap.as_deref()).await?; },
+    }
 
     Ok(())
 }
@@ -0,0 +1,355 @@
+use arrow::array::{ArrayRef, Int64Array, StringArray};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use arrow::util::pretty::pretty_format_batches;   // Pretty print arrow records
+use color_eyre::eyre::{eyre, Report, Result};     // Generic error handling with pretty logging
+use datafusion::datasource::MemTable;
+use datafusion::prelude::*;                       // All the essential datafusion functions.
+use std::path::{Path, PathBuf};                   // System file paths
+use std::sync::Arc;
+
+use crate::extract::PREVIEW_ROWS;
+
+/// How an amino acid deletion range in the annotations (ex. `S:del69-70`) matches the codon deletions of a sample.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DeletionMatch {
+    /// The sample has a deletion at any codon of the range.
+    #[default]
+    Overlap,
+    /// The sample has a deletion at every codon of the range.
+    Exact,
+}
+
+impl std::str::FromStr for DeletionMatch {
+    type Err = Report;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "overlap" => Ok(DeletionMatch::Overlap),
+            "exact"   => Ok(DeletionMatch::Exact),
+            _         => Err(eyre!("Unknown deletion match: {policy}, please choose from: overlap, exact")),
+        }
+    }
+}
+
+/// Options to customize annotation.
+#[derive(Clone, Debug)]
+pub struct AnnotateOptions {
+    /// Minimum depth for ivar variants.
+    pub min_depth: u32,
+    /// Minimum alternate allele frequency for ivar variants.
+    pub min_freq: f64,
+    /// How amino acid deletion ranges in the annotations match the codon deletions of a sample.
+    pub deletions: DeletionMatch,
+    /// Columns of the mutations table to rename onto the expected names, as (name, column)
+    /// pairs (ex. `sample` from `SequenceName`), for variant tables from other pipelines.
+    pub column_map: Vec<(String, String)>,
+    /// An optional file path to per-sample metadata (tsv or csv), whose columns are joined onto
+    /// the annotated table by sample as text (ex. collection date, location, host). Each sample
+    /// can only have one row.
+    pub metadata: Option<PathBuf>,
+    /// The metadata column with the sample names, the first column when not provided.
+    pub metadata_key: Option<String>,
+    /// The case and explicit renames of the output columns (ex. snake case for R).
+    pub column_names: crate::ColumnNames,
+    /// A directory to cache parsed gene models in (see [`crate::gene::genes_cached`]).
+    pub gene_cache: Option<PathBuf>,
+}
+
+impl Default for AnnotateOptions {
+    fn default() -> Self {
+        AnnotateOptions {
+            min_depth:    10,
+            min_freq:     0.03,
+            deletions:    DeletionMatch::default(),
+            column_map:   Vec::new(),
+            metadata:     None,
+            metadata_key: None,
+            column_names: crate::ColumnNames::default(),
+            gene_cache:   None,
+        }
+    }
+}
+
+/// Annotate samples with the presence or absence of catalogued mutations.
+///
+/// Every sample is paired with every annotation, and given a `status` of `present`
+/// or `absent`. Annotations whose `is_gene` column is true match all mutations of
+/// that gene (and column), producing one row per observed mutation.
+///
+/// With ivar input, the status is instead `consensus`, `low-frequency`, or `absent`,
+/// and the allele `frequency` and `depth` are reported. Mutations extracted with minor
+/// variants can also be a `conflict`, when only the minor variants have them.
+///
+/// With a reference, indels are left-aligned in both the mutations and the annotations, so the
+/// annotated table reports catalogued indels at their left-most position (ex. `5-6` -> `4-5`).
+///
+/// Amino acid deletion ranges (ex. `S:del69-70`) are matched against the codon deletions
+/// that nextclade reports (ex. `S:H69-`, `S:V70-`), by overlap or exact coverage of the range.
+///
+/// # Arguments
+///
+///   - `mutations`  : A file path to the mutations table produced by `extract` (tsv or parquet).
+///   - `ivar`       : File paths to ivar variants tables, one per sample, used when `mutations` is not provided.
+///   - `annotations`: A file path to the annotations table (tsv, csv, or xlsx), with at least a `mutation` column.
+///   - `gff`        : An optional file path to GFF3 annotations, to convert HGVS coding positions in the annotations.
+///   - `reference`  : An optional file path to the reference genome fasta, to left-align the indels of the mutations and
+///                    annotations before matching. With the `gff`, nucleotide substitutions in the same codon are also
+///                    merged into one amino acid substitution before matching.
+///   - `output`     : A file path to write the annotated table to (tsv, csv, parquet, sqlite, json, or ndjson).
+///   - `options`    : The ivar filters, deletion matching, column map, sample metadata, output column names, and gene cache (see [`AnnotateOptions`]).
+///
+pub async fn annotate<P>(mutations: Option<P>, ivar: &[P], annotations: P, gff: Option<P>, reference: Option<P>, output: P, options: &AnnotateOptions) -> Result<(), Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Beginning annotation.");
+
+    let ctx = SessionContext::new();
+
+    // ------------------------------------------------------------------------
+    // Inputs
+
+    let genes = gff.as_ref().map(|gff| crate::gene::read_genes(gff, None, options.gene_cache.as_deref())).transpose()?;
+    let ctx = match &genes {
+        Some(genes) => crate::register_genes(genes, ctx, "gff")?,
+        None        => ctx,
+    };
+    let ctx = match mutations {
+        Some(mutations) => crate::register_mutations(&mutations, ctx, "mutations").await?,
+        None            => crate::ivar::register(ivar, ctx, "mutations", options.min_depth, options.min_freq).await?,
+    };
+
+    // Variant tables from other pipelines are mapped onto the expected columns, and
+    // given the optional column and gene when they don't have them
+    let ctx = crate::rename_columns(ctx, "mutations", &options.column_map).await?;
+    let columns = crate::column_names(&ctx.table("mutations").await?);
+    let missing = [
+        ("column", "arrow_cast(NULL, 'Utf8')"),
+        ("gene",   "CASE WHEN mutation LIKE '%:%' THEN split_part(mutation, ':', 1) ELSE NULL END"),
+    ].into_iter().filter(|(c, _)| !columns.iter().any(|column| column == c)).map(|(c, value)| format!("{value} as {c}")).collect::<Vec<_>>();
+    let ctx = match missing.is_empty() {
+        true  => ctx,
+        false => crate::replace_table(ctx, "mutations", &format!("SELECT *, {} FROM mutations", missing.join(", "))).await?,
+    };
+
+    // Multi-nucleotide variants are described by their amino acid change in catalogues
+    let reference = reference.map(|reference| crate::read_fasta(&reference)).transpose()?.map(|mut r| r.swap_remove(0).1);
+    let ctx = match (&reference, &genes) {
+        (Some(reference), Some(genes)) => crate::mnv::merge_codons(ctx, "mutations", reference, genes).await?,
+        (Some(_), None) => { log::warn!("A gff is required to merge substitutions in the same codon, they will not be merged."); ctx },
+        _               => ctx,
+    };
+    let ctx = crate::register_annotations(&annotations, ctx, "annotations").await?;
+
+    // Indels in a repeat can be reported at different anchors (ex. by ivar and in a catalogue),
+    // so both tables are left-aligned before matching
+    let ctx = match &reference {
+        Some(reference) => {
+            let ctx = crate::indel::normalize(ctx, "mutations", reference).await?;
+            crate::indel::normalize(ctx, "annotations", reference).await?
+        },
+        None => { log::info!("No reference was provided, indels will not be left-aligned before matching."); ctx },
+    };
+
+    // Debug Preview
+    if log::log_enabled!(log::Level::Debug) {
+        let batches = ctx.sql(&format!("SELECT * FROM annotations LIMIT {PREVIEW_ROWS}")).await?.collect().await?;
+        log::debug!("Annotations preview:\n{}", pretty_format_batches(&batches)?.to_string());
+    }
+
+    // Annotations can optionally match all mutations in a gene
+    let columns = crate::column_names(&ctx.table("annotations").await?);
+    let is_gene = match columns.iter().any(|c| c == "is_gene") {
+        true  => "COALESCE(lower(arrow_cast(A.is_gene, 'Utf8')) = 'true', false)",
+        false => "false",
+    };
+
+    // Mutations from ivar carry their own status, while all other mutations are present
+    let columns = crate::column_names(&ctx.table("mutations").await?);
+    let (fields, extra) = match columns.iter().any(|c| c == "status") {
+        true  => ("sample, mutation, column, status, frequency, depth", "M.frequency, M.depth,"),
+        false => ("sample, mutation, column, 'present' as status",      ""),
+    };
+
+    // Amino acid deletion ranges, matched by codon position rather than by name
+    let mut ranges: Vec<(String, String, i64, i64)> = Vec::new();
+    let batches = ctx.sql("SELECT DISTINCT mutation FROM annotations WHERE mutation IS NOT NULL").await?.collect().await?;
+    for batch in &batches {
+        let mutation = crate::string_column(batch, "mutation")?;
+        for mutation in mutation.iter().flatten() {
+            let Some((gene, start, end)) = crate::notation::parse_deletion_range(mutation) else { continue };
+            ranges.push((mutation.to_string(), gene, start as i64, end as i64));
+        }
+    }
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("mutation", DataType::Utf8,  false),
+        Field::new("gene",     DataType::Utf8,  false),
+        Field::new("start",    DataType::Int64, false),
+        Field::new("end",      DataType::Int64, false),
+    ]));
+    let records = RecordBatch::try_new(schema.clone(), vec![
+        Arc::new(StringArray::from(ranges.iter().map(|r| r.0.clone()).collect::<Vec<_>>())) as ArrayRef,
+        Arc::new(StringArray::from(ranges.iter().map(|r| r.1.clone()).collect::<Vec<_>>())),
+        Arc::new(Int64Array::from(ranges.iter().map(|r| r.2).collect::<Vec<_>>())),
+        Arc::new(Int64Array::from(ranges.iter().map(|r| r.3).collect::<Vec<_>>())),
+    ])?;
+    ctx.register_table("deletion_ranges", Arc::new(MemTable::try_new(schema, vec![vec![records]])?))?;
+    if !ranges.is_empty() { log::info!("Matching {} amino acid deletion ranges by {:?}.", ranges.len(), options.deletions) }
+
+    let covered = match options.deletions {
+        DeletionMatch::Overlap => "COUNT(DISTINCT D.position) > 0",
+        DeletionMatch::Exact   => "COUNT(DISTINCT D.position) = R.end - R.start + 1",
+    };
+    let (present, range_extra) = match columns.iter().any(|c| c == "status") {
+        true  => ("AND status != 'absent'", "NULL as frequency, NULL as depth,"),
+        false => ("",                       ""),
+    };
+
+    // ------------------------------------------------------------------------
+    // Matching
+
+    // Mutation annotations: every sample is checked for every annotation.
+    // The column is optional in the annotations, to allow mutations that
+    // are unambiguous without it.
+    log::info!("Matching annotations to mutations.");
+    let query = format!("
+        SELECT
+            S.sample, A.mutation, A.column,
+            COALESCE(M.status, 'absent') as status,
+            {extra}
+            A.* EXCEPT(mutation, column)
+        FROM (SELECT DISTINCT sample FROM mutations) S
+        CROSS JOIN (SELECT * FROM annotations A WHERE NOT ({is_gene}) AND A.mutation NOT IN (SELECT mutation FROM deletion_ranges)) A
+        LEFT JOIN (SELECT DISTINCT {fields} FROM mutations) M
+        ON M.sample = S.sample AND M.mutation = A.mutation AND (A.column IS NULL OR M.column = A.column)
+
+        UNION ALL
+
+        SELECT
+            S.sample, A.mutation, A.column,
+            CASE WHEN C.sample IS NULL THEN 'absent' ELSE 'present' END as status,
+            {range_extra}
+            A.* EXCEPT(mutation, column)
+        FROM (SELECT DISTINCT sample FROM mutations) S
+        CROSS JOIN (SELECT * FROM annotations A WHERE NOT ({is_gene}) AND A.mutation IN (SELECT mutation FROM deletion_ranges)) A
+        LEFT JOIN (
+            SELECT D.sample, R.mutation
+            FROM deletion_ranges R
+            JOIN (
+                SELECT
+                    sample,
+                    split_part(mutation, ':', 1) as gene,
+                    arrow_cast(regexp_replace(split_part(mutation, ':', 2), '[^0-9]', '', 'g'), 'Int64') as position
+                FROM mutations
+                WHERE column = 'aaDeletions' {present}
+            ) D
+            ON D.gene = R.gene AND D.position BETWEEN R.start AND R.end
+            GROUP BY D.sample, R.mutation, R.start, R.end
+            HAVING {covered}
+        ) C
+        ON C.sample = S.sample AND C.mutation = A.mutation
+
+        UNION ALL
+
+        SELECT
+            M.sample, M.mutation, M.column,
+            M.status,
+            {extra}
+            A.* EXCEPT(mutation, column)
+        FROM (SELECT DISTINCT {fields}, gene FROM mutations) M
+        JOIN (SELECT * FROM annotations A WHERE {is_gene}) A
+        ON M.gene = A.mutation AND (A.column IS NULL OR M.column = A.column)
+        WHERE M.status != 'absent'
+    ");
+
+    let query = format!("CREATE TABLE annotated AS SELECT * FROM ({query}) ORDER BY sample, mutation");
+    ctx.sql(&query).await?.collect().await?;
+
+    // Debug Preview
+    if log::log_enabled!(log::Level::Debug) {
+        let batches = ctx.sql(&format!("SELECT * FROM annotated LIMIT {PREVIEW_ROWS}")).await?.collect().await?;
+        log::debug!("Annotated preview:\n{}", pretty_format_batches(&batches)?.to_string());
+    }
+
+    // ------------------------------------------------------------------------
+    // Sample Metadata
+
+    // Metadata columns that are already in the annotated table (ex. sample) are not joined
+    let ctx = match &options.metadata {
+        Some(metadata) => {
+            log::info!("Joining sample metadata: {metadata:?}");
+            let table    = crate::read_metadata(metadata, options.metadata_key.as_deref()).await?;
+            let existing = crate::column_names(&ctx.table("annotated").await?);
+            let columns  = table.values()
+                .flat_map(|attributes| attributes.keys())
+                .filter(|c| !existing.contains(*c))
+                .collect::<std::collections::BTreeSet<_>>();
+
+            let mut fields = vec![Field::new("sample", DataType::Utf8, false)];
+            fields.extend(columns.iter().map(|c| Field::new(c.as_str(), DataType::Utf8, true)));
+            let schema = Arc::new(Schema::new(fields));
+            let mut arrays = vec![Arc::new(StringArray::from(table.keys().map(String::as_str).collect::<Vec<_>>())) as ArrayRef];
+            arrays.extend(columns.iter().map(|c| {
+                Arc::new(StringArray::from(table.values().map(|attributes| attributes.get(*c).map(String::as_str)).collect::<Vec<_>>())) as ArrayRef
+            }));
+            let records = RecordBatch::try_new(schema.clone(), arrays)?;
+            ctx.register_table("metadata", Arc::new(MemTable::try_new(schema, vec![vec![records]])?))?;
+
+            let fields = columns.iter().map(|c| format!(", M.{}", crate::quote_identifier(c))).collect::<String>();
+            let query  = format!("
+                SELECT A.*{fields}
+                FROM annotated A
+                LEFT JOIN metadata M ON A.sample = M.sample
+                ORDER BY A.sample, A.mutation");
+            crate::replace_table(ctx, "annotated", &query).await?
+        },
+        None => ctx,
+    };
+
+    // ------------------------------------------------------------------------
+    // Write Table
+
+    log::info!("Writing the annotated table: {output:?}");
+    let df = options.column_names.apply(ctx.sql("SELECT * FROM annotated").await?)?;
+    crate::write_table(df, &output).await?;
+
+    log::info!("Finished annotation.");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn shifted_deletion_matches_catalogue() -> Result<(), Report> {
+        let dir  = tempfile::tempdir()?;
+        let path = |name: &str| dir.path().join(name);
+        // A run of four Ts at 4-7, where the deletions 4-5, 5-6, and 6-7 are the same
+        std::fs::write(path("reference.fasta"), ">reference\nACGTTTTGCA\n")?;
+        std::fs::write(path("mutations.tsv"),   "sample\tmutation\tcolumn\tnuc_start\tnuc_end\ns1\t6-7\tdeletions\t6\t7\ns2\tC9T\tsubstitutions\t9\t9\n")?;
+        std::fs::write(path("annotations.tsv"), "mutation\tcolumn\tinfo\n5-6\tdeletions\tTwo bases of the T run.\n")?;
+
+        let statuses = |output: PathBuf| async move {
+            let ctx = SessionContext::new();
+            ctx.register_csv("annotated", output.to_str().unwrap_or_default(), CsvReadOptions::new().file_extension("tsv").delimiter(b'\t')).await?;
+            let mut rows = Vec::new();
+            for batch in ctx.sql("SELECT sample, mutation, status FROM annotated ORDER BY sample").await?.collect().await? {
+                let (sample, mutation, status) = (crate::string_column(&batch, "sample")?, crate::string_column(&batch, "mutation")?, crate::string_column(&batch, "status")?);
+                for i in 0..batch.num_rows() { rows.push(format!("{} {} {}", sample.value(i), mutation.value(i), status.value(i))) }
+            }
+            Ok::<_, Report>(rows)
+        };
+
+        let output = path("annotated.tsv");
+        annotate(Some(path("mutations.tsv")), &[], path("annotations.tsv"), None, Some(path("reference.fasta")), output.clone(), &AnnotateOptions::default()).await?;
+        assert_eq!(statuses(output.clone()).await?, ["s1 4-5 present", "s2 4-5 absent"]);
+
+        // Without a reference, the two representations don't match
+        annotate(Some(path("mutations.tsv")), &[], path("annotations.tsv"), None, None, output.clone(), &AnnotateOptions::default()).await?;
+        assert_eq!(statuses(output).await?, ["s1 5-6 absent", "s2 5-6 absent"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use color_eyre::eyre::{eyre, Report, Result};
+use datafusion::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// A named external table of a catalog.
+#[derive(Clone, Debug)]
+pub struct CatalogTable {
+    /// The name to register the table as, for SQL queries.
+    pub name: String,
+    /// The file path (or url) of the table. Relative paths are relative to the catalog file.
+    pub path: PathBuf,
+    /// The format of delimited text (`tsv` or `csv`), for extensions that don't say (ex. `.txt`).
+    pub format: Option<String>,
+}
+
+/// Table names that a catalog can't use, because the query session already registers them.
+pub const RESERVED_NAMES: &[&str] = &["mutations"];
+
+/// Read a catalog of named external tables from a TOML file.
+///
+/// Each table is a section named by the table, with a `path` and an optional `format`. All other
+/// formats (parquet, sqlite, delta, arrow) are detected from the extension, like mutations tables.
+/// The tables are only registered for `query --sql` (see [`crate::query::sql`]), and can't use
+/// a [reserved name](RESERVED_NAMES).
+///
+/// ```toml
+/// [gene_aliases]
+/// path = "/lab/reference/gene_aliases.tsv"
+///
+/// [resistance]
+/// path   = "resistance_catalog.txt"
+/// format = "tsv"
+/// ```
+pub fn read<P>(path: P) -> Result<Vec<CatalogTable>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let text    = std::fs::read_to_string(&path)?;
+    let catalog = text.parse::<toml::Table>().map_err(|e| eyre!("Failed to parse the catalog {path:?}: {e}"))?;
+    let dir     = path.as_ref().parent().unwrap_or(Path::new(""));
+
+    catalog.iter().map(|(name, table)| {
+        if RESERVED_NAMES.contains(&name.to_lowercase().as_str()) {
+            return Err(eyre!("Catalog table {name} can't be registered, because the name is reserved for the query: {path:?}"))
+        }
+        let table  = table.as_table().ok_or(eyre!("Catalog entry {name} is not a table, with a path and optional format: {path:?}"))?;
+        let file   = table.get("path").and_then(|p| p.as_str()).ok_or(eyre!("Catalog table {name} has no path: {path:?}"))?;
+        let format = table.get("format").map(|f| match f.as_str() {
+            Some(format @ ("tsv" | "csv")) => Ok(format.to_string()),
+            _                              => Err(eyre!("Unknown format {f} of catalog table {name}, please choose from: tsv, csv")),
+        }).transpose()?;
+        // Urls (ex. s3://bucket/catalog.parquet) and absolute paths are kept as is
+        let file = match file.contains("://") {
+            true  => PathBuf::from(file),
+            false => dir.join(file),
+        };
+        Ok(CatalogTable { name: name.clone(), path: file, format })
+    }).collect()
+}
+
+/// Register every table of a catalog (see [`read`]) in a session, by name.
+///
+/// A catalog table can't replace a table that is already registered in the session.
+///
+/// # Arguments
+///
+///   - `path`: A file path to the catalog TOML.
+///   - `ctx` : The session to register the tables in.
+///
+pub async fn register<P>(path: &P, ctx: SessionContext) -> Result<SessionContext, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Reading table catalog: {path:?}");
+
+    let mut ctx = ctx;
+    for table in read(path)? {
+        if ctx.table_exist(table.name.as_str())? {
+            return Err(eyre!("Catalog table {} can't be registered, because a table of that name already exists: {path:?}", table.name))
+        }
+        log::info!("Registering catalog table {}: {:?}", table.name, table.path);
+        ctx = match table.format.as_deref() {
+            Some("tsv") => crate::register_csv(&table.path, ctx, Some(b'\t'), &table.name).await?,
+            Some("csv") => crate::register_csv(&table.path, ctx, Some(b','), &table.name).await?,
+            _           => crate::register_mutations(&table.path, ctx, &table.name).await?,
+        };
+    }
+
+    Ok(ctx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_rejects_reserved_names() -> Result<(), Report> {
+        let dir  = tempfile::tempdir()?;
+        let path = dir.path().join("catalog.toml");
+        std::fs::write(&path, "[aliases]\npath = \"aliases.txt\"\nformat = \"tsv\"\n")?;
+        let tables = read(&path)?;
+        assert_eq!(tables[0].name, "aliases");
+        assert_eq!(tables[0].path, dir.path().join("aliases.txt"));
+        assert_eq!(tables[0].format.as_deref(), Some("tsv"));
+
+        std::fs::write(&path, "[Mutations]\npath = \"lab.tsv\"\n")?;
+        assert!(read(&path).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+/// The standard genetic code, with codons ordered by base T, C, A, G.
+pub const GENETIC_CODE: &[u8; 64] = b"FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG";
+
+/// One-letter and three-letter amino acid codes.
+pub const AMINO_ACIDS: &[(char, &str)] = &[
+    ('A', "Ala"), ('R', "Arg"), ('N', "Asn"), ('D', "Asp"), ('C', "Cys"),
+    ('Q', "Gln"), ('E', "Glu"), ('G', "Gly"), ('H', "His"), ('I', "Ile"),
+    ('L', "Leu"), ('K', "Lys"), ('M', "Met"), ('F', "Phe"), ('P', "Pro"),
+    ('S', "Ser"), ('T', "Thr"), ('W', "Trp"), ('Y', "Tyr"), ('V', "Val"),
+    ('*', "Ter"), ('X', "Xaa"),
+];
+
+/// Translate a single codon with the standard genetic code.
+///
+/// Codons that are incomplete or contain ambiguous bases translate to `X`.
+pub fn translate_codon(codon: &[u8]) -> char {
+    let index = |base: u8| match base.to_ascii_uppercase() {
+        b'T' | b'U' => Some(0),
+        b'C'        => Some(1),
+        b'A'        => Some(2),
+        b'G'        => Some(3),
+        _           => None,
+    };
+    match codon {
+        [b1, b2, b3] => match (index(*b1), index(*b2), index(*b3)) {
+            (Some(i1), Some(i2), Some(i3)) => GENETIC_CODE[(i1 * 16) + (i2 * 4) + i3] as char,
+            _                              => 'X',
+        },
+        _ => 'X',
+    }
+}
+
+/// Translate a nucleotide sequence in frame, ignoring any trailing partial codon.
+pub fn translate(sequence: &[u8]) -> String {
+    sequence.chunks_exact(3).map(translate_codon).collect()
+}
+
+/// Convert a one-letter amino acid code to its three-letter code.
+pub fn three_letter(aa: char) -> Option<&'static str> {
+    AMINO_ACIDS.iter().find(|(one, _)| *one == aa.to_ascii_uppercase()).map(|(_, three)| *three)
+}
+
+/// Convert a three-letter amino acid code to its one-letter code.
+pub fn one_letter(aa: &str) -> Option<char> {
+    AMINO_ACIDS.iter().find(|(_, three)| three.eq_ignore_ascii_case(aa)).map(|(one, _)| *one)
+}
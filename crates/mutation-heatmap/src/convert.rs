@@ -45,6 +45,6 @@ pub async fn convert() -> Result<(), Report> {
     let batches = df.collect().await?;
     println!("Preview:\n{}", pretty_format_batches(&batches)?.to_string());    
     
-    // Write to delta lake
+    // Delta lake tables are written (appended) by crate::write_table, with the delta feature
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,225 @@
+use arrow::array::{Float64Array, StringArray, UInt32Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use color_eyre::eyre::{eyre, Report, Result};
+use datafusion::prelude::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Amino acid coverage of a gene in a sample.
+#[derive(Clone, Debug)]
+pub struct GeneCoverage {
+    pub sample: String,
+    pub gene: String,
+    /// Number of codons in the gene.
+    pub codons: u32,
+    /// Number of codons with unknown (missing) amino acids.
+    pub unknown: u32,
+    /// Fraction of codons covered.
+    pub coverage: f64,
+}
+
+/// Compute the fraction of codons covered per sample per gene.
+///
+/// Unknown codons are taken from the nextclade `unknownAaRanges` column. When that
+/// isn't available, a codon is unknown if any of its nucleotides are in the `missing`
+/// ranges. Low coverage genes should be flagged before interpreting the absence of mutations.
+///
+/// # Arguments
+///
+///   - `nextclade`: A file path to nextclade TSV or JSON output.
+///   - `gff`      : A file path to nextclade dataset GFF3 annotations.
+///   - `output`   : A file path to write the coverage table to.
+///   - `prefix`   : An optional output prefix to render the coverage as a sample by gene heatmap.
+///
+pub async fn coverage<P>(nextclade: P, gff: P, output: P, prefix: Option<&str>) -> Result<Vec<GeneCoverage>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Beginning coverage computation.");
+
+    let genes = crate::gene::genes(&gff)?;
+
+    let ctx     = SessionContext::new();
+    let ctx     = crate::nextclade::register(&nextclade, ctx, "nextclade").await?;
+    let columns = crate::column_names(&ctx.table("nextclade").await?);
+    let select  = |column: &str| match columns.iter().any(|c| c == column) {
+        true  => format!("arrow_cast({0}, 'Utf8') as {0}", crate::quote_identifier(column)),
+        false => format!("arrow_cast(NULL, 'Utf8') as {}", crate::quote_identifier(column)),
+    };
+    if !columns.iter().any(|c| c == "unknownAaRanges" || c == "missing") {
+        log::warn!("The nextclade file has no 'unknownAaRanges' or 'missing' columns, all genes will be fully covered.");
+    }
+
+    // ------------------------------------------------------------------------
+    // Unknown Codons
+
+    let query   = format!("SELECT arrow_cast(\"seqName\", 'Utf8') as sample, {}, {} FROM nextclade", select("unknownAaRanges"), select("missing"));
+    let batches = ctx.sql(&query).await?.collect().await?;
+
+    let mut results = Vec::new();
+    for batch in &batches {
+        let sample    = crate::string_column(batch, "sample")?;
+        let aa_ranges = crate::string_column(batch, "unknownAaRanges")?;
+        let missing   = crate::string_column(batch, "missing")?;
+
+        for i in 0..batch.num_rows() {
+            // Unknown codon ranges per gene
+            let mut unknown: BTreeMap<&str, BTreeSet<u32>> = BTreeMap::new();
+            if aa_ranges.is_valid(i) {
+                for range in aa_ranges.value(i).split(',').filter(|r| !r.is_empty()) {
+                    let Some((gene, range)) = range.rsplit_once(':') else { continue };
+                    for (start, end) in crate::parse_ranges(range)? {
+                        unknown.entry(gene).or_default().extend(start..=end);
+                    }
+                }
+            } else if missing.is_valid(i) {
+                let missing = crate::parse_ranges(missing.value(i))?;
+                let is_missing = |position: u32| missing.iter().any(|(start, end)| position >= *start && position <= *end);
+                for gene in &genes {
+                    let codons = gene.positions().chunks(3).enumerate()
+                        .filter(|(_, codon)| codon.iter().any(|p| is_missing(*p)))
+                        .map(|(c, _)| c as u32 + 1)
+                        .collect::<BTreeSet<_>>();
+                    unknown.insert(&gene.name, codons);
+                }
+            }
+
+            for gene in &genes {
+                let codons   = gene.aa_length();
+                let unknown  = unknown.get(gene.name.as_str()).map(|c| c.iter().filter(|c| **c <= codons).count() as u32).unwrap_or(0);
+                let coverage = if codons > 0 { (codons - unknown) as f64 / codons as f64 } else { 0.0 };
+                results.push(GeneCoverage { sample: sample.value(i).to_string(), gene: gene.name.clone(), codons, unknown, coverage });
+            }
+        }
+    }
+
+    // ------------------------------------------------------------------------
+    // Write Table
+
+    log::info!("Writing coverage table: {output:?}");
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("sample",   DataType::Utf8,    false),
+        Field::new("gene",     DataType::Utf8,    false),
+        Field::new("codons",   DataType::UInt32,  false),
+        Field::new("unknown",  DataType::UInt32,  false),
+        Field::new("coverage", DataType::Float64, false),
+    ]));
+    let records = RecordBatch::try_new(
+        schema,
+        vec![
+            Arc::new(StringArray::from(results.iter().map(|c| c.sample.clone()).collect::<Vec<_>>())),
+            Arc::new(StringArray::from(results.iter().map(|c| c.gene.clone()).collect::<Vec<_>>())),
+            Arc::new(UInt32Array::from(results.iter().map(|c| c.codons).collect::<Vec<_>>())),
+            Arc::new(UInt32Array::from(results.iter().map(|c| c.unknown).collect::<Vec<_>>())),
+            Arc::new(Float64Array::from(results.iter().map(|c| c.coverage).collect::<Vec<_>>())),
+        ],
+    )?;
+    crate::write_table(SessionContext::new().read_batch(records)?, &output).await?;
+
+    // ------------------------------------------------------------------------
+    // Heatmap
+
+    #[cfg(feature = "plot")]
+    if let Some(prefix) = prefix {
+        log::info!("Rendering coverage heatmap: {prefix}");
+        let samples = results.iter().map(|c| c.sample.clone()).collect::<BTreeSet<_>>().into_iter().collect::<Vec<_>>();
+        let names   = genes.iter().map(|g| g.name.clone()).collect::<Vec<_>>();
+        let values  = results.iter().map(|c| ((c.sample.clone(), c.gene.clone()), c.coverage)).collect::<BTreeMap<_, _>>();
+        let fill    = |sample: &str, gene: &str| crate::plot::scale_color(values.get(&(sample.to_string(), gene.to_string())).copied().unwrap_or(0.0));
+        crate::plot::heatmap(&samples, &names, &fill, None, prefix, &crate::plot::PlotOptions::default(), &crate::plot::Tracks::default())?;
+    }
+    #[cfg(not(feature = "plot"))]
+    crate::warn_without_plot(prefix, "coverage heatmap");
+
+    log::info!("Finished coverage computation.");
+
+    Ok(results)
+}
+
+/// The SQL expression of a sample's genome coverage (0-1), from the columns of a nextclade table.
+///
+/// Uses the nextclade `coverage` column, or `1 - totalMissing / genome_length` for
+/// older versions without it. Empty values (ex. sequences that failed analysis) are null.
+/// Returns `None` when neither can be computed.
+///
+/// # Arguments
+///
+///   - `columns`      : The column names of the nextclade table.
+///   - `genome_length`: The optional length of the reference genome, for the `totalMissing` fallback.
+///
+pub fn genome_coverage_expression(columns: &[String], genome_length: Option<usize>) -> Option<String> {
+    let has = |column: &str| columns.iter().any(|c| c == column);
+    match (has("coverage"), has("totalMissing"), genome_length) {
+        (true, _, _)                => Some("TRY_CAST(arrow_cast(\"coverage\", 'Utf8') AS DOUBLE)".to_string()),
+        (false, true, Some(length)) => Some(format!("1.0 - TRY_CAST(arrow_cast(\"totalMissing\", 'Utf8') AS DOUBLE) / {length}.0")),
+        _                           => None,
+    }
+}
+
+/// Read the genome coverage (0-1) of each sample from nextclade output.
+///
+/// Samples without a numeric coverage (ex. sequences that failed analysis) are skipped.
+///
+/// # Arguments
+///
+///   - `nextclade`    : A file path to nextclade TSV or JSON output.
+///   - `genome_length`: The optional length of the reference genome, for nextclade output without a `coverage` column.
+///
+pub async fn read_genome_coverage<P>(nextclade: &P, genome_length: Option<usize>) -> Result<HashMap<String, f64>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Reading genome coverage: {nextclade:?}");
+
+    let ctx        = SessionContext::new();
+    let ctx        = crate::nextclade::register(nextclade, ctx, "nextclade").await?;
+    let columns    = crate::column_names(&ctx.table("nextclade").await?);
+    let expression = genome_coverage_expression(&columns, genome_length)
+        .ok_or(eyre!("Nextclade file has no 'coverage' column (or 'totalMissing' and a reference length): {nextclade:?}"))?;
+
+    let query   = format!("SELECT arrow_cast(\"seqName\", 'Utf8') as sample, {expression} as coverage FROM nextclade");
+    let batches = ctx.sql(&query).await?.collect().await?;
+
+    let mut coverage = HashMap::new();
+    for batch in &batches {
+        let sample = crate::string_column(batch, "sample")?;
+        let value  = crate::string_column(batch, "coverage")?;
+        for i in 0..batch.num_rows() {
+            if !value.is_valid(i) { continue }
+            let Ok(fraction) = value.value(i).parse::<f64>() else { continue };
+            coverage.insert(sample.value(i).to_string(), fraction);
+        }
+    }
+    log::info!("Read the genome coverage of {} samples.", coverage.len());
+
+    Ok(coverage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn codon_coverage_from_unknown_or_missing() -> Result<(), Report> {
+        let dir  = tempfile::tempdir()?;
+        let path = |name: &str| dir.path().join(name);
+        std::fs::write(path("genes.gff3"), "##gff-version 3\nref\t.\tgene\t1\t30\t.\t+\t.\tgene_name=S\nref\t.\tgene\t31\t45\t.\t+\t.\tgene_name=E\n")?;
+        // s1 has unknown codon ranges, and s2 only has missing nucleotides (codons 1 and 2 of S)
+        std::fs::write(path("nextclade.tsv"), "seqName\tunknownAaRanges\tmissing\ns1\tS:1-2,E:5\t\ns2\t\t1-4\n")?;
+
+        let results = coverage(path("nextclade.tsv"), path("genes.gff3"), path("coverage.tsv"), None).await?;
+        let mut results = results.iter().map(|c| (c.sample.as_str(), c.gene.as_str(), c.codons, c.unknown, c.coverage)).collect::<Vec<_>>();
+        results.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+        assert_eq!(results, [
+            ("s1", "E", 5, 1, 0.8),
+            ("s1", "S", 10, 2, 0.8),
+            ("s2", "E", 5, 0, 1.0),
+            ("s2", "S", 10, 2, 0.8),
+        ]);
+        assert!(path("coverage.tsv").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+use color_eyre::eyre::{eyre, Report, Result};
+use datafusion::prelude::*;
+use deltalake::protocol::SaveMode;
+use deltalake::DeltaOps;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The uri of a delta table directory (ex. `surveillance/mutations.delta`).
+fn table_uri<P>(path: &P) -> Result<String, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let uri = path.as_ref().to_str().ok_or(eyre!("Failed to parse the delta table path: {path:?}"))?;
+    Ok(uri.to_string())
+}
+
+/// Register the latest version of a delta table for SQL queries.
+///
+/// # Arguments
+///
+///   - `path`: A directory path to the delta table (ex. `mutations.delta`).
+///   - `ctx` : The session to register the table in.
+///   - `name`: The name to register the table as.
+///
+pub async fn register<P,N>(path: &P, ctx: SessionContext, name: N) -> Result<SessionContext, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+    N: ToString,
+{
+    let uri = table_uri(path)?;
+    log::info!("Reading delta table: {uri}");
+
+    let table = deltalake::open_table(&uri).await?;
+    log::info!("Read version {} of the delta table.", table.version());
+    ctx.register_table(&name.to_string(), Arc::new(table))?;
+
+    Ok(ctx)
+}
+
+/// Append a dataframe to a delta table, creating the table if it doesn't exist.
+///
+/// Every write is a new version of the table, so the mutations of each run accumulate
+/// for longitudinal surveillance, while the earlier versions stay readable.
+pub async fn write<P>(df: DataFrame, path: &P) -> Result<(), Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let uri = table_uri(path)?;
+    std::fs::create_dir_all(&uri)?;
+
+    let batches = df.collect().await?;
+    let table   = DeltaOps::try_from_uri(&uri).await?.write(batches).with_save_mode(SaveMode::Append).await?;
+    log::info!("Appended version {} of the delta table: {uri}", table.version());
+
+    Ok(())
+}
@@ -0,0 +1,365 @@
+use arrow::array::{ArrayRef, StringArray, UInt32Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use color_eyre::eyre::{eyre, Report, Result};
+use datafusion::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// A square matrix of pairwise SNP distances between samples.
+#[derive(Clone, Debug, Default)]
+pub struct DistanceMatrix {
+    /// Sample names, in matrix order.
+    pub samples: Vec<String>,
+    /// Pairwise distances, indexed by sample position.
+    pub distances: Vec<Vec<u32>>,
+}
+
+impl DistanceMatrix {
+    /// Write the square distance matrix as a table, with a column per sample.
+    pub async fn write_matrix<P>(&self, path: P) -> Result<(), Report>
+    where
+        P: AsRef<Path> + std::fmt::Debug,
+    {
+        log::info!("Writing distance matrix: {path:?}");
+        let mut fields = vec![Field::new("sample", DataType::Utf8, false)];
+        fields.extend(self.samples.iter().map(|sample| Field::new(sample, DataType::UInt32, false)));
+        let mut arrays = vec![Arc::new(StringArray::from(self.samples.clone())) as ArrayRef];
+        arrays.extend((0..self.samples.len()).map(|j| Arc::new(UInt32Array::from(self.distances.iter().map(|row| row[j]).collect::<Vec<_>>())) as ArrayRef));
+        let records = RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays)?;
+        crate::write_table(SessionContext::new().read_batch(records)?, &path).await
+    }
+
+    /// Assign cluster IDs by single-linkage clustering, in sample order.
+    ///
+    /// Samples are linked when their distance is at most the threshold, and clusters are
+    /// the connected components of those links. Cluster IDs start at 1 and are numbered
+    /// by decreasing cluster size, so the largest outbreak is always cluster 1.
+    pub fn clusters(&self, threshold: u32) -> Vec<u32> {
+        // Union-find over sample indices
+        let mut parents = (0..self.samples.len()).collect::<Vec<_>>();
+        fn root(parents: &mut [usize], i: usize) -> usize {
+            let mut i = i;
+            while parents[i] != i {
+                parents[i] = parents[parents[i]];
+                i = parents[i];
+            }
+            i
+        }
+        for i in 0..self.samples.len() {
+            for j in (i + 1)..self.samples.len() {
+                if self.distances[i][j] <= threshold {
+                    let (a, b) = (root(&mut parents, i), root(&mut parents, j));
+                    if a != b { parents[b] = a }
+                }
+            }
+        }
+
+        // Number the clusters by decreasing size, ties broken by first sample
+        let roots = (0..self.samples.len()).map(|i| root(&mut parents, i)).collect::<Vec<_>>();
+        let mut members: BTreeMap<usize, usize> = BTreeMap::new();
+        roots.iter().for_each(|r| *members.entry(*r).or_default() += 1);
+        let mut order = members.into_iter().collect::<Vec<_>>();
+        order.sort_by(|(r1, n1), (r2, n2)| n2.cmp(n1).then(r1.cmp(r2)));
+        let ids = order.iter().enumerate().map(|(i, (r, _))| (*r, i as u32 + 1)).collect::<BTreeMap<_, _>>();
+
+        roots.iter().map(|r| ids[r]).collect()
+    }
+
+    /// Write the cluster assignment of each sample as a table.
+    pub async fn write_clusters<P>(&self, threshold: u32, path: P) -> Result<(), Report>
+    where
+        P: AsRef<Path> + std::fmt::Debug,
+    {
+        log::info!("Writing clusters (threshold {threshold}): {path:?}");
+        let clusters = self.clusters(threshold);
+        let mut sizes: BTreeMap<u32, u32> = BTreeMap::new();
+        clusters.iter().for_each(|c| *sizes.entry(*c).or_default() += 1);
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("sample",       DataType::Utf8,   false),
+            Field::new("cluster",      DataType::UInt32, false),
+            Field::new("cluster_size", DataType::UInt32, false),
+        ]));
+        let records = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(StringArray::from(self.samples.clone())),
+                Arc::new(UInt32Array::from(clusters.clone())),
+                Arc::new(UInt32Array::from(clusters.iter().map(|c| sizes[c]).collect::<Vec<_>>())),
+            ],
+        )?;
+        crate::write_table(SessionContext::new().read_batch(records)?, &path).await?;
+        log::info!("Found {} clusters, {} with more than one sample.", sizes.len(), sizes.values().filter(|n| **n > 1).count());
+        Ok(())
+    }
+
+    /// Compute a minimum spanning tree of the samples (Prim's algorithm).
+    ///
+    /// Returns the tree edges as (sample index, sample index, distance).
+    pub fn minimum_spanning_tree(&self) -> Vec<(usize, usize, u32)> {
+        let n = self.samples.len();
+        let mut edges   = Vec::new();
+        let mut in_tree = vec![false; n];
+        // The closest tree node (and distance) of every sample not yet in the tree
+        let mut closest: Vec<Option<(usize, u32)>> = vec![None; n];
+
+        if n == 0 { return edges }
+        in_tree[0] = true;
+        (1..n).for_each(|j| closest[j] = Some((0, self.distances[0][j])));
+
+        for _ in 1..n {
+            let next = (0..n)
+                .filter(|j| !in_tree[*j])
+                .filter_map(|j| closest[j].map(|(i, d)| (j, i, d)))
+                .min_by_key(|(j, _, d)| (*d, *j));
+            let Some((j, i, d)) = next else { break };
+            in_tree[j] = true;
+            edges.push((i, j, d));
+            for k in (0..n).filter(|k| !in_tree[*k]) {
+                if closest[k].map(|(_, d)| self.distances[j][k] < d).unwrap_or(true) {
+                    closest[k] = Some((j, self.distances[j][k]));
+                }
+            }
+        }
+
+        edges
+    }
+
+    /// Link all pairs of samples within a distance threshold.
+    ///
+    /// Returns the graph edges as (sample index, sample index, distance).
+    pub fn threshold_edges(&self, threshold: u32) -> Vec<(usize, usize, u32)> {
+        let n = self.samples.len();
+        (0..n)
+            .flat_map(|i| ((i + 1)..n).map(move |j| (i, j)))
+            .filter(|(i, j)| self.distances[*i][*j] <= threshold)
+            .map(|(i, j)| (i, j, self.distances[i][j]))
+            .collect()
+    }
+
+    /// Write a sample network as DOT or GraphML, detected from the file extension.
+    ///
+    /// Node attributes are taken from the per-sample metadata, if provided.
+    pub fn write_graph<P>(&self, edges: &[(usize, usize, u32)], metadata: &BTreeMap<String, BTreeMap<String, String>>, path: P) -> Result<(), Report>
+    where
+        P: AsRef<Path> + std::fmt::Debug,
+    {
+        log::info!("Writing sample network: {path:?}");
+
+        let ext = path.as_ref().extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+        let mut writer = BufWriter::new(File::create(&path)?);
+        let escape     = |s: &str| s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;");
+        let no_attributes = BTreeMap::new();
+        let attributes = |sample: &String| metadata.get(sample).unwrap_or(&no_attributes);
+
+        match ext.as_str() {
+            "dot" | "gv" => {
+                writeln!(writer, "graph samples {{")?;
+                for sample in &self.samples {
+                    let attributes = attributes(sample).iter().map(|(k, v)| format!("{k:?}={v:?}")).collect::<Vec<_>>();
+                    match attributes.is_empty() {
+                        true  => writeln!(writer, "  {sample:?};")?,
+                        false => writeln!(writer, "  {sample:?} [{}];", attributes.join(", "))?,
+                    }
+                }
+                for (i, j, d) in edges {
+                    writeln!(writer, "  {:?} -- {:?} [weight={d}, label=\"{d}\"];", self.samples[*i], self.samples[*j])?;
+                }
+                writeln!(writer, "}}")?;
+            },
+            "graphml" => {
+                let keys = metadata.values().flat_map(|a| a.keys()).collect::<BTreeSet<_>>();
+                writeln!(writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+                writeln!(writer, "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">")?;
+                for key in &keys {
+                    writeln!(writer, "  <key id=\"{0}\" for=\"node\" attr.name=\"{0}\" attr.type=\"string\"/>", escape(key))?;
+                }
+                writeln!(writer, "  <key id=\"distance\" for=\"edge\" attr.name=\"distance\" attr.type=\"int\"/>")?;
+                writeln!(writer, "  <graph id=\"samples\" edgedefault=\"undirected\">")?;
+                for sample in &self.samples {
+                    writeln!(writer, "    <node id=\"{}\">", escape(sample))?;
+                    for (key, value) in attributes(sample) {
+                        writeln!(writer, "      <data key=\"{}\">{}</data>", escape(key), escape(value))?;
+                    }
+                    writeln!(writer, "    </node>")?;
+                }
+                for (i, j, d) in edges {
+                    writeln!(writer, "    <edge source=\"{}\" target=\"{}\"><data key=\"distance\">{d}</data></edge>", escape(&self.samples[*i]), escape(&self.samples[*j]))?;
+                }
+                writeln!(writer, "  </graph>")?;
+                writeln!(writer, "</graphml>")?;
+            },
+            _ => return Err(eyre!("Unknown network format, expected a .dot or .graphml extension: {path:?}")),
+        }
+
+        Ok(())
+    }
+
+    /// Write the distances as long-format pairs, one row per unique pair of samples.
+    pub async fn write_pairs<P>(&self, path: P) -> Result<(), Report>
+    where
+        P: AsRef<Path> + std::fmt::Debug,
+    {
+        log::info!("Writing distance pairs: {path:?}");
+        let n     = self.samples.len();
+        let pairs = (0..n).flat_map(|i| ((i + 1)..n).map(move |j| (i, j))).collect::<Vec<_>>();
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("sample_1", DataType::Utf8,   false),
+            Field::new("sample_2", DataType::Utf8,   false),
+            Field::new("distance", DataType::UInt32, false),
+        ]));
+        let records = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(StringArray::from(pairs.iter().map(|(i, _)| self.samples[*i].clone()).collect::<Vec<_>>())),
+                Arc::new(StringArray::from(pairs.iter().map(|(_, j)| self.samples[*j].clone()).collect::<Vec<_>>())),
+                Arc::new(UInt32Array::from(pairs.iter().map(|(i, j)| self.distances[*i][*j]).collect::<Vec<_>>())),
+            ],
+        )?;
+        crate::write_table(SessionContext::new().read_batch(records)?, &path).await
+    }
+}
+
+/// Compute pairwise SNP distances between samples from their nucleotide substitutions.
+///
+/// Two samples differ at a position when their alleles differ, with samples lacking
+/// a substitution carrying the reference allele.
+///
+/// # Arguments
+///
+///   - `mutations`: A file path to the mutations table produced by `extract` (tsv or parquet).
+///   - `nextclade`: An optional file path to nextclade TSV or JSON output. Positions in either sample's `missing` ranges are ignored.
+///   - `mask`     : Position ranges (1-based, inclusive) to ignore in all samples.
+///
+pub async fn distances<P>(mutations: P, nextclade: Option<P>, mask: &[(u32, u32)]) -> Result<DistanceMatrix, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Beginning distance computation.");
+
+    let ctx = SessionContext::new();
+    let ctx = crate::register_mutations(&mutations, ctx, "mutations").await?;
+
+    // ------------------------------------------------------------------------
+    // Substitutions
+
+    let mut substitutions: BTreeMap<String, BTreeMap<u32, String>> = BTreeMap::new();
+    let batches = ctx.sql("SELECT DISTINCT sample FROM mutations").await?.collect().await?;
+    for batch in &batches {
+        for sample in crate::string_column(batch, "sample")?.iter().flatten() {
+            substitutions.entry(sample.to_string()).or_default();
+        }
+    }
+
+    let query   = "SELECT DISTINCT sample, nuc_start, right(mutation, 1) as allele FROM mutations WHERE column = 'substitutions' AND nuc_start IS NOT NULL";
+    let batches = ctx.sql(query).await?.collect().await?;
+    for batch in &batches {
+        let sample   = crate::string_column(batch, "sample")?;
+        let position = crate::string_column(batch, "nuc_start")?;
+        let allele   = crate::string_column(batch, "allele")?;
+        for i in 0..batch.num_rows() {
+            substitutions.entry(sample.value(i).to_string()).or_default().insert(position.value(i).parse()?, allele.value(i).to_string());
+        }
+    }
+
+    // ------------------------------------------------------------------------
+    // Missing Data
+
+    let mut missing: BTreeMap<String, Vec<(u32, u32)>> = BTreeMap::new();
+    if let Some(nextclade) = &nextclade {
+        log::info!("Reading missing positions from nextclade file: {nextclade:?}");
+        let ctx     = crate::nextclade::register(nextclade, ctx, "nextclade").await?;
+        let query   = "SELECT arrow_cast(\"seqName\", 'Utf8') as sample, arrow_cast(missing, 'Utf8') as missing FROM nextclade";
+        let batches = ctx.sql(query).await?.collect().await?;
+        for batch in &batches {
+            let sample = crate::string_column(batch, "sample")?;
+            let ranges = crate::string_column(batch, "missing")?;
+            for i in 0..batch.num_rows() {
+                let ranges = if ranges.is_null(i) { Vec::new() } else { crate::parse_ranges(ranges.value(i))? };
+                substitutions.entry(sample.value(i).to_string()).or_default();
+                missing.insert(sample.value(i).to_string(), ranges);
+            }
+        }
+    }
+
+    // ------------------------------------------------------------------------
+    // Pairwise Distances
+
+    let within  = |ranges: &[(u32, u32)], position: u32| ranges.iter().any(|(start, end)| position >= *start && position <= *end);
+    let samples = substitutions.keys().cloned().collect::<Vec<_>>();
+    log::info!("Computing pairwise distances between {} samples.", samples.len());
+
+    let mut distances = vec![vec![0; samples.len()]; samples.len()];
+    let no_missing    = Vec::new();
+    for i in 0..samples.len() {
+        let (a, a_missing) = (&substitutions[&samples[i]], missing.get(&samples[i]).unwrap_or(&no_missing));
+        for j in (i + 1)..samples.len() {
+            let (b, b_missing) = (&substitutions[&samples[j]], missing.get(&samples[j]).unwrap_or(&no_missing));
+            let positions = a.keys().chain(b.keys()).collect::<BTreeSet<_>>();
+            let distance  = positions
+                .into_iter()
+                .filter(|p| !within(mask, **p) && !within(a_missing, **p) && !within(b_missing, **p))
+                .filter(|p| a.get(p) != b.get(p))
+                .count() as u32;
+            distances[i][j] = distance;
+            distances[j][i] = distance;
+        }
+    }
+
+    log::info!("Finished distance computation.");
+
+    Ok(DistanceMatrix { samples, distances })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn snp_distances_with_mask() -> Result<(), Report> {
+        let dir  = tempfile::tempdir()?;
+        let path = |name: &str| dir.path().join(name);
+        let mutations = [
+            "sample\tmutation\tcolumn\tnuc_start",
+            "s1\tC241T\tsubstitutions\t241",
+            "s1\tA23403G\tsubstitutions\t23403",
+            "s2\tC241T\tsubstitutions\t241",
+            "s3\tG28881A\tsubstitutions\t28881",
+        ];
+        std::fs::write(path("mutations.tsv"), mutations.join("\n") + "\n")?;
+
+        let matrix = distances(path("mutations.tsv"), None, &[]).await?;
+        assert_eq!(matrix.samples, ["s1", "s2", "s3"]);
+        assert_eq!(matrix.distances, [[0, 1, 3], [1, 0, 2], [3, 2, 0]]);
+
+        matrix.write_matrix(path("distances.tsv")).await?;
+        assert_eq!(std::fs::read_to_string(path("distances.tsv"))?.lines().next(), Some("sample\ts1\ts2\ts3"));
+
+        let masked = distances(path("mutations.tsv"), None, &[(23403, 23403)]).await?;
+        assert_eq!(masked.distances, [[0, 0, 2], [0, 0, 2], [2, 2, 0]]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn single_linkage_clusters() -> Result<(), Report> {
+        let dir    = tempfile::tempdir()?;
+        let matrix = DistanceMatrix {
+            samples:   vec!["s1".to_string(), "s2".to_string(), "s3".to_string(), "s4".to_string()],
+            distances: vec![vec![0, 1, 5, 2], vec![1, 0, 5, 3], vec![5, 5, 0, 5], vec![2, 3, 5, 0]],
+        };
+        // s1-s2 and s1-s4 link s2 and s4 through s1, though they are 3 apart
+        assert_eq!(matrix.clusters(2), [1, 1, 2, 1]);
+        assert_eq!(matrix.clusters(0), [1, 2, 3, 4]);
+
+        let path = dir.path().join("clusters.tsv");
+        matrix.write_clusters(2, &path).await?;
+        let lines = std::fs::read_to_string(&path)?.lines().map(String::from).collect::<Vec<_>>();
+        assert_eq!(lines, ["sample\tcluster\tcluster_size", "s1\t1\t3", "s2\t1\t3", "s3\t2\t1", "s4\t1\t3"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+use arrow::array::{Float64Array, StringArray, UInt32Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use color_eyre::eyre::{Report, Result};
+use datafusion::config::CsvOptions;
+use datafusion::dataframe::DataFrameWriteOptions;
+use datafusion::prelude::*;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Per-site diversity of a sample set.
+#[derive(Clone, Debug)]
+pub struct Site {
+    /// 'nucleotide' or 'amino-acid'
+    pub level: String,
+    /// Gene name, for amino acid sites.
+    pub gene: Option<String>,
+    /// Nucleotide or codon position.
+    pub position: u32,
+    /// Number of distinct alleles observed, including the reference.
+    pub alleles: u32,
+    /// Shannon entropy (bits) of the allele frequencies.
+    pub entropy: f64,
+    /// Frequency of all but the most common allele.
+    pub minor_allele_frequency: f64,
+}
+
+/// Compute the Shannon entropy and minor allele frequency per nucleotide and codon position.
+///
+/// Samples without a mutation at a site are counted as carrying the reference allele.
+/// Nucleotide sites are computed from substitutions, and codon sites from amino acid
+/// substitutions and deletions.
+///
+/// # Arguments
+///
+///   - `mutations`: A file path to the mutations table produced by `extract` (tsv or parquet).
+///   - `output`   : A file path to write the per-site diversity table to.
+///   - `prefix`   : An optional output prefix to render the nucleotide entropy as a genome heat track.
+///
+pub async fn diversity<P>(mutations: P, output: P, prefix: Option<&str>) -> Result<Vec<Site>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Beginning diversity computation.");
+
+    let ctx = SessionContext::new();
+    let ctx = crate::register_mutations(&mutations, ctx, "mutations").await?;
+
+    // ------------------------------------------------------------------------
+    // Allele Counts
+
+    let batches = ctx.sql("SELECT COUNT(DISTINCT sample) as samples FROM mutations").await?.collect().await?;
+    let samples: u32 = match batches.first() {
+        Some(batch) => crate::string_column(batch, "samples")?.value(0).parse()?,
+        None        => 0,
+    };
+    log::info!("Computing diversity across {samples} samples.");
+
+    let query = "
+        SELECT level, gene, position, allele, COUNT(DISTINCT sample) as count
+        FROM (
+            SELECT 'nucleotide' as level, NULL as gene, nuc_start as position, right(mutation, 1) as allele, sample
+            FROM mutations WHERE column = 'substitutions'
+            UNION ALL
+            SELECT 'amino-acid' as level, gene, aa_start as position, right(mutation, 1) as allele, sample
+            FROM mutations WHERE column IN ('aaSubstitutions', 'aaDeletions')
+        )
+        WHERE position IS NOT NULL
+        GROUP BY level, gene, position, allele";
+    let batches = ctx.sql(query).await?.collect().await?;
+
+    // Group the alternate allele counts by site
+    let mut site_counts: BTreeMap<(String, Option<String>, u32), Vec<u32>> = BTreeMap::new();
+    for batch in &batches {
+        let level    = crate::string_column(batch, "level")?;
+        let gene     = crate::string_column(batch, "gene")?;
+        let position = crate::string_column(batch, "position")?;
+        let count    = crate::string_column(batch, "count")?;
+        for i in 0..batch.num_rows() {
+            let gene = gene.is_valid(i).then(|| gene.value(i).to_string());
+            let key  = (level.value(i).to_string(), gene, position.value(i).parse()?);
+            site_counts.entry(key).or_default().push(count.value(i).parse()?);
+        }
+    }
+
+    // ------------------------------------------------------------------------
+    // Entropy
+
+    let sites = site_counts.into_iter().map(|((level, gene, position), mut counts)| {
+        // The remaining samples carry the reference allele
+        let alternate: u32 = counts.iter().sum();
+        if samples > alternate { counts.push(samples - alternate) }
+
+        let total   = counts.iter().sum::<u32>().max(1) as f64;
+        let entropy = counts.iter().map(|c| *c as f64 / total).filter(|p| *p > 0.0).map(|p| -p * p.log2()).sum::<f64>();
+        let major   = *counts.iter().max().unwrap_or(&0) as f64;
+
+        Site { level, gene, position, alleles: counts.len() as u32, entropy, minor_allele_frequency: 1.0 - (major / total) }
+    }).collect::<Vec<_>>();
+
+    // ------------------------------------------------------------------------
+    // Write Table
+
+    log::info!("Writing diversity table: {output:?}");
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("level",                  DataType::Utf8,    false),
+        Field::new("gene",                   DataType::Utf8,    true),
+        Field::new("position",               DataType::UInt32,  false),
+        Field::new("alleles",                DataType::UInt32,  false),
+        Field::new("entropy",                DataType::Float64, false),
+        Field::new("minor_allele_frequency", DataType::Float64, false),
+    ]));
+    let records = RecordBatch::try_new(
+        schema,
+        vec![
+            Arc::new(StringArray::from(sites.iter().map(|s| s.level.clone()).collect::<Vec<_>>())),
+            Arc::new(StringArray::from(sites.iter().map(|s| s.gene.clone()).collect::<Vec<_>>())),
+            Arc::new(UInt32Array::from(sites.iter().map(|s| s.position).collect::<Vec<_>>())),
+            Arc::new(UInt32Array::from(sites.iter().map(|s| s.alleles).collect::<Vec<_>>())),
+            Arc::new(Float64Array::from(sites.iter().map(|s| s.entropy).collect::<Vec<_>>())),
+            Arc::new(Float64Array::from(sites.iter().map(|s| s.minor_allele_frequency).collect::<Vec<_>>())),
+        ],
+    )?;
+
+    let (path, _ext, delimiter) = crate::parse_csv_path(&output, None)?;
+    let csv_options = CsvOptions::default().with_delimiter(delimiter);
+    ctx.read_batch(records)?.write_csv(&path, DataFrameWriteOptions::default(), Some(csv_options)).await?;
+
+    // ------------------------------------------------------------------------
+    // Heat Track
+
+    #[cfg(feature = "plot")]
+    if let Some(prefix) = prefix {
+        let values = sites.iter().filter(|s| s.level == "nucleotide").map(|s| (s.position, s.entropy)).collect::<Vec<_>>();
+        crate::plot::track(&values, crate::extract::GENOME_LENGTH, prefix)?;
+    }
+    #[cfg(not(feature = "plot"))]
+    crate::warn_without_plot(prefix, "heat track");
+
+    log::info!("Finished diversity computation.");
+
+    Ok(sites)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn entropy_and_minor_allele_frequency() -> Result<(), Report> {
+        let dir  = tempfile::tempdir()?;
+        let path = |name: &str| dir.path().join(name);
+        let mutations = [
+            "sample\tmutation\tcolumn\tgene\tnuc_start\taa_start",
+            "s1\tC241T\tsubstitutions\t\t241\t",
+            "s2\tC241T\tsubstitutions\t\t241\t",
+            "s3\tC241G\tsubstitutions\t\t241\t",
+            "s4\tA23403G\tsubstitutions\t\t23403\t",
+            "s4\tS:D614G\taaSubstitutions\tS\t\t614",
+        ];
+        std::fs::write(path("mutations.tsv"), mutations.join("\n") + "\n")?;
+
+        let sites = diversity(path("mutations.tsv"), path("diversity.tsv"), None).await?;
+        let sites = sites.iter().map(|s| (s.level.as_str(), s.position, s.alleles, s.entropy, s.minor_allele_frequency)).collect::<Vec<_>>();
+        // C241: T (2 samples), G (1), and the reference C (1)
+        assert_eq!(sites[0], ("amino-acid", 614, 2, -(0.25f64 * 0.25f64.log2() + 0.75 * 0.75f64.log2()), 0.25));
+        assert_eq!(sites[1], ("nucleotide", 241, 3, 1.5, 0.5));
+        assert_eq!(sites[2], ("nucleotide", 23403, 2, -(0.25f64 * 0.25f64.log2() + 0.75 * 0.75f64.log2()), 0.25));
+        assert!(path("diversity.tsv").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,566 @@
+use color_eyre::eyre::{eyre, Report, Result};    // Generic error handling with pretty logging
+use datafusion::prelude::*;                       // All the essential datafusion functions.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;                              // System file paths
+
+/// The source column recorded in exported GFF3 features.
+pub const GFF_SOURCE: &str = "mutation-heatmap";
+
+/// Export mutations as GFF3 features, for layering onto genome browsers.
+///
+/// Annotated tables (from `annotate`) have no coordinates, so they're joined back from the
+/// `extract` table by sample and mutation. Only the mutations observed in a sample are
+/// exported, not the catalogued mutations that are absent from it.
+///
+/// # Arguments
+///
+///   - `mutations`: A file path to the mutations table produced by `extract` or `annotate` (tsv or parquet).
+///   - `output`   : A file path to write the GFF3 features to.
+///   - `seqid`    : The sequence identifier to use in the first GFF3 column.
+///   - `aggregate`: Write one feature per mutation (with count and frequency) rather than one per sample.
+///   - `extract`  : A file path to the mutations table produced by `extract`, required when `mutations` is annotated.
+///
+pub async fn gff<P>(mutations: P, output: P, seqid: &str, aggregate: bool, extract: Option<P>) -> Result<(), Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Beginning GFF3 export.");
+
+    let ctx = SessionContext::new();
+    let ctx = crate::register_mutations(&mutations, ctx, "mutations").await?;
+
+    // ------------------------------------------------------------------------
+    // Coordinates
+
+    // Annotated tables carry a status column, otherwise every row is an observed mutation.
+    let columns = crate::column_names(&ctx.table("mutations").await?);
+    let ctx = match columns.iter().any(|c| c == "status") {
+        true  => ctx,
+        false => crate::replace_table(ctx, "mutations", "SELECT *, 'present' as status FROM mutations").await?,
+    };
+
+    // The annotation columns are dropped, so they can't collide with the coordinate columns (ex. gene)
+    let ctx = match (columns.iter().any(|c| c == "nuc_start"), extract) {
+        (true, _)              => ctx,
+        (false, Some(extract)) => {
+            log::info!("Joining coordinates from the extracted mutations: {extract:?}");
+            let ctx   = crate::register_mutations(&extract, ctx, "extract").await?;
+            let query = "
+                SELECT DISTINCT
+                    A.sample, A.mutation, COALESCE(A.column, E.column) as column, A.status,
+                    E.type, E.gene, E.nuc_start, E.nuc_end
+                FROM mutations A
+                LEFT JOIN extract E
+                ON A.sample = E.sample AND A.mutation = E.mutation AND (A.column IS NULL OR A.column = E.column)";
+            crate::replace_table(ctx, "mutations", query).await?
+        },
+        (false, None) => return Err(eyre!("The mutations table has no coordinates (ex. annotate output), please provide the extract table to join them from: {mutations:?}")),
+    };
+
+    // ------------------------------------------------------------------------
+    // Query Features
+
+    // Mutations without nucleotide coordinates can't be placed on the genome,
+    // (ex. amino acid mutations in a gene missing from the GFF), so we skip them.
+    // Absent mutations aren't features, and the status isn't part of a mutation's
+    // aggregate, so that the ivar statuses (ex. consensus, low-frequency) are counted together.
+    let observed = "status IS NULL OR status != 'absent'";
+    let query = match aggregate {
+        true => format!("
+            SELECT
+                mutation, column, type, gene, nuc_start, nuc_end,
+                COUNT(DISTINCT sample) as count,
+                arrow_cast(COUNT(DISTINCT sample), 'Float64') / (SELECT arrow_cast(COUNT(DISTINCT sample), 'Float64') FROM mutations) as frequency
+            FROM mutations
+            WHERE nuc_start IS NOT NULL AND ({observed})
+            GROUP BY mutation, column, type, gene, nuc_start, nuc_end
+            ORDER BY nuc_start, nuc_end, mutation"),
+        false => format!("
+            SELECT sample, mutation, column, type, gene, nuc_start, nuc_end, status
+            FROM mutations
+            WHERE nuc_start IS NOT NULL AND ({observed})
+            ORDER BY nuc_start, nuc_end, mutation, sample"),
+    };
+    let skipped = ctx.sql(&format!("SELECT COUNT(*) as count FROM mutations WHERE nuc_start IS NULL AND ({observed})")).await?.collect().await?;
+    if let Some(batch) = skipped.first() {
+        let n = crate::string_column(batch, "count")?.value(0).to_string();
+        if n != "0" { log::warn!("Skipping {n} mutations with no nucleotide coordinates.") }
+    }
+    let batches = ctx.sql(&query).await?.collect().await?;
+
+    // ------------------------------------------------------------------------
+    // Write Features
+
+    log::info!("Writing GFF3 features: {output:?}");
+    let mut writer = BufWriter::new(File::create(&output)?);
+    writeln!(writer, "##gff-version 3")?;
+
+    let mut id = 0;
+    for batch in &batches {
+        let mutation  = crate::string_column(batch, "mutation")?;
+        let column    = crate::string_column(batch, "column")?;
+        let gene      = crate::string_column(batch, "gene")?;
+        let start     = crate::string_column(batch, "nuc_start")?;
+        let end       = crate::string_column(batch, "nuc_end")?;
+        let status    = if aggregate { None } else { Some(crate::string_column(batch, "status")?) };
+        let sample    = if aggregate { None } else { Some(crate::string_column(batch, "sample")?) };
+        let count     = if aggregate { Some(crate::string_column(batch, "count")?) } else { None };
+        let frequency = if aggregate { Some(crate::string_column(batch, "frequency")?) } else { None };
+
+        for i in 0..batch.num_rows() {
+            id += 1;
+            let column   = column.value(i);
+            let mutation = mutation.value(i);
+
+            let mut attributes = vec![
+                format!("ID=mutation{id}"),
+                format!("Name={}", escape_attribute(mutation)),
+                format!("column={}", escape_attribute(column)),
+            ];
+            if !gene.is_null(i)  { attributes.push(format!("gene={}", escape_attribute(gene.value(i)))) }
+            if let Some(status) = &status    { attributes.push(format!("status={}", escape_attribute(status.value(i)))) }
+            if let Some(sample) = &sample    { attributes.push(format!("sample={}", escape_attribute(sample.value(i)))) }
+            if let Some(count)  = &count     { attributes.push(format!("count={}", count.value(i))) }
+            if let Some(freq)   = &frequency { attributes.push(format!("frequency={}", freq.value(i))) }
+
+            // Nextclade insertions are reported at the position before the inserted bases,
+            // so the feature end is the same as the start.
+            let end = if end.is_null(i) { start.value(i) } else { end.value(i) };
+
+            writeln!(
+                writer,
+                "{seqid}\t{GFF_SOURCE}\t{}\t{}\t{end}\t.\t.\t.\t{}",
+                sequence_ontology_type(column),
+                start.value(i),
+                attributes.join(";"),
+            )?;
+        }
+    }
+
+    log::info!("Finished GFF3 export.");
+
+    Ok(())
+}
+
+/// Export nucleotide mutations as a multi-sample VCF.
+///
+/// REF and ALT alleles are synthesized from the mutation strings and the reference
+/// sequence. Deletions and insertions are anchored on the preceding reference base,
+/// as required by the VCF specification. Amino acid mutations are not exported.
+///
+/// # Arguments
+///
+///   - `mutations`: A file path to the mutations table produced by `extract` (tsv or parquet).
+///   - `reference`: A file path to the reference genome fasta.
+///   - `output`   : A file path to write the VCF to.
+///
+pub async fn vcf<P>(mutations: P, reference: P, output: P) -> Result<(), Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Beginning VCF export.");
+
+    let (contig, sequence) = crate::read_fasta(&reference)?.swap_remove(0);
+    let contig = contig.split_whitespace().next().unwrap_or(&contig).to_string();
+
+    let ctx = SessionContext::new();
+    let ctx = crate::register_mutations(&mutations, ctx, "mutations").await?;
+
+    // ------------------------------------------------------------------------
+    // Samples
+
+    let batches = ctx.sql("SELECT DISTINCT sample FROM mutations ORDER BY sample").await?.collect().await?;
+    let mut samples: Vec<String> = Vec::new();
+    for batch in &batches {
+        let sample = crate::string_column(batch, "sample")?;
+        samples.extend(sample.iter().flatten().map(String::from));
+    }
+
+    // ------------------------------------------------------------------------
+    // Variants
+
+    log::info!("Synthesizing REF and ALT alleles from the reference.");
+
+    // Variants are keyed on (POS, REF, ALT), so that the same mutation across
+    // samples collapses into one record with multiple genotypes.
+    let mut variants: BTreeMap<(u32, String, String), BTreeSet<String>> = BTreeMap::new();
+    let query   = "SELECT sample, mutation, column, nuc_start, nuc_end FROM mutations WHERE type = 'nucleotide' AND nuc_start IS NOT NULL";
+    let batches = ctx.sql(query).await?.collect().await?;
+    for batch in &batches {
+        let sample   = crate::string_column(batch, "sample")?;
+        let mutation = crate::string_column(batch, "mutation")?;
+        let column   = crate::string_column(batch, "column")?;
+        let start    = crate::string_column(batch, "nuc_start")?;
+        let end      = crate::string_column(batch, "nuc_end")?;
+
+        for i in 0..batch.num_rows() {
+            let start: u32 = start.value(i).parse()?;
+            let end:   u32 = if end.is_null(i) { start } else { end.value(i).parse()? };
+            let variant = vcf_alleles(&sequence, column.value(i), mutation.value(i), start, end)?;
+            match variant {
+                Some(variant) => { variants.entry(variant).or_default().insert(sample.value(i).to_string()); },
+                None          => log::warn!("Skipping mutation that can't be represented in VCF: {}", mutation.value(i)),
+            }
+        }
+    }
+
+    // ------------------------------------------------------------------------
+    // Write VCF
+
+    log::info!("Writing VCF: {output:?}");
+    let mut writer = BufWriter::new(File::create(&output)?);
+    writeln!(writer, "##fileformat=VCFv4.2")?;
+    writeln!(writer, "##source={GFF_SOURCE}")?;
+    writeln!(writer, "##reference={}", reference.as_ref().display())?;
+    writeln!(writer, "##contig=<ID={contig},length={}>", sequence.len())?;
+    writeln!(writer, "##INFO=<ID=AC,Number=A,Type=Integer,Description=\"Number of samples with the alternate allele\">")?;
+    writeln!(writer, "##INFO=<ID=AN,Number=1,Type=Integer,Description=\"Total number of samples\">")?;
+    writeln!(writer, "##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">")?;
+    writeln!(writer, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\t{}", samples.join("\t"))?;
+
+    for ((pos, reference, alternate), carriers) in &variants {
+        let genotypes = samples.iter().map(|s| if carriers.contains(s) { "1" } else { "0" }).collect::<Vec<_>>();
+        writeln!(
+            writer,
+            "{contig}\t{pos}\t.\t{reference}\t{alternate}\t.\t.\tAC={};AN={}\tGT\t{}",
+            carriers.len(),
+            samples.len(),
+            genotypes.join("\t"),
+        )?;
+    }
+
+    log::info!("Finished VCF export.");
+
+    Ok(())
+}
+
+/// Synthesize the VCF (POS, REF, ALT) of a nucleotide mutation from the reference sequence.
+///
+/// Returns `None` if the mutation type has no VCF representation.
+pub fn vcf_alleles(sequence: &[u8], column: &str, mutation: &str, start: u32, end: u32) -> Result<Option<(u32, String, String)>, Report> {
+
+    // Retrieve the reference bases at 1-based, inclusive coordinates.
+    let bases = |start: u32, end: u32| -> Result<String, Report> {
+        let slice = sequence
+            .get((start as usize).saturating_sub(1)..end as usize)
+            .ok_or(eyre!("Mutation {mutation} is outside the reference sequence ({} bp).", sequence.len()))?;
+        Ok(String::from_utf8_lossy(slice).to_string())
+    };
+
+    let variant = match column {
+        // ex. C241T
+        "substitutions" => {
+            let reference = bases(start, end)?;
+            let alternate = mutation.chars().last().ok_or(eyre!("Failed to parse substitution: {mutation}"))?.to_string();
+            if !mutation.starts_with(&reference) {
+                log::warn!("Reference base {reference} does not match substitution {mutation}.")
+            }
+            Some((start, reference, alternate))
+        },
+        // ex. 6513-6515, anchored on the preceding base (or following base at the genome start)
+        "deletions" => match start > 1 {
+            true  => Some((start - 1, bases(start - 1, end)?, bases(start - 1, start - 1)?)),
+            false => Some((start, bases(start, end + 1)?, bases(end + 1, end + 1)?)),
+        },
+        // ex. 28933:T, inserted bases follow the reported position
+        "insertions" => {
+            let inserted  = mutation.split(':').nth(1).ok_or(eyre!("Failed to parse insertion: {mutation}"))?;
+            let reference = bases(start, start)?;
+            Some((start, reference.clone(), format!("{reference}{inserted}")))
+        },
+        _ => None,
+    };
+
+    Ok(variant)
+}
+
+/// Count the samples with an amino acid mutation at each residue of a gene.
+///
+/// Returns (residue, count) pairs ordered by residue. Frameshifts are excluded,
+/// because they span many residues rather than marking a single site.
+pub async fn residue_counts<P>(mutations: P, gene: &str) -> Result<Vec<(u32, u32)>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let ctx = SessionContext::new();
+    let ctx = crate::register_mutations(&mutations, ctx, "mutations").await?;
+
+    let gene  = crate::quote_literal(gene);
+    let query = format!("
+        SELECT aa_start as residue, COUNT(DISTINCT sample) as count
+        FROM mutations
+        WHERE gene = {gene} AND type = 'amino-acid' AND column != 'frameShifts' AND aa_start IS NOT NULL
+        GROUP BY aa_start
+        ORDER BY aa_start");
+    let batches = ctx.sql(&query).await?.collect().await?;
+
+    let mut counts = Vec::new();
+    for batch in &batches {
+        let residue = crate::string_column(batch, "residue")?;
+        let count   = crate::string_column(batch, "count")?;
+        for i in 0..batch.num_rows() {
+            counts.push((residue.value(i).parse()?, count.value(i).parse()?));
+        }
+    }
+
+    if counts.is_empty() {
+        log::warn!("No amino acid mutations were found in gene: {gene}");
+    }
+
+    Ok(counts)
+}
+
+/// Export per-residue mutation counts of a gene as a ChimeraX attribute (defattr) file.
+///
+/// # Arguments
+///
+///   - `mutations`: A file path to the mutations table produced by `extract` (tsv or parquet).
+///   - `gene`     : The gene whose residues will be counted.
+///   - `chain`    : The structure chain identifier the gene is modelled as.
+///   - `output`   : A file path to write the defattr file to.
+///
+pub async fn defattr<P>(mutations: P, gene: &str, chain: &str, output: P) -> Result<(), Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Beginning defattr export.");
+
+    let counts = residue_counts(&mutations, gene).await?;
+
+    log::info!("Writing defattr: {output:?}");
+    let mut writer = BufWriter::new(File::create(&output)?);
+    writeln!(writer, "attribute: mutation_count")?;
+    writeln!(writer, "match mode: 1-to-1")?;
+    writeln!(writer, "recipient: residues")?;
+    for (residue, count) in counts {
+        writeln!(writer, "\t/{chain}:{residue}\t{count}")?;
+    }
+
+    log::info!("Finished defattr export.");
+
+    Ok(())
+}
+
+/// Rewrite the B-factor column of a PDB file with per-residue mutation frequencies of a gene.
+///
+/// Atoms of the selected chain are given the fraction of samples (0-1) with a mutation at
+/// their residue (or 0), so structures can be colored by B-factor in PyMOL or ChimeraX.
+/// A frequency always fits the 6 characters of the column, unlike a count of samples.
+///
+/// # Arguments
+///
+///   - `mutations`: A file path to the mutations table produced by `extract` (tsv or parquet).
+///   - `gene`     : The gene whose residues will be counted.
+///   - `chain`    : The structure chain identifier the gene is modelled as.
+///   - `pdb`      : A file path to the input PDB structure.
+///   - `output`   : A file path to write the rewritten PDB structure to.
+///
+pub async fn bfactor<P>(mutations: P, gene: &str, chain: &str, pdb: P, output: P) -> Result<(), Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Beginning B-factor export.");
+
+    let counts  = residue_counts(&mutations, gene).await?;
+    let ctx     = crate::register_mutations(&mutations, SessionContext::new(), "mutations").await?;
+    let batches = ctx.sql("SELECT COUNT(DISTINCT sample) as samples FROM mutations").await?.collect().await?;
+    let samples: u32 = match batches.first() {
+        Some(batch) => crate::string_column(batch, "samples")?.value(0).parse()?,
+        None        => 0,
+    };
+    let frequencies = counts.into_iter().map(|(residue, count)| (residue, count as f64 / samples.max(1) as f64)).collect::<BTreeMap<_, _>>();
+
+    log::info!("Rewriting PDB B-factors: {pdb:?}");
+    let structure  = std::fs::read_to_string(&pdb)?;
+    let mut writer = BufWriter::new(File::create(&output)?);
+
+    for line in structure.lines() {
+        writeln!(writer, "{}", bfactor_line(line, chain, &frequencies).unwrap_or_else(|| line.to_string()))?;
+    }
+    writer.flush()?;
+
+    log::info!("Finished B-factor export.");
+
+    Ok(())
+}
+
+/// Rewrite the B-factor of a PDB atom line of the chain, with the frequency of its residue (or 0).
+///
+/// Returns `None` for other lines, and for atom lines too short or malformed to hold a B-factor.
+pub fn bfactor_line(line: &str, chain: &str, frequencies: &BTreeMap<u32, f64>) -> Option<String> {
+    // PDB is a fixed-width format: chain (col 22), residue (cols 23-26), B-factor (cols 61-66)
+    let is_atom = line.starts_with("ATOM") || line.starts_with("HETATM");
+    if !is_atom || line.get(21..22) != Some(chain) { return None }
+    let (before, after) = (line.get(..60)?, line.get(66..)?);
+    let residue   = line.get(22..26).and_then(|r| r.trim().parse::<u32>().ok());
+    let frequency = residue.and_then(|r| frequencies.get(&r)).copied().unwrap_or(0.0);
+    Some(format!("{before}{:>6.2}{after}", frequency.clamp(0.0, 1.0)))
+}
+
+/// Export one line per sample, with its comma-joined mutations in nextclade shorthand.
+///
+/// Mutations are ordered by genome position, like the nextclade columns they came
+/// from. Every sample in the table gets a line, even if none of its mutations pass the filters.
+///
+/// # Arguments
+///
+///   - `mutations`: A file path to the mutations table produced by `extract` or `annotate` (tsv or parquet).
+///   - `output`   : A file path to write the sample lists to.
+///   - `columns`  : Only export mutations from these nextclade columns (ex. aaSubstitutions), or all if empty.
+///   - `genes`    : Only export mutations in these genes (ex. S), or all if empty.
+///   - `notation` : Display style of the amino acid mutations.
+///
+pub async fn lists<P>(mutations: P, output: P, columns: &[String], genes: &[String], notation: &crate::notation::LabelStyle) -> Result<(), Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Beginning mutation list export.");
+
+    let ctx = SessionContext::new();
+    let ctx = crate::register_mutations(&mutations, ctx, "mutations").await?;
+
+    // ------------------------------------------------------------------------
+    // Query Mutations
+
+    // Annotated tables also list catalogue mutations that are absent from a sample.
+    let mut filters = vec!["TRUE".to_string()];
+    if crate::column_names(&ctx.table("mutations").await?).iter().any(|c| c == "status") {
+        filters.push("(status IS NULL OR status != 'absent')".to_string());
+    }
+    let values = |items: &[String]| items.iter().map(|i| crate::quote_literal(i)).collect::<Vec<_>>().join(",");
+    if !columns.is_empty() { filters.push(format!("column IN ({})", values(columns))) }
+    if !genes.is_empty()   { filters.push(format!("gene IN ({})", values(genes))) }
+
+    let query = format!("
+        SELECT DISTINCT sample, mutation, nuc_start, aa_start
+        FROM mutations
+        WHERE {}
+        ORDER BY sample, nuc_start, aa_start, mutation", filters.join(" AND "));
+    let batches = ctx.sql(&query).await?.collect().await?;
+
+    let mut lists: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let samples = ctx.sql("SELECT DISTINCT sample FROM mutations").await?.collect().await?;
+    for batch in &samples {
+        let sample = crate::string_column(batch, "sample")?;
+        for i in 0..batch.num_rows() { lists.entry(sample.value(i).to_string()).or_default(); }
+    }
+    for batch in &batches {
+        let sample   = crate::string_column(batch, "sample")?;
+        let mutation = crate::string_column(batch, "mutation")?;
+        for i in 0..batch.num_rows() {
+            lists.entry(sample.value(i).to_string()).or_default().push(crate::notation::format_label(mutation.value(i), notation));
+        }
+    }
+
+    // ------------------------------------------------------------------------
+    // Write Lists
+
+    log::info!("Writing mutation lists: {output:?}");
+    let mut writer = BufWriter::new(File::create(&output)?);
+    writeln!(writer, "sample\tmutations")?;
+    for (sample, mutations) in &lists {
+        writeln!(writer, "{sample}\t{}", mutations.join(","))?;
+    }
+    writer.flush()?;
+
+    log::info!("Finished mutation list export.");
+
+    Ok(())
+}
+
+/// Map a nextclade mutation column to a Sequence Ontology feature type.
+pub fn sequence_ontology_type(column: &str) -> &'static str {
+    match column {
+        "substitutions"                => "SNV",
+        "deletions"   | "aaDeletions"  => "deletion",
+        "insertions"  | "aaInsertions" => "insertion",
+        "frameShifts"                  => "frameshift_variant",
+        "aaSubstitutions"              => "substitution",
+        _                              => "sequence_alteration",
+    }
+}
+
+/// Escape the reserved GFF3 attribute characters with URL percent-encoding.
+pub fn escape_attribute(value: &str) -> String {
+    value.chars().map(|c| match c {
+        ';'  => "%3B".to_string(),
+        '='  => "%3D".to_string(),
+        '&'  => "%26".to_string(),
+        ','  => "%2C".to_string(),
+        '\t' => "%09".to_string(),
+        '%'  => "%25".to_string(),
+        c    => c.to_string(),
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bfactor_lines() {
+        let frequencies = BTreeMap::from([(67, 0.5), (69, 1.0)]);
+        let atom = "ATOM      1  N   ALA A  67      11.104   6.134  -6.504  1.00 35.00           N";
+        assert_eq!(bfactor_line(atom, "A", &frequencies).as_deref(), Some("ATOM      1  N   ALA A  67      11.104   6.134  -6.504  1.00  0.50           N"));
+        // Residues without mutations are 0, and the B-factor column is always 6 characters wide
+        let atom = "ATOM      2  CA  HIS A  70      11.639   6.071  -5.147  1.00 35.00           C";
+        assert_eq!(bfactor_line(atom, "A", &frequencies).map(|l| l.len()), Some(atom.len()));
+        assert!(bfactor_line(atom, "A", &frequencies).is_some_and(|l| l.contains("1.00  0.00 ")));
+        // Other chains, other records, short lines, and non-ASCII lines are kept as they are
+        assert_eq!(bfactor_line(atom, "B", &frequencies), None);
+        assert_eq!(bfactor_line("REMARK   2 RESOLUTION. 2.80 ANGSTROMS.", "A", &frequencies), None);
+        assert_eq!(bfactor_line("ATOM      3  C   ALA A  67      11.104", "A", &frequencies), None);
+        assert_eq!(bfactor_line("ATOM      4  C   ALA A  67      11.104   6.134  -6.504  1.0\u{e9} 35.00           C", "A", &frequencies), None);
+    }
+
+    #[tokio::test]
+    async fn gff_from_annotated() -> Result<(), Report> {
+        let dir       = tempfile::tempdir()?;
+        let extracted = dir.path().join("mutations.tsv");
+        let annotated = dir.path().join("annotated.tsv");
+        let output    = dir.path().join("annotated.gff3");
+
+        std::fs::write(&extracted, [
+            "sample\tmutation\tcolumn\ttype\tgene\tnuc_start\tnuc_end",
+            "sample1\tC241T\tsubstitutions\tsubstitution\tORF1a\t241\t241",
+            "sample2\tC241T\tsubstitutions\tsubstitution\tORF1a\t241\t241",
+            "sample1\tS:A67V\taaSubstitutions\tsubstitution\tS\t21761\t21763",
+        ].join("\n"))?;
+        std::fs::write(&annotated, [
+            "sample\tmutation\tcolumn\tstatus",
+            "sample1\tC241T\tsubstitutions\tpresent",
+            "sample2\tC241T\tsubstitutions\tpresent",
+            "sample1\tS:A67V\taaSubstitutions\tpresent",
+            "sample2\tS:A67V\taaSubstitutions\tabsent",
+        ].join("\n"))?;
+
+        // The annotated table has no coordinates of its own
+        assert!(gff(annotated.as_path(), output.as_path(), ".", false, None).await.is_err());
+
+        // Only the present mutations are features, the absent one in sample2 is not
+        gff(annotated.as_path(), output.as_path(), ".", false, Some(extracted.as_path())).await?;
+        let features = std::fs::read_to_string(&output)?;
+        let features = features.lines().skip(1).map(|line| {
+            let fields = line.split('\t').collect::<Vec<_>>();
+            let name   = fields[8].split(';').find_map(|a| a.strip_prefix("Name=")).unwrap_or_default();
+            let sample = fields[8].split(';').find_map(|a| a.strip_prefix("sample=")).unwrap_or_default();
+            format!("{sample} {name} {}-{}", fields[3], fields[4])
+        }).collect::<Vec<_>>();
+        assert_eq!(features, [
+            "sample1 C241T 241-241",
+            "sample2 C241T 241-241",
+            "sample1 S:A67V 21761-21763",
+        ]);
+
+        // Aggregated features count the samples that have the mutation, out of all samples
+        gff(annotated.as_path(), output.as_path(), ".", true, Some(extracted.as_path())).await?;
+        let features = std::fs::read_to_string(&output)?;
+        let c241t    = features.lines().find(|l| l.contains("Name=C241T;")).unwrap_or_default();
+        assert!(c241t.contains("count=2;frequency=1"), "{c241t}");
+        let a67v     = features.lines().find(|l| l.contains("Name=S:A67V;")).unwrap_or_default();
+        assert!(a67v.contains("count=1;frequency=0.5"), "{a67v}");
+        Ok(())
+    }
+}
@@ -1,10 +1,14 @@
+use arrow::array::{Array, ArrayRef, Int64Array, StringArray, UInt32Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
 use arrow::util::pretty::pretty_format_batches;   // Pretty print arrow records
 use color_eyre::eyre::{eyre, Report, Result};     // Generic error handling with pretty logging
-use datafusion::dataframe::DataFrameWriteOptions; // Customize how to write the final dataframe.
-use datafusion::config::{CsvOptions, TableParquetOptions};  // Customize how to write output CSV or Parquet.
+use datafusion::datasource::MemTable;             // In-memory table of the collected results.
 use datafusion::prelude::*;                       // All the essential datafusion functions.
 use log;                                          // Logging, with verbosity filters
+use std::collections::HashMap;                    // Gene lengths by name
 use std::path::{Path, PathBuf};                   // System file paths
+use std::sync::Arc;                               // Shared ownership of the in-memory table
 
 // Dev constants, to be turned into function arguments
 pub const GENOME_LENGTH: u32 = 29903;
@@ -22,17 +26,195 @@ pub const AMINO_ACID_COLUMNS: &[&str] = &[
     "aaInsertions", 
 ];
 
-/// Extract mutations from nextclade tsv.
+/// Options to customize extraction.
+#[derive(Clone, Debug)]
+pub struct ExtractOptions {
+    /// Nextclade columns of nucleotide mutations.
+    pub nucleotide_columns: Vec<String>,
+    /// Nextclade columns of amino acid mutations, prefixed by the gene name (ex. `S:D614G`).
+    pub amino_acid_columns: Vec<String>,
+    /// Formats to write the mutations table in. The table is computed once, and shared by all formats.
+    pub formats: Vec<crate::OutputFormat>,
+    /// An optional file path to write an html page of extraction statistics to.
+    pub qc: Option<PathBuf>,
+    /// Add integer sample_id and mutation_id columns, and write their lookup tables (`sample_ids`, `mutation_ids`).
+    /// The IDs are derived from the names (see [`stable_id`]), so they're the same across runs and cohorts.
+    pub ids: bool,
+    /// Minimum depth for ivar variants.
+    pub min_depth: u32,
+    /// Minimum alternate allele frequency for ivar variants.
+    pub min_freq: f64,
+    /// The format of the gene annotations, detected from the file when not provided.
+    pub annotation_format: Option<crate::gene::AnnotationFormat>,
+    /// Display format of the fractions in the QC report.
+    pub number: crate::number::NumberFormat,
+    /// BED files of regions of interest. Each adds a column (`region_` and the file stem) of the regions a mutation falls in.
+    pub regions: Vec<PathBuf>,
+    /// Drop nucleotide substitutions outside of genes. Requires a reference.
+    pub coding_only: bool,
+    /// Drop synonymous and noncoding nucleotide substitutions. Requires a reference.
+    pub nonsyn_only: bool,
+    /// What to do with nucleotide mutations outside of every gene.
+    pub intergenic: Intergenic,
+    /// A directory to cache parsed gene models in, keyed by the hash of the annotations.
+    pub gene_cache: Option<PathBuf>,
+    /// Only read the vcf records in this region (ex. `MN908947.3:21563-25384`), using the vcf index.
+    pub region: Option<String>,
+    /// Filters for the quirks of a specific vcf variant caller (ex. long-read callers).
+    pub vcf_preset: Option<crate::VcfPreset>,
+    /// Whether frameshifts are kept in the mutations table, or written to a separate QC table.
+    pub frameshifts: FrameShifts,
+    /// ivar variants tables, one per sample, whose minor variants are merged into the mutations of the input.
+    pub minor_variants: Vec<PathBuf>,
+    /// Write a per-sample summary table (`samples`), with one row per sample.
+    pub sample_table: bool,
+    /// Drop nextclade samples whose genome coverage (0-1) is below this minimum.
+    pub min_coverage: Option<f64>,
+    /// Prepended to the file name of every output table (ex. `results/run1.` -> `results/run1.mutations.tsv`).
+    pub prefix: String,
+    /// A file path to write the mutations table to, in the format of its extension, instead of one file per format.
+    pub output: Option<PathBuf>,
+    /// The case and explicit renames of the columns of every output table.
+    pub column_names: crate::ColumnNames,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        ExtractOptions {
+            nucleotide_columns: NUCLEOTIDE_COLUMNS.iter().map(|c| c.to_string()).collect(),
+            amino_acid_columns: AMINO_ACID_COLUMNS.iter().map(|c| c.to_string()).collect(),
+            formats:            vec![crate::OutputFormat::Tsv, crate::OutputFormat::Parquet],
+            qc:                 None,
+            ids:                false,
+            min_depth:          10,
+            min_freq:           0.03,
+            annotation_format:  None,
+            number:             crate::number::NumberFormat::default(),
+            regions:            Vec::new(),
+            coding_only:        false,
+            nonsyn_only:        false,
+            intergenic:         Intergenic::Null,
+            gene_cache:         None,
+            region:             None,
+            vcf_preset:         None,
+            frameshifts:        FrameShifts::Mutations,
+            minor_variants:     Vec::new(),
+            sample_table:       false,
+            min_coverage:       None,
+            prefix:             String::new(),
+            output:             None,
+            column_names:       crate::ColumnNames::default(),
+        }
+    }
+}
+
+impl ExtractOptions {
+    /// The file path of an output table in a format, with the output prefix (ex. `results/run1.mutations.tsv`).
+    pub fn output_path(&self, table: &str, format: &crate::OutputFormat) -> String {
+        format!("{}{table}.{}", self.prefix, format.extension())
+    }
+}
+
+/// What to do with nucleotide mutations that fall outside of every gene.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Intergenic {
+    /// Leave the gene empty.
+    #[default]
+    Null,
+    /// Label the gene by the region (ex. `5'UTR`, `3'UTR`, `intergenic:28260-28273`).
+    Label,
+    /// Drop the mutations.
+    Drop,
+}
+
+impl std::str::FromStr for Intergenic {
+    type Err = Report;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "null"  => Ok(Intergenic::Null),
+            "label" => Ok(Intergenic::Label),
+            "drop"  => Ok(Intergenic::Drop),
+            _       => Err(eyre!("Unknown intergenic option: {policy}, please choose from: null, label, drop")),
+        }
+    }
+}
+
+/// Where to route the frameshifts, which are often sequencing artifacts (ex. in homopolymers).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FrameShifts {
+    /// Keep the frameshifts in the mutations table.
+    #[default]
+    Mutations,
+    /// Write the frameshifts to a separate `frameshifts` table, which is excluded from the heatmap.
+    Qc,
+}
+
+impl std::str::FromStr for FrameShifts {
+    type Err = Report;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "mutations" => Ok(FrameShifts::Mutations),
+            "qc"        => Ok(FrameShifts::Qc),
+            _           => Err(eyre!("Unknown frameshifts option: {policy}, please choose from: mutations, qc")),
+        }
+    }
+}
+
+/// The source of the sample mutations to extract.
+#[derive(Clone, Debug)]
+pub enum Input<P> {
+    /// Nextclade TSV, JSON (`--output-json`), or NDJSON (`--output-ndjson`) output, one or more files.
+    Nextclade(Vec<P>),
+    /// ivar variants tables, one per sample.
+    Ivar(Vec<P>),
+    /// A single or multi-sample VCF.
+    Vcf(P),
+    /// Per-sample mutation paths from an UShER tree (`matUtils extract --sample-paths`).
+    SamplePaths(P),
+    /// An Auspice v2 JSON tree, with branch mutations (ex. a Nextstrain build).
+    Auspice(P),
+    /// A multi-FASTA alignment to the reference, whose mutations are called by comparing each column.
+    Alignment(P),
+    /// A Mutation Annotation Format file (ex. TCGA), with the protein changes of each tumor sample.
+    Maf(P),
+    /// Per-gene protein alignments (ex. nextalign `--output-translations`), one file per gene.
+    Translations(Vec<P>),
+    /// A plain-text list of `sample<TAB>mutation` lines, in nextclade notation.
+    MutationList(P),
+}
+
+impl<P> Input<P> {
+    /// The name of the input, which is also the name of its table.
+    pub fn source(&self) -> &'static str {
+        match self {
+            Input::Nextclade(_)    => "nextclade",
+            Input::Ivar(_)         => "ivar",
+            Input::Vcf(_)          => "vcf",
+            Input::SamplePaths(_)  => "sample_paths",
+            Input::Auspice(_)      => "auspice",
+            Input::Alignment(_)    => "alignment",
+            Input::Maf(_)          => "maf",
+            Input::Translations(_) => "translations",
+            Input::MutationList(_) => "mutation_list",
+        }
+    }
+}
+
+/// Extract mutations from nextclade tsv or json, ivar variants, a VCF, UShER sample paths, an Auspice tree, an alignment, a MAF, protein alignments, or a mutation list.
 ///
 /// # Arguments
 /// 
-///   - `nextclade`: A file path to nextclade TSV output.
-///   - `gff`      : A file path to nextclade dataset GFF3 annotations.
+///   - `input`    : The source of the sample mutations (see [`Input`]).
+///   - `gff`      : A file path to nextclade dataset GFF3 annotations, or GTF, GFF2, or GenBank annotations.
 ///       - Example: <https://github.com/nextstrain/nextclade_data/blob/master/data/nextstrain/sars-cov-2/wuhan-hu-1/orfs/genome_annotation.gff3>
+///   - `reference`: An optional file path to the reference genome fasta, used for HGVS insertions and frameshifts, to check amino acid substitutions, and to classify substitutions as synonymous or nonsynonymous. Required for an alignment or translations input.
+///   - `options`  : Options to customize extraction, such as which nextclade columns to extract.
 ///
-pub async fn extract<P>(nextclade: P, gff: P) -> Result<(), Report>
+pub async fn extract<P>(input: Input<P>, gff: P, reference: Option<P>, options: &ExtractOptions) -> Result<(), Report>
 where
-    // The nextclade and gff arguments can be any type, as long as we can
+    // The input and gff arguments can be any type, as long as we can
     // convert it to a path, and print it out in a debug log
     P: AsRef<Path> + std::fmt::Debug,
 {
@@ -45,16 +227,15 @@ where
     // multiple tables for querying and joining
     let ctx = SessionContext::new();
 
-    // We won't hard-coded a delimiter for input files, we'll detect 
-    // based on file extension ex. .tsv -> '\t', .csv -> ','
-    let delimiter: Option<u8> = None;
+    // Extraction statistics, the row counts are only computed if a QC report was requested
+    let mut qc = crate::qc::Qc { number: options.number.clone(), ..Default::default() };
 
     // ------------------------------------------------------------------------
     // GFF Input
 
-    // Read in the GFF annotations and register the table for sql queries
-    let name = "gff";
-    let ctx  = crate::register_gff(&gff, ctx, name).await?;
+    // The gene model is parsed once, registered as a table for sql queries, and shared by the later stages
+    let gene_model = crate::gene::read_genes(&gff, options.annotation_format, options.gene_cache.as_deref())?;
+    let ctx        = crate::register_genes(&gene_model, ctx, "gff")?;
 
     // Debug Preview
     if log::log_enabled!(log::Level::Debug) {
@@ -62,78 +243,58 @@ where
         log::debug!("GFF preview:\n{}", pretty_format_batches(&batches)?.to_string());
     }
 
-    // ------------------------------------------------------------------------
-    // Nextclade Input
-
-    log::info!("Reading nextclade file: {:?}", &nextclade);
-
-    // Convert the nextclade path from a generic <P> to specifically a Path object
-    // Give the table a name for SQL queries
-    // Read the nextclade table and register for SQL queries
-    let nextclade: PathBuf = nextclade.as_ref().into();
-    let name               = "nextclade_raw";
-    let ctx                = crate::register_csv(&nextclade, ctx, delimiter, name).await?;
+    // The reference genome is shared by the alignment input and the later stages
+    let (reference_name, reference) = reference.map(|path| crate::read_fasta(&path)).transpose()?.map(|mut r| r.swap_remove(0)).unzip();
 
-    // Check that the table is not empty
-    // We don't display the table preview, because nextclade output is huge!    
-    let batches = ctx.sql("SELECT * FROM nextclade_raw LIMIT 1").await?.collect().await?;
-    if batches.len() == 0 { 
-        return Err(eyre!("No nextclade records were found in file: {:?}", nextclade))
-    }
-
-    // --------------------------------------------------------------------
-    // Column Renaming and Type Conversion (Wide Dataframe)
-
-    log::info!("Converting columns to Utf-8.");
-
-    // Extract only the columns we need, convert them all to UTF-8.
-    let select_options = vec!["seqName"]
-        .iter()
-        .chain(NUCLEOTIDE_COLUMNS)
-        .chain(AMINO_ACID_COLUMNS)
-        .map(|column| format!("arrow_cast(nextclade_raw.\"{column}\", 'Utf8') as {column}"))
-        .collect::<Vec<_>>().join(",");
-
-    ctx.sql(&format!("CREATE TABLE nextclade AS SELECT {select_options} FROM nextclade_raw")).await?.collect().await?;
-
-    // Drop the raw table?
-    ctx.sql("DROP TABLE nextclade_raw").await?;
-
-    // Again, we're not going to display a preview, because nextclade output is too wide
-
-    // --------------------------------------------------------------------
-    // Convert Wide Mutations Dataframe to Long Dataframe
+    // ------------------------------------------------------------------------
+    // Mutations Input
 
-    // Split all mutation columns by their internal separator (',').
-    // ie. Convert the wide nextclade table to a long table with 
-    // a separate row for each mutation. The UNNEST function takes an 
-    // ARRAY and returns a table with a row for each element in the ARRAY.
-    log::info!("{}", format!("Extracting nucleotide mutation columns: {NUCLEOTIDE_COLUMNS:?}"));
-    log::info!("{}", format!("Extracting amino-acid mutation columns: {AMINO_ACID_COLUMNS:?}"));
-    let aa_columns_sql = format!("( '{}' )", AMINO_ACID_COLUMNS.join("','"));
-    let query = NUCLEOTIDE_COLUMNS
-        .iter()
-        .chain(AMINO_ACID_COLUMNS)
-        .map(|column| format!("
-            SELECT 
-                seqName as sample,
-                unnest(string_to_array({column}, ',', '')) as mutation,
-                '{column}' as column,
-                CASE WHEN '{column}' IN {aa_columns_sql} THEN 'amino-acid' ELSE 'nucleotide' END as type
-            FROM nextclade"))
-        .collect::<Vec<_>>().join(" UNION ");
-    // Debug Preview
-    if log::log_enabled!(log::Level::Debug) {
-        let batches = ctx.sql(&format!("{query} LIMIT {PREVIEW_ROWS}")).await?.collect().await?;
-        log::debug!("Mutation columns preview:\n{}", pretty_format_batches(&batches)?.to_string());
-    }
+    // Read the mutations of each sample as a long table (sample, mutation, column, type),
+    // from nextclade output, ivar variants called from amplicon sequencing, a VCF, or a tree.
+    let samples = match &input {
+        Input::Nextclade(_)    => "SELECT DISTINCT \"seqName\" as sample FROM nextclade",
+        Input::Ivar(_)         => "SELECT DISTINCT sample FROM ivar",
+        Input::Vcf(_)          => "SELECT DISTINCT sample FROM vcf",
+        Input::SamplePaths(_)  => "SELECT DISTINCT sample FROM sample_paths",
+        Input::Auspice(_)      => "SELECT DISTINCT sample FROM auspice",
+        Input::Alignment(_)    => "SELECT DISTINCT sample FROM alignment",
+        Input::Maf(_)          => "SELECT DISTINCT sample FROM maf",
+        Input::Translations(_) => "SELECT DISTINCT sample FROM translations",
+        Input::MutationList(_) => "SELECT DISTINCT sample FROM mutation_list",
+    };
+    let (ctx, query, amino_acid_columns) = match &input {
+        Input::Nextclade(nextclade)       => read_nextclade(nextclade, ctx, options, reference.as_ref().map(|r| r.len()), &mut qc).await?,
+        Input::Ivar(ivar)                 => read_ivar(ivar, ctx, options).await?,
+        Input::Vcf(vcf)                   => read_vcf(vcf.as_ref(), ctx, options.region.as_deref(), options.vcf_preset).await?,
+        Input::SamplePaths(paths)         => read_sample_paths(paths.as_ref(), ctx).await?,
+        Input::Auspice(auspice)           => read_auspice(auspice.as_ref(), ctx).await?,
+        Input::Alignment(alignment)       => {
+            let reference = reference.as_ref().ok_or(eyre!("A reference is required to call mutations from an alignment."))?;
+            read_alignment(alignment.as_ref(), reference, ctx).await?
+        },
+        Input::Maf(maf)                   => read_maf(maf.as_ref(), ctx).await?,
+        Input::Translations(translations) => {
+            let reference = reference.as_ref().ok_or(eyre!("A reference is required to call mutations from translations."))?;
+            read_translations(translations, &gene_model, reference, ctx).await?
+        },
+        Input::MutationList(list)         => read_mutation_list(list.as_ref(), ctx).await?,
+    };
+    let (ctx, query, amino_acid_columns) = match options.minor_variants.is_empty() {
+        true  => (ctx, query, amino_acid_columns),
+        false => merge_minor_variants(&options.minor_variants, ctx, &query, amino_acid_columns, input.source(), options).await?,
+    };
+    // An empty list isn't valid SQL, and no column is named ''
+    let aa_columns_sql = match amino_acid_columns.is_empty() {
+        true  => "( '' )".to_string(),
+        false => format!("( {} )", amino_acid_columns.iter().map(|c| crate::quote_literal(c)).collect::<Vec<_>>().join(",")),
+    };
+    if options.qc.is_some() { qc.stage(&ctx, "mutations", "mutations", &query).await? }
 
     // --------------------------------------------------------------------
     // Gene Name
 
     // Extract gene name from amino acid mutations -> (ORF1a:T3255I -> ORF1a)
-    log::info!("Extracting gene name from amino acid mutations: {AMINO_ACID_COLUMNS:?}");
-    let aa_columns_sql = format!("( '{}' )", AMINO_ACID_COLUMNS.join("','"));
+    log::info!("Extracting gene name from amino acid mutations: {amino_acid_columns:?}");
     let query = format!("SELECT *,CASE WHEN column IN {aa_columns_sql} THEN split_part(mutation, ':', 1) ELSE NULL END as gene FROM ({query})");
     // Debug Preview
     if log::log_enabled!(log::Level::Debug) {
@@ -144,7 +305,7 @@ where
     // --------------------------------------------------------------------
     // Coordinates
 
-    // Extract coordinates from mutations ->  (ORF1a:T3255I -> 3255, 28933:T -> 28933, S:214:EPE -> 214, N:221-298 -> 221-298)
+    // Extract coordinates from mutations ->  (ORF1a:T3255I -> 3255, ORF8:Q27* -> 27, 28933:T -> 28933, S:214:EPE -> 214, N:221-298 -> 221-298)
     // Amino Acid mutations are in codon coordinates, so we'll store that as a 
     // separate column from the nucleotide coordinates for now.
     log::info!("Extracting mutation coordinates.");
@@ -154,7 +315,7 @@ where
         CASE WHEN column IN {aa_columns_sql} 
             THEN CASE WHEN column = 'aaInsertions' 
                 THEN split_part(mutation, ':', 2) 
-                ELSE REGEXP_REPLACE(split_part(mutation, ':', 2), '([A-Za-z:]+|-$)', '', 'g')
+                ELSE REGEXP_REPLACE(split_part(mutation, ':', 2), '([A-Za-z:*]+|-$)', '', 'g')
                 END
             ELSE
                 NULL            
@@ -212,6 +373,35 @@ where
         let batches = ctx.sql(&format!("{query} LIMIT {PREVIEW_ROWS}")).await?.collect().await?;
         log::debug!("Join preview:\n{}", pretty_format_batches(&batches)?.to_string());
     }
+    if options.qc.is_some() { qc.stage(&ctx, "joined to gff", "mutations", &query).await? }
+
+    // ------------------------------------------------------------------------
+    // Gene Length Check
+
+    // Amino acid positions beyond the end of their gene usually mean the GFF
+    // doesn't match the nextclade dataset, which would silently produce wrong
+    // nucleotide coordinates in the next step.
+
+    log::info!("Checking amino acid positions against gene lengths.");
+    let genes   = gene_model.iter().map(|g| (g.name.clone(), g.aa_length())).collect::<HashMap<_, _>>();
+    let check   = format!("SELECT gene, COUNT(*) as mutations, MAX(aa_end) as aa_end FROM ({query}) WHERE aa_end IS NOT NULL GROUP BY gene ORDER BY gene");
+    let batches = ctx.sql(&check).await?.collect().await?;
+    for batch in &batches {
+        let gene      = crate::string_column(batch, "gene")?;
+        let mutations = crate::string_column(batch, "mutations")?;
+        let aa_end    = crate::string_column(batch, "aa_end")?;
+        for i in 0..batch.num_rows() {
+            if gene.is_null(i) { continue }
+            let Some(codons) = genes.get(gene.value(i)) else { continue };
+            let aa_end: u32  = aa_end.value(i).parse()?;
+            if aa_end > *codons {
+                qc.warn(format!(
+                    "Gene {} has amino acid mutations up to codon {aa_end}, but is only {codons} codons long in the gff ({} mutations checked). Check that the gff matches the nextclade dataset.",
+                    gene.value(i), mutations.value(i)
+                ));
+            }
+        }
+    }
 
     // ------------------------------------------------------------------------
     // Finalize coordinates
@@ -246,6 +436,24 @@ where
         log::debug!("Finalized coordiantes preview:\n{}", pretty_format_batches(&batches)?.to_string());
     }
 
+    // Nextclade's codon coordinates are authoritative (ex. across the ribosomal slippage
+    // of ORF1ab), so they take precedence over the gff arithmetic when available.
+    let query = match ctx.table_exist("nextclade_codons")? {
+        false => query,
+        true  => {
+            log::info!("Using the nextclade codon coordinates of amino acid mutations.");
+            format!("
+            SELECT
+                M.* EXCEPT(nuc_start,nuc_end,aa_start,aa_end),
+                COALESCE(C.nuc_start, M.nuc_start) as nuc_start,
+                COALESCE(C.nuc_end, M.nuc_end) as nuc_end,
+                M.aa_start,
+                M.aa_end
+            FROM ({query}) M
+            LEFT JOIN nextclade_codons C ON M.mutation = C.mutation AND M.type = 'amino-acid'")
+        },
+    };
+
     // ------------------------------------------------------------------------
     // Create Table
 
@@ -258,24 +466,857 @@ where
         log::debug!("Final table preview:\n{}", pretty_format_batches(&batches)?.to_string());
     }
 
+    // ------------------------------------------------------------------------
+    // Indel Normalization
+
+    // Left-align indels against the reference, so the same indel reported at
+    // different anchors is recognized as identical.
+    let ctx = match &reference {
+        Some(reference) => crate::indel::normalize(ctx, "mutations", reference).await?,
+        None            => { log::info!("No reference was provided, indels will not be left-aligned."); ctx },
+    };
+
+    // ------------------------------------------------------------------------
+    // Linked Mutations
+
+    // Nextclade reports the same change as both a nucleotide and an amino acid
+    // substitution (ex. C21762T and S:A67V). Pair them by sample, gene, and codon,
+    // and give both rows the amino acid mutation as a shared linked_id.
+
+    log::info!("Linking nucleotide and amino acid substitutions.");
+    let pairs = "
+        SELECT N.sample, N.mutation as nuc_mutation, A.mutation as aa_mutation
+        FROM mutations N
+        JOIN mutations A
+        ON N.sample = A.sample AND N.gene = A.gene AND N.aa_start = A.aa_start
+        WHERE N.column = 'substitutions' AND A.column = 'aaSubstitutions'";
+    let query = format!("
+        SELECT M.*, L.linked_id
+        FROM mutations M
+        LEFT JOIN (
+            SELECT sample, nuc_mutation as mutation, 'substitutions' as column, MIN(aa_mutation) as linked_id FROM ({pairs}) GROUP BY sample, nuc_mutation
+            UNION ALL
+            SELECT DISTINCT sample, aa_mutation as mutation, 'aaSubstitutions' as column, aa_mutation as linked_id FROM ({pairs})
+        ) L
+        ON M.sample = L.sample AND M.mutation = L.mutation AND M.column = L.column
+        ORDER BY sample,nuc_start,nuc_end");
+    let ctx = crate::replace_table(ctx, "mutations", &query).await?;
+
+    // ------------------------------------------------------------------------
+    // Translation Check
+
+    // Re-derive amino acid substitutions from the nucleotide substitutions, and
+    // compare them to what nextclade reported. Disagreements usually mean the
+    // reference or gff don't match the dataset nextclade was run with.
+    if let Some(reference) = &reference {
+        let disagreements = crate::mnv::check_translations(&ctx, "mutations", reference, &gene_model).await?;
+        for disagreement in &disagreements { log::debug!("{disagreement}") }
+        if !disagreements.is_empty() {
+            qc.warn(format!(
+                "{} amino acid substitutions disagree with the translated nucleotide substitutions. Check that the reference and gff match the nextclade dataset. First disagreement: {}",
+                disagreements.len(), disagreements[0]
+            ));
+        }
+    }
+
+    // ------------------------------------------------------------------------
+    // Substitution Effects
+
+    // Classify each nucleotide substitution as synonymous, nonsynonymous, or noncoding,
+    // with its position in the codon. Other mutations are kept by the filters.
+    let ctx = match &reference {
+        Some(reference) => {
+            let ctx = crate::mnv::classify_substitutions(ctx, "mutations", reference, &gene_model).await?;
+            let filter = match (options.coding_only, options.nonsyn_only) {
+                (_, true)      => Some("effect IS NULL OR effect = 'nonsynonymous'"),
+                (true, false)  => Some("effect IS NULL OR effect != 'noncoding'"),
+                (false, false) => None,
+            };
+            match filter {
+                Some(filter) => {
+                    log::info!("Filtering nucleotide substitutions: {filter}");
+                    let query = format!("SELECT * FROM mutations WHERE column != 'substitutions' OR {filter} ORDER BY sample,nuc_start,nuc_end");
+                    let ctx   = crate::replace_table(ctx, "mutations", &query).await?;
+                    if options.qc.is_some() { qc.stage(&ctx, "effect filter", "mutations", "SELECT * FROM mutations").await? }
+                    ctx
+                },
+                None => ctx,
+            }
+        },
+        None if options.coding_only || options.nonsyn_only => return Err(eyre!("A reference is required to filter substitutions by their effect.")),
+        None => ctx,
+    };
+
+    // ------------------------------------------------------------------------
+    // HGVS Nomenclature
+
+    // Describe each mutation in HGVS coding (c.) and protein (p.) notation,
+    // which is required for clinical reporting.
+    let ctx = crate::hgvs::hgvs(ctx, "mutations", &gene_model, reference.as_deref()).await?;
+    // Debug Preview
+    if log::log_enabled!(log::Level::Debug) {
+        let batches = ctx.sql(&format!("SELECT * FROM mutations LIMIT {PREVIEW_ROWS}")).await?.collect().await?;
+        log::debug!("HGVS preview:\n{}", pretty_format_batches(&batches)?.to_string());
+    }
+
+    // ------------------------------------------------------------------------
+    // BED Regions
+
+    // Annotate each mutation with the names of the regions of interest it overlaps
+    // (ex. primer-3_LEFT), comma-separated, with one column per BED file. The columns
+    // are prefixed, so that a BED file can't replace a column (ex. gene.bed).
+    let mut ctx = ctx;
+    for (i, bed) in options.regions.iter().enumerate() {
+        let table  = format!("bed_{i}");
+        let column = region_column(bed).ok_or(eyre!("Failed to parse the column name from the BED file: {bed:?}"))?;
+        ctx = crate::register_bed(bed, ctx, &table).await?;
+        log::info!("Annotating mutations with the {column} regions.");
+
+        // The mutations are all on the reference, so regions on other chromosomes are skipped
+        let batches = ctx.sql(&format!("SELECT DISTINCT chrom FROM {table}")).await?.collect().await?;
+        let chroms  = batches.iter().map(|b| b.num_rows()).sum::<usize>();
+        let filter  = match (chroms > 1, &reference_name) {
+            (false, _)          => String::new(),
+            (true, Some(chrom)) => format!("WHERE B.chrom = {}", crate::quote_literal(chrom)),
+            (true, None)        => return Err(eyre!("BED file has regions on {chroms} chromosomes, a reference is required to choose the chromosome of the mutations: {bed:?}")),
+        };
+        let query = format!("
+            WITH overlaps AS (
+                SELECT M.mutation, M.column, array_to_string(array_sort(array_agg(DISTINCT B.name)), ',') as regions
+                FROM mutations M
+                JOIN {table} B ON M.nuc_start <= B.end AND M.nuc_end >= B.start
+                {filter}
+                GROUP BY M.mutation, M.column
+            )
+            SELECT M.*, O.regions as {}
+            FROM mutations M
+            LEFT JOIN overlaps O ON M.mutation = O.mutation AND M.column = O.column
+            ORDER BY sample,nuc_start,nuc_end", crate::quote_identifier(&column));
+        ctx = crate::replace_table(ctx, "mutations", &query).await?;
+    }
+
+    // ------------------------------------------------------------------------
+    // Intergenic Mutations
+
+    // Nucleotide mutations outside of every gene (ex. in the UTRs) have no gene, which
+    // can optionally be labelled by the region between genes, or dropped.
+    let ctx = match options.intergenic {
+        Intergenic::Null  => ctx,
+        Intergenic::Drop  => {
+            log::info!("Dropping nucleotide mutations outside of genes.");
+            let query = "SELECT * FROM mutations WHERE NOT (type = 'nucleotide' AND gene IS NULL AND mutation IS NOT NULL) ORDER BY sample,nuc_start,nuc_end";
+            let ctx   = crate::replace_table(ctx, "mutations", query).await?;
+            if options.qc.is_some() { qc.stage(&ctx, "intergenic dropped", "mutations", "SELECT * FROM mutations").await? }
+            ctx
+        },
+        Intergenic::Label => {
+            log::info!("Labelling nucleotide mutations outside of genes by region.");
+            let regions = crate::gene::intergenic_regions(&gene_model);
+            let schema  = Arc::new(Schema::new(vec![
+                Field::new("label", DataType::Utf8,   false),
+                Field::new("start", DataType::UInt32, false),
+                Field::new("end",   DataType::UInt32, false),
+            ]));
+            let records = RecordBatch::try_new(schema.clone(), vec![
+                Arc::new(StringArray::from(regions.iter().map(|r| r.0.clone()).collect::<Vec<_>>())) as ArrayRef,
+                Arc::new(UInt32Array::from(regions.iter().map(|r| r.1).collect::<Vec<_>>())),
+                Arc::new(UInt32Array::from(regions.iter().map(|r| r.2).collect::<Vec<_>>())),
+            ])?;
+            ctx.register_table("intergenic_regions", Arc::new(MemTable::try_new(schema, vec![vec![records]])?))?;
+            let query = "
+                SELECT M.* EXCEPT(gene), CASE WHEN M.type = 'nucleotide' AND M.gene IS NULL THEN R.label ELSE M.gene END as gene
+                FROM mutations M
+                LEFT JOIN intergenic_regions R ON M.nuc_start BETWEEN R.start AND R.end
+                ORDER BY sample,nuc_start,nuc_end";
+            let ctx = crate::replace_table(ctx, "mutations", query).await?;
+            ctx.deregister_table("intergenic_regions")?;
+            ctx
+        },
+    };
+
+    // ------------------------------------------------------------------------
+    // Frameshifts
+
+    // Frameshifts can be moved to their own table, so that artifacts don't clutter
+    // the heatmap, while still being available for review.
+    let ctx = match options.frameshifts {
+        FrameShifts::Mutations => ctx,
+        FrameShifts::Qc        => {
+            log::info!("Moving frameshifts to a separate QC table.");
+            let df = options.column_names.apply(ctx.sql("SELECT * FROM mutations WHERE column = 'frameShifts' ORDER BY sample,nuc_start,nuc_end").await?)?;
+            for format in &options.formats {
+                let output = options.output_path("frameshifts", format);
+                log::info!("Writing the frameshifts table: {output}");
+                crate::write_table(df.clone(), &output).await?;
+            }
+            let query = "SELECT * FROM mutations WHERE column IS DISTINCT FROM 'frameShifts' ORDER BY sample,nuc_start,nuc_end";
+            let ctx   = crate::replace_table(ctx, "mutations", query).await?;
+            if options.qc.is_some() { qc.stage(&ctx, "frameshifts removed", "mutations", "SELECT * FROM mutations").await? }
+            ctx
+        },
+    };
+
+    // ------------------------------------------------------------------------
+    // QC Report
+
+    if let Some(path) = &options.qc {
+        qc.stage(&ctx, "final table", "mutations", "SELECT * FROM mutations").await?;
+
+        let batches = ctx.sql("SELECT column, COUNT(*) as count FROM mutations GROUP BY column ORDER BY column").await?.collect().await?;
+        for batch in &batches {
+            let column = crate::string_column(batch, "column")?;
+            let count  = crate::string_column(batch, "count")?;
+            for i in 0..batch.num_rows() { qc.mutations.push((column.value(i).to_string(), count.value(i).parse()?)) }
+        }
+
+        let query   = format!("SELECT sample FROM ({samples}) WHERE sample NOT IN (SELECT DISTINCT sample FROM mutations) ORDER BY sample");
+        let batches = ctx.sql(&query).await?.collect().await?;
+        for batch in &batches {
+            let sample = crate::string_column(batch, "sample")?;
+            qc.dropped_samples.extend(sample.iter().flatten().map(String::from));
+        }
+
+        let batches = ctx.sql("SELECT DISTINCT gene FROM mutations WHERE gene IS NOT NULL ORDER BY gene").await?.collect().await?;
+        for batch in &batches {
+            let gene = crate::string_column(batch, "gene")?;
+            // Intergenic region labels aren't genes
+            let is_region = |g: &str| g == "5'UTR" || g == "3'UTR" || g.starts_with("intergenic:");
+            qc.unmatched_genes.extend(gene.iter().flatten().filter(|g| !genes.contains_key(*g) && !is_region(g)).map(String::from));
+        }
+
+        qc.write_html(path)?;
+    }
+
+    // ------------------------------------------------------------------------
+    // Integer IDs
+
+    // IDs are derived from the names alone, so a sample or mutation has the same ID in every run
+    // and cohort. Joining on the integer columns is much faster than on the mutation strings.
+    let ctx = match options.ids {
+        false => ctx,
+        true  => {
+            log::info!("Assigning integer sample and mutation IDs.");
+            let ctx = register_ids(ctx, "sample_ids",   "sample_id",   &["sample"],             "SELECT DISTINCT sample FROM mutations").await?;
+            let ctx = register_ids(ctx, "mutation_ids", "mutation_id", &["mutation", "column"], "SELECT DISTINCT mutation, column FROM mutations").await?;
+            for table in ["sample_ids", "mutation_ids"] {
+                for format in &options.formats {
+                    let output = options.output_path(table, format);
+                    log::info!("Writing the lookup table: {output}");
+                    crate::write_table(options.column_names.apply(ctx.sql(&format!("SELECT * FROM {table} ORDER BY 2, 1")).await?)?, &output).await?;
+                }
+            }
+            let query = "
+                SELECT S.sample_id, I.mutation_id, M.*
+                FROM mutations M
+                JOIN sample_ids S ON M.sample = S.sample
+                JOIN mutation_ids I ON M.mutation = I.mutation AND M.column = I.column
+                ORDER BY sample,nuc_start,nuc_end";
+            crate::replace_table(ctx, "mutations", query).await?
+        },
+    };
+
+    // ------------------------------------------------------------------------
+    // Sample Table
+
+    // One row per sample, with the number of mutations of each column, and of each
+    // BED file's regions of interest. Nextclade's clade and QC status are added when present.
+    if options.sample_table {
+        let mut counts = vec!["COUNT(DISTINCT M.mutation) as mutations".to_string()];
+        let batches = ctx.sql("SELECT DISTINCT column FROM mutations WHERE column IS NOT NULL ORDER BY column").await?.collect().await?;
+        for batch in &batches {
+            let column = crate::string_column(batch, "column")?;
+            for column in column.iter().flatten() {
+                counts.push(format!("COUNT(DISTINCT CASE WHEN M.column = {} THEN M.mutation END) as {}", crate::quote_literal(column), crate::quote_identifier(column)));
+            }
+        }
+        let columns = crate::column_names(&ctx.table("mutations").await?);
+        for bed in &options.regions {
+            let Some(column) = region_column(bed) else { continue };
+            if !columns.contains(&column) { continue }
+            counts.push(format!("COUNT(DISTINCT CASE WHEN M.{} IS NOT NULL THEN M.mutation END) as {}", crate::quote_identifier(&column), crate::quote_identifier(&format!("{column}_hits"))));
+        }
+        let query = format!("
+            SELECT S.sample, {}
+            FROM ({samples}) S
+            LEFT JOIN mutations M ON S.sample = M.sample
+            GROUP BY S.sample", counts.join(", "));
+
+        let nextclade = match ctx.table_exist("nextclade")? {
+            true  => crate::column_names(&ctx.table("nextclade").await?),
+            false => Vec::new(),
+        };
+        let extra = ["clade", "qc.overallStatus", "coverage"].into_iter().filter(|c| nextclade.iter().any(|n| n == c)).collect::<Vec<_>>();
+        let query = match extra.is_empty() {
+            true  => format!("SELECT * FROM ({query}) ORDER BY sample"),
+            false => format!("
+                SELECT C.*, {}
+                FROM ({query}) C
+                LEFT JOIN (SELECT DISTINCT \"seqName\", {} FROM nextclade) N ON C.sample = N.\"seqName\"
+                ORDER BY sample",
+                extra.iter().map(|c| format!("N.{}", crate::quote_identifier(c))).collect::<Vec<_>>().join(", "),
+                extra.iter().map(|c| crate::quote_identifier(c)).collect::<Vec<_>>().join(", ")),
+        };
+
+        let df = options.column_names.apply(ctx.sql(&query).await?)?;
+        for format in &options.formats {
+            let output = options.output_path("samples", format);
+            log::info!("Writing the sample table: {output}");
+            crate::write_table(df.clone(), &output).await?;
+        }
+    }
+
     // ------------------------------------------------------------------------
     // Write Table
 
-    log::info!("Writing the final tsv table.");
-    let df = ctx.sql("SELECT * FROM mutations").await?;
-    let write_options = DataFrameWriteOptions::default();
-    let csv_options = CsvOptions::default().with_delimiter(b'\t');
-    let output = "mutations.tsv";      
-    df.write_csv(output, write_options, Some(csv_options)).await?; 
+    // Collect the final table once, so that writing several formats doesn't
+    // re-execute the query for each one.
+    let df       = ctx.sql("SELECT * FROM mutations").await?;
+    let schema   = Arc::new(df.schema().as_arrow().clone());
+    let batches  = df.collect().await?;
+    let provider = Arc::new(MemTable::try_new(schema, vec![batches])?);
 
-    log::info!("Writing the final parquet table.");
-    let df = ctx.sql("SELECT * FROM mutations").await?;
-    let parquet_options = TableParquetOptions::default();
-    let write_options = DataFrameWriteOptions::default(); 
-    let output = "mutations.parquet";
-    df.write_parquet(output, write_options, Some(parquet_options)).await?; 
+    let outputs = match &options.output {
+        Some(output) => vec![output.to_string_lossy().to_string()],
+        None         => options.formats.iter().map(|format| options.output_path("mutations", format)).collect(),
+    };
+    for output in &outputs {
+        log::info!("Writing the final table: {output}");
+        crate::write_table(options.column_names.apply(ctx.read_table(provider.clone())?)?, &output).await?;
+    }
 
     log::info!("Finished extraction.");
 
     Ok(())
 }
+
+/// Merge the minor variants called by ivar into the mutations of another input (ex. nextclade consensus).
+///
+/// Each row records its `source`: the input name, `ivar`, or both (ex. `nextclade,ivar`).
+/// Mutations of the input have a `status` of `consensus`, while mutations only found in the
+/// minor variants of a sample with a consensus are a `conflict` (the consensus says absent,
+/// the minor variants say present). Samples that are only in the minor variants keep their ivar status.
+async fn merge_minor_variants<P>(ivar: &[P], ctx: SessionContext, query: &str, mut amino_acid_columns: Vec<String>, source: &str, options: &ExtractOptions) -> Result<(SessionContext, String, Vec<String>), Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let ctx = crate::ivar::register(ivar, ctx, "minor_variants", options.min_depth, options.min_freq).await?;
+    log::info!("Merging minor variants into the {source} mutations.");
+
+    // Other columns of the input (ex. run, genotype) are kept as is
+    let shared  = ["sample", "mutation", "column", "type", "status", "frequency", "depth"];
+    let columns = crate::column_names(&ctx.sql(query).await?);
+    let extra   = columns.iter().filter(|c| !shared.contains(&c.as_str())).map(|c| format!("C.{},", crate::quote_identifier(c))).collect::<Vec<_>>().join(" ");
+    let source  = crate::quote_literal(source);
+
+    let query = format!("
+        SELECT
+            COALESCE(C.sample, V.sample) as sample,
+            COALESCE(C.mutation, V.mutation) as mutation,
+            COALESCE(C.column, V.column) as column,
+            COALESCE(C.type, V.type) as type,
+            {extra}
+            CASE
+                WHEN C.sample IS NOT NULL AND C.mutation IS NULL THEN 'absent'
+                WHEN C.sample IS NOT NULL                        THEN 'consensus'
+                WHEN S.sample IS NOT NULL                        THEN 'conflict'
+                ELSE V.status
+                END as status,
+            V.frequency,
+            V.depth,
+            concat_ws(',', CASE WHEN C.sample IS NOT NULL THEN {source} END, CASE WHEN V.sample IS NOT NULL THEN 'ivar' END) as source
+        FROM ({query}) C
+        FULL OUTER JOIN (
+            SELECT
+                sample, mutation, column,
+                CASE WHEN column = 'aaSubstitutions' THEN 'amino-acid' ELSE 'nucleotide' END as type,
+                status, frequency, depth
+            FROM minor_variants
+            WHERE status != 'absent'
+        ) V
+        ON C.sample = V.sample AND C.mutation = V.mutation AND C.column = V.column
+        LEFT JOIN (SELECT DISTINCT sample FROM ({query})) S
+        ON V.sample = S.sample");
+
+    if !amino_acid_columns.iter().any(|c| c == "aaSubstitutions") { amino_acid_columns.push("aaSubstitutions".to_string()) }
+
+    Ok((ctx, query, amino_acid_columns))
+}
+
+/// Read nextclade output as a long table of mutations, one row per sample and mutation.
+///
+/// Multiple files (ex. one per sequencing run) are combined into one table, with a `run`
+/// column named by each file name up to the first `.` (ex. `run1.nextclade.tsv` -> `run1`).
+/// The genome coverage of each sample is kept as a `coverage` column, from the nextclade
+/// `coverage`, or from `totalMissing` and the `genome_length` of the reference.
+/// Returns the session, the query of the long table (sample, mutation, column, type),
+/// and the amino acid columns that were extracted.
+async fn read_nextclade<P>(nextclade: &[P], ctx: SessionContext, options: &ExtractOptions, genome_length: Option<usize>, qc: &mut crate::qc::Qc) -> Result<(SessionContext, String, Vec<String>), Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    // ------------------------------------------------------------------------
+    // Nextclade Input
+
+    if nextclade.is_empty() {
+        return Err(eyre!("No nextclade files were provided."))
+    }
+
+    let mut ctx   = ctx;
+    let mut files = Vec::new();
+    for (i, path) in nextclade.iter().enumerate() {
+        log::info!("Reading nextclade file: {path:?}");
+
+        // Give the table a name for SQL queries
+        // Read the nextclade table and register for SQL queries
+        let name = format!("nextclade_raw_{i}");
+        ctx = crate::nextclade::register(path, ctx, &name).await?;
+
+        // Check that the table is not empty
+        // We don't display the table preview, because nextclade output is huge!
+        let batches = ctx.sql(&format!("SELECT * FROM {name} LIMIT 1")).await?.collect().await?;
+        if batches.len() == 0 {
+            return Err(eyre!("No nextclade records were found in file: {path:?}"))
+        }
+
+        // The sample name column is required, but mutation columns are optional,
+        // because custom datasets and older nextclade versions don't have them all.
+        let columns = crate::column_names(&ctx.table(&name).await?);
+        if !columns.iter().any(|c| c == "seqName") {
+            return Err(eyre!("Nextclade file is missing the required column 'seqName': {path:?}"))
+        }
+        let file = path.as_ref().file_name().and_then(|s| s.to_str()).unwrap_or_default();
+        let run  = file.split('.').next().unwrap_or(file).to_string();
+        files.push((name, run, columns));
+    }
+
+    // Nextclade json reports the reference codon of each amino acid mutation
+    let mut codons = Vec::new();
+    for (name, _, _) in &files {
+        let table = format!("{name}_codons");
+        if ctx.table_exist(&table)? { codons.push(format!("SELECT * FROM {table}")) }
+    }
+    if !codons.is_empty() {
+        log::info!("Reading the codon coordinates of amino acid mutations from nextclade json.");
+        ctx.sql(&format!("CREATE TABLE nextclade_codons AS SELECT DISTINCT * FROM ({})", codons.join(" UNION ALL "))).await?.collect().await?;
+    }
+
+    // --------------------------------------------------------------------
+    // Schema Check
+
+    // A column is extracted if any file has it, and left empty in the files that don't
+    let has = |column: &String| files.iter().any(|(_, _, columns)| columns.contains(column));
+    for column in options.nucleotide_columns.iter().chain(&options.amino_acid_columns).filter(|c| !has(c)) {
+        qc.warn(format!("Nextclade file is missing the column '{column}', it will be skipped."));
+    }
+    let nucleotide_columns = options.nucleotide_columns.iter().filter(|c| has(c)).cloned().collect::<Vec<_>>();
+    let amino_acid_columns = options.amino_acid_columns.iter().filter(|c| has(c)).cloned().collect::<Vec<_>>();
+    if nucleotide_columns.is_empty() && amino_acid_columns.is_empty() {
+        return Err(eyre!("None of the mutation columns were found in the nextclade files: {nextclade:?}"))
+    }
+
+    // --------------------------------------------------------------------
+    // Column Renaming and Type Conversion (Wide Dataframe)
+
+    log::info!("Converting columns to Utf-8.");
+
+    // Extract only the columns we need, convert them all to UTF-8.
+    let multiple = files.len() > 1;
+    let query = files.iter().map(|(name, run, columns)| {
+        let select_options = vec!["seqName".to_string()]
+            .iter()
+            .chain(&nucleotide_columns)
+            .chain(&amino_acid_columns)
+            .map(|column| match columns.contains(column) {
+                true  => format!("arrow_cast({name}.{0}, 'Utf8') as {0}", crate::quote_identifier(column)),
+                false => format!("arrow_cast(NULL, 'Utf8') as {}", crate::quote_identifier(column)),
+            })
+            .chain(multiple.then(|| format!("{} as run", crate::quote_literal(run))))
+            .chain(std::iter::once(format!("arrow_cast({}, 'Float64') as coverage", crate::coverage::genome_coverage_expression(columns, genome_length).unwrap_or("NULL".to_string()))))
+            .collect::<Vec<_>>().join(",");
+        format!("SELECT {select_options} FROM {name}")
+    }).collect::<Vec<_>>().join(" UNION ALL ");
+
+    // --------------------------------------------------------------------
+    // Coverage Filter
+
+    // Samples without a numeric coverage (ex. failed analysis) can't pass the minimum
+    let query = match options.min_coverage {
+        None               => query,
+        Some(min_coverage) => {
+            if !files.iter().any(|(_, _, columns)| crate::coverage::genome_coverage_expression(columns, genome_length).is_some()) {
+                return Err(eyre!("A minimum coverage requires a nextclade 'coverage' column, or 'totalMissing' and a reference: {nextclade:?}"))
+            }
+            let filter  = format!("COALESCE(coverage >= {min_coverage}, false)");
+            let batches = ctx.sql(&format!("SELECT COUNT(DISTINCT \"seqName\") as dropped FROM ({query}) WHERE NOT {filter}")).await?.collect().await?;
+            let dropped = batches.first().map(|b| crate::string_column(b, "dropped")).transpose()?.map(|c| c.value(0).to_string()).unwrap_or("0".to_string());
+            log::info!("Dropping {dropped} samples below the minimum coverage of {min_coverage}.");
+            qc.warn(format!("{dropped} samples were dropped below the minimum coverage of {min_coverage}."));
+            format!("SELECT * FROM ({query}) WHERE {filter}")
+        },
+    };
+
+    ctx.sql(&format!("CREATE TABLE nextclade AS {query}")).await?.collect().await?;
+    if options.qc.is_some() { qc.stage(&ctx, "nextclade records", "records", "SELECT * FROM nextclade").await? }
+
+    // Drop the raw tables?
+    for (name, _, _) in &files {
+        ctx.sql(&format!("DROP TABLE {name}")).await?;
+        ctx.deregister_table(&format!("{name}_codons"))?;
+    }
+
+    // Again, we're not going to display a preview, because nextclade output is too wide
+
+    // --------------------------------------------------------------------
+    // Convert Wide Mutations Dataframe to Long Dataframe
+
+    // Split all mutation columns by their internal separator (',').
+    // ie. Convert the wide nextclade table to a long table with 
+    // a separate row for each mutation. The UNNEST function takes an 
+    // ARRAY and returns a table with a row for each element in the ARRAY.
+    log::info!("{}", format!("Extracting nucleotide mutation columns: {nucleotide_columns:?}"));
+    log::info!("{}", format!("Extracting amino-acid mutation columns: {amino_acid_columns:?}"));
+    let aa_columns_sql = format!("( {} )", amino_acid_columns.iter().map(|c| crate::quote_literal(c)).collect::<Vec<_>>().join(","));
+    let run   = if multiple { " run," } else { "" };
+    let query = nucleotide_columns
+        .iter()
+        .chain(&amino_acid_columns)
+        .map(|column| (crate::quote_identifier(column), crate::quote_literal(column)))
+        .map(|(identifier, literal)| format!("
+            SELECT 
+                \"seqName\" as sample,{run}
+                unnest(string_to_array({identifier}, ',', '')) as mutation,
+                {literal} as column,
+                CASE WHEN {literal} IN {aa_columns_sql} THEN 'amino-acid' ELSE 'nucleotide' END as type
+            FROM nextclade"))
+        .collect::<Vec<_>>().join(" UNION ");
+    // Debug Preview
+    if log::log_enabled!(log::Level::Debug) {
+        let batches = ctx.sql(&format!("{query} LIMIT {PREVIEW_ROWS}")).await?.collect().await?;
+        log::debug!("Mutation columns preview:\n{}", pretty_format_batches(&batches)?.to_string());
+    }
+
+    Ok((ctx, query, amino_acid_columns))
+}
+
+/// Read ivar variants as a long table of mutations, one row per sample and mutation.
+///
+/// Variants that don't pass the depth and frequency thresholds are dropped, and the
+/// rest keep their ivar status (`consensus` or `low-frequency`), frequency, and depth.
+/// Returns the same values as [`read_nextclade`].
+async fn read_ivar<P>(ivar: &[P], ctx: SessionContext, options: &ExtractOptions) -> Result<(SessionContext, String, Vec<String>), Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let ctx = crate::ivar::register(ivar, ctx, "ivar", options.min_depth, options.min_freq).await?;
+
+    log::info!("Extracting mutations from ivar variants.");
+    let query = "
+        SELECT
+            sample, mutation, column,
+            CASE WHEN column = 'aaSubstitutions' THEN 'amino-acid' ELSE 'nucleotide' END as type,
+            status, frequency, depth
+        FROM ivar
+        WHERE status != 'absent'".to_string();
+
+    Ok((ctx, query, vec!["aaSubstitutions".to_string()]))
+}
+
+/// Read UShER sample paths as a long table of nucleotide substitutions, one row per sample and mutation.
+///
+/// Returns the same values as [`read_nextclade`].
+async fn read_sample_paths(paths: &Path, ctx: SessionContext) -> Result<(SessionContext, String, Vec<String>), Report> {
+    let ctx = crate::register_sample_paths(&paths, ctx, "sample_paths").await?;
+
+    log::info!("Extracting mutations from sample paths.");
+    let query = "SELECT sample, mutation, 'substitutions' as column, 'nucleotide' as type FROM sample_paths".to_string();
+
+    Ok((ctx, query, Vec::new()))
+}
+
+/// Read the tip mutations of an Auspice JSON tree as a long table, one row per sample and mutation.
+///
+/// Returns the same values as [`read_nextclade`].
+async fn read_auspice(auspice: &Path, ctx: SessionContext) -> Result<(SessionContext, String, Vec<String>), Report> {
+    let ctx = crate::tree::register_auspice(&auspice, ctx, "auspice").await?;
+
+    log::info!("Extracting mutations from auspice tree.");
+    let query = "SELECT sample, mutation, column, type FROM auspice".to_string();
+
+    Ok((ctx, query, vec!["aaSubstitutions".to_string(), "aaDeletions".to_string()]))
+}
+
+/// The column of a BED file's regions, `region_` and the file stem without extensions (ex. `primers.v4.bed` -> `region_primers`).
+fn region_column(bed: &Path) -> Option<String> {
+    let stem = bed.file_stem().and_then(|s| s.to_str())?;
+    Some(format!("region_{}", stem.split('.').next().unwrap_or(stem)))
+}
+
+/// Call the nucleotide mutations of each sample in an alignment, as a long table, one row per sample and mutation.
+///
+/// See [`crate::register_alignment`] for how the mutations are called. Returns the same values as [`read_nextclade`].
+async fn read_alignment(alignment: &Path, reference: &[u8], ctx: SessionContext) -> Result<(SessionContext, String, Vec<String>), Report> {
+    let ctx = crate::register_alignment(&alignment, reference, ctx, "alignment").await?;
+
+    log::info!("Extracting mutations from alignment.");
+    let query = "SELECT sample, mutation, column, 'nucleotide' as type FROM alignment".to_string();
+
+    Ok((ctx, query, Vec::new()))
+}
+
+/// Call the amino acid mutations of each sample from per-gene protein alignments, as a long table, one row per sample and mutation.
+///
+/// See [`crate::register_translations`] for how the mutations are called. Returns the same values as [`read_nextclade`].
+async fn read_translations<P>(translations: &[P], genes: &[crate::gene::Gene], reference: &[u8], ctx: SessionContext) -> Result<(SessionContext, String, Vec<String>), Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let ctx = crate::register_translations(translations, genes, reference, ctx, "translations").await?;
+
+    log::info!("Extracting mutations from translations.");
+    let query = "SELECT sample, mutation, column, 'amino-acid' as type FROM translations".to_string();
+
+    Ok((ctx, query, vec!["aaSubstitutions".to_string(), "aaDeletions".to_string()]))
+}
+
+/// Read a plain-text mutation list as a long table of mutations, one row per sample and mutation.
+///
+/// The column of each mutation is recognized from its nextclade notation: nucleotide substitutions
+/// (`C913T`), deletions (`6513-6515`), and insertions (`28933:T`), and amino acid substitutions
+/// (`S:E484K`), deletions (`S:H69-`), insertions (`S:214:EPE`), and frameshifts (`ORF1a:4082-4401`).
+/// The coordinates and genes are then resolved like any other input. Returns the same values as [`read_nextclade`].
+async fn read_mutation_list(list: &Path, ctx: SessionContext) -> Result<(SessionContext, String, Vec<String>), Report> {
+    let ctx = crate::register_mutation_list(&list, ctx, "mutation_list").await?;
+
+    log::info!("Extracting mutations from the mutation list.");
+    let query = "
+        SELECT
+            sample, mutation, column,
+            CASE WHEN column LIKE 'aa%' OR column = 'frameShifts' THEN 'amino-acid' ELSE 'nucleotide' END as type
+        FROM (
+            SELECT
+                sample,
+                mutation,
+                CASE
+                    WHEN mutation ~ '^[0-9]+:'                 THEN 'insertions'
+                    WHEN mutation ~ '^[0-9]+(-[0-9]+)?$'       THEN 'deletions'
+                    WHEN mutation ~ '^[A-Za-z]+[0-9]+[A-Za-z]$' THEN 'substitutions'
+                    WHEN mutation ~ ':[0-9]+:'                 THEN 'aaInsertions'
+                    WHEN mutation ~ ':[0-9]+(-[0-9]+)?$'       THEN 'frameShifts'
+                    WHEN mutation LIKE '%-'                    THEN 'aaDeletions'
+                    ELSE 'aaSubstitutions'
+                END as column
+            FROM mutation_list
+        )".to_string();
+
+    Ok((ctx, query, AMINO_ACID_COLUMNS.iter().map(|c| c.to_string()).collect()))
+}
+
+/// Read a MAF as a long table of amino acid mutations, one row per sample and mutation.
+///
+/// The protein changes are converted to nextclade notation (see [`crate::maf_protein_change`]), prefixed
+/// by the gene (ex. `BRAF:V600E`), and sorted into columns by their kind: deletions (`E746-A750del`),
+/// insertions and duplications (`EGFR:770:SVD`), frameshifts (`K1690fs`), and all others as substitutions
+/// (including nonsense, `R213*`). The MAF `Variant_Classification` is carried through as the consequence column.
+/// Returns the same values as [`read_nextclade`].
+async fn read_maf(maf: &Path, ctx: SessionContext) -> Result<(SessionContext, String, Vec<String>), Report> {
+    let ctx = crate::register_maf(&maf, ctx, "maf").await?;
+
+    log::info!("Extracting mutations from maf variants.");
+    let query = "
+        SELECT
+            sample,
+            concat(gene, ':', hgvs_p) as mutation,
+            CASE
+                WHEN hgvs_p LIKE '%delins%' THEN 'aaSubstitutions'
+                WHEN hgvs_p LIKE '%del'     THEN 'aaDeletions'
+                WHEN hgvs_p LIKE '%:%'      THEN 'aaInsertions'
+                WHEN hgvs_p LIKE '%fs%'     THEN 'frameShifts'
+                ELSE 'aaSubstitutions'
+            END as column,
+            'amino-acid' as type,
+            consequence
+        FROM maf".to_string();
+
+    Ok((ctx, query, AMINO_ACID_COLUMNS.iter().map(|c| c.to_string()).collect()))
+}
+
+/// A stable integer ID of a name, from the first 60 bits of its SHA-256 (a non-negative 64-bit integer).
+///
+/// The ID only depends on the name, not on the other samples or mutations of the run, so
+/// tables extracted from different cohorts can be joined on it.
+pub fn stable_id(name: &str) -> i64 {
+    // 15 hex digits are 60 bits, which always fit in a non-negative i64
+    i64::from_str_radix(&crate::sha256(name.as_bytes())[..15], 16).unwrap_or_default()
+}
+
+/// Register a lookup table of stable integer IDs (see [`stable_id`]) for the distinct rows of a query.
+///
+/// The ID of a row is derived from its values, joined by tabs. An ID shared by two rows is an error,
+/// though with 60 bits it's vanishingly unlikely.
+async fn register_ids(ctx: SessionContext, name: &str, id: &str, columns: &[&str], query: &str) -> Result<SessionContext, Report> {
+    let mut ids    = Vec::new();
+    let mut values = vec![Vec::new(); columns.len()];
+    for batch in ctx.sql(query).await?.collect().await? {
+        let arrays = columns.iter().map(|c| crate::string_column(&batch, c)).collect::<Result<Vec<_>, Report>>()?;
+        for i in 0..batch.num_rows() {
+            let row = arrays.iter().map(|a| a.is_valid(i).then(|| a.value(i).to_string())).collect::<Vec<_>>();
+            ids.push(stable_id(&row.iter().map(|v| v.as_deref().unwrap_or_default()).collect::<Vec<_>>().join("\t")));
+            for (column, value) in values.iter_mut().zip(row) { column.push(value) }
+        }
+    }
+    if ids.iter().collect::<std::collections::HashSet<_>>().len() < ids.len() {
+        return Err(eyre!("Two rows of the {name} lookup table have the same {id}, please report this."))
+    }
+
+    let fields = std::iter::once(Field::new(id, DataType::Int64, false)).chain(columns.iter().map(|c| Field::new(*c, DataType::Utf8, true)));
+    let schema = Arc::new(Schema::new(fields.collect::<Vec<_>>()));
+    let arrays = std::iter::once(Arc::new(Int64Array::from(ids)) as ArrayRef).chain(values.into_iter().map(|v| Arc::new(StringArray::from(v)) as ArrayRef));
+    let records = RecordBatch::try_new(schema.clone(), arrays.collect())?;
+    ctx.register_table(name, Arc::new(MemTable::try_new(schema, vec![vec![records]])?))?;
+    Ok(ctx)
+}
+
+/// Read a VCF as a long table of nucleotide mutations, one row per sample and mutation.
+///
+/// Alleles are converted to nextclade notation, substitutions (C241T), deletions
+/// (21765-21770) and insertions (28881:GG) anchored on the preceding base. Multi-nucleotide,
+/// complex, symbolic (ex. `<DEL>`), and spanning deletion (`*`) alleles have no nextclade
+/// equivalent, and are skipped. The snpEff or VEP
+/// consequence columns (see [`crate::register_vcf`]) are carried through to the mutations table.
+/// With a `region`, only the records in the region are read from the indexed vcf or bcf.
+/// Returns the same values as [`read_nextclade`].
+async fn read_vcf(vcf: &Path, ctx: SessionContext, region: Option<&str>, preset: Option<crate::VcfPreset>) -> Result<(SessionContext, String, Vec<String>), Report> {
+    let ctx = crate::register_vcf(&vcf, ctx, "vcf", region, preset).await?;
+
+    // Alleles with a nextclade notation, all others are counted and skipped
+    let notation = "
+        alt != '*' AND NOT starts_with(alt, '<')
+        AND (
+            (length(ref) = 1 AND length(alt) = 1)
+            OR (length(alt) = 1 AND starts_with(ref, alt))
+            OR (length(ref) = 1 AND starts_with(alt, ref))
+        )";
+
+    log::info!("Extracting mutations from vcf variants.");
+    let query = format!("
+        SELECT
+            sample,
+            CASE
+                WHEN length(ref) = 1 AND length(alt) = 1 THEN concat(ref, position, alt)
+                WHEN length(alt) = 1 AND length(ref) = 2 THEN arrow_cast(position + 1, 'Utf8')
+                WHEN length(alt) = 1 THEN concat(position + 1, '-', position + length(ref) - 1)
+                ELSE concat(position, ':', substr(alt, 2))
+            END as mutation,
+            CASE
+                WHEN length(ref) = 1 AND length(alt) = 1 THEN 'substitutions'
+                WHEN length(alt) = 1 THEN 'deletions'
+                ELSE 'insertions'
+            END as column,
+            'nucleotide' as type,
+            genotype,
+            consequence,
+            consequence_gene,
+            consequence_hgvs_p
+        FROM vcf
+        WHERE {notation}");
+
+    let skipped = ctx.sql(&format!("SELECT COUNT(*) as count FROM vcf WHERE NOT ({notation})")).await?.collect().await?;
+    if let Some(batch) = skipped.first() {
+        let n = crate::string_column(batch, "count")?.value(0).to_string();
+        if n != "0" { log::warn!("Skipping {n} multi-nucleotide, complex, symbolic, or spanning deletion vcf alleles, which have no nextclade notation.") }
+    }
+
+    Ok((ctx, query, Vec::new()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stable_ids() {
+        assert_eq!(stable_id("sample1"), stable_id("sample1"));
+        assert_ne!(stable_id("C241T\tsubstitutions"), stable_id("C241T\tprivateSubstitutions"));
+        // The first 15 hex digits of the SHA-256 of "abc"
+        assert_eq!(stable_id("abc"), 0xba7816bf8f01cfe);
+        assert!(stable_id("") >= 0);
+    }
+
+    #[test]
+    fn region_columns() {
+        assert_eq!(region_column(Path::new("gene.bed")).as_deref(), Some("region_gene"));
+        assert_eq!(region_column(Path::new("schemes/primers.v4.bed")).as_deref(), Some("region_primers"));
+    }
+
+    #[tokio::test]
+    async fn read_vcf_skips_symbolic_and_spanning_alleles() -> Result<(), Report> {
+        let dir  = tempfile::tempdir()?;
+        let path = dir.path().join("alleles.vcf");
+        let vcf  = [
+            "##fileformat=VCFv4.2",
+            "##contig=<ID=MN908947.3,length=29903>",
+            "##ALT=<ID=DEL,Description=\"Deletion\">",
+            "##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">",
+            "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\ts1",
+            "MN908947.3\t241\t.\tC\tT,*\t.\tPASS\t.\tGT\t1/2",
+            "MN908947.3\t11287\t.\tG\t<DEL>\t.\tPASS\t.\tGT\t1/1",
+            "MN908947.3\t21764\t.\tATACATG\tA\t.\tPASS\t.\tGT\t1/1",
+            "MN908947.3\t28280\t.\tGA\tCT\t.\tPASS\t.\tGT\t1/1",
+        ];
+        std::fs::write(&path, vcf.join("\n") + "\n")?;
+
+        let (ctx, query, _) = read_vcf(&path, SessionContext::new(), None, None).await?;
+        let batches   = ctx.sql(&format!("SELECT mutation FROM ({query}) ORDER BY mutation")).await?.collect().await?;
+        let mutations = batches.iter().flat_map(|b| crate::string_column(b, "mutation").unwrap().iter().map(|m| m.unwrap_or_default().to_string()).collect::<Vec<_>>()).collect::<Vec<_>>();
+        assert_eq!(mutations, ["21765-21770", "C241T"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_maf_protein_changes() -> Result<(), Report> {
+        let dir  = tempfile::tempdir()?;
+        let path = dir.path().join("variants.maf");
+        let maf  = [
+            "#version 2.4",
+            "Hugo_Symbol\tTumor_Sample_Barcode\tVariant_Classification\tHGVSp_Short",
+            "S\ts1\tMissense_Mutation\tp.D614G",
+            "S\ts1\tNonsense_Mutation\tp.R213*",
+            "S\ts1\tSilent\tp.L858=",
+            "S\ts1\tIn_Frame_Ins\tp.D770_N771insSVD",
+            "S\ts1\tIn_Frame_Ins\tp.A767_V769dup",
+            "S\ts1\tIn_Frame_Del\tp.E746_A750del",
+            "S\ts1\tFrame_Shift_Del\tp.K1190Nfs*7",
+            "S\ts1\tSplice_Site\tp.X125_splice",
+            "S\ts1\tTranslation_Start_Site\tp.M1?",
+            "S\ts1\tIntron\t",
+        ];
+        std::fs::write(&path, maf.join("\n") + "\n")?;
+
+        let options = ExtractOptions { formats: vec![crate::OutputFormat::Tsv], prefix: format!("{}/", dir.path().display()), ..Default::default() };
+        extract(Input::Maf(path.as_path()), Path::new(mutation_core::TEST_GFF), None, &options).await?;
+
+        let ctx     = SessionContext::new();
+        let tsv     = dir.path().join("mutations.tsv");
+        ctx.register_csv("mutations", tsv.to_str().unwrap(), CsvReadOptions::new().file_extension("tsv").delimiter(b'\t')).await?;
+        let batches = ctx.sql("SELECT mutation, column, aa_start, aa_end FROM mutations ORDER BY aa_start, mutation").await?.collect().await?;
+        let mut rows = Vec::new();
+        for batch in &batches {
+            let column = |name: &str| crate::string_column(batch, name);
+            let (mutation, kind, start, end) = (column("mutation")?, column("column")?, column("aa_start")?, column("aa_end")?);
+            for i in 0..batch.num_rows() {
+                rows.push(format!("{} {} {}-{}", mutation.value(i), kind.value(i), start.value(i), end.value(i)));
+            }
+        }
+        // The splice site, start loss, and intronic variant have no residue coordinates
+        assert_eq!(rows, [
+            "S:R213* aaSubstitutions 213-213",
+            "S:D614G aaSubstitutions 614-614",
+            "S:E746-A750del aaDeletions 746-750",
+            "S:769:AXV aaInsertions 769-769",
+            "S:770:SVD aaInsertions 770-770",
+            "S:L858L aaSubstitutions 858-858",
+            "S:K1190fs frameShifts 1190-1190",
+        ]);
+        Ok(())
+    }
+}
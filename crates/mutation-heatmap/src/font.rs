@@ -0,0 +1,331 @@
+use color_eyre::eyre::{eyre, Report, Result};
+use std::collections::BTreeSet;
+
+/// Tables dropped from a subset font.
+///
+/// Substitutions (ex. the `fi` ligature) replace the glyphs of the text with glyphs that
+/// aren't in the subset, so they would be drawn empty.
+const DROPPED_TABLES: &[&[u8; 4]] = &[b"GSUB", b"DSIG"];
+
+// Composite glyph flags
+const ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+const WE_HAVE_A_SCALE:       u16 = 0x0008;
+const MORE_COMPONENTS:       u16 = 0x0020;
+const WE_HAVE_AN_X_AND_Y:    u16 = 0x0040;
+const WE_HAVE_A_TWO_BY_TWO:  u16 = 0x0080;
+
+/// A TrueType font, as its raw data and table directory.
+struct Font<'a> {
+    data:   &'a [u8],
+    tables: Vec<([u8; 4], usize, usize)>,
+}
+
+impl<'a> Font<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, Report> {
+        let num_tables = read_u16(data, 4)? as usize;
+        let tables = (0..num_tables).map(|i| {
+            let record = 12 + i * 16;
+            let tag    = data.get(record..record + 4).ok_or_else(|| eyre!("Font table directory is truncated."))?;
+            let offset = read_u32(data, record + 8)? as usize;
+            let length = read_u32(data, record + 12)? as usize;
+            Ok((tag.try_into()?, offset, length))
+        }).collect::<Result<Vec<_>, Report>>()?;
+        Ok(Font { data, tables })
+    }
+
+    fn table(&self, tag: &[u8; 4]) -> Result<&'a [u8], Report> {
+        let (_, offset, length) = self.tables.iter().find(|(t, _, _)| t == tag)
+            .ok_or_else(|| eyre!("Font has no {} table.", String::from_utf8_lossy(tag)))?;
+        self.data.get(*offset..*offset + *length).ok_or_else(|| eyre!("Font {} table is truncated.", String::from_utf8_lossy(tag)))
+    }
+
+    /// The glyph offsets into the glyf table, one more than the number of glyphs.
+    fn locations(&self) -> Result<Vec<usize>, Report> {
+        let num_glyphs = read_u16(self.table(b"maxp")?, 4)? as usize;
+        let loca       = self.table(b"loca")?;
+        match read_u16(self.table(b"head")?, 50)? {
+            0 => (0..=num_glyphs).map(|g| Ok(read_u16(loca, g * 2)? as usize * 2)).collect(),
+            _ => (0..=num_glyphs).map(|g| Ok(read_u32(loca, g * 4)? as usize)).collect(),
+        }
+    }
+}
+
+/// The glyph of a character, from the unicode subtables of a cmap.
+///
+/// Both the BMP (format 4) and full unicode (format 12) subtables are searched.
+pub fn glyph_id(cmap: &[u8], c: char) -> Result<Option<u16>, Report> {
+    let num_tables = read_u16(cmap, 2)? as usize;
+    for i in 0..num_tables {
+        let record   = 4 + i * 8;
+        let platform = read_u16(cmap, record)?;
+        let encoding = read_u16(cmap, record + 2)?;
+        if !matches!((platform, encoding), (0, _) | (3, 1) | (3, 10)) { continue }
+        let subtable = read_u32(cmap, record + 4)? as usize;
+        let glyph    = match read_u16(cmap, subtable)? {
+            4  => format_4_glyph(cmap, subtable, c as u32)?,
+            12 => format_12_glyph(cmap, subtable, c as u32)?,
+            _  => None,
+        };
+        if glyph.is_some() { return Ok(glyph) }
+    }
+    Ok(None)
+}
+
+fn format_4_glyph(cmap: &[u8], subtable: usize, c: u32) -> Result<Option<u16>, Report> {
+    if c > 0xFFFF { return Ok(None) }
+    let seg_count     = read_u16(cmap, subtable + 6)? as usize / 2;
+    let end_codes     = subtable + 14;
+    let start_codes   = end_codes + seg_count * 2 + 2;
+    let id_deltas     = start_codes + seg_count * 2;
+    let range_offsets = id_deltas + seg_count * 2;
+    for segment in 0..seg_count {
+        if c > read_u16(cmap, end_codes + segment * 2)? as u32 { continue }
+        let start = read_u16(cmap, start_codes + segment * 2)? as u32;
+        if c < start { return Ok(None) }
+        let delta        = read_u16(cmap, id_deltas + segment * 2)?;
+        let range_offset = range_offsets + segment * 2;
+        let glyph = match read_u16(cmap, range_offset)? as usize {
+            0      => (c as u16).wrapping_add(delta),
+            offset => match read_u16(cmap, range_offset + offset + (c - start) as usize * 2)? {
+                0     => 0,
+                glyph => glyph.wrapping_add(delta),
+            },
+        };
+        return Ok((glyph != 0).then_some(glyph))
+    }
+    Ok(None)
+}
+
+fn format_12_glyph(cmap: &[u8], subtable: usize, c: u32) -> Result<Option<u16>, Report> {
+    let num_groups = read_u32(cmap, subtable + 12)? as usize;
+    for group in 0..num_groups {
+        let record = subtable + 16 + group * 12;
+        let start  = read_u32(cmap, record)?;
+        let end    = read_u32(cmap, record + 4)?;
+        if (start..=end).contains(&c) {
+            let glyph = read_u32(cmap, record + 8)? + (c - start);
+            return Ok((glyph != 0).then_some(glyph as u16))
+        }
+    }
+    Ok(None)
+}
+
+/// The glyphs that a composite glyph is built from.
+fn components(glyph: &[u8]) -> Result<Vec<u16>, Report> {
+    let mut components = Vec::new();
+    // Simple glyphs have a positive number of contours, and empty glyphs have no data
+    if glyph.is_empty() || (read_u16(glyph, 0)? as i16) >= 0 { return Ok(components) }
+    let mut offset = 10;
+    loop {
+        let flags = read_u16(glyph, offset)?;
+        components.push(read_u16(glyph, offset + 2)?);
+        offset += 4;
+        offset += if flags & ARG_1_AND_2_ARE_WORDS != 0 { 4 } else { 2 };
+        offset += match flags {
+            f if f & WE_HAVE_A_SCALE      != 0 => 2,
+            f if f & WE_HAVE_AN_X_AND_Y   != 0 => 4,
+            f if f & WE_HAVE_A_TWO_BY_TWO != 0 => 8,
+            _                                  => 0,
+        };
+        if flags & MORE_COMPONENTS == 0 { break }
+    }
+    Ok(components)
+}
+
+/// Subset a TrueType font to the glyphs of some characters.
+///
+/// Glyphs keep their ids, so the metrics, kerning and hinting tables are unchanged, and
+/// the outlines of the unused glyphs are emptied instead. The cmap is rebuilt with only
+/// the characters of the subset. The notdef glyph, and the components of composite
+/// glyphs (ex. accented letters), are always kept.
+///
+/// # Arguments
+///
+///   - `font` : The raw data of a TrueType (glyf) font.
+///   - `chars`: The characters to keep.
+///
+pub fn subset(font: &[u8], chars: &BTreeSet<char>) -> Result<Vec<u8>, Report> {
+    let parsed    = Font::parse(font)?;
+    let glyf      = parsed.table(b"glyf")?;
+    let locations = parsed.locations()?;
+    let glyph     = |g: u16| glyf.get(locations[g as usize]..locations[g as usize + 1]).ok_or_else(|| eyre!("Font glyph {g} is outside the glyf table."));
+
+    // ------------------------------------------------------------------------
+    // Glyphs
+
+    let cmap    = parsed.table(b"cmap")?;
+    let mut map = Vec::new();
+    for c in chars {
+        if let Some(g) = glyph_id(cmap, *c)? {
+            if (g as usize) < locations.len() - 1 { map.push((*c, g)) }
+        }
+    }
+
+    let mut keep  = BTreeSet::from([0]);
+    let mut stack = map.iter().map(|(_, g)| *g).collect::<Vec<_>>();
+    while let Some(g) = stack.pop() {
+        if (g as usize) >= locations.len() - 1 || !keep.insert(g) { continue }
+        stack.extend(components(glyph(g)?)?);
+    }
+
+    // ------------------------------------------------------------------------
+    // Glyph Outlines
+
+    // Glyphs are padded to 4 bytes, so that the short loca format (offset / 2) is exact
+    let mut new_glyf      = Vec::new();
+    let mut new_locations = vec![0];
+    for g in 0..locations.len() - 1 {
+        if keep.contains(&(g as u16)) {
+            new_glyf.extend_from_slice(glyph(g as u16)?);
+            new_glyf.resize(new_glyf.len().next_multiple_of(4), 0);
+        }
+        new_locations.push(new_glyf.len());
+    }
+    let short = new_glyf.len() / 2 <= u16::MAX as usize;
+    let new_loca = match short {
+        true  => new_locations.iter().flat_map(|l| ((*l / 2) as u16).to_be_bytes()).collect::<Vec<_>>(),
+        false => new_locations.iter().flat_map(|l| (*l as u32).to_be_bytes()).collect::<Vec<_>>(),
+    };
+
+    // ------------------------------------------------------------------------
+    // Tables
+
+    let mut tables = Vec::new();
+    for (tag, _, _) in &parsed.tables {
+        if DROPPED_TABLES.contains(&tag) { continue }
+        let data = match tag {
+            b"glyf" => new_glyf.clone(),
+            b"loca" => new_loca.clone(),
+            b"cmap" => cmap_table(&map),
+            b"head" => {
+                let mut head = parsed.table(tag)?.to_vec();
+                let format: u16 = if short { 0 } else { 1 };
+                head.get_mut(8..12).ok_or_else(|| eyre!("Font head table is truncated."))?.copy_from_slice(&[0; 4]);
+                head.get_mut(50..52).ok_or_else(|| eyre!("Font head table is truncated."))?.copy_from_slice(&format.to_be_bytes());
+                head
+            },
+            _       => parsed.table(tag)?.to_vec(),
+        };
+        tables.push((*tag, data));
+    }
+
+    Ok(write_font(read_u32(font, 0)?, &tables))
+}
+
+/// A cmap with a single unicode BMP (format 4) subtable, with one segment per character.
+fn cmap_table(map: &[(char, u16)]) -> Vec<u8> {
+    let mut segments = map.iter().filter(|(c, _)| (*c as u32) < 0xFFFF).map(|(c, g)| (*c as u16, *g)).collect::<Vec<_>>();
+    // The last segment must end at 0xFFFF
+    segments.push((0xFFFF, 0));
+
+    let seg_count      = segments.len() as u16;
+    let entry_selector = 15 - seg_count.leading_zeros() as u16;
+    let search_range   = 2 * (1 << entry_selector);
+    let length         = 16 + segments.len() * 8;
+
+    let mut subtable = Vec::with_capacity(length);
+    for value in [4, length as u16, 0, seg_count * 2, search_range, entry_selector, seg_count * 2 - search_range] {
+        subtable.extend(value.to_be_bytes());
+    }
+    subtable.extend(segments.iter().flat_map(|(c, _)| c.to_be_bytes()));
+    subtable.extend([0, 0]);
+    subtable.extend(segments.iter().flat_map(|(c, _)| c.to_be_bytes()));
+    // The final segment maps 0xFFFF to the notdef glyph (0xFFFF + 1)
+    subtable.extend(segments.iter().flat_map(|(c, g)| match *c {
+        0xFFFF => 1u16.to_be_bytes(),
+        c      => g.wrapping_sub(c).to_be_bytes(),
+    }));
+    subtable.extend(segments.iter().flat_map(|_| 0u16.to_be_bytes()));
+
+    let mut cmap = Vec::with_capacity(12 + subtable.len());
+    for value in [0u16, 1, 3, 1] { cmap.extend(value.to_be_bytes()) }
+    cmap.extend(12u32.to_be_bytes());
+    cmap.extend(subtable);
+    cmap
+}
+
+/// Write the font file of some tables, with the table directory and checksums.
+fn write_font(version: u32, tables: &[([u8; 4], Vec<u8>)]) -> Vec<u8> {
+    let num_tables     = tables.len() as u16;
+    let entry_selector = 15 - num_tables.leading_zeros() as u16;
+    let search_range   = 16 * (1 << entry_selector);
+
+    let mut font = Vec::new();
+    font.extend(version.to_be_bytes());
+    for value in [num_tables, search_range, entry_selector, num_tables * 16 - search_range] {
+        font.extend(value.to_be_bytes());
+    }
+
+    let mut offset = 12 + tables.len() * 16;
+    for (tag, data) in tables {
+        font.extend(tag);
+        font.extend(checksum(data).to_be_bytes());
+        font.extend((offset as u32).to_be_bytes());
+        font.extend((data.len() as u32).to_be_bytes());
+        offset += data.len().next_multiple_of(4);
+    }
+    let mut head = None;
+    for (tag, data) in tables {
+        if tag == b"head" { head = Some(font.len()) }
+        font.extend(data);
+        font.resize(font.len().next_multiple_of(4), 0);
+    }
+
+    // The head checksum adjustment makes the checksum of the whole font 0xB1B0AFBA
+    if let Some(head) = head {
+        let adjustment = 0xB1B0AFBAu32.wrapping_sub(checksum(&font));
+        font[head + 8..head + 12].copy_from_slice(&adjustment.to_be_bytes());
+    }
+    font
+}
+
+/// The sum of the big-endian u32 words of a table, padded with zeros.
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, Report> {
+    let bytes = data.get(offset..offset + 2).ok_or_else(|| eyre!("Font data is truncated at byte {offset}."))?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, Report> {
+    let bytes = data.get(offset..offset + 4).ok_or_else(|| eyre!("Font data is truncated at byte {offset}."))?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plot::FONT;
+
+    #[test]
+    fn subset_keeps_used_glyphs() {
+        let chars  = "S1:A123T".chars().collect::<BTreeSet<_>>();
+        let subset = subset(FONT, &chars).unwrap();
+        assert!(subset.len() < FONT.len() / 2);
+
+        let font       = Font::parse(&subset).unwrap();
+        let original   = Font::parse(FONT).unwrap();
+        let cmap       = font.table(b"cmap").unwrap();
+        let locations  = font.locations().unwrap();
+        let outline    = |c: char| {
+            let g = glyph_id(original.table(b"cmap").unwrap(), c).unwrap().unwrap() as usize;
+            locations[g + 1] - locations[g]
+        };
+        for c in &chars {
+            assert_eq!(glyph_id(cmap, *c).unwrap(), glyph_id(original.table(b"cmap").unwrap(), *c).unwrap());
+            assert!(outline(*c) > 0);
+        }
+        assert_eq!(glyph_id(cmap, 'Z').unwrap(), None);
+        assert_eq!(outline('Z'), 0);
+        assert!(font.table(b"GSUB").is_err());
+
+        // The whole font sums to the magic checksum
+        assert_eq!(checksum(&subset), 0xB1B0AFBA);
+    }
+}
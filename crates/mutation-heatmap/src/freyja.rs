@@ -0,0 +1,206 @@
+use arrow::array::{Float64Array, StringArray};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use color_eyre::eyre::{eyre, Report, Result};
+use datafusion::prelude::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::BufRead;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The abundance of a lineage-defining mutation in a (wastewater) sample.
+#[derive(Clone, Debug)]
+pub struct MutationAbundance {
+    pub sample: String,
+    pub mutation: String,
+    /// Sum of the abundances of the sample lineages whose barcode has the mutation.
+    pub abundance: f64,
+}
+
+/// Read the lineage abundances of each sample from Freyja `demix` output.
+///
+/// Both a single `demix` file (key-value rows, named by the file stem) and the output of
+/// `freyja aggregate` (one row per sample, with `lineages` and `abundances` columns) are supported.
+pub fn read_demix<P>(path: P) -> Result<Vec<(String, Vec<(String, f64)>)>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Reading freyja demix: {path:?}");
+
+    let lines = crate::open(&path)?.lines().collect::<Result<Vec<_>, _>>()?;
+    let parse = |lineages: &str, abundances: &str| -> Result<Vec<(String, f64)>, Report> {
+        let lineages   = lineages.split_whitespace().map(String::from);
+        let abundances = abundances.split_whitespace().map(|a| a.parse::<f64>()).collect::<Result<Vec<_>, _>>()?;
+        Ok(lineages.zip(abundances).collect())
+    };
+
+    let header = lines.first().map(|l| l.split('\t').collect::<Vec<_>>()).unwrap_or_default();
+    let column = |name: &str| header.iter().position(|c| *c == name);
+
+    let mut samples = Vec::new();
+    match (column("lineages"), column("abundances")) {
+        // freyja aggregate
+        (Some(lineages), Some(abundances)) => {
+            for line in lines.iter().skip(1).filter(|l| !l.trim().is_empty()) {
+                let fields = line.split('\t').collect::<Vec<_>>();
+                let (Some(l), Some(a)) = (fields.get(lineages), fields.get(abundances)) else {
+                    return Err(eyre!("Freyja aggregate line is missing the lineages or abundances: {line}"))
+                };
+                let sample = fields[0].split('.').next().unwrap_or(fields[0]).to_string();
+                samples.push((sample, parse(l, a)?));
+            }
+        },
+        // freyja demix
+        _ => {
+            let values = lines.iter().filter_map(|l| l.split_once('\t')).collect::<HashMap<_, _>>();
+            let (Some(l), Some(a)) = (values.get("lineages"), values.get("abundances")) else {
+                return Err(eyre!("Freyja demix file has no lineages or abundances: {path:?}"))
+            };
+            let file   = path.as_ref().file_name().and_then(|s| s.to_str()).unwrap_or_default();
+            let sample = file.split('.').next().unwrap_or(file).to_string();
+            samples.push((sample, parse(l, a)?));
+        },
+    }
+
+    Ok(samples)
+}
+
+/// Read the lineage-defining mutations of the given lineages from a Freyja barcodes csv (ex. `usher_barcodes.csv`).
+///
+/// The barcodes have one row per lineage and one column per mutation (ex. `A1234G`), where 1
+/// means the lineage has the mutation. Only the requested lineages are kept, since the barcodes
+/// describe thousands of lineages.
+pub fn read_barcodes<P>(path: P, lineages: &BTreeSet<String>) -> Result<HashMap<String, Vec<String>>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Reading freyja barcodes: {path:?}");
+
+    let mut lines  = crate::open(&path)?.lines();
+    let header     = lines.next().transpose()?.ok_or(eyre!("Freyja barcodes file is empty: {path:?}"))?;
+    let mutations  = header.split(',').skip(1).map(|m| m.trim_matches('"').to_string()).collect::<Vec<_>>();
+
+    let mut barcodes = HashMap::new();
+    for line in lines {
+        let line = line?;
+        let mut fields = line.split(',');
+        let Some(lineage) = fields.next().map(|l| l.trim_matches('"')) else { continue };
+        if !lineages.contains(lineage) { continue }
+        let barcode = fields.zip(&mutations).filter(|(v, _)| v.parse::<f64>().map(|v| v > 0.0).unwrap_or(false)).map(|(_, m)| m.clone()).collect();
+        barcodes.insert(lineage.to_string(), barcode);
+    }
+
+    for lineage in lineages.iter().filter(|l| !barcodes.contains_key(*l)) {
+        log::warn!("Lineage {lineage} was not found in the barcodes.");
+    }
+    Ok(barcodes)
+}
+
+/// Compute the abundance of lineage-defining mutations in wastewater samples, from Freyja output.
+///
+/// The abundance of a mutation in a sample is the sum of the abundances of the sample's
+/// lineages whose barcode has the mutation.
+///
+/// # Arguments
+///
+///   - `demix`   : File paths to Freyja `demix` (or `aggregate`) output.
+///   - `barcodes`: A file path to the Freyja barcodes csv that the samples were demixed with.
+///   - `output`  : A file path to write the long table of sample, mutation, and abundance to.
+///   - `prefix`  : An optional output prefix to render a mutation by sample heatmap, shaded by abundance.
+///
+pub async fn freyja<P>(demix: &[P], barcodes: P, output: P, prefix: Option<&str>) -> Result<Vec<MutationAbundance>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Beginning freyja abundances.");
+
+    let samples  = demix.iter().map(read_demix).collect::<Result<Vec<_>, Report>>()?.into_iter().flatten().collect::<Vec<_>>();
+    let lineages = samples.iter().flat_map(|(_, l)| l.iter().map(|(lineage, _)| lineage.clone())).collect::<BTreeSet<_>>();
+    let barcodes = read_barcodes(&barcodes, &lineages)?;
+
+    // ------------------------------------------------------------------------
+    // Abundances
+
+    let mut abundances: BTreeMap<(String, String), f64> = BTreeMap::new();
+    for (sample, lineages) in &samples {
+        for (lineage, abundance) in lineages {
+            for mutation in barcodes.get(lineage).into_iter().flatten() {
+                *abundances.entry((sample.clone(), mutation.clone())).or_default() += abundance;
+            }
+        }
+    }
+
+    // Mutations in genome order (ex. A1234G -> 1234)
+    let position = |mutation: &str| mutation.chars().filter(|c| c.is_ascii_digit()).collect::<String>().parse::<u32>().unwrap_or(0);
+    let mut mutations = abundances.keys().map(|(_, m)| m.clone()).collect::<BTreeSet<_>>().into_iter().collect::<Vec<_>>();
+    mutations.sort_by_key(|m| position(m));
+
+    let mut results = Vec::new();
+    for (sample, _) in &samples {
+        for mutation in &mutations {
+            let Some(abundance) = abundances.get(&(sample.clone(), mutation.clone())) else { continue };
+            results.push(MutationAbundance { sample: sample.clone(), mutation: mutation.clone(), abundance: *abundance });
+        }
+    }
+    log::info!("Found {} lineage-defining mutations in {} samples.", mutations.len(), samples.len());
+
+    // ------------------------------------------------------------------------
+    // Write Table
+
+    log::info!("Writing mutation abundances: {output:?}");
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("sample",    DataType::Utf8,    false),
+        Field::new("mutation",  DataType::Utf8,    false),
+        Field::new("abundance", DataType::Float64, false),
+    ]));
+    let records = RecordBatch::try_new(
+        schema,
+        vec![
+            Arc::new(StringArray::from(results.iter().map(|r| r.sample.clone()).collect::<Vec<_>>())),
+            Arc::new(StringArray::from(results.iter().map(|r| r.mutation.clone()).collect::<Vec<_>>())),
+            Arc::new(Float64Array::from(results.iter().map(|r| r.abundance).collect::<Vec<_>>())),
+        ],
+    )?;
+    crate::write_table(SessionContext::new().read_batch(records)?, &output).await?;
+
+    // ------------------------------------------------------------------------
+    // Heatmap
+
+    #[cfg(feature = "plot")]
+    if let Some(prefix) = prefix {
+        log::info!("Rendering abundance heatmap: {prefix}");
+        let names   = samples.iter().map(|(sample, _)| sample.clone()).collect::<Vec<_>>();
+        let options = crate::plot::PlotOptions::default();
+        let value   = |sample: &str, mutation: &str| abundances.get(&(sample.to_string(), mutation.to_string())).copied().unwrap_or(0.0);
+        let fill    = |sample: &str, mutation: &str| crate::plot::scale_color(value(sample, mutation));
+        let detail  = |sample: &str, mutation: &str| format!("Abundance: {}", options.number.format(value(sample, mutation)));
+        crate::plot::heatmap(&names, &mutations, &fill, Some(&detail), prefix, &options, &crate::plot::Tracks::default())?;
+    }
+    #[cfg(not(feature = "plot"))]
+    crate::warn_without_plot(prefix, "abundance heatmap");
+
+    log::info!("Finished freyja abundances.");
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn abundances_of_barcode_mutations() -> Result<(), Report> {
+        let dir  = tempfile::tempdir()?;
+        let path = |name: &str| dir.path().join(name);
+        std::fs::write(path("ww1.demix.tsv"), "\tww1.bam\nlineages\tBA.1 BA.2\nabundances\t0.75 0.25\nresid\t1.0\n")?;
+        std::fs::write(path("barcodes.csv"), ",A1234G,C241T,G300A\nBA.1,1,1,0\nBA.2,0,1,1\nXBB,1,1,1\n")?;
+
+        let results = freyja(&[path("ww1.demix.tsv")], path("barcodes.csv"), path("abundances.tsv"), None).await?;
+        let results = results.iter().map(|r| (r.sample.as_str(), r.mutation.as_str(), r.abundance)).collect::<Vec<_>>();
+        // Mutations are in genome order, and shared mutations sum the abundances of their lineages
+        assert_eq!(results, [("ww1", "C241T", 1.0), ("ww1", "G300A", 0.25), ("ww1", "A1234G", 0.75)]);
+        assert!(path("abundances.tsv").exists());
+        Ok(())
+    }
+}
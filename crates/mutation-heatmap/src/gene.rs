@@ -0,0 +1,685 @@
+use arrow::array::{ArrayRef, StringArray, UInt32Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use color_eyre::eyre::{eyre, Report, Result};
+use datafusion::parquet::arrow::ArrowWriter;
+use datafusion::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use noodles::gff;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// A gene, as parsed from the gene annotations.
+#[derive(Clone, Debug, Default)]
+pub struct Gene {
+    pub name: String,
+    /// '+', '-', or '.' when unknown.
+    pub strand: String,
+    /// 1-based, inclusive coordinates of the gene.
+    pub start: u32,
+    pub end: u32,
+    /// 1-based, inclusive coordinates of the CDS segments, in genome order.
+    /// Genes without CDS features have a single segment spanning the gene.
+    pub segments: Vec<(u32, u32)>,
+}
+
+impl Gene {
+    /// Length of the coding sequence, in nucleotides.
+    pub fn nuc_length(&self) -> u32 {
+        self.segments.iter().map(|(start, end)| end - start + 1).sum()
+    }
+
+    /// Length of the translated coding sequence, in codons.
+    pub fn aa_length(&self) -> u32 {
+        self.nuc_length() / 3
+    }
+
+    /// Genomic positions of the coding sequence, in translation order.
+    pub fn positions(&self) -> Vec<u32> {
+        let positions = self.segments.iter().flat_map(|(start, end)| *start..=*end);
+        match self.strand.as_str() {
+            "-" => positions.rev().collect(),
+            _   => positions.collect(),
+        }
+    }
+}
+
+/// Search the attributes for these possible identifier names, in order.
+///
+/// The sars-cov-2 gff has a strange space before " gene_name", and GTF
+/// features without a gene_name fall back to their gene_id.
+pub const NAME_ATTRIBUTES: &[&str] = &["Name", "gene_name", " gene_name", "gene", "gene_id"];
+
+/// A feature of the gene annotations, with the attributes needed to build the gene model.
+#[derive(Clone, Debug, Default)]
+pub struct Feature {
+    /// The feature type (ex. gene, CDS).
+    pub ty: String,
+    /// 1-based, inclusive coordinates of the feature.
+    pub start: u32,
+    pub end: u32,
+    pub strand: String,
+    pub name: Option<String>,
+    pub id: Option<String>,
+    pub parent: Option<String>,
+}
+
+/// The file format of the gene annotations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnnotationFormat {
+    Gff3,
+    /// GTF or GFF2, with `key "value";` attributes.
+    Gtf,
+    /// GenBank flat file, with gene and CDS features.
+    Genbank,
+}
+
+/// How many lines of the annotations are read to sniff their format (see [`AnnotationFormat::sniff`]).
+const SNIFF_LINES: usize = 100;
+
+impl AnnotationFormat {
+    /// Detect the format from the extension (`.gtf`, `.gff2`, `.gb`, `.gbk`), or from the content
+    /// for other extensions and files without one (see [`AnnotationFormat::sniff`]).
+    pub fn detect<P>(path: P) -> Result<Self, Report>
+    where
+        P: AsRef<Path> + std::fmt::Debug,
+    {
+        // The extension is the one before the compression extension, if any (ex. `.gtf.gz`)
+        let (_, suffix) = crate::parse_compression(&path);
+        let name        = path.as_ref().file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        let ext         = Path::new(name.strip_suffix(suffix.as_str()).unwrap_or(name)).extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+        let format = match ext.as_str() {
+            "gtf" | "gff2"           => AnnotationFormat::Gtf,
+            "gb" | "gbk" | "genbank" => AnnotationFormat::Genbank,
+            _                        => {
+                let lines = crate::open(&path)?.lines().take(SNIFF_LINES).collect::<Result<Vec<_>, _>>()?;
+                AnnotationFormat::sniff(&lines)
+            },
+        };
+        log::debug!("Detected {format:?} annotations: {path:?}");
+        Ok(format)
+    }
+
+    /// Sniff the format from the first lines of the annotations.
+    ///
+    /// A `##gff-version` or `LOCUS` header decides the format. Otherwise, the attributes of the
+    /// first feature line decide it: `key "value";` pairs are GTF, and `key=value` pairs GFF3.
+    /// Content that matches neither is assumed to be GFF3.
+    pub fn sniff(lines: &[String]) -> Self {
+        let header = lines.first().map(|l| l.as_str()).unwrap_or_default();
+        if header.starts_with("LOCUS")           { return AnnotationFormat::Genbank }
+        if header.starts_with("##gff-version 2") { return AnnotationFormat::Gtf }
+        if header.starts_with("##gff-version 3") { return AnnotationFormat::Gff3 }
+
+        let attributes = lines.iter()
+            .filter(|l| !l.starts_with('#') && !l.trim().is_empty())
+            .find_map(|l| l.split('\t').nth(8));
+        match attributes {
+            Some(attributes) if attributes.contains('"') && !attributes.contains('=') => AnnotationFormat::Gtf,
+            _                                                                       => AnnotationFormat::Gff3,
+        }
+    }
+}
+
+impl std::str::FromStr for AnnotationFormat {
+    type Err = Report;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "gff" | "gff3"     => Ok(AnnotationFormat::Gff3),
+            "gtf" | "gff2"     => Ok(AnnotationFormat::Gtf),
+            "gb"  | "genbank"  => Ok(AnnotationFormat::Genbank),
+            _                  => Err(eyre!("Unknown annotation format: {format}, please choose from: gff3, gtf, genbank")),
+        }
+    }
+}
+
+/// Read the features of GFF3, GTF, GFF2, or GenBank gene annotations, with the format detected.
+pub fn features<P>(path: P) -> Result<Vec<Feature>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    features_as(&path, AnnotationFormat::detect(&path)?)
+}
+
+/// Read the features of gene annotations in a given format.
+pub fn features_as<P>(path: P, format: AnnotationFormat) -> Result<Vec<Feature>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    match format {
+        AnnotationFormat::Gff3    => gff_features(&path),
+        AnnotationFormat::Gtf     => gtf_features(&path),
+        AnnotationFormat::Genbank => genbank_features(&path),
+    }
+}
+
+/// Read the features of GFF3 annotations.
+fn gff_features<P>(path: P) -> Result<Vec<Feature>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let mut reader   = gff::io::Reader::new(crate::open(&path)?);
+    let mut features = Vec::new();
+    for result in reader.records() {
+        let record     = result?;
+        let attributes = record.attributes();
+        features.push(Feature {
+            ty:     record.ty().to_string(),
+            start:  record.start().get() as u32,
+            end:    record.end().get() as u32,
+            strand: record.strand().to_string(),
+            name:   NAME_ATTRIBUTES.iter().find_map(|n| attributes.get(&n.to_string())).map(|v| v.to_string()),
+            id:     attributes.get(&"ID".to_string()).map(|v| v.to_string()),
+            parent: attributes.get(&"Parent".to_string()).map(|v| v.to_string()),
+        });
+    }
+    Ok(features)
+}
+
+/// Read the features of GTF or GFF2 annotations, with `key "value";` attributes.
+///
+/// GTF has no IDs or parents, every feature names its gene directly. The CDS of
+/// every transcript are assigned to the gene, so the annotations should have one
+/// transcript per gene, as viral annotations do.
+fn gtf_features<P>(path: P) -> Result<Vec<Feature>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let mut features = Vec::new();
+    for line in crate::open(&path)?.lines() {
+        let line = line?;
+        if line.starts_with('#') || line.trim().is_empty() { continue }
+        let fields = line.split('\t').collect::<Vec<_>>();
+        let [_seqid, _source, ty, start, end, _score, strand, _phase, attributes] = fields[..] else {
+            return Err(eyre!("GTF line does not have 9 fields: {line}"))
+        };
+        let attributes = attributes.split(';')
+            .filter_map(|a| a.trim().split_once(' '))
+            .map(|(key, value)| (key, value.trim().trim_matches('"')))
+            .collect::<HashMap<_, _>>();
+        features.push(Feature {
+            ty:     ty.to_string(),
+            start:  start.parse()?,
+            end:    end.parse()?,
+            strand: strand.to_string(),
+            name:   NAME_ATTRIBUTES.iter().find_map(|n| attributes.get(n)).map(|v| v.to_string()),
+            id:     None,
+            parent: None,
+        });
+    }
+    Ok(features)
+}
+
+/// Read the gene, CDS, and mature peptide features of a GenBank flat file.
+///
+/// Features are named by their `/gene` or `/locus_tag` qualifier, and mature peptides by their `/product`. Joined locations
+/// (ex. `join(13442..13468,13468..21555)`) become one feature per segment, and
+/// `complement` locations are on the minus strand.
+fn genbank_features<P>(path: P) -> Result<Vec<Feature>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    // Features as (type, location, qualifiers), before parsing the locations
+    let mut records: Vec<(String, String, HashMap<String, String>)> = Vec::new();
+    let mut in_features = false;
+    let mut in_location = false;
+
+    for line in crate::open(&path)?.lines() {
+        let line = line?;
+        if line.starts_with("FEATURES") { in_features = true; continue }
+        if !in_features { continue }
+        // The sequence (or the next record) ends the feature table
+        if !line.starts_with(' ') { in_features = false; continue }
+
+        // Feature keys start in column 6, qualifiers and continuations in column 22
+        let key   = line.get(5..21).unwrap_or_default().trim();
+        let value = line.get(21..).unwrap_or_default().trim();
+        if !key.is_empty() {
+            records.push((key.to_string(), value.to_string(), HashMap::new()));
+            in_location = true;
+        } else if let Some((_, location, qualifiers)) = records.last_mut() {
+            if let Some(qualifier) = value.strip_prefix('/') {
+                in_location = false;
+                let (name, value) = qualifier.split_once('=').unwrap_or((qualifier, ""));
+                qualifiers.entry(name.to_string()).or_insert_with(|| value.trim_matches('"').to_string());
+            } else if in_location {
+                location.push_str(value);
+            }
+        }
+    }
+
+    let mut features = Vec::new();
+    for (ty, location, qualifiers) in records.into_iter().filter(|(ty, _, _)| ty == "gene" || ty == "CDS" || ty == "mat_peptide") {
+        // Mature peptides are named by their product (ex. nsp12), rather than their gene
+        let name = match ty.as_str() {
+            "mat_peptide" => qualifiers.get("product").or_else(|| qualifiers.get("gene")).cloned(),
+            _             => qualifiers.get("gene").or_else(|| qualifiers.get("locus_tag")).cloned(),
+        };
+        let strand = if location.contains("complement") { "-" } else { "+" };
+        // Strip the partial markers (<, >) and operators, leaving the ranges
+        let ranges = location.replace(['<', '>'], "");
+        let ranges = ranges.split(|c: char| !(c.is_ascii_digit() || c == '.')).filter(|r| !r.is_empty());
+        for range in ranges {
+            let (start, end) = range.split_once("..").unwrap_or((range, range));
+            let (Ok(start), Ok(end)) = (start.parse(), end.parse()) else {
+                log::warn!("Skipping unsupported GenBank location: {location}");
+                continue
+            };
+            features.push(Feature { ty: ty.clone(), start, end, strand: strand.to_string(), name: name.clone(), id: None, parent: None });
+        }
+    }
+
+    Ok(features)
+}
+
+/// Parse the gene model from GFF3, GTF, GFF2, or GenBank annotations, with the format detected.
+///
+/// Genes are named from the `Name`, `gene_name`, `gene`, or `gene_id` attributes, and CDS
+/// features are assigned to a gene by their own name or their `Parent`.
+pub fn genes<P>(path: P) -> Result<Vec<Gene>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    genes_as(&path, AnnotationFormat::detect(&path)?)
+}
+
+/// Parse the gene model from gene annotations in a given format (see [`genes`]).
+pub fn genes_as<P>(path: P, format: AnnotationFormat) -> Result<Vec<Gene>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Reading gene model from {format:?} file: {path:?}");
+
+    let mut genes: BTreeMap<String, Gene>  = BTreeMap::new();
+    let mut ids:   HashMap<String, String> = HashMap::new();
+    // CDS features as (name, parent, start, end, strand)
+    let mut cds = Vec::new();
+
+    for feature in features_as(&path, format)? {
+        let Feature { ty, start, end, strand, name, id, parent } = feature;
+        match ty.as_str() {
+            "gene" => {
+                let Some(name) = name else { continue };
+                if let Some(id) = id { ids.insert(id, name.clone()); }
+                genes.entry(name.clone()).or_insert(Gene { name, strand, start, end, segments: Vec::new() });
+            },
+            "CDS" => cds.push((name, parent, start, end, strand)),
+            _ => (),
+        }
+    }
+
+    // Assign the CDS segments to their gene, creating genes for orphan CDS
+    for (name, parent, start, end, strand) in cds {
+        let name = name.or_else(|| parent.and_then(|p| ids.get(&p).cloned()));
+        let Some(name) = name else { log::warn!("Skipping unnamed CDS at {start}-{end}."); continue };
+        let gene = genes.entry(name.clone()).or_insert(Gene { name, strand, start, end, segments: Vec::new() });
+        gene.start = gene.start.min(start);
+        gene.end   = gene.end.max(end);
+        if !gene.segments.contains(&(start, end)) { gene.segments.push((start, end)) }
+    }
+
+    let genes = genes.into_values().map(|mut gene| {
+        if gene.segments.is_empty() { gene.segments.push((gene.start, gene.end)) }
+        gene.segments.sort();
+        gene
+    }).collect::<Vec<_>>();
+
+    if genes.is_empty() {
+        return Err(eyre!("No genes were found in gff file: {path:?}"))
+    }
+    log::info!("Found {} genes.", genes.len());
+
+    Ok(genes)
+}
+
+/// A protein product of a gene (ex. the nsp12 product of ORF1ab), in the gene's amino acid coordinates.
+#[derive(Clone, Debug, Default)]
+pub struct Product {
+    pub gene: String,
+    pub name: String,
+    /// 1-based, inclusive codon coordinates of the product within the gene.
+    pub aa_start: u32,
+    pub aa_end: u32,
+}
+
+/// The feature types of mature peptides, in GFF3 (sequence ontology) and GenBank.
+pub const PRODUCT_TYPES: &[&str] = &["mature_protein_region_of_CDS", "mature_peptide", "mat_peptide"];
+
+/// Read the protein products of genes, from a mapping table or mature peptide features.
+///
+/// A table (`.tsv`, `.csv`, `.txt`) has the columns gene, product, start, end, in the
+/// gene's amino acid coordinates (ex. `ORF1ab  nsp12  4393  5324`). Otherwise the file is
+/// read as gene annotations, and each mature peptide feature is mapped to the codons of
+/// every gene whose coding sequence contains it.
+pub fn products<P>(path: P) -> Result<Vec<Product>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Reading gene products: {path:?}");
+
+    let (_, ext, _) = crate::parse_csv_path(&path, None)?;
+    let mut products = Vec::new();
+
+    if matches!(ext.as_str(), "tsv" | "csv" | "txt") {
+        let delimiter = if ext == "csv" { ',' } else { '\t' };
+        for line in crate::open(&path)?.lines() {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') || line.starts_with("gene") { continue }
+            let fields = line.split(delimiter).map(str::trim).collect::<Vec<_>>();
+            let [gene, name, start, end, ..] = fields[..] else {
+                return Err(eyre!("Gene products line does not have 4 fields (gene, product, start, end): {line}"))
+            };
+            products.push(Product { gene: gene.to_string(), name: name.to_string(), aa_start: start.parse()?, aa_end: end.parse()? });
+        }
+    } else {
+        let genes = genes(&path)?;
+        for feature in features(&path)?.into_iter().filter(|f| PRODUCT_TYPES.contains(&f.ty.as_str())) {
+            let Some(name) = feature.name else { continue };
+            for gene in &genes {
+                let positions = gene.positions();
+                let start = positions.iter().position(|p| *p == feature.start);
+                let end   = positions.iter().position(|p| *p == feature.end);
+                let (Some(start), Some(end)) = (start, end) else { continue };
+                let (start, end) = (start.min(end) as u32 / 3 + 1, start.max(end) as u32 / 3 + 1);
+                products.push(Product { gene: gene.name.clone(), name: name.clone(), aa_start: start, aa_end: end });
+            }
+        }
+    }
+
+    if products.is_empty() {
+        log::warn!("No gene products were found: {path:?}");
+    }
+    Ok(products)
+}
+
+/// Find the product of a mutation in gene amino acid notation (ex. `ORF1ab:P4715L` -> `nsp12`).
+///
+/// The gene is the text before the first `:`, and the position is the first number after it, so
+/// labels in other notations (ex. `ORF1ab:Pro4715Leu`, `S:del69`) and label templates that start
+/// with the mutation also match.
+pub fn product_of<'a>(mutation: &str, products: &'a [Product]) -> Option<&'a Product> {
+    let (gene, change) = mutation.split_once(':')?;
+    let position = change.chars().skip_while(|c| !c.is_ascii_digit()).take_while(|c| c.is_ascii_digit()).collect::<String>();
+    let position: u32 = position.parse().ok()?;
+    products.iter().find(|p| p.gene == gene && p.aa_start <= position && position <= p.aa_end)
+}
+
+/// The genome position of a mutation label, to place it along the genome (ex. `C241T` -> 241, `S:D614G` -> 23402).
+///
+/// Nucleotide mutations (ex. `C241T`, `6513-6515`, `28933:T`) are placed at their first number.
+/// Amino acid mutations are placed at the first base of their codon, and have no position when
+/// their gene isn't one of the `genes`.
+pub fn genome_position(mutation: &str, genes: &[Gene]) -> Option<u32> {
+    let number = |text: &str| text.chars().skip_while(|c| !c.is_ascii_digit()).take_while(|c| c.is_ascii_digit()).collect::<String>().parse::<u32>().ok();
+    match mutation.split_once(':') {
+        Some((prefix, _)) if prefix.chars().all(|c| c.is_ascii_digit()) => prefix.parse().ok(),
+        Some((gene, change)) => {
+            let gene  = genes.iter().find(|g| g.name == gene)?;
+            let codon = number(change)?;
+            gene.positions().get((codon.checked_sub(1)? * 3) as usize).copied()
+        },
+        None => number(mutation),
+    }
+}
+
+/// The regions between genes, as (label, start, end), with 1-based inclusive coordinates.
+///
+/// The region before the first gene is labelled `5'UTR`, the region after the last gene
+/// `3'UTR` (up to the end of the genome), and the gaps between genes by their coordinates
+/// (ex. `intergenic:28260-28273`). Overlapping genes (ex. ORF1a and ORF1ab) are merged first.
+pub fn intergenic_regions(genes: &[Gene]) -> Vec<(String, u32, u32)> {
+    let mut spans = genes.iter().map(|g| (g.start, g.end)).collect::<Vec<_>>();
+    spans.sort();
+    let mut merged: Vec<(u32, u32)> = Vec::new();
+    for (start, end) in spans {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 + 1 => last.1 = last.1.max(end),
+            _                                 => merged.push((start, end)),
+        }
+    }
+
+    let mut regions = Vec::new();
+    let (Some(first), Some(last)) = (merged.first(), merged.last()) else { return regions };
+    if first.0 > 1 { regions.push(("5'UTR".to_string(), 1, first.0 - 1)) }
+    for pair in merged.windows(2) {
+        let (start, end) = (pair[0].1 + 1, pair[1].0 - 1);
+        regions.push((format!("intergenic:{start}-{end}"), start, end));
+    }
+    regions.push(("3'UTR".to_string(), last.1 + 1, u32::MAX));
+    regions
+}
+
+/// Parse the gene model, in the given format (or detected) and through the cache directory when given.
+///
+/// This is how every command reads its gene model (see [`genes_as`] and [`genes_cached`]).
+pub fn read_genes<P>(path: P, format: Option<AnnotationFormat>, cache: Option<&Path>) -> Result<Vec<Gene>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let format = match format {
+        Some(format) => format,
+        None         => AnnotationFormat::detect(&path)?,
+    };
+    match cache {
+        Some(cache) => genes_cached(&path, format, cache),
+        None        => genes_as(&path, format),
+    }
+}
+
+/// Parse the gene model through a cache directory, so repeated runs on the same annotations skip parsing.
+///
+/// The gene model is stored as a small parquet file (one row per CDS segment), under the SHA-256
+/// of the annotations content and format. Edited annotations get a new digest, so stale gene
+/// models are never read.
+pub fn genes_cached<P>(path: P, format: AnnotationFormat, cache: &Path) -> Result<Vec<Gene>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let mut content = std::fs::read(&path)?;
+    content.extend(format!("{format:?}").as_bytes());
+    let cached = cache.join(format!("{}.genes.parquet", crate::sha256(&content)));
+
+    if cached.exists() {
+        log::info!("Reading cached gene model: {cached:?}");
+        return read_genes_parquet(&cached)
+    }
+
+    let genes = genes_as(&path, format)?;
+    log::info!("Caching gene model: {cached:?}");
+    std::fs::create_dir_all(cache)?;
+    write_genes_parquet(&genes, &cached)?;
+    Ok(genes)
+}
+
+/// Write the gene model as parquet, one row per CDS segment (see [`genes_cached`]).
+fn write_genes_parquet(genes: &[Gene], path: &Path) -> Result<(), Report> {
+    let rows = genes.iter().flat_map(|g| g.segments.iter().map(move |s| (g, s))).collect::<Vec<_>>();
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("name",          DataType::Utf8,   false),
+        Field::new("strand",        DataType::Utf8,   false),
+        Field::new("start",         DataType::UInt32, false),
+        Field::new("end",           DataType::UInt32, false),
+        Field::new("segment_start", DataType::UInt32, false),
+        Field::new("segment_end",   DataType::UInt32, false),
+    ]));
+    let records = RecordBatch::try_new(schema.clone(), vec![
+        Arc::new(StringArray::from(rows.iter().map(|(g, _)| g.name.clone()).collect::<Vec<_>>())) as ArrayRef,
+        Arc::new(StringArray::from(rows.iter().map(|(g, _)| g.strand.clone()).collect::<Vec<_>>())),
+        Arc::new(UInt32Array::from(rows.iter().map(|(g, _)| g.start).collect::<Vec<_>>())),
+        Arc::new(UInt32Array::from(rows.iter().map(|(g, _)| g.end).collect::<Vec<_>>())),
+        Arc::new(UInt32Array::from(rows.iter().map(|(_, s)| s.0).collect::<Vec<_>>())),
+        Arc::new(UInt32Array::from(rows.iter().map(|(_, s)| s.1).collect::<Vec<_>>())),
+    ])?;
+    let mut writer = ArrowWriter::try_new(File::create(path)?, schema, None)?;
+    writer.write(&records)?;
+    writer.close()?;
+    Ok(())
+}
+
+/// Read a gene model written by [`write_genes_parquet`], keeping the gene order.
+fn read_genes_parquet(path: &Path) -> Result<Vec<Gene>, Report> {
+    let mut genes: Vec<Gene> = Vec::new();
+    for batch in ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?.build()? {
+        let batch = batch?;
+        let name          = crate::string_column(&batch, "name")?;
+        let strand        = crate::string_column(&batch, "strand")?;
+        let start         = crate::string_column(&batch, "start")?;
+        let end           = crate::string_column(&batch, "end")?;
+        let segment_start = crate::string_column(&batch, "segment_start")?;
+        let segment_end   = crate::string_column(&batch, "segment_end")?;
+        for i in 0..batch.num_rows() {
+            let segment = (segment_start.value(i).parse()?, segment_end.value(i).parse()?);
+            match genes.last_mut().filter(|g| g.name == name.value(i)) {
+                Some(gene) => gene.segments.push(segment),
+                None       => genes.push(Gene {
+                    name:     name.value(i).to_string(),
+                    strand:   strand.value(i).to_string(),
+                    start:    start.value(i).parse()?,
+                    end:      end.value(i).parse()?,
+                    segments: vec![segment],
+                }),
+            }
+        }
+    }
+    Ok(genes)
+}
+
+/// Write the gene model as a tsv, one row per gene.
+pub fn write_genes<P>(genes: &[Gene], path: P) -> Result<(), Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Writing gene model: {path:?}");
+    let mut writer = BufWriter::new(File::create(&path)?);
+    writeln!(writer, "gene\tstrand\tstart\tend\tsegments\tnuc_length\taa_length")?;
+    for gene in genes {
+        let segments = gene.segments.iter().map(|(start, end)| format!("{start}-{end}")).collect::<Vec<_>>().join(",");
+        writeln!(writer, "{}\t{}\t{}\t{}\t{segments}\t{}\t{}", gene.name, gene.strand, gene.start, gene.end, gene.nuc_length(), gene.aa_length())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn sniff_headers() {
+        assert_eq!(AnnotationFormat::sniff(&lines("LOCUS       MN908947  29903 bp")), AnnotationFormat::Genbank);
+        assert_eq!(AnnotationFormat::sniff(&lines("##gff-version 2")),                AnnotationFormat::Gtf);
+        assert_eq!(AnnotationFormat::sniff(&lines("##gff-version 3")),                AnnotationFormat::Gff3);
+    }
+
+    #[test]
+    fn sniff_attributes() {
+        let gtf  = "#!genome-build ASM985889v3\nMN908947.3\tGenBank\tCDS\t21563\t25384\t.\t+\t0\tgene_id \"S\"; gene_name \"S\";";
+        let gff3 = "MN908947.3\tGenBank\tgene\t21563\t25384\t.\t+\t.\tID=gene-S;Name=S";
+        assert_eq!(AnnotationFormat::sniff(&lines(gtf)),  AnnotationFormat::Gtf);
+        assert_eq!(AnnotationFormat::sniff(&lines(gff3)), AnnotationFormat::Gff3);
+        assert_eq!(AnnotationFormat::sniff(&[]),          AnnotationFormat::Gff3);
+    }
+
+    #[test]
+    fn gtf_genes() -> Result<(), Report> {
+        let dir  = tempfile::tempdir()?;
+        let path = dir.path().join("genes.gtf");
+        std::fs::write(&path, [
+            "#!genome-build ASM985889v3",
+            "MN908947.3\tGenBank\tgene\t21563\t25384\t.\t+\t.\tgene_id \"S\"; gene_name \"S\";",
+            "MN908947.3\tGenBank\tCDS\t21563\t25381\t.\t+\t0\tgene_id \"S\"; transcript_id \"S.1\"; gene_name \"S\";",
+            "MN908947.3\tGenBank\tCDS\t26523\t27191\t.\t-\t0\tgene_id \"M\";",
+        ].join("\n"))?;
+
+        let features = gtf_features(&path)?;
+        assert_eq!(features.len(), 3);
+        assert_eq!((features[1].ty.as_str(), features[1].name.as_deref(), features[1].end), ("CDS", Some("S"), 25381));
+        // Features without a gene_name fall back to their gene_id
+        assert_eq!((features[2].name.as_deref(), features[2].strand.as_str()), (Some("M"), "-"));
+
+        let genes = genes_as(&path, AnnotationFormat::Gtf)?;
+        assert_eq!(genes.iter().map(|g| g.name.as_str()).collect::<Vec<_>>(), ["M", "S"]);
+        assert_eq!((genes[0].strand.as_str(), genes[0].segments.clone()), ("-", vec![(26523, 27191)]));
+        assert_eq!(genes[1].segments, vec![(21563, 25381)]);
+
+        // Empty annotations have no genes, and lines without 9 fields are an error
+        std::fs::write(&path, "")?;
+        assert!(gtf_features(&path)?.is_empty());
+        assert!(genes_as(&path, AnnotationFormat::Gtf).is_err());
+        std::fs::write(&path, "MN908947.3\tGenBank\tgene\t21563\t25384")?;
+        assert!(gtf_features(&path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn genbank_genes() -> Result<(), Report> {
+        let dir  = tempfile::tempdir()?;
+        let path = dir.path().join("genes.gb");
+        // Feature keys start in column 6, and qualifiers and continuations in column 22
+        let key       = |key: &str, value: &str| format!("     {key:<16}{value}");
+        let qualifier = |value: &str| format!("{:21}{value}", "");
+        std::fs::write(&path, [
+            "LOCUS       MN908947               29903 bp    RNA     linear   VRL 18-MAR-2020".to_string(),
+            "FEATURES             Location/Qualifiers".to_string(),
+            key("source", "1..29903"),
+            qualifier("/organism=\"Severe acute respiratory syndrome coronavirus 2\""),
+            key("gene", "266..21555"),
+            qualifier("/gene=\"ORF1ab\""),
+            key("CDS", "join(266..13468,"),
+            qualifier("13468..21555)"),
+            qualifier("/gene=\"ORF1ab\""),
+            qualifier("/product=\"ORF1ab polyprotein\""),
+            key("mat_peptide", "13442..16236"),
+            qualifier("/gene=\"ORF1ab\""),
+            qualifier("/product=\"RNA-dependent RNA polymerase\""),
+            key("CDS", "complement(<100..>200)"),
+            qualifier("/locus_tag=\"X1\""),
+            "ORIGIN".to_string(),
+            "        1 attaaaggtt tataccttcc".to_string(),
+            "//".to_string(),
+        ].join("\n"))?;
+
+        let features = genbank_features(&path)?;
+        let summary  = features.iter().map(|f| format!("{} {} {}-{} {}", f.ty, f.name.as_deref().unwrap_or_default(), f.start, f.end, f.strand)).collect::<Vec<_>>();
+        assert_eq!(summary, [
+            "gene ORF1ab 266-21555 +",
+            "CDS ORF1ab 266-13468 +",
+            "CDS ORF1ab 13468-21555 +",
+            "mat_peptide RNA-dependent RNA polymerase 13442-16236 +",
+            "CDS X1 100-200 -",
+        ]);
+
+        let genes = genes_as(&path, AnnotationFormat::Genbank)?;
+        assert_eq!(genes.iter().map(|g| g.name.as_str()).collect::<Vec<_>>(), ["ORF1ab", "X1"]);
+        assert_eq!(genes[0].segments, vec![(266, 13468), (13468, 21555)]);
+        assert_eq!(genes[1].strand, "-");
+
+        // A record without a feature table has no features
+        std::fs::write(&path, "LOCUS       MN908947\nORIGIN\n//\n")?;
+        assert!(genbank_features(&path)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn intergenic() {
+        let gene = |name: &str, start, end| Gene { name: name.to_string(), strand: "+".to_string(), start, end, segments: vec![(start, end)] };
+        let genes = [gene("N", 28274, 29533), gene("ORF1ab", 266, 21555), gene("ORF1a", 266, 13483), gene("S", 21563, 25384)];
+        let region = |label: &str, start, end| (label.to_string(), start, end);
+        // Overlapping genes are merged, and the genes don't need to be sorted
+        assert_eq!(intergenic_regions(&genes), [
+            region("5'UTR", 1, 265),
+            region("intergenic:21556-21562", 21556, 21562),
+            region("intergenic:25385-28273", 25385, 28273),
+            region("3'UTR", 29534, u32::MAX),
+        ]);
+        // Adjacent genes leave no gap, and a gene at the start of the genome leaves no 5'UTR
+        assert_eq!(intergenic_regions(&[gene("A", 1, 10), gene("B", 11, 20)]), [region("3'UTR", 21, u32::MAX)]);
+        assert!(intergenic_regions(&[]).is_empty());
+    }
+}
@@ -0,0 +1,271 @@
+use arrow::array::{ArrayRef, StringArray};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use color_eyre::eyre::{Report, Result};
+use crate::codon::three_letter;
+use crate::gene::Gene;
+use crate::mnv::{codon_change, complement};
+use datafusion::datasource::MemTable;
+use datafusion::prelude::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The coding sequence of a gene, to convert genomic positions to coding (`c.`) positions.
+#[derive(Clone, Debug)]
+pub struct CodingSequence {
+    /// Genomic positions of the coding sequence, in translation order.
+    positions: Vec<u32>,
+    /// The coding position of each genomic position, the first one where segments overlap (ex. ORF1ab).
+    index: HashMap<u32, u32>,
+    /// Whether the gene is on the minus strand, so coding positions run backwards along the genome.
+    minus: bool,
+}
+
+impl CodingSequence {
+    /// The coding sequence of a gene, from its CDS segments and strand.
+    pub fn new(gene: &Gene) -> Self {
+        let positions = gene.positions();
+        let mut index = HashMap::new();
+        for (i, position) in positions.iter().enumerate() { index.entry(*position).or_insert(i as u32 + 1); }
+        CodingSequence { positions, index, minus: gene.strand == "-" }
+    }
+
+    /// How far a genomic position is downstream of another, in translation order.
+    fn offset(&self, from: u32, to: u32) -> i64 {
+        match self.minus {
+            true  => from as i64 - to as i64,
+            false => to as i64 - from as i64,
+        }
+    }
+
+    /// Convert a genomic position to a coding position.
+    ///
+    /// Positions upstream of the start codon are negative (ex. `c.-25`, with no `c.0`), positions
+    /// downstream of the stop codon are starred (ex. `c.*10`), and positions between CDS segments
+    /// are offset from the nearest coding position (ex. `c.88+2`, `c.89-1`).
+    pub fn position(&self, position: u32) -> Option<String> {
+        if let Some(coding) = self.index.get(&position) { return Some(coding.to_string()) }
+        let (first, last) = (*self.positions.first()?, *self.positions.last()?);
+        if self.offset(first, position) < 0 { return Some(format!("-{}", -self.offset(first, position))) }
+        if self.offset(last, position) > 0  { return Some(format!("*{}", self.offset(last, position))) }
+
+        // Between segments, the closest coding positions before and after, in translation order
+        let before = self.positions.iter().enumerate().filter(|(_, p)| self.offset(**p, position) > 0).min_by_key(|(_, p)| self.offset(**p, position))?;
+        let after  = self.positions.iter().enumerate().filter(|(_, p)| self.offset(position, **p) > 0).min_by_key(|(_, p)| self.offset(position, **p))?;
+        let (up, down) = (self.offset(*before.1, position), self.offset(position, *after.1));
+        match up <= down {
+            true  => Some(format!("{}+{up}", before.0 + 1)),
+            false => Some(format!("{}-{down}", after.0 + 1)),
+        }
+    }
+}
+
+/// Add `hgvs_c` and `hgvs_p` columns to a registered mutations table.
+///
+/// Coding (`c.`) positions are relative to the first base of the coding sequence of the
+/// mutation's gene, following its CDS segments and strand (see [`Gene::positions`]), and the bases
+/// of minus strand genes are complemented. Protein (`p.`) descriptions use three-letter amino acid
+/// codes. The reference sequence is optional, and only needed for the flanking residues of amino
+/// acid insertions and frameshifts.
+pub async fn hgvs(ctx: SessionContext, name: &str, genes: &[Gene], reference: Option<&[u8]>) -> Result<SessionContext, Report> {
+
+    log::info!("Adding HGVS nomenclature columns.");
+
+    // The first gene of each name, like the gene length check
+    let mut models: HashMap<&str, (&Gene, CodingSequence)> = HashMap::new();
+    for gene in genes { models.entry(gene.name.as_str()).or_insert_with(|| (gene, CodingSequence::new(gene))); }
+
+    let table   = crate::quote_identifier(name);
+    let query   = format!("SELECT * FROM {table} ORDER BY sample,nuc_start,nuc_end");
+    let df      = ctx.sql(&query).await?;
+    let schema  = df.schema().as_arrow().clone();
+    let batches = df.collect().await?;
+
+    // The final schema adds the hgvs columns.
+    let fields = schema.fields().iter()
+        .cloned()
+        .chain([
+            Arc::new(Field::new("hgvs_c", DataType::Utf8, true)),
+            Arc::new(Field::new("hgvs_p", DataType::Utf8, true)),
+        ])
+        .collect::<Vec<_>>();
+    let output_schema = Arc::new(Schema::new(fields));
+
+    let batches = batches.iter().map(|batch| {
+        let mutation  = crate::string_column(batch, "mutation")?;
+        let column    = crate::string_column(batch, "column")?;
+        let gene      = crate::string_column(batch, "gene")?;
+        let nuc_start = crate::string_column(batch, "nuc_start")?;
+        let nuc_end   = crate::string_column(batch, "nuc_end")?;
+
+        let mut hgvs_c: Vec<Option<String>> = Vec::new();
+        let mut hgvs_p: Vec<Option<String>> = Vec::new();
+
+        for i in 0..batch.num_rows() {
+            let model     = gene.is_valid(i).then(|| models.get(gene.value(i))).flatten();
+            let nuc_start = nuc_start.is_valid(i).then(|| nuc_start.value(i).parse::<u32>()).transpose()?;
+            let nuc_end   = nuc_end.is_valid(i).then(|| nuc_end.value(i).parse::<u32>()).transpose()?;
+            let (mutation, column) = (mutation.value(i), column.value(i));
+
+            let c = match (model, nuc_start) {
+                (Some((_, cds)), Some(nuc_start)) => hgvs_coding(mutation, column, nuc_start, nuc_end.unwrap_or(nuc_start), cds),
+                _                                 => None,
+            };
+            let p = hgvs_protein(mutation, column, model.map(|(gene, _)| *gene).zip(reference));
+            hgvs_c.push(c);
+            hgvs_p.push(p);
+        }
+
+        let mut columns: Vec<ArrayRef> = batch.columns().to_vec();
+        columns.push(Arc::new(StringArray::from(hgvs_c)));
+        columns.push(Arc::new(StringArray::from(hgvs_p)));
+
+        Ok(RecordBatch::try_new(output_schema.clone(), columns)?)
+    }).collect::<Result<Vec<_>, Report>>()?;
+
+    // Replace the original table with the new columns
+    ctx.deregister_table(name)?;
+    let provider = MemTable::try_new(output_schema, vec![batches])?;
+    ctx.register_table(name, Arc::new(provider))?;
+
+    Ok(ctx)
+}
+
+/// Describe a nucleotide mutation in HGVS coding notation (ex. C241T -> c.-25C>T).
+///
+/// Mutations are reported on the plus strand, so the bases of minus strand genes are
+/// complemented, and ranges are reversed into translation order.
+pub fn hgvs_coding(mutation: &str, column: &str, start: u32, end: u32, cds: &CodingSequence) -> Option<String> {
+    let bases = |bases: &str| -> String {
+        match cds.minus {
+            true  => bases.bytes().rev().map(|b| complement(b) as char).collect(),
+            false => bases.to_string(),
+        }
+    };
+    // The first and last coding positions of a genomic range, in translation order
+    let range = |start: u32, end: u32| -> Option<(String, String)> {
+        match cds.minus {
+            true  => Some((cds.position(end)?, cds.position(start)?)),
+            false => Some((cds.position(start)?, cds.position(end)?)),
+        }
+    };
+    match column {
+        // ex. C241T
+        "substitutions" => {
+            let reference = bases(mutation.get(..1)?);
+            let alternate = bases(mutation.get(mutation.len() - 1..)?);
+            let position  = cds.position(start)?;
+            Some(format!("c.{position}{reference}>{alternate}"))
+        },
+        // ex. 6513-6515
+        "deletions" => {
+            let (first, last) = range(start, end)?;
+            match start == end {
+                true  => Some(format!("c.{first}del")),
+                false => Some(format!("c.{first}_{last}del")),
+            }
+        },
+        // ex. 28933:T, inserted between the position and the next
+        "insertions" => {
+            let inserted      = bases(mutation.split(':').nth(1)?);
+            let (first, last) = range(start, start + 1)?;
+            Some(format!("c.{first}_{last}ins{inserted}"))
+        },
+        _ => None,
+    }
+}
+
+/// Describe an amino acid mutation in HGVS protein notation (ex. S:N501Y -> p.Asn501Tyr).
+///
+/// The gene and reference are needed to look up flanking residues for insertions and frameshifts.
+pub fn hgvs_protein(mutation: &str, column: &str, gene_reference: Option<(&Gene, &[u8])>) -> Option<String> {
+    let (_gene, change) = mutation.split_once(':')?;
+
+    // Look up the reference residue at a 1-based codon position, following the CDS segments and strand
+    let residue = |position: u32| -> Option<&'static str> {
+        let (gene, reference) = gene_reference?;
+        let (residue, _)      = codon_change(gene, position, &[], reference)?;
+        three_letter(residue)
+    };
+
+    match column {
+        // ex. S:N501Y, S:F49F
+        "aaSubstitutions" => {
+            let reference = three_letter(change.chars().next()?)?;
+            let alternate = three_letter(change.chars().last()?)?;
+            let position  = &change[1..change.len() - 1];
+            match reference == alternate {
+                true  => Some(format!("p.{reference}{position}=")),
+                false => Some(format!("p.{reference}{position}{alternate}")),
+            }
+        },
+        // ex. S:H69-
+        "aaDeletions" => {
+            let reference = three_letter(change.chars().next()?)?;
+            let position  = change[1..].trim_end_matches('-');
+            Some(format!("p.{reference}{position}del"))
+        },
+        // ex. S:214:EPE
+        "aaInsertions" => {
+            let (position, inserted) = change.split_once(':')?;
+            let position: u32        = position.parse().ok()?;
+            let inserted             = inserted.chars().map(three_letter).collect::<Option<String>>()?;
+            let (before, after)      = (residue(position)?, residue(position + 1)?);
+            Some(format!("p.{before}{position}_{after}{}ins{inserted}", position + 1))
+        },
+        // ex. N:221-298
+        "frameShifts" => {
+            let position: u32 = change.split('-').next()?.parse().ok()?;
+            Some(format!("p.{}{position}fs", residue(position)?))
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A plus strand gene with UTRs around a CDS split in two segments, and a minus strand gene
+    const REFERENCE: &[u8] = b"GGGGATGAAATTTTCCCTAAGGGGGGGGGGTTAGGGTTTCATGGG";
+
+    fn genes() -> Vec<Gene> {
+        vec![
+            Gene { name: "g".to_string(), strand: "+".to_string(), start: 1,  end: 30, segments: vec![(5, 10), (15, 20)] },
+            Gene { name: "m".to_string(), strand: "-".to_string(), start: 31, end: 45, segments: vec![(31, 42)] },
+        ]
+    }
+
+    #[test]
+    fn coding_positions_with_utrs() {
+        let cds = CodingSequence::new(&genes()[0]);
+        let positions = [1, 4, 5, 10, 11, 12, 13, 14, 15, 20, 22].map(|p| cds.position(p).unwrap_or_default());
+        assert_eq!(positions, ["-4", "-1", "1", "6", "6+1", "6+2", "7-2", "7-1", "7", "12", "*2"]);
+
+        assert_eq!(hgvs_coding("C15T", "substitutions", 15, 15, &cds).as_deref(), Some("c.7C>T"));
+        assert_eq!(hgvs_coding("G2A",  "substitutions", 2,  2,  &cds).as_deref(), Some("c.-3G>A"));
+        assert_eq!(hgvs_coding("7-9",  "deletions",     7,  9,  &cds).as_deref(), Some("c.3_5del"));
+    }
+
+    #[test]
+    fn coding_positions_minus_strand() {
+        let cds = CodingSequence::new(&genes()[1]);
+        let positions = [44, 42, 40, 31, 30].map(|p| cds.position(p).unwrap_or_default());
+        assert_eq!(positions, ["-2", "1", "3", "12", "*1"]);
+
+        // The bases are complemented, and ranges are in translation order
+        assert_eq!(hgvs_coding("C40T",  "substitutions", 40, 40, &cds).as_deref(), Some("c.3G>A"));
+        assert_eq!(hgvs_coding("33-35", "deletions",     33, 35, &cds).as_deref(), Some("c.8_10del"));
+        assert_eq!(hgvs_coding("36:AC", "insertions",    36, 36, &cds).as_deref(), Some("c.6_7insGT"));
+    }
+
+    #[test]
+    fn protein_flanking_residues() {
+        let genes = genes();
+        // The residues are read across the CDS segments, and from the minus strand
+        assert_eq!(hgvs_protein("g:3-4",  "frameShifts",  Some((&genes[0], REFERENCE))).as_deref(), Some("p.Pro3fs"));
+        assert_eq!(hgvs_protein("m:1:G",  "aaInsertions", Some((&genes[1], REFERENCE))).as_deref(), Some("p.Met1_Lys2insGly"));
+        assert_eq!(hgvs_protein("m:K2R",  "aaSubstitutions", None).as_deref(), Some("p.Lys2Arg"));
+        assert_eq!(hgvs_protein("m:1:G",  "aaInsertions", None), None);
+    }
+}
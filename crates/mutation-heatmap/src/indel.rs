@@ -0,0 +1,270 @@
+use arrow::array::{StringArray, UInt32Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use color_eyre::eyre::{Report, Result};
+use datafusion::prelude::*;
+use std::sync::Arc;
+
+/// Left-align and trim the indels of a registered mutations or annotations table against the reference.
+///
+/// Callers report the same indel at different anchors in repetitive sequence
+/// (ex. nextclade vs ivar vs VCF), so indels are shifted to their left-most
+/// equivalent position and duplicate rows are removed. Indels are parsed from their
+/// notation (see [`parse_indel`]), and the `nuc_start` and `nuc_end` columns are updated
+/// when the table has them. Tables without a `column` (ex. annotations) are matched by notation alone.
+pub async fn normalize(ctx: SessionContext, name: &str, reference: &[u8]) -> Result<SessionContext, Report> {
+
+    log::info!("Left-aligning indels of {name} against the reference.");
+
+    let table   = crate::quote_identifier(name);
+    let columns = crate::column_names(&ctx.table(name).await?);
+    let has     = |column: &str| columns.iter().any(|c| c == column);
+    let column  = if has("column") { "arrow_cast(column, 'Utf8')" } else { "arrow_cast(NULL, 'Utf8')" };
+    let query   = format!("SELECT DISTINCT arrow_cast(mutation, 'Utf8') as mutation, {column} as column FROM {table} WHERE mutation IS NOT NULL");
+    let batches = ctx.sql(&query).await?.collect().await?;
+
+    // Containers for the normalized indels
+    let mut mutations:  Vec<String>         = Vec::new();
+    let mut kinds:      Vec<Option<String>> = Vec::new();
+    let mut normalized: Vec<String>         = Vec::new();
+    let mut starts:     Vec<u32>            = Vec::new();
+    let mut ends:       Vec<u32>            = Vec::new();
+
+    for batch in &batches {
+        let mutation = crate::string_column(batch, "mutation")?;
+        let column   = crate::string_column(batch, "column")?;
+
+        for i in 0..batch.num_rows() {
+            let (mutation, column) = (mutation.value(i), column.is_valid(i).then(|| column.value(i)));
+            let Some((kind, start, end, inserted)) = parse_indel(mutation) else { continue };
+            if column.is_some_and(|c| c != kind) { continue }
+
+            let (new_mutation, new_start, new_end) = match kind {
+                "deletions" => {
+                    let (start, end) = left_align_deletion(reference, start, end);
+                    let mutation = if start == end { start.to_string() } else { format!("{start}-{end}") };
+                    (mutation, start, end)
+                },
+                _ => {
+                    let (position, inserted) = left_align_insertion(reference, start, inserted);
+                    (format!("{position}:{inserted}"), position, position)
+                },
+            };
+
+            if new_mutation != mutation {
+                log::debug!("Left-aligned {kind} {mutation} to {new_mutation}");
+            }
+
+            mutations.push(mutation.to_string());
+            kinds.push(column.map(String::from));
+            normalized.push(new_mutation);
+            starts.push(new_start);
+            ends.push(new_end);
+        }
+    }
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("mutation",   DataType::Utf8,   false),
+        Field::new("column",     DataType::Utf8,   true),
+        Field::new("normalized", DataType::Utf8,   false),
+        Field::new("start",      DataType::UInt32, false),
+        Field::new("end",        DataType::UInt32, false),
+    ]));
+    let records = RecordBatch::try_new(
+        schema,
+        vec![
+            Arc::new(StringArray::from(mutations)),
+            Arc::new(StringArray::from(kinds)),
+            Arc::new(StringArray::from(normalized)),
+            Arc::new(UInt32Array::from(starts)),
+            Arc::new(UInt32Array::from(ends)),
+        ],
+    )?;
+    ctx.register_batch("indels", records)?;
+
+    // Replace the original indels, in the same column order, and remove duplicates that now share an anchor
+    let coordinates = has("nuc_start") && has("nuc_end");
+    let fields = columns.iter().map(|c| match c.as_str() {
+        "mutation"                 => "COALESCE(I.normalized, arrow_cast(M.mutation, 'Utf8')) as mutation".to_string(),
+        "nuc_start" if coordinates => "COALESCE(I.start, M.nuc_start) as nuc_start".to_string(),
+        "nuc_end"   if coordinates => "COALESCE(I.end, M.nuc_end) as nuc_end".to_string(),
+        c                          => format!("M.{}", crate::quote_identifier(c)),
+    }).collect::<Vec<_>>().join(", ");
+    let same_column = if has("column") { "AND arrow_cast(M.column, 'Utf8') IS NOT DISTINCT FROM I.column" } else { "" };
+    let order       = if coordinates && has("sample") { "ORDER BY sample,nuc_start,nuc_end" } else { "" };
+    let query = format!("
+        SELECT DISTINCT {fields}
+        FROM {table} M
+        LEFT JOIN indels I
+        ON arrow_cast(M.mutation, 'Utf8') = I.mutation {same_column}
+        {order}");
+    let ctx = crate::replace_table(ctx, name, &query).await?;
+    ctx.deregister_table("indels")?;
+
+    Ok(ctx)
+}
+
+/// Parse a nucleotide indel in nextclade notation, as its column, 1-based start and end, and inserted bases.
+///
+/// Deletions are a position or a range (ex. `6513`, `6513-6515`), and insertions follow
+/// a position (ex. `28933:T`). Other mutations (ex. `C241T`, `S:H69-`) are `None`.
+pub fn parse_indel(mutation: &str) -> Option<(&'static str, u32, u32, &str)> {
+    let number = |text: &str| text.bytes().all(|b| b.is_ascii_digit()).then(|| text.parse::<u32>().ok()).flatten();
+    match (mutation.split_once(':'), mutation.split_once('-')) {
+        (Some((position, inserted)), _) => {
+            let position = number(position)?;
+            let is_bases = !inserted.is_empty() && inserted.bytes().all(|b| b.is_ascii_alphabetic());
+            is_bases.then_some(("insertions", position, position, inserted))
+        },
+        (None, Some((start, end))) => Some(("deletions", number(start)?, number(end)?, "")),
+        (None, None)               => Some(("deletions", number(mutation)?, number(mutation)?, "")),
+    }
+}
+
+/// Shift a deletion (1-based, inclusive) to its left-most equivalent position.
+pub fn left_align_deletion(reference: &[u8], mut start: u32, mut end: u32) -> (u32, u32) {
+    // The deletion can shift left while the base before it matches the last deleted base
+    while start > 1 && reference.get(start as usize - 2) == reference.get(end as usize - 1) && reference.get(end as usize - 1).is_some() {
+        start -= 1;
+        end   -= 1;
+    }
+    (start, end)
+}
+
+/// Shift an insertion (inserted after a 1-based position) to its left-most equivalent position.
+pub fn left_align_insertion(reference: &[u8], mut position: u32, inserted: &str) -> (u32, String) {
+    let mut inserted = inserted.as_bytes().to_vec();
+    // The insertion can shift left while the base at its anchor matches the last inserted base
+    while position > 0 && !inserted.is_empty() && reference.get(position as usize - 1) == inserted.last() {
+        inserted.rotate_right(1);
+        position -= 1;
+    }
+    (position, String::from_utf8_lossy(&inserted).to_string())
+}
+
+/// Trim the shared bases of VCF-style alleles, then left-align the remaining indel.
+///
+/// Returns the 1-based position and alleles of the minimal representation, where
+/// indels keep a single shared anchor base, as required by VCF.
+pub fn trim_alleles(reference: &[u8], mut position: u32, alleles: (&str, &str)) -> (u32, String, String) {
+    let (mut ref_allele, mut alt_allele) = (alleles.0.as_bytes().to_vec(), alleles.1.as_bytes().to_vec());
+
+    // Trim the common suffix, then the common prefix, keeping one anchor base
+    while ref_allele.len() > 1 && alt_allele.len() > 1 && ref_allele.last() == alt_allele.last() {
+        ref_allele.pop();
+        alt_allele.pop();
+    }
+    while ref_allele.len() > 1 && alt_allele.len() > 1 && ref_allele.first() == alt_allele.first() {
+        ref_allele.remove(0);
+        alt_allele.remove(0);
+        position += 1;
+    }
+
+    // Left-align the indel after the anchor base
+    let to_string = |bases: &[u8]| String::from_utf8_lossy(bases).to_string();
+    match (ref_allele.len(), alt_allele.len()) {
+        (r, 1) if r > 1 => {
+            let (start, end) = left_align_deletion(reference, position + 1, position + r as u32 - 1);
+            let anchor       = (start - 1).checked_sub(1).map(|i| i as usize);
+            let ref_bases    = anchor.and_then(|i| reference.get(i..end as usize)).map(to_string);
+            let alt_bases    = anchor.and_then(|i| reference.get(i..i + 1)).map(to_string);
+            match (ref_bases, alt_bases) {
+                (Some(r), Some(a)) => (start - 1, r, a),
+                _                  => (position, to_string(&ref_allele), to_string(&alt_allele)),
+            }
+        },
+        (1, a) if a > 1 => {
+            let (anchor, inserted) = left_align_insertion(reference, position, &to_string(&alt_allele[1..]));
+            let base = anchor.checked_sub(1).and_then(|i| reference.get(i as usize..anchor as usize)).map(to_string);
+            match base {
+                Some(base) => (anchor, base.clone(), format!("{base}{inserted}")),
+                None       => (position, to_string(&ref_allele), to_string(&alt_allele)),
+            }
+        },
+        _ => (position, to_string(&ref_allele), to_string(&alt_allele)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A run of four Ts at 4-7 (1-based)
+    const REFERENCE: &[u8] = b"ACGTTTTGCA";
+
+    #[test]
+    fn left_align_deletions() {
+        assert_eq!(left_align_deletion(REFERENCE, 7, 7), (4, 4));
+        assert_eq!(left_align_deletion(REFERENCE, 6, 7), (4, 5));
+        assert_eq!(left_align_deletion(REFERENCE, 2, 2), (2, 2));
+        // The start of the reference, past its end, and an empty reference can't shift
+        assert_eq!(left_align_deletion(b"AACG", 2, 2), (1, 1));
+        assert_eq!(left_align_deletion(REFERENCE, 20, 20), (20, 20));
+        assert_eq!(left_align_deletion(b"", 5, 5), (5, 5));
+    }
+
+    #[test]
+    fn left_align_insertions() {
+        assert_eq!(left_align_insertion(REFERENCE, 7, "T"),  (3, "T".to_string()));
+        assert_eq!(left_align_insertion(REFERENCE, 8, "TG"), (6, "TG".to_string()));
+        assert_eq!(left_align_insertion(REFERENCE, 2, "A"),  (2, "A".to_string()));
+        assert_eq!(left_align_insertion(REFERENCE, 0, "A"),  (0, "A".to_string()));
+        assert_eq!(left_align_insertion(REFERENCE, 7, ""),   (7, String::new()));
+    }
+
+    #[test]
+    fn trim_vcf_alleles() {
+        let alleles = |position, r: &str, a: &str| (position, r.to_string(), a.to_string());
+        assert_eq!(trim_alleles(REFERENCE, 3, ("GT", "G")),   alleles(3, "GT", "G"));
+        assert_eq!(trim_alleles(REFERENCE, 6, ("TT", "T")),   alleles(3, "GT", "G"));
+        assert_eq!(trim_alleles(REFERENCE, 6, ("TTG", "TG")), alleles(3, "GT", "G"));
+        assert_eq!(trim_alleles(REFERENCE, 7, ("T", "TT")),   alleles(3, "G", "GT"));
+        assert_eq!(trim_alleles(REFERENCE, 4, ("TTG", "TCG")), alleles(5, "T", "C"));
+        assert_eq!(trim_alleles(REFERENCE, 2, ("C", "T")),     alleles(2, "C", "T"));
+        // A deletion that shifts to the first base has no anchor, and is kept as written
+        assert_eq!(trim_alleles(b"AACG", 1, ("AA", "A")),     alleles(1, "AA", "A"));
+    }
+
+    #[test]
+    fn parse_indels() {
+        assert_eq!(parse_indel("6513-6515"), Some(("deletions", 6513, 6515, "")));
+        assert_eq!(parse_indel("6513"),      Some(("deletions", 6513, 6513, "")));
+        assert_eq!(parse_indel("28933:TA"),  Some(("insertions", 28933, 28933, "TA")));
+        for mutation in ["C241T", "S:H69-", "S:214:EPE", "28933:", "-5", ""] {
+            assert_eq!(parse_indel(mutation), None, "{mutation}");
+        }
+    }
+
+    #[tokio::test]
+    async fn normalize_annotations_without_column() -> Result<(), Report> {
+        let ctx = SessionContext::new();
+        ctx.sql("CREATE TABLE annotations AS SELECT column1 as mutation, column2 as info FROM (VALUES
+            ('6-7', 'deletion'),
+            ('7:T', 'insertion'),
+            ('C9T', 'substitution'))").await?.collect().await?;
+        let ctx = normalize(ctx, "annotations", REFERENCE).await?;
+
+        let batches   = ctx.sql("SELECT mutation, info FROM annotations ORDER BY info").await?.collect().await?;
+        let batch     = &batches[0];
+        let mutations = crate::string_column(batch, "mutation")?;
+        assert_eq!(mutations.iter().flatten().collect::<Vec<_>>(), ["4-5", "3:T", "C9T"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn normalize_removes_duplicates() -> Result<(), Report> {
+        let ctx = SessionContext::new();
+        ctx.sql("CREATE TABLE mutations AS SELECT column1 as sample, column2 as mutation, column3 as column, column4 as nuc_start, column5 as nuc_end FROM (VALUES
+            ('s1', '7',    'deletions',     7, 7),
+            ('s1', '5',    'deletions',     5, 5),
+            ('s2', '7:T',  'insertions',    7, 7),
+            ('s2', 'C9T',  'substitutions', 9, 9))").await?.collect().await?;
+        let ctx = normalize(ctx, "mutations", REFERENCE).await?;
+
+        let batches   = ctx.sql("SELECT sample, mutation, nuc_start FROM mutations ORDER BY sample, nuc_start").await?.collect().await?;
+        let batch     = &batches[0];
+        let mutations = crate::string_column(batch, "mutation")?;
+        assert_eq!(mutations.iter().flatten().collect::<Vec<_>>(), ["4", "3:T", "C9T"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use color_eyre::eyre::{eyre, Report, Result};
+use datafusion::prelude::*;
+use std::path::Path;
+
+/// Minimum allele frequency for a variant to be considered part of the consensus.
+///
+/// This matches the majority rule of `ivar consensus` with its default threshold.
+pub const CONSENSUS_FREQ: f64 = 0.5;
+
+/// Register one or more ivar variants tables as a mutations table.
+///
+/// Each file is one sample, named by its file stem. Every variant is given a
+/// `status` of `consensus` (frequency of at least [`CONSENSUS_FREQ`]), `low-frequency`,
+/// or `absent` when it doesn't pass the depth and frequency thresholds.
+///
+/// # Arguments
+///
+///   - `paths`    : File paths to ivar variants tsv output.
+///   - `ctx`      : The session to register the table in.
+///   - `name`     : The name of the mutations table.
+///   - `min_depth`: Minimum total depth at the variant position.
+///   - `min_freq` : Minimum alternate allele frequency.
+///
+pub async fn register<P>(paths: &[P], ctx: SessionContext, name: &str, min_depth: u32, min_freq: f64) -> Result<SessionContext, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    if paths.is_empty() {
+        return Err(eyre!("No ivar variants files were provided."))
+    }
+
+    let mut ctx     = ctx;
+    let mut queries = Vec::new();
+
+    for (i, path) in paths.iter().enumerate() {
+        log::info!("Reading ivar variants file: {path:?}");
+        let sample = path.as_ref().file_stem().and_then(|s| s.to_str()).ok_or(eyre!("Failed to parse sample name from file: {path:?}"))?;
+        let sample = crate::quote_literal(sample.trim_end_matches(".variants"));
+        let table  = format!("ivar_{i}");
+        ctx = crate::register_csv(path, ctx, Some(b'\t'), &table).await?;
+
+        let status = format!("
+            CASE
+                WHEN depth < {min_depth} OR frequency < {min_freq} THEN 'absent'
+                WHEN frequency >= {CONSENSUS_FREQ} THEN 'consensus'
+                ELSE 'low-frequency'
+            END");
+
+        // Nucleotide variants, in nextclade notation: C241T, 28881:GG, 21765-21770
+        let query = format!("
+            SELECT
+                {sample} as sample,
+                CASE
+                    WHEN starts_with(alt, '+') THEN concat(pos, ':', substr(alt, 2))
+                    WHEN starts_with(alt, '-') AND length(alt) = 2 THEN arrow_cast(pos + 1, 'Utf8')
+                    WHEN starts_with(alt, '-') THEN concat(pos + 1, '-', pos + length(alt) - 1)
+                    ELSE concat(ref, pos, alt)
+                END as mutation,
+                CASE
+                    WHEN starts_with(alt, '+') THEN 'insertions'
+                    WHEN starts_with(alt, '-') THEN 'deletions'
+                    ELSE 'substitutions'
+                END as column,
+                gene, frequency, depth
+            FROM (
+                SELECT
+                    arrow_cast(\"POS\", 'Int64') as pos,
+                    arrow_cast(\"REF\", 'Utf8') as ref,
+                    arrow_cast(\"ALT\", 'Utf8') as alt,
+                    arrow_cast(NULL, 'Utf8') as gene,
+                    arrow_cast(\"ALT_FREQ\", 'Float64') as frequency,
+                    arrow_cast(\"TOTAL_DP\", 'UInt32') as depth
+                FROM {table}
+            )");
+        let query = format!("SELECT DISTINCT *, {status} as status FROM ({query})");
+        queries.push(query);
+
+        // Amino acid variants, only reported by ivar versions with the POS_AA column
+        let columns = crate::column_names(&ctx.table(&table).await?);
+        if columns.iter().any(|c| c == "POS_AA") {
+            let query = format!("
+                SELECT DISTINCT
+                    {sample} as sample,
+                    concat(gene, ':', ref_aa, pos_aa, alt_aa) as mutation,
+                    'aaSubstitutions' as column,
+                    gene, frequency, depth
+                FROM (
+                    SELECT
+                        arrow_cast(\"GFF_FEATURE\", 'Utf8') as gene,
+                        arrow_cast(\"REF_AA\", 'Utf8') as ref_aa,
+                        arrow_cast(\"POS_AA\", 'Utf8') as pos_aa,
+                        arrow_cast(\"ALT_AA\", 'Utf8') as alt_aa,
+                        arrow_cast(\"ALT_FREQ\", 'Float64') as frequency,
+                        arrow_cast(\"TOTAL_DP\", 'UInt32') as depth
+                    FROM {table}
+                )
+                WHERE gene IS NOT NULL AND gene != 'NA' AND ref_aa IS NOT NULL AND alt_aa IS NOT NULL AND ref_aa != alt_aa");
+            let query = format!("SELECT *, {status} as status FROM ({query})");
+            queries.push(query);
+        }
+    }
+
+    let query = queries.join(" UNION ALL ");
+    ctx.sql(&format!("CREATE TABLE {} AS SELECT * FROM ({query}) ORDER BY sample, mutation", crate::quote_identifier(name))).await?.collect().await?;
+    for i in 0..paths.len() {
+        ctx.deregister_table(format!("ivar_{i}"))?;
+    }
+
+    Ok(ctx)
+}
@@ -1,31 +1,108 @@
-use arrow::array::{StringArray, UInt32Array};
+use arrow::array::{ArrayRef, AsArray, StringArray, UInt32Array};
 use arrow::datatypes::DataType;
 use arrow::record_batch::RecordBatch;
 use color_eyre::eyre::{eyre, Report, Result};
 use datafusion::arrow::datatypes::{Field, Schema};
+use datafusion::common::parsers::CompressionTypeVariant;
+use datafusion::config::CsvOptions;
+use datafusion::dataframe::DataFrameWriteOptions;
 use datafusion::datasource::MemTable;
 use datafusion::prelude::*;
-use noodles::gff;
+use noodles::{bcf, fasta, vcf};
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
-use std::io::BufReader;
+use std::io::{BufRead, BufReader};
 use std::sync::Arc;
 
+pub mod annotate;
+pub mod catalog;
+pub mod codon;
 pub mod convert;
+pub mod coverage;
+#[cfg(feature = "delta")]
+pub mod delta;
+pub mod distance;
+pub mod diversity;
+pub mod export;
+pub mod gene;
 pub mod extract;
 #[cfg(feature = "plot")]
+pub mod font;
+pub mod freyja;
+pub mod hgvs;
+pub mod indel;
+pub mod ivar;
+pub mod mnv;
+pub mod nextclade;
+pub mod notation;
+pub mod number;
+#[cfg(feature = "plot")]
 pub mod plot;
+pub mod primers;
+pub mod qc;
+pub mod query;
+pub mod selection;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod sweep;
+pub mod tree;
 
+#[doc(inline)]
+pub use crate::annotate::annotate;
 #[doc(inline)]
 pub use crate::convert::convert;
 #[doc(inline)]
+pub use crate::distance::distances;
+#[doc(inline)]
+pub use crate::diversity::diversity;
+#[doc(inline)]
 pub use crate::extract::extract;
 #[cfg(feature = "plot")]
 pub use crate::plot::plot;
+#[doc(inline)]
+pub use crate::query::query;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum OutputFormat {
     Tsv,
-    Parquet
+    Parquet,
+    Sqlite,
+    Delta,
+    Arrow,
+    Json,
+    Ndjson,
+}
+
+impl OutputFormat {
+    /// The file extension of the format, which [`write_table`] uses to pick the writer.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Tsv     => "tsv",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Sqlite  => "sqlite",
+            OutputFormat::Delta   => "delta",
+            OutputFormat::Arrow   => "arrow",
+            OutputFormat::Json    => "json",
+            OutputFormat::Ndjson  => "ndjson",
+        }
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = Report;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "tsv"                       => Ok(OutputFormat::Tsv),
+            "parquet"                   => Ok(OutputFormat::Parquet),
+            "sqlite" | "db"             => Ok(OutputFormat::Sqlite),
+            "delta"                     => Ok(OutputFormat::Delta),
+            "arrow" | "feather" | "ipc" => Ok(OutputFormat::Arrow),
+            "json"                      => Ok(OutputFormat::Json),
+            "ndjson" | "jsonl"          => Ok(OutputFormat::Ndjson),
+            _                           => Err(eyre!("Unknown output format: {format}, please choose from: tsv, parquet, sqlite, delta, arrow, json, ndjson")),
+        }
+    }
 }
 
 /// Light wrapper around datafusions register_csv.
@@ -36,9 +113,12 @@ where
 {
     // Convert the csv path to a plain string, and identify the extension and delimiter
     // This is needed to make datafusion happy.
+    register_object_store(&ctx, path)?;
+    let (compression, suffix) = parse_compression(path);
     let (path, ext, delimiter) = parse_csv_path(path, delimiter)?;
     // Use our dynamically detected extensions and delimiter to configure the reader
-    let read_options = CsvReadOptions::new().file_extension(&ext).delimiter(delimiter);  
+    let ext          = format!("{ext}{suffix}");
+    let read_options = CsvReadOptions::new().file_extension(&ext).delimiter(delimiter).file_compression_type(compression.into());
     // Register the csv as dataframe that can accept SQL queries.
     ctx.register_csv(&name.to_string(), &path, read_options).await?;
     Ok(ctx)
@@ -51,14 +131,557 @@ where
 {
     // Convert the csv path to a plain string, and identify the extension and delimiter
     // This is needed to make datafusion happy.
+    register_object_store(ctx, path)?;
+    let (compression, suffix) = parse_compression(path);
     let (path, ext, delimiter) = parse_csv_path(path, delimiter)?;
     // Use our dynamically detected extensions and delimiter to configure the reader
-    let read_options = CsvReadOptions::new().file_extension(&ext).delimiter(delimiter);  
+    let ext          = format!("{ext}{suffix}");
+    let read_options = CsvReadOptions::new().file_extension(&ext).delimiter(delimiter).file_compression_type(compression.into());
     // Register the csv as dataframe that can accept SQL queries.
     let df = ctx.read_csv(path, read_options).await?;
     Ok(df)
 }
 
+/// Read the first sheet of an Excel workbook (xlsx or xls), with a header row, as a dataframe of strings.
+///
+/// Empty cells are null, and numbers are written as they would be in a text table (ex. 1.0 as `1`).
+#[cfg(feature = "xlsx")]
+pub fn read_xlsx<P>(path: &P, ctx: &SessionContext) -> Result<DataFrame, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    use calamine::Reader;
+
+    let mut workbook = calamine::open_workbook_auto(path)?;
+    let sheet = workbook.sheet_names().first().cloned().ok_or(eyre!("Excel workbook has no sheets: {path:?}"))?;
+    log::info!("Reading the first sheet of the workbook: {sheet}");
+    let range = workbook.worksheet_range(&sheet)?;
+
+    let mut rows = range.rows();
+    let header   = rows.next().ok_or(eyre!("Excel sheet {sheet} is empty: {path:?}"))?;
+    let header   = header.iter().map(|cell| cell.to_string().trim().to_string()).collect::<Vec<_>>();
+    let mut values: Vec<Vec<Option<String>>> = vec![Vec::new(); header.len()];
+    for row in rows {
+        for (i, column) in values.iter_mut().enumerate() {
+            let value = row.get(i).map(|cell| cell.to_string()).filter(|v| !v.is_empty());
+            column.push(value);
+        }
+    }
+
+    let schema  = Arc::new(Schema::new(header.iter().map(|h| Field::new(h, DataType::Utf8, true)).collect::<Vec<_>>()));
+    let columns = values.into_iter().map(|v| Arc::new(StringArray::from(v)) as ArrayRef).collect::<Vec<_>>();
+    let batch   = RecordBatch::try_new(schema, columns)?;
+    Ok(ctx.read_batch(batch)?)
+}
+
+/// Read the first sheet of an Excel workbook, which requires the xlsx feature.
+#[cfg(not(feature = "xlsx"))]
+pub fn read_xlsx<P>(path: &P, _ctx: &SessionContext) -> Result<DataFrame, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    Err(eyre!("Excel input can't be read, because the xlsx feature is not enabled: {path:?}"))
+}
+
+/// Register an annotations table, normalizing HGVS and SPDI mutations to nextclade shorthand.
+///
+/// The original mutation text is kept in the `original_mutation` column. If a `gff` table
+/// is already registered, its gene coordinates are used to convert HGVS coding positions.
+pub async fn register_annotations<P,N>(path: &P, ctx: SessionContext, name: N) -> Result<SessionContext, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+    N: ToString,
+{
+    log::info!("Reading annotations file: {path:?}");
+
+    // Gene coordinates for HGVS coding positions
+    let mut gene_starts: HashMap<String, u32> = HashMap::new();
+    if ctx.table_exist("gff")? {
+        let batches = ctx.sql("SELECT name, MIN(start) as start FROM gff WHERE type = 'gene' GROUP BY name").await?.collect().await?;
+        for batch in &batches {
+            let names  = string_column(batch, "name")?;
+            let starts = string_column(batch, "start")?;
+            for (name, start) in names.iter().zip(starts.iter()) {
+                if let (Some(name), Some(start)) = (name, start) { gene_starts.insert(name.to_string(), start.parse()?); }
+            }
+        }
+    }
+
+    let xlsx    = path.as_ref().extension().is_some_and(|ext| ext == "xlsx" || ext == "xls");
+    let df      = match xlsx {
+        true  => read_xlsx(path, &ctx)?,
+        false => read_csv(path, &ctx, None).await?,
+    };
+    let schema  = df.schema().as_arrow().clone();
+    let batches = df.collect().await?;
+
+    let missing = mutation_core::missing_columns(&schema, &mutation_core::annotations_schema());
+    if !missing.is_empty() {
+        return Err(eyre!("Annotations file is missing the required columns {missing:?}: {path:?}"))
+    }
+    let has_column = schema.column_with_name("column").is_some();
+
+    // The mutation and column fields are replaced with their normalized values
+    let mut fields = schema.fields().iter().map(|f| match f.name().as_str() {
+        "mutation" | "column" => Arc::new(Field::new(f.name(), DataType::Utf8, true)),
+        _                     => f.clone(),
+    }).collect::<Vec<_>>();
+    if !has_column { fields.push(Arc::new(Field::new("column", DataType::Utf8, true))) }
+    fields.push(Arc::new(Field::new("original_mutation", DataType::Utf8, true)));
+    let output_schema = Arc::new(Schema::new(fields));
+
+    let batches = batches.iter().map(|batch| {
+        let original = string_column(batch, "mutation")?;
+        let columns  = if has_column { Some(string_column(batch, "column")?) } else { None };
+
+        let mut mutation: Vec<Option<String>> = Vec::new();
+        let mut column:   Vec<Option<String>> = Vec::new();
+        for i in 0..batch.num_rows() {
+            let given_column = columns.as_ref().filter(|c| c.is_valid(i)).map(|c| c.value(i).to_string());
+            if original.is_null(i) { mutation.push(None); column.push(given_column); continue }
+            match crate::notation::normalize(original.value(i), &gene_starts) {
+                Ok((normalized, implied)) => {
+                    if normalized != original.value(i) { log::debug!("Normalized annotation {} to {normalized}", original.value(i)) }
+                    mutation.push(Some(normalized));
+                    column.push(given_column.or(implied.map(String::from)));
+                },
+                Err(e) => {
+                    log::warn!("{e} The annotation will be matched as written.");
+                    mutation.push(Some(original.value(i).to_string()));
+                    column.push(given_column);
+                },
+            }
+        }
+
+        let mut arrays: Vec<ArrayRef> = batch.schema().fields().iter().zip(batch.columns()).map(|(f, c)| match f.name().as_str() {
+            "mutation" => Arc::new(StringArray::from(mutation.clone())) as ArrayRef,
+            "column"   => Arc::new(StringArray::from(column.clone())) as ArrayRef,
+            _          => c.clone(),
+        }).collect();
+        if !has_column { arrays.push(Arc::new(StringArray::from(column))) }
+        arrays.push(Arc::new(original));
+
+        Ok(RecordBatch::try_new(output_schema.clone(), arrays)?)
+    }).collect::<Result<Vec<_>, Report>>()?;
+
+    let provider = MemTable::try_new(output_schema, vec![batches])?;
+    ctx.register_table(&name.to_string(), Arc::new(provider))?;
+
+    Ok(ctx)
+}
+
+/// Register a mutations table produced by `extract`, either parquet or delimited text.
+pub async fn register_mutations<P,N>(path: &P, ctx: SessionContext, name: N) -> Result<SessionContext, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+    N: ToString,
+{
+    log::info!("Reading mutations file: {path:?}");
+    let ext = path.as_ref().extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+    match ext {
+        "parquet"                   => {
+            register_object_store(&ctx, path)?;
+            let path = path.as_ref().to_str().ok_or(eyre!("Failed to parse file path: {:?}", path))?;
+            ctx.register_parquet(&name.to_string(), path, ParquetReadOptions::default()).await?;
+            Ok(ctx)
+        },
+        #[cfg(feature = "sqlite")]
+        "sqlite" | "db"             => crate::sqlite::register(path, ctx, name).await,
+        #[cfg(not(feature = "sqlite"))]
+        "sqlite" | "db"             => Err(eyre!("SQLite input can't be read, because the sqlite feature is not enabled: {path:?}")),
+        #[cfg(feature = "delta")]
+        "delta"                     => crate::delta::register(path, ctx, name).await,
+        #[cfg(not(feature = "delta"))]
+        "delta"                     => Err(eyre!("Delta tables can't be read, because the delta feature is not enabled: {path:?}")),
+        "arrow" | "feather" | "ipc" => {
+            let path      = path.as_ref().to_str().ok_or(eyre!("Failed to parse file path: {:?}", path))?;
+            let extension = format!(".{ext}");
+            ctx.register_arrow(&name.to_string(), path, ArrowReadOptions { file_extension: &extension, ..Default::default() }).await?;
+            Ok(ctx)
+        },
+        "json" if is_json_array(path)? => register_json_array(path, ctx, name),
+        "json" | "ndjson" | "jsonl" => {
+            let path      = path.as_ref().to_str().ok_or(eyre!("Failed to parse file path: {:?}", path))?;
+            let extension = format!(".{ext}");
+            ctx.register_json(&name.to_string(), path, NdJsonReadOptions { file_extension: &extension, ..Default::default() }).await?;
+            Ok(ctx)
+        },
+        _                           => register_csv(path, ctx, None, name).await,
+    }
+}
+
+/// Whether a json file holds one array of records (`[{...}, {...}]`), rather than one record per line.
+fn is_json_array<P>(path: &P) -> Result<bool, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let mut reader = open(path)?;
+    let first      = reader.fill_buf()?.iter().find(|b| !b.is_ascii_whitespace()).copied();
+    Ok(first == Some(b'['))
+}
+
+/// Register a json array of records (ex. a mutations table written as `.json`), with the schema inferred from the records.
+fn register_json_array<P,N>(path: &P, ctx: SessionContext, name: N) -> Result<SessionContext, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+    N: ToString,
+{
+    let records: Vec<serde_json::Value> = serde_json::from_reader(open(path)?)?;
+    let schema  = Arc::new(arrow::json::reader::infer_json_schema_from_iterator(records.iter().map(Ok))?);
+    let mut decoder = arrow::json::ReaderBuilder::new(schema.clone()).build_decoder()?;
+    let mut batches = Vec::new();
+    for chunk in records.chunks(1024) {
+        decoder.serialize(chunk)?;
+        if let Some(batch) = decoder.flush()? { batches.push(batch) }
+    }
+
+    let provider = MemTable::try_new(schema, vec![batches])?;
+    ctx.register_table(&name.to_string(), Arc::new(provider))?;
+
+    Ok(ctx)
+}
+
+/// Write a dataframe to a file, with the format detected from the extension (tsv, csv, parquet, sqlite, delta, arrow, json, ndjson).
+///
+/// Delimited text is compressed if the path ends in a compression extension (ex. `mutations.tsv.gz`).
+/// SQLite databases (`.sqlite` or `.db`) get a table named by the file stem (ex. `mutations`).
+/// Delta tables (`.delta` directories) are appended to, rather than replaced. Arrow IPC files
+/// (`.arrow`, `.feather`, or `.ipc`) keep the schema exactly, for Python and R consumers. JSON
+/// (`.json`) is written as one array of objects, for web dashboards, and NDJSON (`.ndjson` or
+/// `.jsonl`) as one object per line, for streaming. Missing directories are created.
+pub async fn write_table<P>(df: DataFrame, path: &P) -> Result<(), Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let (compression, _) = parse_compression(path);
+    // Create the output directory (ex. of an extract --prefix), unless it's a url
+    if let Some(parent) = path.as_ref().parent().filter(|p| !p.as_os_str().is_empty() && !p.to_string_lossy().contains("://")) {
+        std::fs::create_dir_all(parent)?;
+    }
+    let (path, ext, delimiter) = parse_csv_path(path, None)?;
+    let write_options = DataFrameWriteOptions::default();
+    match ext.as_str() {
+        #[cfg(feature = "sqlite")]
+        "sqlite" | "db" => crate::sqlite::write(df, &path).await?,
+        #[cfg(not(feature = "sqlite"))]
+        "sqlite" | "db" => return Err(eyre!("SQLite output can't be written, because the sqlite feature is not enabled: {path:?}")),
+        #[cfg(feature = "delta")]
+        "delta"         => crate::delta::write(df, &path).await?,
+        #[cfg(not(feature = "delta"))]
+        "delta"         => return Err(eyre!("Delta tables can't be written, because the delta feature is not enabled: {path:?}")),
+        "arrow" | "feather" | "ipc" => {
+            let schema     = df.schema().as_arrow().clone();
+            let batches    = df.collect().await?;
+            let mut writer = arrow::ipc::writer::FileWriter::try_new(std::fs::File::create(&path)?, &schema)?;
+            for batch in &batches { writer.write(batch)? }
+            writer.finish()?;
+        },
+        "json" => {
+            let batches    = df.collect().await?;
+            let mut writer = arrow::json::ArrayWriter::new(std::io::BufWriter::new(std::fs::File::create(&path)?));
+            writer.write_batches(&batches.iter().collect::<Vec<_>>())?;
+            writer.finish()?;
+        },
+        "ndjson" | "jsonl" => df.write_json(&path, write_options, None).await.map(|_| ())?,
+        "parquet" => {
+            // Sample and mutation names repeat on every row, and the parquet writer dictionary
+            // encodes them by default, though they're read back as plain (not dictionary) strings.
+            df.write_parquet(&path, write_options, None).await?;
+        },
+        _ => {
+            let csv_options = CsvOptions::default().with_delimiter(delimiter).with_file_compression_type(compression);
+            df.write_csv(&path, write_options, Some(csv_options)).await?;
+        },
+    }
+    Ok(())
+}
+
+/// Quote an identifier (ex. a table or column name) for generated SQL.
+///
+/// Quoted identifiers keep their case, and may contain spaces, dots, and quotes.
+pub fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Quote a string literal for generated SQL.
+pub fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Materialize the results of a query, and register them in place of an existing table.
+pub async fn replace_table(ctx: SessionContext, name: &str, query: &str) -> Result<SessionContext, Report> {
+    let df      = ctx.sql(query).await?;
+    let schema  = Arc::new(df.schema().as_arrow().clone());
+    let batches = df.collect().await?;
+    ctx.deregister_table(name)?;
+    let provider = MemTable::try_new(schema, vec![batches])?;
+    ctx.register_table(name, Arc::new(provider))?;
+    Ok(ctx)
+}
+
+/// Warn that a requested plot (with an output `prefix`) isn't rendered, because the plot feature is not enabled.
+#[cfg(not(feature = "plot"))]
+pub(crate) fn warn_without_plot(prefix: Option<&str>, plot: &str) {
+    if prefix.is_some() {
+        log::warn!("The {plot} can't be rendered, because the plot feature is not enabled.");
+    }
+}
+
+/// The hex-encoded SHA-256 digest of some bytes, which is stable across builds and platforms (unlike [`std::hash::Hash`]).
+pub fn sha256(bytes: &[u8]) -> String {
+    use sha2::Digest;
+    sha2::Sha256::digest(bytes).iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// The unqualified column names of a dataframe.
+pub fn column_names(df: &DataFrame) -> Vec<String> {
+    df.schema().fields().iter().map(|f| f.name().to_string()).collect()
+}
+
+/// Retrieve a column from a record batch as strings, regardless of its original data type.
+pub fn string_column(batch: &RecordBatch, name: &str) -> Result<StringArray, Report> {
+    let column = batch.column_by_name(name).ok_or(eyre!("Column {name} was not found in the record batch."))?;
+    let column = arrow::compute::cast(column, &DataType::Utf8)?;
+    Ok(column.as_string::<i32>().clone())
+}
+
+/// Expand input paths that contain glob patterns (ex. `runs/*.tsv`), in sorted order.
+///
+/// Paths without a pattern are kept as they are, so `-` and urls pass through.
+/// A pattern that matches nothing is an error, rather than silently reading no input.
+pub fn expand_globs<P>(paths: &[P]) -> Result<Vec<PathBuf>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let mut expanded = Vec::new();
+    for path in paths {
+        let pattern = path.as_ref().to_string_lossy();
+        if !pattern.contains(['*', '?', '[']) {
+            expanded.push(path.as_ref().to_path_buf());
+            continue
+        }
+        let mut matches = glob::glob(&pattern)?.collect::<Result<Vec<_>, _>>()?;
+        if matches.is_empty() {
+            return Err(eyre!("No files matched the pattern: {path:?}"))
+        }
+        matches.sort();
+        log::info!("Expanded {path:?} to {} files.", matches.len());
+        expanded.extend(matches);
+    }
+    Ok(expanded)
+}
+
+/// Read a list of sample names, one per line.
+///
+/// Blank lines and lines starting with `#` are skipped, and only the first
+/// tab-delimited field is used, so the first column of a table also works.
+pub fn read_sample_list<P>(path: P) -> Result<std::collections::HashSet<String>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Reading sample list: {path:?}");
+    let mut samples = std::collections::HashSet::new();
+    for line in open(&path)?.lines() {
+        let line = line?;
+        let sample = line.split('\t').next().unwrap_or_default().trim();
+        if sample.is_empty() || sample.starts_with('#') { continue }
+        samples.insert(sample.to_string());
+    }
+    Ok(samples)
+}
+
+/// Read a per-sample metadata table into a map of sample -> (column -> value).
+///
+/// The key column identifies the sample, and defaults to the first column of the table.
+/// Each sample must have only one row.
+pub async fn read_metadata<P>(path: &P, key: Option<&str>) -> Result<std::collections::BTreeMap<String, std::collections::BTreeMap<String, String>>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Reading metadata file: {path:?}");
+
+    let ctx     = SessionContext::new();
+    let df      = read_csv(path, &ctx, None).await?;
+    let columns = column_names(&df);
+    let key     = match key {
+        Some(key) => key.to_string(),
+        None      => columns.first().ok_or(eyre!("Metadata file has no columns: {path:?}"))?.to_string(),
+    };
+    if !columns.contains(&key) {
+        return Err(eyre!("Metadata key column {key} was not found in: {path:?}"))
+    }
+
+    let mut metadata = std::collections::BTreeMap::new();
+    for batch in df.collect().await? {
+        let samples = string_column(&batch, &key)?;
+        let values  = columns.iter().filter(|c| **c != key).map(|c| Ok((c, string_column(&batch, c)?))).collect::<Result<Vec<_>, Report>>()?;
+        for i in 0..batch.num_rows() {
+            if samples.is_null(i) { continue }
+            let attributes = values.iter().filter(|(_, v)| v.is_valid(i)).map(|(c, v)| (c.to_string(), v.value(i).to_string())).collect();
+            if metadata.insert(samples.value(i).to_string(), attributes).is_some() {
+                return Err(eyre!("Metadata key column {key} has more than one row for {}: {path:?}", samples.value(i)))
+            }
+        }
+    }
+
+    Ok(metadata)
+}
+
+/// Parse comma-separated coordinate ranges (ex. 1-54,3874-4816,29168) into (start, end) pairs.
+pub fn parse_ranges(ranges: &str) -> Result<Vec<(u32, u32)>, Report> {
+    ranges
+        .split(',')
+        .map(str::trim)
+        .filter(|r| !r.is_empty())
+        .map(|range| {
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            let start: u32 = start.parse().map_err(|_| eyre!("Failed to parse range: {range}"))?;
+            let end:   u32 = end.parse().map_err(|_| eyre!("Failed to parse range: {range}"))?;
+            Ok((start, end))
+        })
+        .collect()
+}
+
+/// Parse a comma-separated column mapping into (name, column) pairs, where each column is renamed to its name.
+///
+/// The new name comes first, like an assignment: `sample=SequenceName,mutation=AAChange` renames
+/// `SequenceName` to `sample` and `AAChange` to `mutation`.
+pub fn parse_column_map(map: &str) -> Result<Vec<(String, String)>, Report> {
+    map
+        .split(',')
+        .map(str::trim)
+        .filter(|m| !m.is_empty())
+        .map(|mapping| {
+            let (name, column) = mapping.split_once('=').ok_or(eyre!("Failed to parse column mapping, expected name=column: {mapping}"))?;
+            Ok((name.trim().to_string(), column.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Rename the columns of a dataframe, from (name, column) pairs (see [`parse_column_map`]).
+///
+/// Columns keep their order, and columns that aren't mapped are kept as they are. A name that
+/// is already a column (ex. an empty `sample` column) is replaced by the mapped one.
+pub fn rename_dataframe(df: DataFrame, map: &[(String, String)]) -> Result<DataFrame, Report> {
+    if map.is_empty() { return Ok(df) }
+
+    let columns = column_names(&df);
+    for (_, column) in map {
+        if !columns.contains(column) {
+            return Err(eyre!("Column {column} of the column mapping was not found, the columns are: {columns:?}"))
+        }
+    }
+    let select = columns.iter().filter_map(|c| match map.iter().find(|(_, column)| column == c) {
+        Some((name, _))                               => Some(ident(c).alias(name)),
+        None if map.iter().any(|(name, _)| name == c) => None,
+        None                                          => Some(ident(c)),
+    }).collect::<Vec<_>>();
+    Ok(df.select(select)?)
+}
+
+/// Rename the columns of a registered table onto the expected names, from (name, column) pairs (see [`rename_dataframe`]).
+pub async fn rename_columns(ctx: SessionContext, name: &str, map: &[(String, String)]) -> Result<SessionContext, Report> {
+    if map.is_empty() { return Ok(ctx) }
+
+    log::info!("Renaming the columns of the table {name}: {map:?}");
+    let df = rename_dataframe(ctx.table(name).await?, map)?;
+    ctx.deregister_table(name)?;
+    ctx.register_table(name, df.into_view())?;
+    Ok(ctx)
+}
+
+/// The case of the column names of output tables.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColumnCase {
+    /// Keep the names as they are, including the nextclade names (ex. `seqName`, `qc.overallStatus`).
+    #[default]
+    Original,
+    /// Lowercase words joined by underscores (ex. `seq_name`, `qc_overall_status`), for R and SQL.
+    Snake,
+    /// Words joined with the first letter of each capitalized, after the first (ex. `seqName`, `qcOverallStatus`).
+    Camel,
+}
+
+impl ColumnCase {
+    /// Convert a column name to the case. Words are split at `_`, `.`, `-`, spaces, and lowercase to uppercase changes.
+    pub fn convert(&self, name: &str) -> String {
+        if *self == ColumnCase::Original { return name.to_string() }
+
+        let mut words: Vec<String> = Vec::new();
+        let mut previous = None;
+        for c in name.chars() {
+            match c {
+                '_' | '.' | '-' | ' ' => { words.push(String::new()); previous = None; continue },
+                _ if c.is_uppercase() && previous.is_some_and(|p: char| p.is_lowercase() || p.is_ascii_digit()) => words.push(String::new()),
+                _ if words.is_empty() => words.push(String::new()),
+                _ => (),
+            }
+            if let Some(word) = words.last_mut() { word.push(c) }
+            previous = Some(c);
+        }
+        let words = words.into_iter().filter(|w| !w.is_empty()).map(|w| w.to_lowercase());
+
+        match self {
+            ColumnCase::Snake => words.collect::<Vec<_>>().join("_"),
+            _                 => words.enumerate().map(|(i, word)| match i {
+                0 => word,
+                _ => word.chars().take(1).flat_map(char::to_uppercase).chain(word.chars().skip(1)).collect(),
+            }).collect(),
+        }
+    }
+}
+
+impl std::str::FromStr for ColumnCase {
+    type Err = Report;
+
+    fn from_str(case: &str) -> Result<Self, Self::Err> {
+        match case {
+            "original" => Ok(ColumnCase::Original),
+            "snake"    => Ok(ColumnCase::Snake),
+            "camel"    => Ok(ColumnCase::Camel),
+            _          => Err(eyre!("Unknown column case: {case}, please choose from: original, snake, camel")),
+        }
+    }
+}
+
+/// How the columns of output tables are named, for downstream code with its own conventions.
+#[derive(Clone, Debug, Default)]
+pub struct ColumnNames {
+    /// The case every column is converted to.
+    pub case: ColumnCase,
+    /// Explicit renames, as (name, column) pairs (ex. `qc_status` from `qc.overallStatus`, see [`parse_column_map`]),
+    /// which take precedence over the case. Renames of columns a table doesn't have are skipped.
+    pub rename: Vec<(String, String)>,
+}
+
+impl ColumnNames {
+    /// The output name of a column.
+    pub fn name(&self, column: &str) -> String {
+        match self.rename.iter().find(|(_, original)| original == column) {
+            Some((name, _)) => name.clone(),
+            None            => self.case.convert(column),
+        }
+    }
+
+    /// Rename the columns of a dataframe to their output names, before it's written.
+    ///
+    /// Two columns with the same output name (ex. `seqName` and `seq_name` in snake case) are an error.
+    pub fn apply(&self, df: DataFrame) -> Result<DataFrame, Report> {
+        if self.case == ColumnCase::Original && self.rename.is_empty() { return Ok(df) }
+
+        let columns = column_names(&df);
+        let names   = columns.iter().map(|c| self.name(c)).collect::<Vec<_>>();
+        for (i, name) in names.iter().enumerate() {
+            if let Some(j) = names[..i].iter().position(|n| n == name) {
+                return Err(eyre!("The output columns {} and {} would both be named {name}, please rename one of them.", columns[j], columns[i]))
+            }
+        }
+
+        let map = names.into_iter().zip(columns).filter(|(name, column)| name != column).collect::<Vec<_>>();
+        rename_dataframe(df, &map)
+    }
+}
+
 pub fn parse_csv_path<P>(path: P, delimiter: Option<u8>) -> Result<(String, String, u8), Report>
 where
     P: AsRef<Path> + std::fmt::Debug
@@ -72,8 +695,13 @@ where
     //         way to convert it ot a plain String.
     let path: PathBuf = path.as_ref().into();
 
-    // Step 2. Parse the file extension ('tsv', 'csv', etc.))
-    let ext = path.extension()
+    // Step 2. Parse the file extension ('tsv', 'csv', etc.)), skipping the compression extension.
+    let (_, suffix) = parse_compression(&path);
+    let inner = match suffix.is_empty() {
+        true  => path.clone(),
+        false => PathBuf::from(path.file_stem().ok_or(eyre!("Failed to parse file extension: {:?}", path))?),
+    };
+    let ext = inner.extension()
         .and_then(|p| p.to_str())
         .ok_or(eyre!("Failed to parse file extension: {:?}", path))?
         .to_string();
@@ -96,17 +724,131 @@ where
     Ok((path, ext, delimiter))
 }
 
-/// Light wrapper around noodles GFF reader and datafusion register.
+/// Register the object store of a remote path (`https://`, `s3://`, or `gs://`), so datafusion can read it.
+///
+/// Only tables read by datafusion (delimited text and parquet) can be remote, while
+/// the gff, fasta, and json inputs must be local. Local paths are left as-is. Bucket credentials and regions are read from the
+/// standard environment variables (ex. `AWS_ACCESS_KEY_ID`, `GOOGLE_APPLICATION_CREDENTIALS`).
+pub fn register_object_store<P>(ctx: &SessionContext, path: P) -> Result<(), Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let Some(path) = path.as_ref().to_str() else { return Ok(()) };
+    let Ok(url)    = url::Url::parse(path) else { return Ok(()) };
+    // Local paths can parse as a url too (ex. file:///data, and the drive letter of C:\data)
+    if !matches!(url.scheme(), "s3" | "gs" | "http" | "https") { return Ok(()) }
+
+    let host = url.host_str().ok_or(eyre!("Remote path has no host or bucket: {path}"))?;
+    let store: Arc<dyn object_store::ObjectStore> = match url.scheme() {
+        "s3" => Arc::new(object_store::aws::AmazonS3Builder::from_env().with_bucket_name(host).build()?),
+        "gs" => Arc::new(object_store::gcp::GoogleCloudStorageBuilder::from_env().with_bucket_name(host).build()?),
+        _    => Arc::new(object_store::http::HttpBuilder::new().with_url(&url[..url::Position::BeforePath]).build()?),
+    };
+    // The store serves every path under the scheme, host, and port
+    let base = url::Url::parse(&url[..url::Position::BeforePath])?;
+    log::debug!("Registering object store: {base}");
+    ctx.register_object_store(&base, store);
+    Ok(())
+}
+
+/// Identify the compression of a file from its extension (gz, bgz, zst).
+///
+/// Returns the compression type, and the compression extension with its dot (ex. `.gz`),
+/// or an empty extension if the file is uncompressed. Bgzip is a series of gzip blocks,
+/// so it's read as gzip.
+pub fn parse_compression<P>(path: P) -> (CompressionTypeVariant, String)
+where
+    P: AsRef<Path>,
+{
+    let ext = path.as_ref().extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+    match ext.as_str() {
+        "gz" | "bgz" => (CompressionTypeVariant::GZIP, format!(".{ext}")),
+        "zst"        => (CompressionTypeVariant::ZSTD, format!(".{ext}")),
+        _            => (CompressionTypeVariant::UNCOMPRESSED, String::new()),
+    }
+}
+
+/// Open a file for buffered reading, decompressing it if it's gz, bgz, or zst.
+pub fn open<P>(path: P) -> Result<Box<dyn BufRead>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let input = std::fs::File::open(&path)?;
+    let (compression, _) = parse_compression(&path);
+    let reader: Box<dyn BufRead> = match compression {
+        CompressionTypeVariant::GZIP => Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(input))),
+        CompressionTypeVariant::ZSTD => Box::new(BufReader::new(zstd::stream::read::Decoder::new(input)?)),
+        _                            => Box::new(BufReader::new(input)),
+    };
+    Ok(reader)
+}
+
+/// Resolve an input path, spooling standard input to a temporary file when the path is `-`.
+///
+/// Datafusion and the format detection both need a file path, so standard input is written
+/// to a temporary file with an extension sniffed from its content: `json` (an object with
+/// `results`), `ndjson` (one object per line), `tsv` (tab-delimited), or `csv`. The file is
+/// removed when the returned guard is dropped, so keep it until the input has been read.
+pub fn resolve_stdin<P>(path: P) -> Result<(PathBuf, Option<tempfile::TempPath>), Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    if path.as_ref() != Path::new("-") {
+        return Ok((path.as_ref().into(), None))
+    }
+
+    log::info!("Reading standard input.");
+    let mut content = Vec::new();
+    std::io::Read::read_to_end(&mut std::io::stdin(), &mut content)?;
+    if content.is_empty() {
+        return Err(eyre!("Standard input is empty."))
+    }
+
+    let first_line = content.split(|b| *b == b'\n').next().unwrap_or_default();
+    let ext = match content.trim_ascii_start().first() {
+        Some(b'{') => match serde_json::from_slice::<serde_json::Value>(&content) {
+            Ok(json) if json.get("results").is_some() => "json",
+            _                                         => "ndjson",
+        },
+        _ => match first_line.contains(&b'\t') {
+            true  => "tsv",
+            false => "csv",
+        },
+    };
+    log::debug!("Standard input is assumed to be {ext}.");
+
+    let mut file = tempfile::Builder::new().prefix("stdin").suffix(&format!(".{ext}")).tempfile()?;
+    std::io::Write::write_all(&mut file, &content)?;
+    Ok((file.path().into(), Some(file.into_temp_path())))
+}
+
+/// Light wrapper around the gene annotations reader and datafusion register.
+///
+/// GFF3, GTF, GFF2, and GenBank annotations are supported, with the format
+/// detected (see [`gene::AnnotationFormat::detect`]).
 pub async fn register_gff<N, P>(path: P, ctx: SessionContext, name: N) -> Result<SessionContext, Report>
 where
     P: AsRef<Path> + std::fmt::Debug,
     N: ToString,
 {
     log::info!("Reading gff file: {path:?}");
+    register_genes(&gene::genes(&path)?, ctx, name)
+}
 
-    let input = std::fs::File::open(&path)?;
-    let buffered = BufReader::new(input);
-    let mut reader = gff::io::Reader::new(buffered);
+/// Register a gene model as a table of name, type, start, and end.
+///
+/// Each gene has a `gene` row spanning the gene, and a `CDS` row per coding segment. The
+/// table is built from the parsed (or cached, see [`gene::read_genes`]) gene model, so the
+/// annotations are only parsed once.
+pub fn register_genes<N>(genes: &[gene::Gene], ctx: SessionContext, name: N) -> Result<SessionContext, Report>
+where
+    N: ToString,
+{
+    let features = genes.iter().flat_map(|g| {
+        let gene     = gene::Feature { ty: "gene".to_string(), start: g.start, end: g.end, strand: g.strand.clone(), name: Some(g.name.clone()), ..Default::default() };
+        let segments = g.segments.iter().map(|(start, end)| gene::Feature { ty: "CDS".to_string(), start: *start, end: *end, strand: g.strand.clone(), name: Some(g.name.clone()), ..Default::default() });
+        std::iter::once(gene).chain(segments)
+    });
 
     // define the schema.
     // example: https://github.com/apache/datafusion/blob/main/datafusion-examples/examples/simple_udaf.rs
@@ -124,22 +866,12 @@ where
     let mut starts: Vec<u32>    = Vec::new();
     let mut ends:   Vec<u32>    = Vec::new();
 
-    // Search the attributes for these possible identifier names
-    // The sars-cov-2 gff has a strange space before " gene_name"
-    let name_attributes = vec!["Name", "gene_name", " gene_name", "gene"];
-
-    for result in reader.records() {
-        let record = result?;
-        let attributes = record.attributes();
-        for n in &name_attributes {
-            if let Some(name) = attributes.get(&n.to_string()) {
-                names.push(name.to_string());
-                types.push(record.ty().to_string());
-                starts.push(record.start().get() as u32);
-                ends.push(record.end().get() as u32);
-                break
-            }
-        }
+    for feature in features {
+        let Some(name) = feature.name else { continue };
+        names.push(name);
+        types.push(feature.ty);
+        starts.push(feature.start);
+        ends.push(feature.end);
     }
 
     let records = RecordBatch::try_new(
@@ -157,4 +889,926 @@ where
     ctx.register_table(&name.to_string(), Arc::new(provider))?;
 
     Ok(ctx)
-}
\ No newline at end of file
+}
+
+/// Light wrapper around a BED reader and datafusion register, for regions of interest
+/// (ex. primer schemes, antigenic sites).
+///
+/// The table has the columns chrom, start, end, and name. BED starts are 0-based, and
+/// are converted to 1-based inclusive coordinates like the GFF. Regions without a name
+/// are named by their coordinates (ex. `MN908947.3:100-200`).
+pub async fn register_bed<N, P>(path: P, ctx: SessionContext, name: N) -> Result<SessionContext, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+    N: ToString,
+{
+    log::info!("Reading bed file: {path:?}");
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("chrom", DataType::Utf8,   false),
+        Field::new("start", DataType::UInt32, false),
+        Field::new("end",   DataType::UInt32, false),
+        Field::new("name",  DataType::Utf8,   false),
+    ]));
+
+    let mut chroms: Vec<String> = Vec::new();
+    let mut starts: Vec<u32>    = Vec::new();
+    let mut ends:   Vec<u32>    = Vec::new();
+    let mut names:  Vec<String> = Vec::new();
+
+    for line in open(&path)?.lines() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') || line.starts_with("track") || line.starts_with("browser") { continue }
+        let fields = line.split('\t').collect::<Vec<_>>();
+        let [chrom, start, end, ..] = fields[..] else {
+            return Err(eyre!("BED line does not have at least 3 fields: {line}"))
+        };
+        let start: u32 = start.parse()?;
+        let end:   u32 = end.parse()?;
+        names.push(fields.get(3).map(|n| n.to_string()).unwrap_or_else(|| format!("{chrom}:{}-{end}", start + 1)));
+        chroms.push(chrom.to_string());
+        starts.push(start + 1);
+        ends.push(end);
+    }
+
+    let records = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(StringArray::from(chroms)),
+            Arc::new(UInt32Array::from(starts)),
+            Arc::new(UInt32Array::from(ends)),
+            Arc::new(StringArray::from(names)),
+        ],
+    )?;
+
+    let provider = MemTable::try_new(schema, vec![vec![records]])?;
+    ctx.register_table(&name.to_string(), Arc::new(provider))?;
+
+    Ok(ctx)
+}
+
+/// Call the mutations of each sample in a multi-FASTA alignment, and register them as a table.
+///
+/// Every sequence is aligned to the reference, so each column is a reference position.
+/// Substitutions between unambiguous bases (ex. `C241T`) and runs of gaps (ex. `6513-6515`,
+/// or `11288` for one base) are called in nextclade notation. Ambiguous bases (ex. `N`) and
+/// the gaps at the ends of a sequence, which are missing coverage rather than deletions, are
+/// skipped. The table has the columns sample, mutation, column (substitutions or deletions), and position.
+pub async fn register_alignment<N, P>(path: P, reference: &[u8], ctx: SessionContext, name: N) -> Result<SessionContext, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+    N: ToString,
+{
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("sample",   DataType::Utf8,   false),
+        Field::new("mutation", DataType::Utf8,   false),
+        Field::new("column",   DataType::Utf8,   false),
+        Field::new("position", DataType::UInt32, false),
+    ]));
+
+    let mut names:     Vec<String> = Vec::new();
+    let mut mutations: Vec<String> = Vec::new();
+    let mut columns:   Vec<String> = Vec::new();
+    let mut positions: Vec<u32>    = Vec::new();
+
+    let reference = reference.to_ascii_uppercase();
+    let is_base   = |b: u8| matches!(b, b'A' | b'C' | b'G' | b'T');
+
+    for (sample, sequence) in read_fasta(&path)? {
+        if sequence.len() != reference.len() {
+            return Err(eyre!("Sequence {sample} has length {}, but the reference has length {}. Is the alignment to the reference? {path:?}", sequence.len(), reference.len()))
+        }
+        // The leading and trailing gaps are missing coverage
+        let first = sequence.iter().position(|b| *b != b'-').unwrap_or(sequence.len());
+        let last  = sequence.iter().rposition(|b| *b != b'-').map(|i| i + 1).unwrap_or(first);
+
+        let mut gap: Option<usize> = None;
+        for i in first..last {
+            let (r, s) = (reference[i], sequence[i]);
+            match (s == b'-', gap) {
+                (true, None)         => gap = Some(i),
+                (false, Some(start)) => {
+                    let mutation = match i - start {
+                        1 => (start + 1).to_string(),
+                        _ => format!("{}-{i}", start + 1),
+                    };
+                    names.push(sample.clone());
+                    mutations.push(mutation);
+                    columns.push("deletions".to_string());
+                    positions.push(start as u32 + 1);
+                    gap = None;
+                },
+                _                    => (),
+            }
+            if is_base(r) && is_base(s) && r != s {
+                names.push(sample.clone());
+                mutations.push(format!("{}{}{}", r as char, i + 1, s as char));
+                columns.push("substitutions".to_string());
+                positions.push(i as u32 + 1);
+            }
+        }
+    }
+    log::info!("Called {} mutations from the alignment.", mutations.len());
+
+    let records = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(StringArray::from(names)),
+            Arc::new(StringArray::from(mutations)),
+            Arc::new(StringArray::from(columns)),
+            Arc::new(UInt32Array::from(positions)),
+        ],
+    )?;
+
+    let provider = MemTable::try_new(schema, vec![vec![records]])?;
+    ctx.register_table(&name.to_string(), Arc::new(provider))?;
+
+    Ok(ctx)
+}
+
+/// Call the amino acid mutations of each sample from per-gene protein alignments, and register them as a table.
+///
+/// Each file is the aligned translation of one gene (ex. from nextalign `--output-translations`),
+/// named by the last part of the file stem (ex. `nextalign.gene.S.fasta` or `nextclade.cds_translation.S.fasta`
+/// -> `S`). Each sequence is compared to the gene translated from the reference, calling substitutions
+/// (ex. `S:D614G`) and deleted codons (ex. `S:H69-`) in nextclade notation. Unknown residues (`X`)
+/// and the gaps at the ends of a sequence are skipped. The table has the columns sample, mutation,
+/// column (aaSubstitutions or aaDeletions), and position (the codon).
+pub async fn register_translations<N, P>(paths: &[P], genes: &[crate::gene::Gene], reference: &[u8], ctx: SessionContext, name: N) -> Result<SessionContext, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+    N: ToString,
+{
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("sample",   DataType::Utf8,   false),
+        Field::new("mutation", DataType::Utf8,   false),
+        Field::new("column",   DataType::Utf8,   false),
+        Field::new("position", DataType::UInt32, false),
+    ]));
+
+    let mut names:     Vec<String> = Vec::new();
+    let mut mutations: Vec<String> = Vec::new();
+    let mut columns:   Vec<String> = Vec::new();
+    let mut positions: Vec<u32>    = Vec::new();
+
+    for path in paths {
+        let stem = path.as_ref().file_stem().and_then(|s| s.to_str()).ok_or(eyre!("Failed to parse the gene name from file: {path:?}"))?;
+        let gene = stem.rsplit('.').next().unwrap_or(stem);
+        let gene = genes.iter().find(|g| g.name == gene).ok_or(eyre!("Gene {gene} of the translations was not found in the gene annotations: {path:?}"))?;
+
+        // The reference protein, translated from the coding sequence of the gene
+        let mut bases = gene.positions().iter().map(|p| reference.get(*p as usize - 1).map(|b| b.to_ascii_uppercase()).unwrap_or(b'N')).collect::<Vec<_>>();
+        if gene.strand == "-" { bases = bases.into_iter().map(crate::mnv::complement).collect() }
+        let protein = crate::codon::translate(&bases).into_bytes();
+
+        for (sample, sequence) in read_fasta(path)? {
+            if sequence.len() != protein.len() {
+                log::warn!("Translation of {} in sample {sample} has length {}, but the reference protein has length {}: {path:?}", gene.name, sequence.len(), protein.len());
+            }
+            // The leading and trailing gaps are missing coverage
+            let first = sequence.iter().position(|aa| *aa != b'-').unwrap_or(sequence.len());
+            let last  = sequence.iter().rposition(|aa| *aa != b'-').map(|i| i + 1).unwrap_or(first);
+
+            for i in first..last.min(protein.len()) {
+                let (r, s) = (protein[i], sequence[i]);
+                if r == s || r == b'X' || s == b'X' { continue }
+                let column = match s {
+                    b'-' => "aaDeletions",
+                    _    => "aaSubstitutions",
+                };
+                names.push(sample.clone());
+                mutations.push(format!("{}:{}{}{}", gene.name, r as char, i + 1, s as char));
+                columns.push(column.to_string());
+                positions.push(i as u32 + 1);
+            }
+        }
+    }
+    log::info!("Called {} amino acid mutations from the translations.", mutations.len());
+
+    let records = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(StringArray::from(names)),
+            Arc::new(StringArray::from(mutations)),
+            Arc::new(StringArray::from(columns)),
+            Arc::new(UInt32Array::from(positions)),
+        ],
+    )?;
+
+    let provider = MemTable::try_new(schema, vec![vec![records]])?;
+    ctx.register_table(&name.to_string(), Arc::new(provider))?;
+
+    Ok(ctx)
+}
+
+/// Convert a MAF protein change (`HGVSp_Short`, ex. `p.V600E`) to nextclade notation.
+///
+/// Substitutions and nonsense changes are kept (ex. `V600E`, `R213*`), and silent changes are
+/// written as a substitution to the same residue (ex. `L858=` -> `L858L`). Frameshifts are trimmed after
+/// their first residue (ex. `K1690Nfs*7` -> `K1690fs`), and ranges use a dash (ex. `E746_A750del` ->
+/// `E746-A750del`). Insertions and duplications use the nextclade insertion notation, after the last
+/// residue before the inserted sequence (ex. `D770_N771insSVD` -> `770:SVD`, `A767_V769dup` -> `769:AXV`),
+/// where the residues inside a duplicated range aren't known.
+///
+/// Returns `None` for changes without residue coordinates, such as splice sites (`X125_splice`),
+/// start losses (`M1?`), and stop losses (`*1191Lext*?`).
+pub fn maf_protein_change(change: &str) -> Option<String> {
+    let change = change.trim_start_matches("p.");
+    // A residue and its position, followed by the rest of the change (ex. E746_A750del -> E, 746, _A750del)
+    let residue = |text: &str| -> Option<(char, u32, String)> {
+        let aa       = text.chars().next().filter(|c| c.is_ascii_uppercase() || *c == '*')?;
+        let digits   = text[1..].find(|c: char| !c.is_ascii_digit()).map(|i| i + 1).unwrap_or(text.len());
+        let position = text[1..digits].parse().ok()?;
+        Some((aa, position, text[digits..].to_string()))
+    };
+    let is_residues = |text: &str| !text.is_empty() && text.chars().all(|c| c.is_ascii_uppercase() || c == '*');
+
+    let (start_aa, start, rest) = residue(change)?;
+    let (end, rest) = match rest.strip_prefix('_').and_then(residue) {
+        Some((end_aa, end, rest)) if end > start => (Some((end_aa, end)), rest),
+        Some(_)                                  => return None,
+        None                                     => (None, rest),
+    };
+    let first = format!("{start_aa}{start}");
+    let range = match end {
+        Some((end_aa, end)) => format!("{first}-{end_aa}{end}"),
+        None                => first.clone(),
+    };
+
+    let inserted = rest.strip_prefix("ins").filter(|r| is_residues(r));
+    let replaced = rest.strip_prefix("delins").filter(|r| is_residues(r));
+    match (end, rest.as_str()) {
+        (_, r) if r.contains("fs")         => Some(format!("{first}fs")),
+        (_, "del")                         => Some(format!("{range}del")),
+        (_, r) if replaced.is_some()       => Some(format!("{range}{r}")),
+        (Some(_), _) if inserted.is_some() => Some(format!("{start}:{}", inserted?)),
+        (None, "dup")                      => Some(format!("{start}:{start_aa}")),
+        (Some((end_aa, end)), "dup")       => Some(format!("{end}:{start_aa}{}{end_aa}", "X".repeat((end - start - 1) as usize))),
+        (None, "=")                        => Some(format!("{first}{start_aa}")),
+        (None, r) if r.len() == 1 && is_residues(r) => Some(format!("{first}{r}")),
+        _                                  => None,
+    }
+}
+
+/// Light wrapper around a Mutation Annotation Format (MAF) reader and datafusion register.
+///
+/// MAF files (ex. from TCGA) are tab-delimited, with `#` comment lines (ex. `#version 2.4`)
+/// before the header. The table has the columns sample (`Tumor_Sample_Barcode`), gene
+/// (`Hugo_Symbol`), hgvs_p (`HGVSp_Short` in nextclade notation, see [`maf_protein_change`]), and consequence
+/// (`Variant_Classification`). Variants without a protein change (ex. intronic), or whose change has
+/// no residue coordinates (ex. splice sites), are skipped and counted.
+pub async fn register_maf<N, P>(path: P, ctx: SessionContext, name: N) -> Result<SessionContext, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+    N: ToString,
+{
+    log::info!("Reading maf file: {path:?}");
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("sample",      DataType::Utf8, false),
+        Field::new("gene",        DataType::Utf8, false),
+        Field::new("hgvs_p",      DataType::Utf8, false),
+        Field::new("consequence", DataType::Utf8, true),
+    ]));
+
+    let mut names:        Vec<String>         = Vec::new();
+    let mut genes:        Vec<String>         = Vec::new();
+    let mut hgvs_p:       Vec<String>         = Vec::new();
+    let mut consequences: Vec<Option<String>> = Vec::new();
+
+    // The comment lines are skipped, so the first line is the header
+    let mut lines   = open(&path)?.lines().filter(|l| l.as_ref().map(|l| !l.starts_with('#') && !l.trim().is_empty()).unwrap_or(true));
+    let header      = lines.next().transpose()?.ok_or(eyre!("Maf file has no header: {path:?}"))?;
+    let header      = header.split('\t').collect::<Vec<_>>();
+    let column      = |name: &str| header.iter().position(|c| *c == name).ok_or(eyre!("Maf file is missing the column {name}: {path:?}"));
+    let sample      = column("Tumor_Sample_Barcode")?;
+    let gene        = column("Hugo_Symbol")?;
+    let change      = column("HGVSp_Short")?;
+    let consequence = header.iter().position(|c| *c == "Variant_Classification");
+
+    let (mut skipped, mut unsupported) = (0, 0);
+    for line in lines {
+        let line   = line?;
+        let fields = line.split('\t').collect::<Vec<_>>();
+        let value  = |i: usize| fields.get(i).map(|v| v.trim()).filter(|v| !v.is_empty() && *v != ".");
+        let (Some(s), Some(g), Some(c)) = (value(sample), value(gene), value(change)) else { skipped += 1; continue };
+        let Some(c) = maf_protein_change(c) else { unsupported += 1; continue };
+        names.push(s.to_string());
+        genes.push(g.to_string());
+        hgvs_p.push(c);
+        consequences.push(consequence.and_then(value).map(String::from));
+    }
+    if skipped > 0 { log::info!("Skipping {skipped} maf variants without a protein change.") }
+    if unsupported > 0 { log::warn!("Skipping {unsupported} maf variants whose protein change has no residue coordinates (ex. splice sites, start losses, extensions).") }
+
+    let records = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(StringArray::from(names)),
+            Arc::new(StringArray::from(genes)),
+            Arc::new(StringArray::from(hgvs_p)),
+            Arc::new(StringArray::from(consequences)),
+        ],
+    )?;
+
+    let provider = MemTable::try_new(schema, vec![vec![records]])?;
+    ctx.register_table(&name.to_string(), Arc::new(provider))?;
+
+    Ok(ctx)
+}
+
+/// Light wrapper around a plain-text mutation list reader and datafusion register.
+///
+/// The list has one mutation per line, as `sample<TAB>mutation` (ex. `sample1<TAB>S:E484K`
+/// or `sample1<TAB>C913T`). Empty lines, `#` comments, and a `sample<TAB>mutation` header are
+/// skipped. The table has the columns sample and mutation.
+pub async fn register_mutation_list<N, P>(path: P, ctx: SessionContext, name: N) -> Result<SessionContext, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+    N: ToString,
+{
+    log::info!("Reading mutation list: {path:?}");
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("sample",   DataType::Utf8, false),
+        Field::new("mutation", DataType::Utf8, false),
+    ]));
+
+    let mut names:     Vec<String> = Vec::new();
+    let mut mutations: Vec<String> = Vec::new();
+
+    for line in open(&path)?.lines() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') { continue }
+        let Some((sample, mutation)) = line.split_once('\t') else {
+            return Err(eyre!("Mutation list line is not a sample and mutation separated by a tab: {line:?} {path:?}"))
+        };
+        let (sample, mutation) = (sample.trim(), mutation.trim());
+        if sample == "sample" && mutation == "mutation" { continue }
+        names.push(sample.to_string());
+        mutations.push(mutation.to_string());
+    }
+
+    let records = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(StringArray::from(names)),
+            Arc::new(StringArray::from(mutations)),
+        ],
+    )?;
+
+    let provider = MemTable::try_new(schema, vec![vec![records]])?;
+    ctx.register_table(&name.to_string(), Arc::new(provider))?;
+
+    Ok(ctx)
+}
+
+/// Light wrapper around an UShER sample paths reader and datafusion register.
+///
+/// The sample paths (`matUtils extract --sample-paths`) list the mutations on each branch from
+/// the root to a sample (ex. `sample<TAB>node_1:C241T,A23403G node_5:G28881A`). The mutations
+/// along the path are combined, so a later mutation at the same position replaces an earlier one,
+/// and reversions to the reference are dropped. The table has the columns sample, mutation
+/// (ex. C241T), and position.
+pub async fn register_sample_paths<N, P>(path: P, ctx: SessionContext, name: N) -> Result<SessionContext, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+    N: ToString,
+{
+    log::info!("Reading sample paths file: {path:?}");
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("sample",   DataType::Utf8,   false),
+        Field::new("mutation", DataType::Utf8,   false),
+        Field::new("position", DataType::UInt32, false),
+    ]));
+
+    let mut names:     Vec<String> = Vec::new();
+    let mut mutations: Vec<String> = Vec::new();
+    let mut positions: Vec<u32>    = Vec::new();
+
+    for line in open(&path)?.lines() {
+        let line = line?;
+        if line.trim().is_empty() { continue }
+        let (sample, branches) = line.split_once('\t').unwrap_or((line.as_str(), ""));
+
+        // The reference and current base at each mutated position, in genome order
+        let mut states: BTreeMap<u32, (char, char)> = BTreeMap::new();
+        for branch in branches.split_whitespace() {
+            let changes = branch.rsplit_once(':').map(|(_, changes)| changes).unwrap_or(branch);
+            for change in changes.split(',').filter(|c| !c.is_empty()) {
+                let (Some(reference), Some(alternate)) = (change.chars().next(), change.chars().last()) else { continue };
+                let Some(Ok(position)) = change.get(1..change.len().saturating_sub(1)).map(str::parse::<u32>) else {
+                    return Err(eyre!("Failed to parse mutation {change} of sample {sample}: {path:?}"))
+                };
+                states.entry(position).and_modify(|(_, current)| *current = alternate).or_insert((reference, alternate));
+            }
+        }
+
+        for (position, (reference, alternate)) in states.into_iter().filter(|(_, (r, a))| r != a) {
+            names.push(sample.to_string());
+            mutations.push(format!("{reference}{position}{alternate}"));
+            positions.push(position);
+        }
+    }
+
+    let records = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(StringArray::from(names)),
+            Arc::new(StringArray::from(mutations)),
+            Arc::new(UInt32Array::from(positions)),
+        ],
+    )?;
+
+    let provider = MemTable::try_new(schema, vec![vec![records]])?;
+    ctx.register_table(&name.to_string(), Arc::new(provider))?;
+
+    Ok(ctx)
+}
+
+/// Records below this quality are dropped by the [`VcfPreset::Ont`] preset.
+pub const ONT_MIN_QUAL: f32 = 20.0;
+
+/// INFO keys of modified base (methylation) calls, carried over from the SAM `MM` and `ML` tags.
+pub const METHYLATION_KEYS: &[&str] = &["MM", "ML"];
+
+/// Filters for the quirks of specific variant callers, applied while reading a vcf.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VcfPreset {
+    /// Long-read callers (ex. medaka, longshot, clair3). Drops records that fail a filter
+    /// (ex. `LowQual`, `RefCall`, `dn`), are below [`ONT_MIN_QUAL`], or are methylation calls
+    /// (see [`METHYLATION_KEYS`]), and skips symbolic alleles (ex. `<5mC>`, `*`). The shared
+    /// bases of multi-allelic records are trimmed, so that a padded allele (ex. `ACT` -> `GCT`)
+    /// is read as the substitution it is (`A` -> `G`).
+    Ont,
+}
+
+impl std::str::FromStr for VcfPreset {
+    type Err = Report;
+
+    fn from_str(preset: &str) -> Result<Self, Self::Err> {
+        match preset {
+            "ont" => Ok(VcfPreset::Ont),
+            _     => Err(eyre!("Unknown vcf preset: {preset}, please choose from: ont")),
+        }
+    }
+}
+
+/// Trim the bases shared by the end, then the start, of a reference and alternate allele,
+/// keeping at least one base of each. Returns the position, reference, and alternate.
+fn trim_alleles(position: u32, reference: &str, alternate: &str) -> (u32, String, String) {
+    let (mut reference, mut alternate) = (reference.as_bytes(), alternate.as_bytes());
+    while reference.len() > 1 && alternate.len() > 1 && reference.last() == alternate.last() {
+        reference = &reference[..reference.len() - 1];
+        alternate = &alternate[..alternate.len() - 1];
+    }
+    let mut position = position;
+    while reference.len() > 1 && alternate.len() > 1 && reference.first() == alternate.first() {
+        reference = &reference[1..];
+        alternate = &alternate[1..];
+        position += 1;
+    }
+    (position, String::from_utf8_lossy(reference).to_string(), String::from_utf8_lossy(alternate).to_string())
+}
+
+/// The INFO field of snpEff (`ANN`) or VEP (`CSQ`) annotations, and the positions of
+/// the allele, consequence, gene, and protein change in its pipe-delimited entries.
+struct ConsequenceFields {
+    key:         &'static str,
+    allele:      usize,
+    consequence: usize,
+    gene:        usize,
+    hgvs_p:      usize,
+}
+
+impl ConsequenceFields {
+    /// snpEff has a fixed format, VEP describes its format in the header (`Format: Allele|Consequence|...`).
+    fn from_header(header: &vcf::Header) -> Option<Self> {
+        if header.infos().contains_key("ANN") {
+            return Some(ConsequenceFields { key: "ANN", allele: 0, consequence: 1, gene: 3, hgvs_p: 10 })
+        }
+        let format = header.infos().get("CSQ")?.description().split("Format: ").nth(1)?;
+        let fields = format.trim_end_matches('"').split('|').map(str::trim).collect::<Vec<_>>();
+        let index  = |name: &str| fields.iter().position(|f| *f == name);
+        Some(ConsequenceFields { key: "CSQ", allele: index("Allele")?, consequence: index("Consequence")?, gene: index("SYMBOL")?, hgvs_p: index("HGVSp")? })
+    }
+}
+
+/// The columns of a vcf table, filled in one record at a time (see [`register_vcf`]).
+struct VcfColumns {
+    samples:            Vec<String>,
+    fields:             Option<ConsequenceFields>,
+    preset:             Option<VcfPreset>,
+    filtered:           usize,
+    names:              Vec<String>,
+    positions:          Vec<u32>,
+    refs:               Vec<String>,
+    alts:               Vec<String>,
+    genotypes:          Vec<Option<String>>,
+    consequences:       Vec<Option<String>>,
+    consequence_genes:  Vec<Option<String>>,
+    consequence_hgvs_p: Vec<Option<String>>,
+}
+
+impl VcfColumns {
+    /// VCFs without genotypes have one sample, named by the file stem.
+    fn new<P>(header: &vcf::Header, path: &P, preset: Option<VcfPreset>) -> Result<Self, Report>
+    where
+        P: AsRef<Path> + std::fmt::Debug,
+    {
+        let stem    = path.as_ref().file_stem().and_then(|s| s.to_str()).ok_or(eyre!("Failed to parse sample name from file: {path:?}"))?;
+        let samples = match header.sample_names().is_empty() {
+            true  => vec![stem.trim_end_matches(".vcf").to_string()],
+            false => header.sample_names().iter().cloned().collect::<Vec<_>>(),
+        };
+
+        let fields = ConsequenceFields::from_header(header);
+        if let Some(fields) = &fields { log::info!("Reading variant consequences from the {} field.", fields.key) }
+
+        Ok(VcfColumns {
+            samples,
+            fields,
+            preset,
+            filtered:           0,
+            names:              Vec::new(),
+            positions:          Vec::new(),
+            refs:               Vec::new(),
+            alts:               Vec::new(),
+            genotypes:          Vec::new(),
+            consequences:       Vec::new(),
+            consequence_genes:  Vec::new(),
+            consequence_hgvs_p: Vec::new(),
+        })
+    }
+
+    /// Add a row for each alternate allele carried by each sample of a record.
+    fn push(&mut self, record: &vcf::variant::RecordBuf) {
+        let Some(position) = record.variant_start() else { return };
+        let alternates = record.alternate_bases().as_ref();
+        let fields     = self.fields.as_ref();
+
+        if self.preset == Some(VcfPreset::Ont) {
+            let passed      = record.filters().as_ref().iter().all(|f| f == "PASS");
+            let quality     = record.quality_score().map_or(true, |q| q >= ONT_MIN_QUAL);
+            let methylation = METHYLATION_KEYS.iter().any(|key| record.info().get(*key).is_some());
+            if !passed || !quality || methylation {
+                self.filtered += 1;
+                return
+            }
+        }
+
+        // The annotations of all alternate alleles, one entry per allele and transcript
+        let annotations: Vec<String> = match fields.and_then(|f| record.info().get(f.key).flatten()) {
+            Some(vcf::variant::record_buf::info::field::Value::String(value)) => value.split(',').map(String::from).collect(),
+            Some(vcf::variant::record_buf::info::field::Value::Array(vcf::variant::record_buf::info::field::value::Array::String(values))) => {
+                values.iter().flatten().cloned().collect()
+            },
+            _ => Vec::new(),
+        };
+        let annotations = annotations.iter().map(|a| a.split('|').collect::<Vec<_>>()).collect::<Vec<_>>();
+
+        // The allele indices called in each sample, ex. [0, 1] for 0/1, and the genotype as written
+        let genotype = record.samples().select(vcf::variant::record::samples::keys::key::GENOTYPE);
+        let calls    = self.samples.iter().enumerate().map(|(i, sample)| {
+            let (alleles, text) = match genotype.as_ref().map(|g| g.get(i).flatten()) {
+                Some(Some(vcf::variant::record_buf::samples::sample::Value::Genotype(gt))) => (gt.as_ref().iter().map(|a| a.position()).collect(), Some(genotype_text(gt))),
+                Some(_)                                                                   => (Vec::new(), None),
+                None                                                                      => ((1..=alternates.len()).map(Some).collect(), None),
+            };
+            (sample, alleles, text)
+        }).collect::<Vec<(&String, Vec<Option<usize>>, Option<String>)>>();
+
+        for (sample, alleles, text) in calls {
+            for (i, alternate) in alternates.iter().enumerate() {
+                if !alleles.contains(&Some(i + 1)) { continue }
+                let (position, reference, alt) = match self.preset {
+                    Some(VcfPreset::Ont) if alternate.starts_with('<') || alternate.as_str() == "*" => continue,
+                    Some(VcfPreset::Ont)                                                            => trim_alleles(position.get() as u32, record.reference_bases(), alternate),
+                    None                                                                            => (position.get() as u32, record.reference_bases().to_string(), alternate.clone()),
+                };
+                self.names.push(sample.to_string());
+                self.positions.push(position);
+                self.refs.push(reference);
+                self.alts.push(alt);
+                self.genotypes.push(text.clone());
+
+                // VEP trims the shared first base of indel alleles, so fall back to the only annotated allele
+                let annotation = fields.and_then(|f| {
+                    let annotation = annotations.iter().find(|a| a.get(f.allele) == Some(&alternate.as_str()));
+                    let annotation = annotation.or_else(|| annotations.first().filter(|_| alternates.len() == 1))?;
+                    Some((f, annotation))
+                });
+                let value = |index: fn(&ConsequenceFields) -> usize| {
+                    annotation.and_then(|(f, a)| a.get(index(f))).filter(|v| !v.is_empty()).map(|v| v.to_string())
+                };
+                self.consequences.push(value(|f| f.consequence));
+                self.consequence_genes.push(value(|f| f.gene));
+                self.consequence_hgvs_p.push(value(|f| f.hgvs_p));
+            }
+        }
+    }
+}
+
+/// Write a genotype as in the vcf, keeping its phasing (ex. `0/1`, `0|1`, `./.`).
+fn genotype_text(genotype: &vcf::variant::record_buf::samples::sample::value::Genotype) -> String {
+    use vcf::variant::record::samples::series::value::genotype::Phasing;
+    genotype.as_ref().iter().enumerate().map(|(i, allele)| {
+        let separator = match (i, allele.phasing()) {
+            (0, _)               => "",
+            (_, Phasing::Phased) => "|",
+            _                    => "/",
+        };
+        let position = allele.position().map(|p| p.to_string()).unwrap_or(".".to_string());
+        format!("{separator}{position}")
+    }).collect()
+}
+
+/// Light wrapper around noodles VCF and BCF readers and datafusion register.
+///
+/// The table has one row per sample per alternate allele carried by that sample,
+/// with the columns sample, position, ref, alt, and genotype (ex. 0/1, or 0|1 when phased). VCFs without
+/// genotypes (ex. from single sample variant callers) are treated as one sample named
+/// by the file stem, that carries every alternate allele.
+///
+/// VCFs annotated by snpEff (`ANN`) or VEP (`CSQ`) fill the consequence, consequence_gene,
+/// and consequence_hgvs_p columns from the first annotation of each alternate allele.
+///
+/// Plain and bgzipped VCFs (`.vcf.gz`), and BCFs (`.bcf`) are supported. With a `region`
+/// (ex. `MN908947.3:21563-25384`), only the records overlapping it are read, using the
+/// tabix (`.tbi`) or CSI (`.csi`) index next to the file, so large cohort files aren't read in full.
+/// A `preset` (see [`VcfPreset`]) filters the records of a specific kind of variant caller.
+pub async fn register_vcf<N, P>(path: P, ctx: SessionContext, name: N, region: Option<&str>, preset: Option<VcfPreset>) -> Result<SessionContext, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+    N: ToString,
+{
+    log::info!("Reading vcf file: {path:?}");
+
+    let region = region.map(|r| r.parse::<noodles::core::Region>().map_err(|e| eyre!("Failed to parse region {r:?}: {e}"))).transpose()?;
+    let is_bcf = path.as_ref().extension().is_some_and(|ext| ext == "bcf");
+    if let Some(region) = &region { log::info!("Querying the vcf index for region: {region}") }
+
+    // The builders detect bgzip compression from the extension, and the indexed
+    // readers find the index next to the file (ex. cohort.vcf.gz.tbi).
+    let columns = match (is_bcf, &region) {
+        (false, None) => {
+            let mut reader  = vcf::io::reader::Builder::default().build_from_path(&path)?;
+            let header      = reader.read_header()?;
+            let mut columns = VcfColumns::new(&header, &path, preset)?;
+            for result in reader.record_bufs(&header) { columns.push(&result?) }
+            columns
+        },
+        (false, Some(region)) => {
+            let mut reader  = vcf::io::indexed_reader::Builder::default().build_from_path(&path)?;
+            let header      = reader.read_header()?;
+            let mut columns = VcfColumns::new(&header, &path, preset)?;
+            for result in reader.query(&header, region)? {
+                columns.push(&vcf::variant::RecordBuf::try_from_variant_record(&header, &result?)?)
+            }
+            columns
+        },
+        (true, None) => {
+            let mut reader  = bcf::io::reader::Builder::default().build_from_path(&path)?;
+            let header      = reader.read_header()?;
+            let mut columns = VcfColumns::new(&header, &path, preset)?;
+            for result in reader.records() {
+                columns.push(&vcf::variant::RecordBuf::try_from_variant_record(&header, &result?)?)
+            }
+            columns
+        },
+        (true, Some(region)) => {
+            let mut reader  = bcf::io::indexed_reader::Builder::default().build_from_path(&path)?;
+            let header      = reader.read_header()?;
+            let mut columns = VcfColumns::new(&header, &path, preset)?;
+            for result in reader.query(&header, region)? {
+                columns.push(&vcf::variant::RecordBuf::try_from_variant_record(&header, &result?)?)
+            }
+            columns
+        },
+    };
+    if let Some(preset) = preset { log::info!("Filtered {} vcf records with the {preset:?} preset.", columns.filtered) }
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("sample",             DataType::Utf8,   false),
+        Field::new("position",           DataType::UInt32, false),
+        Field::new("ref",                DataType::Utf8,   false),
+        Field::new("alt",                DataType::Utf8,   false),
+        Field::new("genotype",           DataType::Utf8,   true),
+        Field::new("consequence",        DataType::Utf8,   true),
+        Field::new("consequence_gene",   DataType::Utf8,   true),
+        Field::new("consequence_hgvs_p", DataType::Utf8,   true),
+    ]));
+
+    let records = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(StringArray::from(columns.names)),
+            Arc::new(UInt32Array::from(columns.positions)),
+            Arc::new(StringArray::from(columns.refs)),
+            Arc::new(StringArray::from(columns.alts)),
+            Arc::new(StringArray::from(columns.genotypes)),
+            Arc::new(StringArray::from(columns.consequences)),
+            Arc::new(StringArray::from(columns.consequence_genes)),
+            Arc::new(StringArray::from(columns.consequence_hgvs_p)),
+        ],
+    )?;
+
+    let provider = MemTable::try_new(schema, vec![vec![records]])?;
+    ctx.register_table(&name.to_string(), Arc::new(provider))?;
+
+    Ok(ctx)
+}
+
+/// Light wrapper around noodles FASTA reader, returning the (name, sequence) of each record.
+pub fn read_fasta<P>(path: P) -> Result<Vec<(String, Vec<u8>)>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Reading fasta file: {path:?}");
+
+    let mut reader = fasta::io::Reader::new(open(&path)?);
+
+    let records = reader.records().map(|result| {
+        let record   = result?;
+        let name     = String::from_utf8_lossy(record.name()).to_string();
+        let sequence = record.sequence().as_ref().to_ascii_uppercase();
+        Ok((name, sequence))
+    }).collect::<Result<Vec<_>, Report>>()?;
+
+    if records.is_empty() {
+        return Err(eyre!("No sequences were found in fasta file: {path:?}"))
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha256_known_digest() {
+        assert_eq!(sha256(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(sha256(b""),    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    }
+
+    #[tokio::test]
+    async fn register_vcf_keeps_phasing() -> Result<(), Report> {
+        let dir  = tempfile::tempdir()?;
+        let path = dir.path().join("phased.vcf");
+        let vcf  = [
+            "##fileformat=VCFv4.2",
+            "##contig=<ID=MN908947.3,length=29903>",
+            "##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">",
+            "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\ts1\ts2",
+            "MN908947.3\t241\t.\tC\tT,*\t.\tPASS\t.\tGT\t0|1\t2/2",
+        ];
+        std::fs::write(&path, vcf.join("\n") + "\n")?;
+
+        let ctx     = register_vcf(&path, SessionContext::new(), "vcf", None, None).await?;
+        let batches = ctx.sql("SELECT sample, alt, genotype FROM vcf ORDER BY sample").await?.collect().await?;
+        let batch   = &batches[0];
+        let column  = |name: &str| string_column(batch, name).map(|c| c.iter().map(|v| v.unwrap_or_default().to_string()).collect::<Vec<_>>());
+        assert_eq!(column("sample")?,   ["s1", "s2"]);
+        assert_eq!(column("alt")?,      ["T", "*"]);
+        assert_eq!(column("genotype")?, ["0|1", "2/2"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_metadata_duplicate_keys() -> Result<(), Report> {
+        let dir  = tempfile::tempdir()?;
+        let path = dir.path().join("metadata.tsv");
+        std::fs::write(&path, "strain\tlineage\ns1\tBA.1\ns2\tBA.2\n")?;
+        let metadata = read_metadata(&path, Some("strain")).await?;
+        assert_eq!(metadata["s2"]["lineage"], "BA.2");
+
+        std::fs::write(&path, "strain\tlineage\ns1\tBA.1\ns1\tBA.2\n")?;
+        assert!(read_metadata(&path, Some("strain")).await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn column_case_convert() {
+        let columns = ["qc.overallStatus", "aaSubstitutions", "nuc_start", "seqName", "totalMissing", "sample"];
+        let snake   = columns.iter().map(|c| ColumnCase::Snake.convert(c)).collect::<Vec<_>>();
+        let camel   = columns.iter().map(|c| ColumnCase::Camel.convert(c)).collect::<Vec<_>>();
+        assert_eq!(snake, ["qc_overall_status", "aa_substitutions", "nuc_start", "seq_name", "total_missing", "sample"]);
+        assert_eq!(camel, ["qcOverallStatus", "aaSubstitutions", "nucStart", "seqName", "totalMissing", "sample"]);
+        assert_eq!(ColumnCase::Original.convert("qc.overallStatus"), "qc.overallStatus");
+        assert_eq!(ColumnCase::Snake.convert(""), "");
+    }
+
+    #[test]
+    fn parse_column_map_name_first() -> Result<(), Report> {
+        let map = parse_column_map(" sample=SequenceName, mutation = AAChange,")?;
+        assert_eq!(map, [("sample".to_string(), "SequenceName".to_string()), ("mutation".to_string(), "AAChange".to_string())]);
+        assert!(parse_column_map("")?.is_empty());
+        assert!(parse_column_map("sample").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn column_names_rename_before_case() {
+        let names = ColumnNames { case: ColumnCase::Snake, rename: vec![("qc_status".to_string(), "qc.overallStatus".to_string())] };
+        assert_eq!(names.name("qc.overallStatus"), "qc_status");
+        assert_eq!(names.name("seqName"), "seq_name");
+    }
+
+    #[test]
+    fn compression_from_extension() {
+        assert_eq!(parse_compression("mutations.tsv.gz"),  (CompressionTypeVariant::GZIP, ".gz".to_string()));
+        assert_eq!(parse_compression("calls.vcf.bgz"),     (CompressionTypeVariant::GZIP, ".bgz".to_string()));
+        assert_eq!(parse_compression("mutations.tsv.ZST"), (CompressionTypeVariant::ZSTD, ".zst".to_string()));
+        assert_eq!(parse_compression("mutations.tsv"),     (CompressionTypeVariant::UNCOMPRESSED, String::new()));
+        assert_eq!(parse_compression(""),                  (CompressionTypeVariant::UNCOMPRESSED, String::new()));
+    }
+
+    #[test]
+    fn open_compressed() -> Result<(), Report> {
+        use std::io::{Read, Write};
+        let dir = tempfile::tempdir()?;
+
+        // Bgzip is several gzip members, which must all be read
+        let mut bgzip = Vec::new();
+        for text in ["sample\tmutation\n", "s1\tC241T\n"] {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(text.as_bytes())?;
+            bgzip.extend(encoder.finish()?);
+        }
+        let files = [
+            ("mutations.tsv.bgz", bgzip),
+            ("mutations.tsv.zst", zstd::encode_all("sample\tmutation\ns1\tC241T\n".as_bytes(), 0)?),
+            ("mutations.tsv",     b"sample\tmutation\ns1\tC241T\n".to_vec()),
+        ];
+        for (name, content) in files {
+            let path = dir.path().join(name);
+            std::fs::write(&path, content)?;
+            let mut text = String::new();
+            open(&path)?.read_to_string(&mut text)?;
+            assert_eq!(text, "sample\tmutation\ns1\tC241T\n", "{name}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn object_store_local_paths() -> Result<(), Report> {
+        let ctx = SessionContext::new();
+        for path in ["C:\\data\\mutations.tsv", "file:///data/mutations.tsv", "data/mutations.tsv", "/data/mutations.tsv"] {
+            register_object_store(&ctx, path)?;
+        }
+        assert!(register_object_store(&ctx, "s3:mutations.tsv").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sample_paths() -> Result<(), Report> {
+        let dir  = tempfile::tempdir()?;
+        let path = dir.path().join("sample-paths.tsv");
+        std::fs::write(&path, [
+            "s1\tnode_1:C241T,A23403G node_5:G28881A",
+            "s2\tnode_1:C241T,A23403G node_7:T241C",
+            "s3\tnode_1:C241T node_9:T241G",
+            "",
+            "root",
+        ].join("\n"))?;
+
+        let ctx     = register_sample_paths(&path, SessionContext::new(), "paths").await?;
+        let batches = ctx.sql("SELECT sample, mutation FROM paths ORDER BY sample, position").await?.collect().await?;
+        let mut rows = Vec::new();
+        for batch in &batches {
+            let (sample, mutation) = (string_column(batch, "sample")?, string_column(batch, "mutation")?);
+            for i in 0..batch.num_rows() { rows.push(format!("{} {}", sample.value(i), mutation.value(i))) }
+        }
+        // Reversions are dropped, later mutations at a position replace earlier ones, and the root has none
+        assert_eq!(rows, ["s1 C241T", "s1 A23403G", "s1 G28881A", "s2 A23403G", "s3 C241G"]);
+
+        std::fs::write(&path, "s1\tnode_1:X")?;
+        assert!(register_sample_paths(&path, SessionContext::new(), "paths").await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn alignment_mutations() -> Result<(), Report> {
+        let dir  = tempfile::tempdir()?;
+        let path = dir.path().join("aligned.fasta");
+        let reference = b"acgtacgtac";
+        std::fs::write(&path, ">s1\n--GTTCGTA-\n>s2\nAC--ACGNAC\n>s3\nACGTA-GTAC\n>s4\n----------\n")?;
+
+        let ctx     = register_alignment(&path, reference, SessionContext::new(), "alignment").await?;
+        let batches = ctx.sql("SELECT sample, mutation, column FROM alignment ORDER BY sample, position").await?.collect().await?;
+        let mut rows = Vec::new();
+        for batch in &batches {
+            let (sample, mutation, column) = (string_column(batch, "sample")?, string_column(batch, "mutation")?, string_column(batch, "column")?);
+            for i in 0..batch.num_rows() { rows.push(format!("{} {} {}", sample.value(i), mutation.value(i), column.value(i))) }
+        }
+        // The gaps at the ends, ambiguous bases, and an all-gap sequence are missing coverage
+        assert_eq!(rows, ["s1 A5T substitutions", "s2 3-4 deletions", "s3 6 deletions"]);
+
+        // Sequences must be aligned to the reference
+        std::fs::write(&path, ">s1\nACGTACGTA\n")?;
+        assert!(register_alignment(&path, reference, SessionContext::new(), "alignment").await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,484 @@
+use arrow::array::{ArrayRef, Float64Array, StringArray, UInt32Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use color_eyre::eyre::{Report, Result};
+use datafusion::datasource::MemTable;
+use datafusion::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::codon::translate_codon;
+use crate::gene::Gene;
+
+/// A nucleotide substitution observed in a sample, with its optional ivar fields.
+#[derive(Clone, Debug)]
+struct Substitution {
+    offset:    usize,
+    alt:       u8,
+    status:    Option<String>,
+    frequency: Option<f64>,
+    depth:     Option<u32>,
+}
+
+/// Merge nucleotide substitutions in the same codon into a single amino acid substitution.
+///
+/// Catalogues describe multi-nucleotide variants (MNV) by their amino acid change, which
+/// can't be matched from the separate nucleotide rows, or from amino acid rows that
+/// translate each substitution on its own (ex. ivar). For every sample with two or more
+/// substitutions in a codon, the codon is translated with all of them, and the result
+/// replaces any amino acid substitutions already reported at that codon.
+///
+/// # Arguments
+///
+///   - `ctx`      : The session with the mutations table registered.
+///   - `name`     : The name of the mutations table, with at least sample, mutation, and column.
+///   - `reference`: The reference genome sequence.
+///   - `genes`    : The gene model, to locate codons.
+///
+pub async fn merge_codons(ctx: SessionContext, name: &str, reference: &[u8], genes: &[Gene]) -> Result<SessionContext, Report> {
+
+    log::info!("Merging nucleotide substitutions in the same codon.");
+
+    let table   = crate::quote_identifier(name);
+    let columns = crate::column_names(&ctx.table(name).await?);
+    let has     = |column: &str| columns.iter().any(|c| c == column);
+
+    let codons = codon_index(genes);
+
+    // ------------------------------------------------------------------------
+    // Substitutions by Codon
+
+    let select = ["status", "frequency", "depth"].iter().map(|c| match has(c) {
+        true  => crate::quote_identifier(c),
+        false => format!("NULL as {c}"),
+    }).collect::<Vec<_>>().join(", ");
+    let query   = format!("SELECT DISTINCT sample, mutation, {select} FROM {table} WHERE column = 'substitutions'");
+    let batches = ctx.sql(&query).await?.collect().await?;
+
+    let mut observed: BTreeMap<(String, usize, u32), Vec<Substitution>> = BTreeMap::new();
+    for batch in &batches {
+        let sample    = crate::string_column(batch, "sample")?;
+        let mutation  = crate::string_column(batch, "mutation")?;
+        let status    = crate::string_column(batch, "status")?;
+        let frequency = crate::string_column(batch, "frequency")?;
+        let depth     = crate::string_column(batch, "depth")?;
+        for i in 0..batch.num_rows() {
+            if status.is_valid(i) && status.value(i) == "absent" { continue }
+            let Some((position, alt)) = parse_substitution(mutation.value(i)) else { continue };
+            for (gene, codon, offset) in codons.get(&position).into_iter().flatten() {
+                observed.entry((sample.value(i).to_string(), *gene, *codon)).or_default().push(Substitution {
+                    offset:    *offset,
+                    alt,
+                    status:    status.is_valid(i).then(|| status.value(i).to_string()),
+                    frequency: frequency.is_valid(i).then(|| frequency.value(i).parse()).transpose()?,
+                    depth:     depth.is_valid(i).then(|| depth.value(i).parse()).transpose()?,
+                });
+            }
+        }
+    }
+
+    // ------------------------------------------------------------------------
+    // Translate Merged Codons
+
+    let mut merged: Vec<(String, String, String, Substitution)> = Vec::new();
+    let mut replaced: HashSet<(String, String, u32)> = HashSet::new();
+    for ((sample, g, codon), substitutions) in observed.into_iter().filter(|(_, s)| s.len() > 1) {
+        let gene = &genes[g];
+        let changes = substitutions.iter().map(|s| (s.offset, s.alt)).collect::<Vec<_>>();
+        let Some((ref_aa, alt_aa)) = codon_change(gene, codon, &changes, reference) else { continue };
+        if ref_aa == alt_aa { continue }
+
+        // The merged variant is only as strong as its weakest substitution
+        let weakest = Substitution {
+            offset:    0,
+            alt:       b'N',
+            status:    substitutions.iter().filter_map(|s| s.status.clone()).min_by_key(|s| s != "low-frequency"),
+            frequency: substitutions.iter().filter_map(|s| s.frequency).reduce(f64::min),
+            depth:     substitutions.iter().filter_map(|s| s.depth).min(),
+        };
+        let mutation = format!("{}:{ref_aa}{codon}{alt_aa}", gene.name);
+        log::debug!("Merged {} substitutions in {sample} into {mutation}", substitutions.len());
+        replaced.insert((sample.clone(), gene.name.clone(), codon));
+        merged.push((sample, mutation, gene.name.clone(), weakest));
+    }
+
+    if merged.is_empty() {
+        log::info!("No codons with multiple substitutions were found.");
+        return Ok(ctx)
+    }
+    log::info!("Merged {} codons with multiple substitutions.", merged.len());
+
+    // ------------------------------------------------------------------------
+    // Replace Amino Acid Substitutions
+
+    // Amino acid substitutions already reported at a merged codon are dropped
+    let batches = ctx.sql(&format!("SELECT DISTINCT sample, mutation FROM {table} WHERE column = 'aaSubstitutions'")).await?.collect().await?;
+    let mut dropped: Vec<(String, String)> = Vec::new();
+    for batch in &batches {
+        let sample   = crate::string_column(batch, "sample")?;
+        let mutation = crate::string_column(batch, "mutation")?;
+        for i in 0..batch.num_rows() {
+            let Some((gene, change)) = mutation.value(i).split_once(':') else { continue };
+            let codon = change.chars().filter(|c| c.is_ascii_digit()).collect::<String>();
+            let Ok(codon) = codon.parse::<u32>() else { continue };
+            if replaced.contains(&(sample.value(i).to_string(), gene.to_string(), codon)) {
+                dropped.push((sample.value(i).to_string(), mutation.value(i).to_string()));
+            }
+        }
+    }
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("sample",    DataType::Utf8,    false),
+        Field::new("mutation",  DataType::Utf8,    false),
+        Field::new("gene",      DataType::Utf8,    false),
+        Field::new("status",    DataType::Utf8,    true),
+        Field::new("frequency", DataType::Float64, true),
+        Field::new("depth",     DataType::UInt32,  true),
+    ]));
+    let records = RecordBatch::try_new(schema.clone(), vec![
+        Arc::new(StringArray::from(merged.iter().map(|m| m.0.clone()).collect::<Vec<_>>())) as ArrayRef,
+        Arc::new(StringArray::from(merged.iter().map(|m| m.1.clone()).collect::<Vec<_>>())),
+        Arc::new(StringArray::from(merged.iter().map(|m| m.2.clone()).collect::<Vec<_>>())),
+        Arc::new(StringArray::from(merged.iter().map(|m| m.3.status.clone()).collect::<Vec<_>>())),
+        Arc::new(Float64Array::from(merged.iter().map(|m| m.3.frequency).collect::<Vec<_>>())),
+        Arc::new(UInt32Array::from(merged.iter().map(|m| m.3.depth).collect::<Vec<_>>())),
+    ])?;
+    ctx.register_table("merged_codons", Arc::new(MemTable::try_new(schema, vec![vec![records]])?))?;
+
+    let schema  = Arc::new(Schema::new(vec![Field::new("sample", DataType::Utf8, false), Field::new("mutation", DataType::Utf8, false)]));
+    let records = RecordBatch::try_new(schema.clone(), vec![
+        Arc::new(StringArray::from(dropped.iter().map(|d| d.0.clone()).collect::<Vec<_>>())) as ArrayRef,
+        Arc::new(StringArray::from(dropped.iter().map(|d| d.1.clone()).collect::<Vec<_>>())),
+    ])?;
+    ctx.register_table("dropped_codons", Arc::new(MemTable::try_new(schema, vec![vec![records]])?))?;
+
+    // The merged rows fill in the columns they have, and leave the rest empty
+    let merged_columns = columns.iter().map(|c| match c.as_str() {
+        "sample" | "mutation" | "gene" | "status" | "frequency" | "depth" => crate::quote_identifier(c),
+        "column" => "'aaSubstitutions' as column".to_string(),
+        "type"   => "'amino-acid' as type".to_string(),
+        _        => format!("NULL as {}", crate::quote_identifier(c)),
+    }).collect::<Vec<_>>().join(", ");
+    let query = format!("
+        SELECT * FROM {table} M
+        WHERE NOT (M.column = 'aaSubstitutions' AND EXISTS (SELECT 1 FROM dropped_codons D WHERE D.sample = M.sample AND D.mutation = M.mutation))
+        UNION ALL
+        SELECT {merged_columns} FROM merged_codons");
+    let ctx = crate::replace_table(ctx, name, &query).await?;
+
+    ctx.deregister_table("merged_codons")?;
+    ctx.deregister_table("dropped_codons")?;
+
+    Ok(ctx)
+}
+
+/// The (gene index, codon, offset within the codon) of each coding position of the genes.
+pub fn codon_index(genes: &[Gene]) -> HashMap<u32, Vec<(usize, u32, usize)>> {
+    let mut codons: HashMap<u32, Vec<(usize, u32, usize)>> = HashMap::new();
+    for (g, gene) in genes.iter().enumerate() {
+        for (i, position) in gene.positions().into_iter().enumerate() {
+            codons.entry(position).or_default().push((g, i as u32 / 3 + 1, i % 3));
+        }
+    }
+    codons
+}
+
+/// Translate the reference and alternate amino acids of a codon, with substitutions given as (offset, base).
+///
+/// Returns `None` when the codon is past the end of the gene.
+pub fn codon_change(gene: &Gene, codon: u32, substitutions: &[(usize, u8)], reference: &[u8]) -> Option<(char, char)> {
+    let positions = gene.positions();
+    let start     = (codon as usize).checked_sub(1)? * 3;
+    let codon_positions = positions.get(start..start + 3)?;
+
+    let base = |position: u32| reference.get(position as usize - 1).copied().unwrap_or(b'N');
+    let mut ref_codon = codon_positions.iter().map(|p| base(*p)).collect::<Vec<_>>();
+    let mut alt_codon = ref_codon.clone();
+    for (offset, alt) in substitutions { alt_codon[*offset] = *alt }
+    if gene.strand == "-" {
+        ref_codon = ref_codon.into_iter().map(complement).collect();
+        alt_codon = alt_codon.into_iter().map(complement).collect();
+    }
+    Some((translate_codon(&ref_codon), translate_codon(&alt_codon)))
+}
+
+/// Check the amino acid substitutions of a mutations table against the translated nucleotide substitutions.
+///
+/// Every codon with nucleotide substitutions in a sample is translated, and compared to the
+/// reported amino acid substitution at that codon. Returns a description of each disagreement,
+/// which usually means that the reference or gene model don't match the nextclade dataset.
+pub async fn check_translations(ctx: &SessionContext, name: &str, reference: &[u8], genes: &[Gene]) -> Result<Vec<String>, Report> {
+
+    log::info!("Checking amino acid substitutions against translated nucleotide substitutions.");
+
+    let table  = crate::quote_identifier(name);
+    let codons = codon_index(genes);
+
+    // Nucleotide substitutions by (sample, gene, codon)
+    let mut observed: BTreeMap<(String, usize, u32), Vec<(usize, u8)>> = BTreeMap::new();
+    let batches = ctx.sql(&format!("SELECT DISTINCT sample, mutation FROM {table} WHERE column = 'substitutions'")).await?.collect().await?;
+    for batch in &batches {
+        let sample   = crate::string_column(batch, "sample")?;
+        let mutation = crate::string_column(batch, "mutation")?;
+        for i in 0..batch.num_rows() {
+            let Some((position, alt)) = parse_substitution(mutation.value(i)) else { continue };
+            for (gene, codon, offset) in codons.get(&position).into_iter().flatten() {
+                observed.entry((sample.value(i).to_string(), *gene, *codon)).or_default().push((*offset, alt));
+            }
+        }
+    }
+
+    // Reported amino acid substitutions by (sample, gene, codon)
+    let names = genes.iter().enumerate().map(|(g, gene)| (gene.name.as_str(), g)).collect::<HashMap<_, _>>();
+    let mut reported: BTreeMap<(String, usize, u32), String> = BTreeMap::new();
+    let batches = ctx.sql(&format!("SELECT DISTINCT sample, mutation FROM {table} WHERE column = 'aaSubstitutions'")).await?.collect().await?;
+    for batch in &batches {
+        let sample   = crate::string_column(batch, "sample")?;
+        let mutation = crate::string_column(batch, "mutation")?;
+        for i in 0..batch.num_rows() {
+            let Some((gene, change)) = mutation.value(i).split_once(':') else { continue };
+            let Some(g) = names.get(gene) else { continue };
+            let Ok(codon) = change.chars().filter(|c| c.is_ascii_digit()).collect::<String>().parse::<u32>() else { continue };
+            reported.insert((sample.value(i).to_string(), *g, codon), mutation.value(i).to_string());
+        }
+    }
+
+    let mut disagreements = Vec::new();
+    for ((sample, g, codon), substitutions) in &observed {
+        let gene = &genes[*g];
+        let Some((ref_aa, alt_aa)) = codon_change(gene, *codon, substitutions, reference) else { continue };
+        // Ambiguous codons can't be checked
+        if ref_aa == 'X' || alt_aa == 'X' { continue }
+        let expected = (ref_aa != alt_aa).then(|| format!("{}:{ref_aa}{codon}{alt_aa}", gene.name));
+        let actual   = reported.get(&(sample.clone(), *g, *codon));
+        match (&expected, actual) {
+            (Some(expected), Some(actual)) if expected != actual => disagreements.push(format!("{sample}: {actual} was reported, but the nucleotide substitutions translate to {expected}.")),
+            (Some(expected), None)                               => disagreements.push(format!("{sample}: the nucleotide substitutions translate to {expected}, but no amino acid substitution was reported.")),
+            (None, Some(actual))                                 => disagreements.push(format!("{sample}: {actual} was reported, but the nucleotide substitutions are synonymous.")),
+            _                                                    => (),
+        }
+    }
+
+    Ok(disagreements)
+}
+
+/// Add `effect` and `codon_pos` columns to the nucleotide substitutions of a mutations table.
+///
+/// Each substitution is classified within the codon of the gene it was assigned to (or the
+/// first overlapping gene), translated with every other substitution of the same sample in
+/// that codon, as [`merge_codons`] does. A substitution that is synonymous on its own, but part
+/// of a nonsynonymous codon change, is therefore `nonsynonymous`. Substitutions outside of
+/// every gene are `noncoding`, and other mutations are left empty.
+///
+/// # Arguments
+///
+///   - `ctx`      : The session with the mutations table registered.
+///   - `name`     : The name of the mutations table, with at least sample, mutation, column, and gene.
+///   - `reference`: The reference genome sequence.
+///   - `genes`    : The gene model, to locate codons.
+///
+pub async fn classify_substitutions(ctx: SessionContext, name: &str, reference: &[u8], genes: &[Gene]) -> Result<SessionContext, Report> {
+
+    log::info!("Classifying nucleotide substitutions as synonymous or nonsynonymous.");
+
+    let table   = crate::quote_identifier(name);
+    let columns = crate::column_names(&ctx.table(name).await?);
+    let codons  = codon_index(genes);
+
+    // ------------------------------------------------------------------------
+    // Substitutions by Codon
+
+    let status  = match columns.iter().any(|c| c == "status") {
+        true  => "status",
+        false => "NULL as status",
+    };
+    let batches = ctx.sql(&format!("SELECT DISTINCT sample, mutation, gene, {status} FROM {table} WHERE column = 'substitutions'")).await?.collect().await?;
+
+    // (sample, mutation, gene, position, alt) of each substitution
+    let mut substitutions: Vec<(String, String, Option<String>, Option<(u32, u8)>)> = Vec::new();
+    let mut observed: HashMap<(String, usize, u32), Vec<(usize, u8)>> = HashMap::new();
+    for batch in &batches {
+        let sample   = crate::string_column(batch, "sample")?;
+        let mutation = crate::string_column(batch, "mutation")?;
+        let gene     = crate::string_column(batch, "gene")?;
+        let status   = crate::string_column(batch, "status")?;
+        for i in 0..batch.num_rows() {
+            let name         = gene.is_valid(i).then(|| gene.value(i).to_string());
+            let substitution = parse_substitution(mutation.value(i));
+            // Absent substitutions are classified, but don't change the codon of the sample
+            let absent = status.is_valid(i) && status.value(i) == "absent";
+            if let (Some((position, alt)), false) = (substitution, absent) {
+                for (g, codon, offset) in codons.get(&position).into_iter().flatten() {
+                    observed.entry((sample.value(i).to_string(), *g, *codon)).or_default().push((*offset, alt));
+                }
+            }
+            substitutions.push((sample.value(i).to_string(), mutation.value(i).to_string(), name, substitution));
+        }
+    }
+
+    // ------------------------------------------------------------------------
+    // Classify Codons
+
+    let mut effects: Vec<(String, String, Option<String>, Option<String>, Option<u32>)> = Vec::new();
+    for (sample, mutation, name, substitution) in substitutions {
+        let Some((position, alt)) = substitution else { continue };
+        let overlaps = codons.get(&position).map(|c| c.as_slice()).unwrap_or_default();
+        let codon    = overlaps.iter().find(|(g, _, _)| Some(&genes[*g].name) == name.as_ref()).or(overlaps.first());
+        let (effect, codon_pos) = match codon {
+            None                     => (Some("noncoding"), None),
+            Some((g, codon, offset)) => {
+                let mut changes = observed.get(&(sample.clone(), *g, *codon)).cloned().unwrap_or_default();
+                if !changes.contains(&(*offset, alt)) { changes.push((*offset, alt)) }
+                let effect = match codon_change(&genes[*g], *codon, &changes, reference) {
+                    // Ambiguous codons can't be classified
+                    Some((ref_aa, alt_aa)) if ref_aa == 'X' || alt_aa == 'X' => None,
+                    Some((ref_aa, alt_aa)) if ref_aa == alt_aa               => Some("synonymous"),
+                    Some(_)                                                  => Some("nonsynonymous"),
+                    None                                                     => None,
+                };
+                (effect, Some(*offset as u32 + 1))
+            },
+        };
+        effects.push((sample, mutation, name, effect.map(String::from), codon_pos));
+    }
+
+    let synonymous    = effects.iter().filter(|e| e.3.as_deref() == Some("synonymous")).count();
+    let nonsynonymous = effects.iter().filter(|e| e.3.as_deref() == Some("nonsynonymous")).count();
+    log::info!("Classified {synonymous} synonymous and {nonsynonymous} nonsynonymous substitutions.");
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("sample",    DataType::Utf8,   false),
+        Field::new("mutation",  DataType::Utf8,   false),
+        Field::new("gene",      DataType::Utf8,   true),
+        Field::new("effect",    DataType::Utf8,   true),
+        Field::new("codon_pos", DataType::UInt32, true),
+    ]));
+    let records = RecordBatch::try_new(schema.clone(), vec![
+        Arc::new(StringArray::from(effects.iter().map(|e| e.0.clone()).collect::<Vec<_>>())) as ArrayRef,
+        Arc::new(StringArray::from(effects.iter().map(|e| e.1.clone()).collect::<Vec<_>>())),
+        Arc::new(StringArray::from(effects.iter().map(|e| e.2.clone()).collect::<Vec<_>>())),
+        Arc::new(StringArray::from(effects.iter().map(|e| e.3.clone()).collect::<Vec<_>>())),
+        Arc::new(UInt32Array::from(effects.iter().map(|e| e.4).collect::<Vec<_>>())),
+    ])?;
+    ctx.register_table("substitution_effects", Arc::new(MemTable::try_new(schema, vec![vec![records]])?))?;
+
+    let query = format!("
+        SELECT M.*, E.effect, E.codon_pos
+        FROM {table} M
+        LEFT JOIN substitution_effects E
+        ON M.column = 'substitutions' AND M.sample = E.sample AND M.mutation = E.mutation AND M.gene IS NOT DISTINCT FROM E.gene
+        ORDER BY sample,nuc_start,nuc_end");
+    let ctx = crate::replace_table(ctx, name, &query).await?;
+
+    ctx.deregister_table("substitution_effects")?;
+
+    Ok(ctx)
+}
+
+/// Parse a nucleotide substitution (ex. C241T) into its position and alternate base.
+fn parse_substitution(mutation: &str) -> Option<(u32, u8)> {
+    let bytes = mutation.as_bytes();
+    if bytes.len() < 3 { return None }
+    let position = mutation.get(1..mutation.len() - 1)?.parse().ok()?;
+    Some((position, bytes[bytes.len() - 1].to_ascii_uppercase()))
+}
+
+/// The complementary nucleotide.
+pub(crate) fn complement(base: u8) -> u8 {
+    match base.to_ascii_uppercase() {
+        b'A' => b'T',
+        b'T' => b'A',
+        b'C' => b'G',
+        b'G' => b'C',
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REFERENCE: &[u8] = b"ATGAAACCCGGGTTTAAA";
+
+    /// A forward gene (ATG AAA CCC), and a reverse gene on the minus strand (TTT AAA CCC).
+    fn genes() -> Vec<Gene> {
+        vec![
+            Gene { name: "g".to_string(), strand: "+".to_string(), start: 1,  end: 9,  segments: vec![(1, 9)] },
+            Gene { name: "m".to_string(), strand: "-".to_string(), start: 10, end: 18, segments: vec![(10, 18)] },
+        ]
+    }
+
+    #[tokio::test]
+    async fn merge_codon_substitutions() -> Result<(), Report> {
+        let ctx = SessionContext::new();
+        ctx.sql("CREATE TABLE mutations AS SELECT column1 as sample, column2 as mutation, column3 as column, column4 as gene, column5 as type FROM (VALUES
+            ('s1', 'A4G',   'substitutions',   NULL, 'nucleotide'),
+            ('s1', 'A5T',   'substitutions',   NULL, 'nucleotide'),
+            ('s1', 'g:K2E', 'aaSubstitutions', 'g',  'amino-acid'),
+            ('s2', 'A4G',   'substitutions',   NULL, 'nucleotide'),
+            ('s2', 'g:K2E', 'aaSubstitutions', 'g',  'amino-acid'),
+            ('s3', 'A18G',  'substitutions',   NULL, 'nucleotide'),
+            ('s3', 'A17G',  'substitutions',   NULL, 'nucleotide'))").await?.collect().await?;
+        let ctx = merge_codons(ctx, "mutations", REFERENCE, &genes()).await?;
+
+        let batches = ctx.sql("SELECT sample, mutation FROM mutations WHERE column = 'aaSubstitutions' ORDER BY sample, mutation").await?.collect().await?;
+        let mut rows = Vec::new();
+        for batch in &batches {
+            let (sample, mutation) = (crate::string_column(batch, "sample")?, crate::string_column(batch, "mutation")?);
+            for i in 0..batch.num_rows() { rows.push(format!("{} {}", sample.value(i), mutation.value(i))) }
+        }
+        // AAA>GTA replaces the single substitution K2E in s1, and the minus strand TTT>CCT is F1P
+        assert_eq!(rows, ["s1 g:K2V", "s2 g:K2E", "s3 m:F1P"]);
+
+        // An empty table is left as is
+        ctx.sql("CREATE TABLE empty AS SELECT * FROM mutations WHERE false").await?.collect().await?;
+        let ctx = merge_codons(ctx, "empty", REFERENCE, &genes()).await?;
+        assert_eq!(ctx.table("empty").await?.count().await?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn codon_changes() {
+        let genes = genes();
+        assert_eq!(codon_change(&genes[0], 2, &[(0, b'G')], REFERENCE),            Some(('K', 'E')));
+        assert_eq!(codon_change(&genes[0], 2, &[(2, b'G')], REFERENCE),            Some(('K', 'K')));
+        assert_eq!(codon_change(&genes[0], 2, &[(0, b'G'), (1, b'T')], REFERENCE), Some(('K', 'V')));
+        assert_eq!(codon_change(&genes[0], 1, &[(2, b'A')], REFERENCE),            Some(('M', 'I')));
+        assert_eq!(codon_change(&genes[0], 2, &[], REFERENCE),                     Some(('K', 'K')));
+
+        // Minus strand codons are read from the end of the gene, and complemented
+        assert_eq!(codon_change(&genes[1], 1, &[(0, b'G')], REFERENCE), Some(('F', 'L')));
+        assert_eq!(codon_change(&genes[1], 3, &[(0, b'A')], REFERENCE), Some(('P', 'S')));
+
+        // Codons of a spliced gene skip the intron
+        let spliced = Gene { name: "s".to_string(), strand: "+".to_string(), start: 1, end: 9, segments: vec![(1, 3), (7, 9)] };
+        assert_eq!(codon_change(&spliced, 2, &[(1, b'A')], REFERENCE), Some(('P', 'H')));
+
+        // Ambiguous bases, and codons outside of the gene or reference
+        assert_eq!(codon_change(&genes[0], 2, &[(0, b'N')], REFERENCE), Some(('K', 'X')));
+        assert_eq!(codon_change(&genes[0], 0, &[], REFERENCE),           None);
+        assert_eq!(codon_change(&genes[0], 4, &[], REFERENCE),           None);
+        assert_eq!(codon_change(&genes[0], 2, &[], b""),                 Some(('X', 'X')));
+    }
+
+    #[tokio::test]
+    async fn classify_at_merged_codon() -> Result<(), Report> {
+        let ctx = SessionContext::new();
+        ctx.sql("CREATE TABLE mutations AS SELECT column1 as sample, column2 as mutation, column3 as column, column4 as gene, column5 as nuc_start, column5 as nuc_end FROM (VALUES
+            ('s1', 'A4G',  'substitutions', 'g',  4),
+            ('s1', 'A6G',  'substitutions', 'g',  6),
+            ('s2', 'A6G',  'substitutions', 'g',  6),
+            ('s2', 'T14C', 'substitutions', NULL, 14))").await?.collect().await?;
+        let ctx = classify_substitutions(ctx, "mutations", REFERENCE, &genes()[..1]).await?;
+
+        let batches = ctx.sql("SELECT sample, mutation, effect FROM mutations ORDER BY sample, mutation").await?.collect().await?;
+        let batch   = &batches[0];
+        let effect  = crate::string_column(batch, "effect")?;
+        // A6G is synonymous on its own (AAG), but part of AAA>GAG in s1
+        assert_eq!(effect.value(0), "nonsynonymous");
+        assert_eq!(effect.value(1), "nonsynonymous");
+        assert_eq!(effect.value(2), "synonymous");
+        assert_eq!(effect.value(3), "noncoding");
+        Ok(())
+    }
+}
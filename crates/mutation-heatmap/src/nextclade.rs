@@ -0,0 +1,237 @@
+use arrow::array::{ArrayRef, StringArray, UInt32Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use color_eyre::eyre::{eyre, Report, Result};
+use datafusion::datasource::MemTable;
+use datafusion::prelude::*;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::BufRead;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Register nextclade output as a wide table, with the format detected from the extension.
+///
+/// TSV and CSV are registered as-is, while JSON (`--output-json`) and NDJSON
+/// (`--output-ndjson`) are flattened into the same columns as the TSV.
+pub async fn register<P,N>(path: &P, ctx: SessionContext, name: N) -> Result<SessionContext, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+    N: ToString,
+{
+    let (_, ext, _) = crate::parse_csv_path(path, None)?;
+    match ext.to_lowercase().as_str() {
+        "json"   => register_json(path, ctx, name).await,
+        "ndjson" => register_ndjson(path, ctx, name).await,
+        _        => crate::register_csv(path, ctx, None, name).await,
+    }
+}
+
+/// Register nextclade JSON output (`--output-json`) as a wide table matching the nextclade TSV.
+///
+/// The nested mutation arrays are formatted as the comma-separated TSV notation
+/// (ex. `C241T`, `6513-6515`, `28933:T`, `E:T9I`, `S:214:EPE`), and all other scalar
+/// fields of each result are kept as text.
+///
+/// The reference codon of each amino acid change (`aaChangesGroups`, `nucToAaMuts`, or the
+/// `codonNucRange` of older versions) is registered as a second table, named `{name}_codons`,
+/// with the columns mutation, nuc_start, and nuc_end (1-based, inclusive).
+pub async fn register_json<P,N>(path: &P, ctx: SessionContext, name: N) -> Result<SessionContext, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+    N: ToString,
+{
+    log::info!("Reading nextclade json file: {path:?}");
+
+    let json: Value = serde_json::from_reader(crate::open(path)?)?;
+    let results = json.get("results").and_then(|r| r.as_array()).ok_or(eyre!("Nextclade json has no 'results' array: {path:?}"))?;
+
+    register_results(results, ctx, name)
+}
+
+/// Register nextclade NDJSON output (`--output-ndjson`) as a wide table matching the nextclade TSV.
+///
+/// Each line is one result, flattened the same way as [`register_json`]. Lines
+/// for sequences that failed analysis (with an `error` and no mutations) are kept,
+/// so those samples are still reported.
+pub async fn register_ndjson<P,N>(path: &P, ctx: SessionContext, name: N) -> Result<SessionContext, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+    N: ToString,
+{
+    log::info!("Reading nextclade ndjson file: {path:?}");
+
+    let mut results = Vec::new();
+    for line in crate::open(path)?.lines() {
+        let line = line?;
+        if line.trim().is_empty() { continue }
+        results.push(serde_json::from_str::<Value>(&line)?);
+    }
+
+    register_results(&results, ctx, name)
+}
+
+/// Flatten nextclade results into a wide table of text columns, and register it.
+fn register_results<N: ToString>(results: &[Value], ctx: SessionContext, name: N) -> Result<SessionContext, Report> {
+    // Each result becomes one row of text columns
+    let mut rows: Vec<BTreeMap<String, Option<String>>> = Vec::new();
+    let mut codons = BTreeMap::new();
+    for result in results {
+        let result = result.as_object().ok_or(eyre!("Nextclade json result is not an object: {result}"))?;
+        codon_ranges(result, &mut codons);
+        let mut row = BTreeMap::new();
+        for (key, value) in result {
+            let value = match key.as_str() {
+                "substitutions"   => join(value, substitution),
+                "deletions"       => join(value, deletion),
+                "insertions"      => join(value, insertion),
+                "frameShifts"     => join(value, frame_shift),
+                "aaSubstitutions" => join(value, aa_substitution),
+                "aaDeletions"     => join(value, aa_substitution),
+                "aaInsertions"    => join(value, aa_insertion),
+                "missing"         => join(value, deletion),
+                _ => match value {
+                    Value::String(s) => Some(s.clone()),
+                    Value::Number(n) => Some(n.to_string()),
+                    Value::Bool(b)   => Some(b.to_string()),
+                    _                => continue,
+                },
+            };
+            row.insert(key.clone(), value);
+        }
+        rows.push(row);
+    }
+
+    // The columns are the union of all result fields, with seqName first
+    let mut columns = rows.iter().flat_map(|r| r.keys().cloned()).collect::<BTreeSet<_>>().into_iter().collect::<Vec<_>>();
+    columns.retain(|c| c != "seqName");
+    columns.insert(0, "seqName".to_string());
+
+    let schema = Arc::new(Schema::new(columns.iter().map(|c| Field::new(c, DataType::Utf8, true)).collect::<Vec<_>>()));
+    let arrays = columns.iter().map(|c| {
+        Arc::new(StringArray::from(rows.iter().map(|r| r.get(c).cloned().flatten()).collect::<Vec<_>>())) as ArrayRef
+    }).collect::<Vec<_>>();
+    let records = RecordBatch::try_new(schema.clone(), arrays)?;
+
+    let provider = MemTable::try_new(schema, vec![vec![records]])?;
+    ctx.register_table(&name.to_string(), Arc::new(provider))?;
+
+    if !codons.is_empty() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("mutation",  DataType::Utf8,   false),
+            Field::new("nuc_start", DataType::UInt32, false),
+            Field::new("nuc_end",   DataType::UInt32, false),
+        ]));
+        let records = RecordBatch::try_new(schema.clone(), vec![
+            Arc::new(StringArray::from(codons.keys().cloned().collect::<Vec<_>>())) as ArrayRef,
+            Arc::new(UInt32Array::from(codons.values().map(|(start, _)| *start).collect::<Vec<_>>())),
+            Arc::new(UInt32Array::from(codons.values().map(|(_, end)| *end).collect::<Vec<_>>())),
+        ])?;
+        ctx.register_table(&format!("{}_codons", name.to_string()), Arc::new(MemTable::try_new(schema, vec![vec![records]])?))?;
+    }
+
+    Ok(ctx)
+}
+
+/// Collect the reference codon (1-based, inclusive) of each amino acid change of a result.
+///
+/// Nextclade v3 lists the changes in `aaChangesGroups` and `nucToAaMuts`, while older versions
+/// put the `codonNucRange` on each amino acid substitution and deletion.
+fn codon_ranges(result: &Map<String, Value>, codons: &mut BTreeMap<String, (u32, u32)>) {
+    fn array(value: Option<&Value>) -> impl Iterator<Item = &Value> {
+        value.and_then(|v| v.as_array()).into_iter().flatten()
+    }
+    let mut changes = array(result.get("aaSubstitutions")).chain(array(result.get("aaDeletions"))).collect::<Vec<_>>();
+    for group in array(result.get("aaChangesGroups")) {
+        changes.extend(array(group.get("changes")));
+    }
+    for mutations in result.get("nucToAaMuts").and_then(|v| v.as_object()).into_iter().flat_map(|m| m.values()) {
+        changes.extend(array(Some(mutations)));
+    }
+    for change in changes.iter().filter_map(|c| c.as_object()) {
+        let (Some(mutation), Some((begin, end))) = (aa_substitution(change), change.get("codonNucRange").and_then(range)) else { continue };
+        codons.insert(mutation, (begin as u32 + 1, end as u32));
+    }
+}
+
+/// Format each element of a json array, and join them with commas.
+///
+/// Empty arrays are null, like empty fields in the TSV.
+fn join(value: &Value, format: fn(&Map<String, Value>) -> Option<String>) -> Option<String> {
+    let values = value.as_array()?.iter().filter_map(|v| v.as_object()).filter_map(format).collect::<Vec<_>>();
+    (!values.is_empty()).then(|| values.join(","))
+}
+
+/// Get the first of several possible keys, which differ between nextclade versions.
+fn get<'a>(value: &'a Map<String, Value>, keys: &[&str]) -> Option<&'a Value> {
+    keys.iter().find_map(|k| value.get(*k))
+}
+
+/// Get a 0-based position, from either a plain number or a `{ begin, end }` range.
+fn position(value: &Value) -> Option<u64> {
+    value.as_u64().or_else(|| value.get("begin")?.as_u64())
+}
+
+/// A 0-based half-open range, from a `{ begin, end }` object.
+fn range(value: &Value) -> Option<(u64, u64)> {
+    Some((value.get("begin")?.as_u64()?, value.get("end")?.as_u64()?))
+}
+
+/// Format a 0-based half-open range as 1-based inclusive coordinates (ex. 6513-6515, 12506).
+fn format_range((begin, end): (u64, u64)) -> String {
+    match end.saturating_sub(begin) <= 1 {
+        true  => (begin + 1).to_string(),
+        false => format!("{}-{end}", begin + 1),
+    }
+}
+
+/// Nucleotide substitution (ex. C241T).
+fn substitution(value: &Map<String, Value>) -> Option<String> {
+    let position = position(get(value, &["pos"])?)?;
+    let reference = get(value, &["refNuc", "ref"])?.as_str()?;
+    let query     = get(value, &["qryNuc", "queryNuc", "alt"])?.as_str()?;
+    Some(format!("{reference}{}{query}", position + 1))
+}
+
+/// Nucleotide deletion or missing range (ex. 6513-6515).
+fn deletion(value: &Map<String, Value>) -> Option<String> {
+    let range = match value.get("range") {
+        Some(r) => range(r)?,
+        None    => {
+            let start = value.get("start")?.as_u64()?;
+            (start, start + value.get("length")?.as_u64()?)
+        },
+    };
+    Some(format_range(range))
+}
+
+/// Nucleotide insertion (ex. 28933:T).
+fn insertion(value: &Map<String, Value>) -> Option<String> {
+    let position = position(get(value, &["pos"])?)?;
+    let inserted = get(value, &["ins", "query"])?.as_str()?;
+    Some(format!("{}:{inserted}", position + 1))
+}
+
+/// Frame shift (ex. ORF1a:4082-4401).
+fn frame_shift(value: &Map<String, Value>) -> Option<String> {
+    let gene  = get(value, &["cdsName", "geneName", "gene"])?.as_str()?;
+    let codon = range(get(value, &["codon"])?)?;
+    Some(format!("{gene}:{}", format_range(codon)))
+}
+
+/// Amino acid substitution or deletion (ex. E:T9I, N:E31-).
+fn aa_substitution(value: &Map<String, Value>) -> Option<String> {
+    let gene      = get(value, &["cdsName", "gene", "cds"])?.as_str()?;
+    let position  = position(get(value, &["pos", "codon"])?)?;
+    let reference = get(value, &["refAa"])?.as_str()?;
+    let query     = get(value, &["qryAa", "queryAa"])?.as_str()?;
+    Some(format!("{gene}:{reference}{}{query}", position + 1))
+}
+
+/// Amino acid insertion (ex. S:214:EPE).
+fn aa_insertion(value: &Map<String, Value>) -> Option<String> {
+    let gene     = get(value, &["cdsName", "cds", "gene"])?.as_str()?;
+    let position = position(get(value, &["pos", "codon"])?)?;
+    let inserted = get(value, &["ins"])?.as_str()?;
+    Some(format!("{gene}:{}:{inserted}", position + 1))
+}
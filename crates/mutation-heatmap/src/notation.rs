@@ -0,0 +1,378 @@
+use color_eyre::eyre::{eyre, Report, Result};
+use crate::codon::{one_letter, three_letter};
+use std::collections::HashMap;
+
+/// Normalize a mutation written in HGVS or SPDI notation to nextclade shorthand.
+///
+/// Returns the normalized mutation, and the nextclade column it belongs to when the
+/// notation makes it unambiguous. Mutations already in nextclade shorthand are
+/// returned unchanged.
+///
+/// | Notation       | Example                   | Nextclade      |
+/// |----------------|---------------------------|----------------|
+/// | HGVS genomic   | `NC_045512.2:g.241C>T`    | `C241T`        |
+/// | HGVS coding    | `S:c.1501A>T`             | `A23063T`      |
+/// | HGVS protein   | `S:p.Glu484Lys`           | `S:E484K`      |
+/// | SPDI           | `NC_045512.2:240:C:T`     | `C241T`        |
+/// | Insertion      | `S:ins214EPE`             | `S:214:EPE`    |
+/// | Deletion range | `S:HV69-70del`            | `S:del69-70`   |
+///
+/// # Arguments
+///
+///   - `mutation`   : The mutation to normalize.
+///   - `gene_starts`: Gene start coordinates, needed to convert HGVS coding positions to genomic positions.
+///
+pub fn normalize(mutation: &str, gene_starts: &HashMap<String, u32>) -> Result<(String, Option<&'static str>), Report> {
+    let mutation = mutation.trim();
+
+    // HGVS, the prefix is an accession (genomic) or gene name (coding, protein)
+    let (prefix, description) = match mutation.rsplit_once(':') {
+        Some((prefix, description)) => (Some(prefix), description),
+        None                        => (None, mutation),
+    };
+    if let Some(description) = description.strip_prefix("g.") {
+        return parse_nucleotide(description, |position| position.parse().ok())
+    }
+    if let Some(description) = description.strip_prefix("c.") {
+        let gene       = prefix.ok_or(eyre!("HGVS coding mutation has no gene name: {mutation}"))?;
+        let gene_start = *gene_starts.get(gene).ok_or(eyre!("Gene {gene} of HGVS coding mutation {mutation} was not found in the GFF."))?;
+        return parse_nucleotide(description, |position| coding_to_genomic(position, gene_start))
+    }
+    if let Some(description) = description.strip_prefix("p.") {
+        let gene = prefix.ok_or(eyre!("HGVS protein mutation has no gene name: {mutation}"))?;
+        return parse_protein(gene, description)
+    }
+
+    // Amino acid deletion ranges, ex. S:del69-70, S:HV69-70del
+    if let Some((gene, start, end)) = parse_deletion_range(mutation) {
+        let normalized = match start == end {
+            true  => format!("{gene}:del{start}"),
+            false => format!("{gene}:del{start}-{end}"),
+        };
+        return Ok((normalized, Some("aaDeletions")))
+    }
+
+    // Insertion shorthand, ex. S:ins214EPE, S:214insEPE, ins28933T, 28933:t
+    if let Some(normalized) = parse_insertion(mutation) {
+        return Ok(normalized)
+    }
+
+    // SPDI, Sequence:Position(0-based):Deletion:Insertion
+    let fields = mutation.split(':').collect::<Vec<_>>();
+    if let [_sequence, position, deletion, insertion] = fields[..] {
+        if let Ok(position) = position.parse::<u32>() {
+            return parse_spdi(position, deletion, insertion)
+        }
+    }
+
+    Ok((mutation.to_string(), None))
+}
+
+/// Display options for amino acid mutation labels.
+///
+/// These only change how mutations are shown in plots and exports, the
+/// mutations table always keeps the nextclade shorthand used for matching.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LabelStyle {
+    /// Write residues in three-letter code (ex. `S:Phe456Leu` instead of `S:F456L`).
+    pub three_letter: bool,
+    /// Write deletions with `del` (ex. `S:del69` or `S:His69del` instead of `S:H69-`).
+    pub del: bool,
+}
+
+/// Format an amino acid substitution or deletion in nextclade shorthand with a label style.
+///
+/// Nucleotide mutations, insertions, and mutations that can't be parsed are returned unchanged.
+///
+/// | Nextclade | `three_letter` | `del`     | Both          |
+/// |-----------|----------------|-----------|---------------|
+/// | `S:F456L` | `S:Phe456Leu`  | `S:F456L` | `S:Phe456Leu` |
+/// | `S:H69-`  | `S:His69-`     | `S:del69` | `S:His69del`  |
+pub fn format_label(mutation: &str, style: &LabelStyle) -> String {
+    if *style == LabelStyle::default() { return mutation.to_string() }
+    let Some((gene, change)) = mutation.split_once(':') else { return mutation.to_string() };
+
+    // Substitutions and deletions are a single residue, position, and residue or '-'
+    let Some(digits) = change.find(|c: char| c.is_ascii_digit()) else { return mutation.to_string() };
+    let (reference, rest) = change.split_at(digits);
+    let Some(end) = rest.find(|c: char| !c.is_ascii_digit()) else { return mutation.to_string() };
+    let (position, alternate) = rest.split_at(end);
+    let (Some(reference), Some(alternate)) = (single(reference), single(alternate)) else { return mutation.to_string() };
+
+    let residue = |aa: char| match style.three_letter {
+        true  => three_letter(aa).map(String::from).unwrap_or(aa.to_string()),
+        false => aa.to_string(),
+    };
+    let change = match (alternate, style.del, style.three_letter) {
+        ('-', true, true)  => format!("{}{position}del", residue(reference)),
+        ('-', true, false) => format!("del{position}"),
+        ('-', false, _)    => format!("{}{position}-", residue(reference)),
+        _                  => format!("{}{position}{}", residue(reference), residue(alternate)),
+    };
+    format!("{gene}:{change}")
+}
+
+/// The only character of a string.
+fn single(text: &str) -> Option<char> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _               => None,
+    }
+}
+
+/// Convert a 1-based HGVS coding position (ex. 1501, -25) to a genomic position.
+pub fn coding_to_genomic(position: &str, gene_start: u32) -> Option<u32> {
+    match position.strip_prefix('-') {
+        Some(upstream) => gene_start.checked_sub(upstream.parse().ok()?),
+        None           => Some(gene_start + position.parse::<u32>().ok()? - 1),
+    }
+}
+
+/// Parse an HGVS nucleotide description (ex. 241C>T, 6513_6515del, 28933_28934insT).
+fn parse_nucleotide<F>(description: &str, position: F) -> Result<(String, Option<&'static str>), Report>
+where
+    F: Fn(&str) -> Option<u32>,
+{
+    let error = || eyre!("Unsupported HGVS nucleotide description: {description}");
+
+    // Substitution, ex. 241C>T
+    if let Some((left, alternate)) = description.split_once('>') {
+        let reference = left.chars().last().ok_or_else(error)?;
+        let start     = position(&left[..left.len() - 1]).ok_or_else(error)?;
+        return Ok((format!("{reference}{start}{alternate}"), Some("substitutions")))
+    }
+    // Deletion, ex. 6513_6515del, 12506del
+    if let Some(range) = description.strip_suffix("del") {
+        let mutation = match range.split_once('_') {
+            Some((start, end)) => format!("{}-{}", position(start).ok_or_else(error)?, position(end).ok_or_else(error)?),
+            None               => position(range).ok_or_else(error)?.to_string(),
+        };
+        return Ok((mutation, Some("deletions")))
+    }
+    // Insertion, ex. 28933_28934insT
+    if let Some((range, inserted)) = description.split_once("ins") {
+        let start = range.split('_').next().ok_or_else(error)?;
+        return Ok((format!("{}:{inserted}", position(start).ok_or_else(error)?), Some("insertions")))
+    }
+
+    Err(error())
+}
+
+/// Parse an HGVS protein description (ex. Glu484Lys, E484K, His69del, Asp214_Arg215insGluProGlu).
+fn parse_protein(gene: &str, description: &str) -> Result<(String, Option<&'static str>), Report> {
+    let error = || eyre!("Unsupported HGVS protein description: {gene}:p.{description}");
+
+    // Predicted consequences are wrapped in parentheses, ex. p.(Glu484Lys)
+    let description = description.trim_start_matches('(').trim_end_matches(')');
+
+    // Split into reference residue, position, and the remaining change
+    let digits    = description.find(|c: char| c.is_ascii_digit()).ok_or_else(error)?;
+    let reference = residue(&description[..digits]).ok_or_else(error)?;
+    let rest      = &description[digits..];
+    let change    = rest.find(|c: char| !c.is_ascii_digit()).ok_or_else(error)?;
+    let (position, change) = rest.split_at(change);
+
+    // Insertion, ex. 214_Arg215insGluProGlu
+    if let Some((_, inserted)) = change.split_once("ins") {
+        let inserted = residues(inserted).ok_or_else(error)?;
+        return Ok((format!("{gene}:{position}:{inserted}"), Some("aaInsertions")))
+    }
+
+    let mutation = match change {
+        "del" => return Ok((format!("{gene}:{reference}{position}-"), Some("aaDeletions"))),
+        "="   => format!("{gene}:{reference}{position}{reference}"),
+        _     => format!("{gene}:{reference}{position}{}", residue(change).ok_or_else(error)?),
+    };
+
+    Ok((mutation, Some("aaSubstitutions")))
+}
+
+/// Parse an amino acid deletion range in shorthand notation into its gene, start, and end codon.
+///
+/// Nextclade reports one deletion per codon (ex. `S:H69-`), which catalogues often
+/// combine into a range, matched against the codon deletions by `annotate`.
+///
+/// | Shorthand                    | Range      |
+/// |------------------------------|------------|
+/// | `S:del69-70`, `S:del69_70`   | S 69-70    |
+/// | `S:69-70del`, `S:HV69-70del` | S 69-70    |
+/// | `S:del144`                   | S 144-144  |
+pub fn parse_deletion_range(mutation: &str) -> Option<(String, u32, u32)> {
+    let (gene, change) = mutation.trim().split_once(':')?;
+    let range = match change.strip_prefix("del") {
+        Some(range) => range,
+        None        => change.strip_suffix("del")?.trim_start_matches(|c: char| c.is_ascii_alphabetic()),
+    };
+    let (start, end) = range.split_once(['-', '_']).unwrap_or((range, range));
+    let (start, end) = (start.parse::<u32>().ok()?, end.parse::<u32>().ok()?);
+    if gene.is_empty() || end < start { return None }
+    Some((gene.to_string(), start, end))
+}
+
+/// Parse an insertion in shorthand notation, with an uppercase inserted sequence.
+///
+/// | Shorthand                   | Nextclade   |
+/// |-----------------------------|-------------|
+/// | `S:ins214EPE`, `S:214insEPE`| `S:214:EPE` |
+/// | `S:214:epe`                 | `S:214:EPE` |
+/// | `ins28933T`, `28933insT`    | `28933:T`   |
+/// | `28933:t`                   | `28933:T`   |
+fn parse_insertion(mutation: &str) -> Option<(String, Option<&'static str>)> {
+    let fields = mutation.split(':').collect::<Vec<_>>();
+    let (gene, position, inserted) = match fields[..] {
+        [gene, position, inserted] => (Some(gene), position, inserted),
+        [position, inserted]       => match split_insertion(inserted) {
+            // A gene and a marked insertion, ex. S:ins214EPE
+            Some((p, i)) => (Some(position), p, i),
+            None         => (None, position, inserted),
+        },
+        [description]              => {
+            let (position, inserted) = split_insertion(description)?;
+            (None, position, inserted)
+        },
+        _ => return None,
+    };
+
+    let is_position = !position.is_empty() && position.chars().all(|c| c.is_ascii_digit());
+    let is_sequence = !inserted.is_empty() && inserted.chars().all(|c| c.is_ascii_alphabetic() || c == '*');
+    if !is_position || !is_sequence { return None }
+
+    match gene {
+        Some(gene) => Some((format!("{gene}:{position}:{}", residues(inserted)?), Some("aaInsertions"))),
+        None       => Some((format!("{position}:{}", inserted.to_ascii_uppercase()), Some("insertions"))),
+    }
+}
+
+/// Split an insertion marked with `ins` into its position and inserted sequence (ex. ins214EPE, 214insEPE).
+fn split_insertion(description: &str) -> Option<(&str, &str)> {
+    match description.strip_prefix("ins") {
+        Some(rest) => Some(rest.split_at(rest.find(|c: char| !c.is_ascii_digit())?)),
+        None       => description.split_once("ins"),
+    }
+}
+
+/// Parse a SPDI variant into nextclade shorthand.
+fn parse_spdi(position: u32, deletion: &str, insertion: &str) -> Result<(String, Option<&'static str>), Report> {
+    // The deleted sequence can also be written as a length
+    let deleted = match deletion.parse::<u32>() {
+        Ok(length) => length,
+        Err(_)     => deletion.len() as u32,
+    };
+    let start = position + 1;
+
+    let normalized = match (deleted, insertion.len()) {
+        (1, 1) if deletion.parse::<u32>().is_err() => (format!("{deletion}{start}{insertion}"), Some("substitutions")),
+        (1, 0)                                     => (start.to_string(), Some("deletions")),
+        (_, 0)                                     => (format!("{start}-{}", position + deleted), Some("deletions")),
+        (0, _)                                     => (format!("{position}:{insertion}"), Some("insertions")),
+        _                                          => return Err(eyre!("Unsupported SPDI variant: {position}:{deletion}:{insertion}")),
+    };
+
+    Ok(normalized)
+}
+
+/// Parse a single amino acid residue in one-letter or three-letter code.
+fn residue(code: &str) -> Option<char> {
+    match code.len() {
+        1 => code.chars().next().map(|c| c.to_ascii_uppercase()),
+        3 => one_letter(code),
+        _ => None,
+    }
+}
+
+/// Parse a sequence of amino acid residues in one-letter or three-letter code.
+///
+/// Three-letter codes are capitalized (ex. `GluProGlu`), so that lowercase one-letter
+/// codes (ex. `epe`) aren't mistaken for them.
+fn residues(codes: &str) -> Option<String> {
+    let capitalized  = codes.chars().step_by(3).all(|c| c.is_ascii_uppercase());
+    let three_letter = codes.len() % 3 == 0 && capitalized && codes.chars().skip(1).step_by(3).all(|c| c.is_ascii_lowercase());
+    match three_letter {
+        true  => (0..codes.len()).step_by(3).map(|i| one_letter(&codes[i..i + 3])).collect(),
+        false => Some(codes.to_ascii_uppercase()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_notations() -> Result<(), Report> {
+        let gene_starts = HashMap::from([("S".to_string(), 21563)]);
+        let normalized  = |mutation: &str| normalize(mutation, &gene_starts);
+
+        assert_eq!(normalized("NC_045512.2:g.241C>T")?,       ("C241T".to_string(),     Some("substitutions")));
+        assert_eq!(normalized("NC_045512.2:g.6513_6515del")?, ("6513-6515".to_string(), Some("deletions")));
+        assert_eq!(normalized("NC_045512.2:g.28933_28934insT")?, ("28933:T".to_string(), Some("insertions")));
+        assert_eq!(normalized("S:c.1501A>T")?,                ("A23063T".to_string(),   Some("substitutions")));
+        assert_eq!(normalized("S:p.Glu484Lys")?,              ("S:E484K".to_string(),   Some("aaSubstitutions")));
+        assert_eq!(normalized("S:p.(Glu484Lys)")?,            ("S:E484K".to_string(),   Some("aaSubstitutions")));
+        assert_eq!(normalized("S:p.Asp614=")?,                ("S:D614D".to_string(),   Some("aaSubstitutions")));
+        assert_eq!(normalized("S:p.Gln493Ter")?,              ("S:Q493*".to_string(),   Some("aaSubstitutions")));
+        assert_eq!(normalized("S:p.His69del")?,               ("S:H69-".to_string(),    Some("aaDeletions")));
+        assert_eq!(normalized("NC_045512.2:240:C:T")?,        ("C241T".to_string(),     Some("substitutions")));
+        assert_eq!(normalized("NC_045512.2:6512:3:")?,        ("6513-6515".to_string(), Some("deletions")));
+        assert_eq!(normalized("S:HV69-70del")?,               ("S:del69-70".to_string(), Some("aaDeletions")));
+        assert_eq!(normalized("S:ins214EPE")?,                ("S:214:EPE".to_string(), Some("aaInsertions")));
+
+        // Nextclade shorthand, and empty input, are returned unchanged
+        assert_eq!(normalized("C241T")?,   ("C241T".to_string(),   None));
+        assert_eq!(normalized("S:E484K")?, ("S:E484K".to_string(), None));
+        assert_eq!(normalized("")?,        (String::new(),         None));
+
+        // Coding positions need the gene start
+        assert!(normalized("ORF1a:c.1A>G").is_err());
+        assert!(normalized("c.1A>G").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn deletion_ranges() {
+        let range = |gene: &str, start, end| Some((gene.to_string(), start, end));
+        assert_eq!(parse_deletion_range("S:del69-70"),   range("S", 69, 70));
+        assert_eq!(parse_deletion_range("S:del69_70"),   range("S", 69, 70));
+        assert_eq!(parse_deletion_range("S:69-70del"),   range("S", 69, 70));
+        assert_eq!(parse_deletion_range("S:HV69-70del"), range("S", 69, 70));
+        assert_eq!(parse_deletion_range("S:del144"),     range("S", 144, 144));
+        assert_eq!(parse_deletion_range(" S:del144 "),   range("S", 144, 144));
+
+        // Reversed ranges, missing genes, and nextclade codon deletions aren't ranges
+        assert_eq!(parse_deletion_range("S:del70-69"), None);
+        assert_eq!(parse_deletion_range(":del69-70"),  None);
+        assert_eq!(parse_deletion_range("del69-70"),   None);
+        assert_eq!(parse_deletion_range("S:H69-"),     None);
+        assert_eq!(parse_deletion_range("S:del"),      None);
+        assert_eq!(parse_deletion_range(""),           None);
+    }
+
+    #[test]
+    fn insertions() {
+        let aa = |m: &str| Some((m.to_string(), Some("aaInsertions")));
+        let nt = |m: &str| Some((m.to_string(), Some("insertions")));
+        assert_eq!(parse_insertion("S:ins214EPE"),          aa("S:214:EPE"));
+        assert_eq!(parse_insertion("S:214insEPE"),          aa("S:214:EPE"));
+        assert_eq!(parse_insertion("S:214:epe"),            aa("S:214:EPE"));
+        assert_eq!(parse_insertion("S:214:GluProGlu"),      aa("S:214:EPE"));
+        assert_eq!(parse_insertion("ORF3a:257:*"),          aa("ORF3a:257:*"));
+        assert_eq!(parse_insertion("ins28933T"),            nt("28933:T"));
+        assert_eq!(parse_insertion("28933insT"),            nt("28933:T"));
+        assert_eq!(parse_insertion("28933:t"),              nt("28933:T"));
+
+        // Substitutions, and insertions without a position or sequence, aren't insertions
+        assert_eq!(parse_insertion("S:E484K"),   None);
+        assert_eq!(parse_insertion("C241T"),     None);
+        assert_eq!(parse_insertion("ins28933"),  None);
+        assert_eq!(parse_insertion("S:214:"),    None);
+        assert_eq!(parse_insertion(""),          None);
+    }
+
+    #[test]
+    fn coding_positions() {
+        assert_eq!(coding_to_genomic("1", 266),    Some(266));
+        assert_eq!(coding_to_genomic("-25", 266),  Some(241));
+        assert_eq!(coding_to_genomic("-300", 266), None);
+        assert_eq!(coding_to_genomic("", 266),     None);
+    }
+}
@@ -0,0 +1,37 @@
+/// Display format of frequencies and fractions in plots and reports.
+///
+/// Tables stay machine-readable, this is only for numbers read by people.
+#[derive(Clone, Debug)]
+pub struct NumberFormat {
+    /// Number of digits after the decimal separator.
+    pub precision: usize,
+    /// The decimal separator (ex. `,` for many European locales).
+    pub decimal_separator: char,
+    /// Show fractions as percentages (ex. 0.25 as `25%`).
+    pub percent: bool,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        NumberFormat {
+            precision:         2,
+            decimal_separator: '.',
+            percent:           false,
+        }
+    }
+}
+
+impl NumberFormat {
+    /// Format a fraction (ex. a frequency between 0 and 1).
+    pub fn format(&self, value: f64) -> String {
+        let (value, suffix) = match self.percent {
+            true  => (value * 100.0, "%"),
+            false => (value, ""),
+        };
+        let text = format!("{value:.*}{suffix}", self.precision);
+        match self.decimal_separator {
+            '.'       => text,
+            separator => text.replace('.', &separator.to_string()),
+        }
+    }
+}
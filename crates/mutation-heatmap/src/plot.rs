@@ -1,9 +1,13 @@
 use base64::prelude::*;
 use color_eyre::eyre::{eyre, Result, Report};
-use svg::Document;
-use svg::node::element::{Path, Group, Text, Style};
+use datafusion::prelude::SessionContext;
+use svg::{Document, Node};
+use svg::node::element::{Anchor, Element, Path, Group, Text, Style, Title};
 use svg::node::element::path::Data;
 use rand::Rng;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use resvg::tiny_skia::Pixmap;
 use tiny_skia_path;
 use usvg;
@@ -13,23 +17,434 @@ pub const FONT_FAMILY: &str   = "Roboto";
 pub const FONT: &[u8] = include_bytes!("../../../assets/fonts/roboto/Roboto-Regular.ttf");
 
 
-pub fn plot<P>(prefix: P) -> Result<(), Report>
+/// Plot a heatmap of mutations (columns) by samples (rows).
+///
+/// Cells are colored by the mutation `status` in the annotated table produced by
+/// `annotate`, or by presence in the mutations table produced by `extract`. Without a
+/// table, a small demonstration dataset is plotted.
+///
+/// # Arguments
+///
+///   - `annotated`: An optional file path to the annotated table produced by `annotate`, or the mutations table produced by `extract`.
+///   - `prefix`   : The output prefix of the svg and png.
+///   - `label`    : An optional template for the mutation labels, filled in from the annotated table columns (ex. `{mutation} | {drug}`).
+///   - `options`  : Placement and rotation of the labels, or the compact barcode and aggregate modes.
+///   - `data`     : Side data drawn with the heatmap (see [`PlotData`]). The sample groups are drawn as labelled
+///                  blocks of rows, band the rows in barcode mode, and are the rows in aggregate mode.
+///
+pub async fn plot<P>(annotated: Option<P>, prefix: &str, label: Option<&str>, options: &PlotOptions, data: &PlotData) -> Result<(), Report>
 where
-    P: ToString
+    P: AsRef<std::path::Path> + std::fmt::Debug,
 {
-    let prefix = prefix.to_string();
+    log::debug!("Parsing data.");
+
+    let inputs = annotated.iter().map(|a| a.as_ref().to_path_buf()).collect::<Vec<_>>();
+    let (samples, mutations, statuses, raw) = match annotated {
+        Some(annotated) => read_statuses(&annotated, label, &options.notation).await?,
+        None            => demo_statuses(),
+    };
+    let scores      = label_scores(&data.scores, &raw);
+    let mut samples = options.filter_samples(samples);
+    let groups      = data.groups.as_ref();
+
+    // Grouped samples are drawn as blocks of rows, in order of their group, keeping their order within the group
+    let sample_groups = match (groups, options.barcode || options.aggregate) {
+        (Some(groups), false) => {
+            samples.sort_by_key(|sample| groups.get(sample).cloned().unwrap_or_else(|| "ungrouped".to_string()));
+            groups.clone()
+        },
+        _ => HashMap::new(),
+    };
+
+    // Mutations with the highest scores first, and unscored mutations last
+    let mut mutations = mutations;
+    if options.sort_by_score {
+        mutations.sort_by(|a, b| scores.get(b).partial_cmp(&scores.get(a)).unwrap_or(std::cmp::Ordering::Equal));
+    }
+    if options.sort_by_score || !sample_groups.is_empty() {
+        write_order(format!("{prefix}.order.json"), &samples, &mutations, &sample_groups, options)?;
+    }
+
+    // Aggregated groups are drawn (and embedded) in place of the samples
+    if options.aggregate {
+        let groups = groups.ok_or(eyre!("Sample groups are required to aggregate the heatmap by group."))?;
+        // The rows are groups, so the sample and cell links would fill {sample} with a group label
+        if options.sample_url.is_some() || options.cell_url.is_some() {
+            log::warn!("The sample and cell links are not drawn in aggregate mode, because the rows are groups rather than samples.");
+        }
+        let options = &PlotOptions { sample_url: None, cell_url: None, ..options.clone() };
+        let (rows, frequencies) = aggregate(&samples, &mutations, &statuses, groups);
+        let frequency = |row: &str, mutation: &str| frequencies.get(&(row.to_string(), mutation.to_string())).copied().unwrap_or(0.0);
+        let fill      = |row: &str, mutation: &str| scale_color(frequency(row, mutation));
+        let detail    = |row: &str, mutation: &str| format!("Frequency: {}", options.number.format(frequency(row, mutation)));
+        let value     = |row: &str, mutation: &str| -> serde_json::Value { frequency(row, mutation).into() };
+        let tracks    = Tracks {
+            products: data.products.clone(),
+            metadata: embed_data(options, &rows, &mutations, &value, &inputs, label, Some(groups))?,
+            ..Default::default()
+        };
+        return heatmap(&rows, &mutations, &fill, Some(&detail), prefix, options, &tracks)
+    }
+
+    let status   = |sample: &str, mutation: &str| -> serde_json::Value { statuses.get(&(sample.to_string(), mutation.to_string())).cloned().unwrap_or("absent".to_string()).into() };
+    let observed = |sample: &str, mutation: &str| statuses.get(&(sample.to_string(), mutation.to_string())).map(|s| s != "absent").unwrap_or(false);
+    let tracks   = Tracks {
+        products:      data.products.clone(),
+        sparklines:    sparklines(options, &data.genes, &samples, &mutations, &observed),
+        sample_groups,
+        coverage:      data.coverage.clone(),
+        metadata:      embed_data(options, &samples, &mutations, &status, &inputs, label, groups)?,
+    };
+
+    // Observed mutations can be shaded by their score, relative to the largest absolute score
+    let max_score = scores.values().fold(0.0_f64, |max, s| max.max(s.abs()));
+    let fill = |sample: &str, mutation: &str| {
+        let status = statuses.get(&(sample.to_string(), mutation.to_string())).map(|s| s.as_str()).unwrap_or("absent");
+        match (options.color_by_score, scores.get(mutation)) {
+            (true, Some(score)) if status != "absent" && max_score > 0.0 => scale_color(score.abs() / max_score),
+            _                                                            => status_color(status).to_string(),
+        }
+    };
+    let score_detail = |_: &str, mutation: &str| match scores.get(mutation) {
+        Some(score) => format!("Score: {}", options.number.format(*score)),
+        None        => "Score: NA".to_string(),
+    };
+    let detail: Option<&dyn Fn(&str, &str) -> String> = match scores.is_empty() {
+        true  => None,
+        false => Some(&score_detail),
+    };
+
+    match options.barcode {
+        true  => barcode(&samples, &mutations, &fill, groups, tracks.metadata.as_deref(), prefix, options),
+        false => heatmap(&samples, &mutations, &fill, detail, prefix, options, &tracks),
+    }
+}
+
+/// Read a per-mutation score table (ex. deep mutational scanning escape scores).
+///
+/// The table has a `mutation` column, and the scores are read from the given column. The
+/// scores are keyed on the mutation as written in the annotated table (ex. `S:E484K`), and
+/// matched to the mutation labels while plotting (see [`label_scores`]). Rows without a
+/// numeric score are skipped.
+pub async fn read_scores<P>(path: &P, column: &str) -> Result<HashMap<String, f64>, Report>
+where
+    P: AsRef<std::path::Path> + std::fmt::Debug,
+{
+    let table  = crate::read_metadata(path, Some("mutation")).await?;
+    let mut scores = HashMap::new();
+    for (mutation, values) in table {
+        let value = values.get(column).ok_or(eyre!("Score table is missing the column {column:?}: {path:?}"))?;
+        let Ok(score) = value.parse::<f64>() else { log::debug!("Skipping score {value:?} of mutation {mutation}."); continue };
+        scores.insert(mutation, score);
+    }
+    log::info!("Read scores of {} mutations.", scores.len());
+    Ok(scores)
+}
+
+/// Key the per-mutation scores on the mutation labels, warning about scores that match no mutation.
+///
+/// # Arguments
+///
+///   - `scores`: The scores, keyed on the mutation (see [`read_scores`]).
+///   - `raw`   : The mutation of each label (see [`read_statuses`]).
+///
+fn label_scores(scores: &HashMap<String, f64>, raw: &HashMap<String, String>) -> HashMap<String, f64> {
+    let labelled = raw.iter().filter_map(|(label, mutation)| Some((label.clone(), *scores.get(mutation)?))).collect::<HashMap<_, _>>();
+    let matched  = raw.values().filter(|mutation| scores.contains_key(*mutation)).collect::<HashSet<_>>().len();
+    if matched < scores.len() {
+        log::warn!("{} of {} scores don't match a mutation in the annotated table.", scores.len() - matched, scores.len());
+    }
+    labelled
+}
+
+/// Plot a heatmap of how the mutations of each sample changed between two runs (ex. this week vs last week).
+///
+/// Cells are colored by change: `gained` (green) and `lost` (red) mutations stand out against
+/// the `present` (purple) and `absent` (white) mutations that didn't change. Any status other
+/// than `absent` counts as observed, and samples missing from a run have no mutations in it.
+///
+/// # Arguments
+///
+///   - `current` : A file path to the annotated table of the current run.
+///   - `previous`: A file path to the annotated table of the previous run.
+///   - `prefix`  : The output prefix of the svg and png.
+///   - `label`   : An optional template for the mutation labels (see [`plot`]).
+///   - `options` : Placement and rotation of the labels, or the compact barcode mode.
+///   - `data`    : Side data drawn with the heatmap (ex. gene products, coverage).
+///
+pub async fn diff<P>(current: P, previous: P, prefix: &str, label: Option<&str>, options: &PlotOptions, data: &PlotData) -> Result<(), Report>
+where
+    P: AsRef<std::path::Path> + std::fmt::Debug,
+{
+    let inputs = [current.as_ref().to_path_buf(), previous.as_ref().to_path_buf()];
+    let (current_samples, current_mutations, current, _)    = read_statuses(&current, label, &options.notation).await?;
+    let (previous_samples, previous_mutations, previous, _) = read_statuses(&previous, label, &options.notation).await?;
+
+    let samples   = current_samples.into_iter().chain(previous_samples).collect::<BTreeSet<_>>().into_iter().collect::<Vec<_>>();
+    let samples   = options.filter_samples(samples);
+    let mutations = current_mutations.into_iter().chain(previous_mutations).collect::<BTreeSet<_>>().into_iter().collect::<Vec<_>>();
+
+    let observed = |statuses: &HashMap<(String, String), String>, key: &(String, String)| statuses.get(key).map(|s| s != "absent").unwrap_or(false);
+    let mut changes = HashMap::new();
+    for sample in &samples {
+        for mutation in &mutations {
+            let key    = (sample.clone(), mutation.clone());
+            let change = match (observed(&previous, &key), observed(&current, &key)) {
+                (false, true)  => "gained",
+                (true,  false) => "lost",
+                (true,  true)  => "present",
+                (false, false) => "absent",
+            };
+            changes.insert(key, change);
+        }
+    }
+    let gained = changes.values().filter(|c| **c == "gained").count();
+    let lost   = changes.values().filter(|c| **c == "lost").count();
+    log::info!("Between runs, {gained} sample mutations were gained and {lost} were lost.");
+
+    let change = |sample: &str, mutation: &str| -> serde_json::Value { changes.get(&(sample.to_string(), mutation.to_string())).copied().unwrap_or("absent").into() };
+    let tracks = Tracks {
+        products: data.products.clone(),
+        coverage: data.coverage.clone(),
+        metadata: embed_data(options, &samples, &mutations, &change, &inputs, label, None)?,
+        ..Default::default()
+    };
+
+    let fill = |sample: &str, mutation: &str| {
+        let change = changes.get(&(sample.to_string(), mutation.to_string())).copied().unwrap_or("absent");
+        change_color(change).to_string()
+    };
+
+    match options.barcode {
+        true  => barcode(&samples, &mutations, &fill, None, tracks.metadata.as_deref(), prefix, options),
+        false => heatmap(&samples, &mutations, &fill, None, prefix, options, &tracks),
+    }
+}
+
+/// Aggregate samples into groups (rows), with the frequency of each mutation within the group.
+///
+/// Any status other than `absent` counts as observed. The rows are labelled with the
+/// group size (ex. `BA.2 (n=12)`), and samples without a group are grouped as `ungrouped`.
+fn aggregate(samples: &[String], mutations: &[String], statuses: &HashMap<(String, String), String>, groups: &HashMap<String, String>) -> (Vec<String>, HashMap<(String, String), f64>) {
+    log::info!("Aggregating samples by group.");
+
+    let mut members: BTreeMap<String, Vec<&String>> = BTreeMap::new();
+    for sample in samples {
+        let group = groups.get(sample).cloned().unwrap_or_else(|| "ungrouped".to_string());
+        members.entry(group).or_default().push(sample);
+    }
+
+    let mut rows        = Vec::new();
+    let mut frequencies = HashMap::new();
+    for (group, samples) in &members {
+        let row = format!("{group} (n={})", samples.len());
+        for mutation in mutations {
+            let observed = samples.iter().filter(|sample| {
+                statuses.get(&(sample.to_string(), mutation.to_string())).map(|s| s != "absent").unwrap_or(false)
+            }).count();
+            frequencies.insert((row.clone(), mutation.clone()), observed as f64 / samples.len() as f64);
+        }
+        rows.push(row);
+    }
+
+    (rows, frequencies)
+}
+
+/// The side of the heatmap that a set of labels is drawn on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+impl std::str::FromStr for Side {
+    type Err = Report;
+
+    fn from_str(side: &str) -> Result<Self, Self::Err> {
+        match side {
+            "top"    => Ok(Side::Top),
+            "bottom" => Ok(Side::Bottom),
+            "left"   => Ok(Side::Left),
+            "right"  => Ok(Side::Right),
+            _        => Err(eyre!("Unknown label side: {side}, please choose from: top, bottom, left, right")),
+        }
+    }
+}
+
+/// Placement, rotation, and style of the heatmap, as chosen by the user.
+#[derive(Clone, Debug)]
+pub struct PlotOptions {
+    /// Rotation of the mutation labels, in degrees counter-clockwise from horizontal (0-90).
+    pub rotation: u32,
+    /// Side of the mutation labels, either top or bottom.
+    pub mutation_labels: Side,
+    /// Side of the sample labels, either left or right.
+    pub sample_labels: Side,
+    /// Repeat the mutation labels on the opposite side, for tall heatmaps.
+    pub repeat_mutation_labels: bool,
+    /// Repeat the sample labels on the opposite side, for wide heatmaps.
+    pub repeat_sample_labels: bool,
+    /// Render a compact barcode without sample labels, for thousands of samples.
+    pub barcode: bool,
+    /// Aggregate the samples by group, and color the cells by the within-group mutation frequency.
+    pub aggregate: bool,
+    /// An optional directory of rendered pngs, keyed on the SHA-256 of their svg document.
+    pub cache: Option<std::path::PathBuf>,
+    /// Embed the font, subset to the characters of the labels, in the svg. Without it, viewers fall back to a local font.
+    pub embed_font: bool,
+    /// Render a png alongside the svg.
+    pub png: bool,
+    /// The most cells of a heatmap rendered to a png (see [`MAX_PNG_CELLS`]). Larger heatmaps are an error, unless the png is skipped.
+    pub max_png_cells: usize,
+    /// Optional url templates to link the sample labels (`{sample}`), mutation labels (`{mutation}`),
+    /// and cells (`{sample}` and `{mutation}`) to (ex. `https://lims.example.org/samples/{sample}`).
+    pub sample_url:   Option<String>,
+    pub mutation_url: Option<String>,
+    pub cell_url:     Option<String>,
+    /// Only plot these samples, if given.
+    pub include_samples: Option<HashSet<String>>,
+    /// Don't plot these samples.
+    pub exclude_samples: HashSet<String>,
+    /// Display style of the amino acid mutation labels.
+    pub notation: crate::notation::LabelStyle,
+    /// Display format of the frequencies in the cell tooltips.
+    pub number: crate::number::NumberFormat,
+    /// Embed the plotted matrix, options, and input hashes in the svg `<metadata>`, so the figure can be traced and re-plotted.
+    pub embed_data: bool,
+    /// Shade the observed mutations by their score, rather than their status.
+    pub color_by_score: bool,
+    /// Sort the mutations by descending score.
+    pub sort_by_score: bool,
+    /// Draw a sparkline of each sample's mutation positions along the genome, right of its row.
+    pub sparkline: bool,
+}
+
+impl PlotOptions {
+    /// Whether a sample passes the include and exclude lists.
+    pub fn keep_sample(&self, sample: &str) -> bool {
+        let included = self.include_samples.as_ref().map(|s| s.contains(sample)).unwrap_or(true);
+        included && !self.exclude_samples.contains(sample)
+    }
+
+    /// Filter samples by the include and exclude lists, warning when none are left.
+    pub fn filter_samples(&self, samples: Vec<String>) -> Vec<String> {
+        let total   = samples.len();
+        let samples = samples.into_iter().filter(|s| self.keep_sample(s)).collect::<Vec<_>>();
+        if samples.len() < total {
+            log::info!("Plotting {} of {total} samples.", samples.len());
+        }
+        if samples.is_empty() && total > 0 {
+            log::warn!("No samples are left to plot after the include and exclude lists.");
+        }
+        samples
+    }
+}
+
+impl Default for PlotOptions {
+    fn default() -> Self {
+        PlotOptions {
+            rotation:               90,
+            mutation_labels:        Side::Top,
+            sample_labels:          Side::Left,
+            repeat_mutation_labels: false,
+            repeat_sample_labels:   false,
+            barcode:                false,
+            aggregate:              false,
+            cache:                  None,
+            embed_font:             true,
+            png:                    true,
+            max_png_cells:          MAX_PNG_CELLS,
+            sample_url:             None,
+            mutation_url:           None,
+            cell_url:               None,
+            include_samples:        None,
+            exclude_samples:        HashSet::new(),
+            notation:               crate::notation::LabelStyle::default(),
+            number:                 crate::number::NumberFormat::default(),
+            embed_data:             false,
+            color_by_score:         false,
+            sort_by_score:          false,
+            sparkline:              false,
+        }
+    }
+}
+
+/// Side data read from other tables, and drawn with the annotated table.
+#[derive(Clone, Debug, Default)]
+pub struct PlotData {
+    /// The group of each sample (ex. lineage, facility).
+    pub groups: Option<HashMap<String, String>>,
+    /// Gene products (ex. the nsps of ORF1ab), drawn as a band opposite the mutation labels.
+    pub products: Vec<crate::gene::Product>,
+    /// Per-mutation scores (ex. antibody escape), keyed on the raw mutation (not its label) and shown in the cell tooltips.
+    pub scores: HashMap<String, f64>,
+    /// Genes, used to place amino acid mutations along the genome in the sparklines.
+    pub genes: Vec<crate::gene::Gene>,
+    /// The genome coverage (0-1) of each sample (ex. from nextclade).
+    pub coverage: HashMap<String, f64>,
+}
+
+/// The tracks drawn around the cells of one heatmap, gathered while plotting.
+///
+/// Every track is optional, and an empty track isn't drawn.
+#[derive(Clone, Debug, Default)]
+pub struct Tracks {
+    /// Gene products, drawn as a band opposite the mutation labels.
+    pub products: Vec<crate::gene::Product>,
+    /// The histogram of each row's mutation positions (see [`sparklines`]), drawn right of the row.
+    pub sparklines: HashMap<String, Vec<u32>>,
+    /// The group of each row, drawn as labelled blocks of rows.
+    pub sample_groups: HashMap<String, String>,
+    /// The genome coverage (0-1) of each row, drawn as a bar beyond its label.
+    pub coverage: HashMap<String, f64>,
+    /// The encoded `<metadata>` payload (see [`embed_data`]).
+    pub metadata: Option<String>,
+}
+
+/// The svg stylesheet for the label font.
+///
+/// The vendored TTF font is subset to the characters of the text, and converted to Base64,
+/// so we can directly embed the raw font data into the final svg. This ensures a consistent
+/// font experience across platforms. Without embedding, viewers use a local Roboto or their
+/// default sans-serif font.
+pub fn font_css<'a>(embed: bool, text: impl IntoIterator<Item = &'a str>) -> Result<String, Report> {
+    match embed {
+        true  => {
+            let chars       = text.into_iter().flat_map(|t| t.chars()).collect::<BTreeSet<_>>();
+            let font_base64 = BASE64_STANDARD.encode(crate::font::subset(FONT, &chars)?);
+            Ok(format!("@font-face {{ font-family: '{FONT_FAMILY}'; src: url('data:application/font-ttf;charset=utf-8;base64,{font_base64}'); }}"))
+        },
+        false => Ok(format!("text {{ font-family: '{FONT_FAMILY}', sans-serif; }}")),
+    }
+}
+
+/// Render a heatmap of columns (ex. mutations) by samples (rows).
+///
+/// The fill color of each cell is given by a function of its sample and column, and
+/// an optional detail function adds a line to the cell tooltip (ex. a frequency).
+pub fn heatmap(samples: &[String], mutations: &[String], fill: &dyn Fn(&str, &str) -> String, detail: Option<&dyn Fn(&str, &str) -> String>, prefix: &str, options: &PlotOptions, tracks: &Tracks) -> Result<(), Report> {
+
+    if options.rotation > 90 {
+        return Err(eyre!("Mutation label rotation must be between 0 and 90 degrees: {}", options.rotation))
+    }
+    if !matches!(options.mutation_labels, Side::Top | Side::Bottom) {
+        return Err(eyre!("Mutation labels must be on the top or bottom: {:?}", options.mutation_labels))
+    }
+    if !matches!(options.sample_labels, Side::Left | Side::Right) {
+        return Err(eyre!("Sample labels must be on the left or right: {:?}", options.sample_labels))
+    }
+    let cells = samples.len() * mutations.len();
+    if options.png && cells > options.max_png_cells {
+        return Err(eyre!("Heatmap of {cells} cells is too large for a png (more than {}), skip the png or raise the limit.", options.max_png_cells))
+    }
 
     // ------------------------------------------------------------------------
     // Fonts
  
     log::debug!("Loading fonts.");
 
-    // Convert the vendored TTF fonts to Base64, so we can directly
-    // embed the raw font data into the final svg. This ensures a 
-    // consistent font experience across platforms.
-    let font_base64 = BASE64_STANDARD.encode(FONT);
-    let font_css = format!("@font-face {{ font-family: '{FONT_FAMILY}'; src: url('data:application/font-ttf;charset=utf-8;base64,{font_base64}'); }}" );
-
     // let bold_font_base64 = BASE64_STANDARD.encode(BOLD_FONT);
     // let bold_font_css = format!("@font-face {{ 
     //     font-family: '{bold_font_family}'; src: url('data:application/font-ttf;charset=utf-8;base64,{bold_font_base64}'); }}"
@@ -44,35 +459,9 @@ where
     opt.font_family = FONT_FAMILY.to_string();
     opt.font_size   = font_size;
 
-    let top_y  = 0;
-    let left_x = 0;    
-
-    // ------------------------------------------------------------------------
-    // Parse Data
-
-    log::debug!("Parsing data.");
-
-    let samples = vec![
-        "Sample1", 
-        "Sample2", 
-        "SampleAB", 
-        "UnÌcödé Characters",
-        "Reeeeeeeeeeeeeeeeeeeeeeeeeeaally Long Label",
-    ];
-
-    // stress testing
-    // let samples: Vec<_> = (0..100).map(|i| format!("Sample{i}")).collect();
-
-    let mutations = vec![
-        "OPG057:H238Q | Tecovirimat",
-        "OPG057:A295E | Tecovirimat",
-        "OPG071:L108F | Brincidofovir",
-        "OPG048:F49F  | Hydroxyurea",
-        "OPG180:A50R  | Mitoxantrone",
-    ];
-
-    // stress testing
-    // let mutations: Vec<_> = (0..100).map(|i| format!("Mutation{i}")).collect();
+    if samples.is_empty() || mutations.is_empty() {
+        return Err(eyre!("No samples or columns were found to plot."))
+    }
 
     // ------------------------------------------------------------------------
     // Text Calculation: Largest Labels
@@ -92,38 +481,180 @@ where
 
     // Figure out which the maximum width and height of the mutation labels.
     let mutation_font_size = font_size;
-    // Reversing height and width, because we're going to rotate these labels 90 degrees
-    let (mutation_height, _mutation_width) = largest_text(&mutations, FONT_FAMILY, mutation_font_size, &opt)?;
+    let (mutation_width, mutation_height) = largest_text(&mutations, FONT_FAMILY, mutation_font_size, &opt)?;
+
+    // The space the rotated mutation labels take up, above (or below) the boxes, and
+    // how far they overhang the boxes to the side they lean towards.
+    let angle       = (options.rotation as f32).to_radians();
+    let (sin, cos)  = angle.sin_cos();
+    let label_space = (mutation_width as f32 * sin + mutation_height as f32 * cos).ceil() as u32;
+    let overhang    = match options.rotation {
+        0 => mutation_width / 2,
+        _ => (mutation_width as f32 * cos).ceil() as u32,
+    };
+
+    // ------------------------------------------------------------------------
+    // Layout
+
+    let mutation_boxes_w = (mutations.len() as u32 * unit) + ((mutations.len() - 1) as u32 * padding);
+    let mutation_boxes_h = (samples.len() as u32 * unit) + ((samples.len() - 1) as u32 * padding);
+
+    // Space taken by the axes on each side of the boxes. The labels can be
+    // repeated on the opposite side, for tall or wide heatmaps.
+    let opposite = |side: Side| match side {
+        Side::Top    => Side::Bottom,
+        Side::Bottom => Side::Top,
+        Side::Left   => Side::Right,
+        Side::Right  => Side::Left,
+    };
+    let mut sample_sides   = vec![options.sample_labels];
+    let mut mutation_sides = vec![options.mutation_labels];
+    if options.repeat_sample_labels   { sample_sides.push(opposite(options.sample_labels)) }
+    if options.repeat_mutation_labels { mutation_sides.push(opposite(options.mutation_labels)) }
+
+    let sample_axis_w   = sample_width + padding + tick_length;
+    let mutation_axis_h = label_space + padding + tick_length;
+
+    // Consecutive samples in the same group form a block of rows, as (group, first row, end row)
+    let mut group_blocks: Vec<(String, usize, usize)> = Vec::new();
+    if !tracks.sample_groups.is_empty() {
+        for (i, sample) in samples.iter().enumerate() {
+            let group = tracks.sample_groups.get(sample).cloned().unwrap_or_else(|| "ungrouped".to_string());
+            match group_blocks.last_mut() {
+                Some((last, _, end)) if *last == group => *end = i + 1,
+                _                                      => group_blocks.push((group, i, i + 1)),
+            }
+        }
+    }
+    // The coverage bars sit beyond the sample labels, and the group labels beyond them,
+    // with a bracket spanning each block
+    let coverage_w   = match tracks.coverage.is_empty() { true => 0, false => unit * 2 + padding };
+    let group_axis_w = match group_blocks.is_empty() {
+        true  => 0,
+        false => largest_text(&group_blocks.iter().map(|(group, _, _)| group.as_str()).collect::<Vec<_>>(), FONT_FAMILY, font_size, &opt)?.0 + padding * 2 + stroke as u32,
+    };
+    let group_offset = |side: Side| if side == options.sample_labels { coverage_w + group_axis_w } else { 0 };
+    let (mut left_overhang, mut right_overhang) = (0, 0);
+    for side in &mutation_sides {
+        match (options.rotation, side) {
+            (0, _)         => { left_overhang = overhang; right_overhang = overhang },
+            (_, Side::Top) => right_overhang = overhang,
+            _              => left_overhang = overhang,
+        }
+    }
+    let left   = unit + group_offset(Side::Left)  + match sample_sides.contains(&Side::Left)  { true => sample_axis_w.max(left_overhang),  false => left_overhang };
+    let right  = unit + group_offset(Side::Right) + match sample_sides.contains(&Side::Right) { true => sample_axis_w.max(right_overhang), false => right_overhang };
+    // The gene product band sits between the boxes and the opposite mutation labels
+    let band_side   = opposite(options.mutation_labels);
+    let band_h      = if tracks.products.is_empty() { 0 } else { unit + padding };
+    let band_offset = |side: &Side| if *side == band_side { band_h } else { 0 };
+
+    let top    = unit + band_offset(&Side::Top)    + match mutation_sides.contains(&Side::Top)    { true => mutation_axis_h, false => 0 };
+    let bottom = unit + band_offset(&Side::Bottom) + match mutation_sides.contains(&Side::Bottom) { true => mutation_axis_h, false => 0 };
+
+    let mutation_boxes_x = left;
+    let mutation_boxes_y = top;
 
     // ------------------------------------------------------------------------
     // Y Axis: Sample Labels
 
     log::debug!("Drawing sample labels.");
 
-    let sample_axis_x = left_x + unit + sample_width;
-    let sample_axis_y = top_y + unit + mutation_height + padding + tick_length;
+    let mut sample_axes = Group::new();
+    for side in &sample_sides {
+        // The axis starts at the tick, with the labels beyond it
+        let (sample_axis_x, text_x, text_anchor) = match side {
+            Side::Right => (mutation_boxes_x + mutation_boxes_w, (tick_length + padding) as i32, "start"),
+            _           => (mutation_boxes_x - tick_length,      -(padding as i32),              "end"),
+        };
+        let mut sample_axis = Group::new().set("transform", format!("translate({sample_axis_x} {mutation_boxes_y})"));
 
-    let mut sample_axis = Group::new().set("transform", format!("translate({sample_axis_x} {sample_axis_y})"));
+        let mut y = (unit / 2) as u32;
+        for (i, sample) in samples.iter().enumerate() {
+            if i > 0 { y += unit + padding; } 
 
-    let x = 0;
-    let mut y = (unit / 2) as u32;
-    for (i, sample) in samples.iter().enumerate() {
-        if i > 0 { y += unit + padding; } 
+            // Draw the sample text label
+            let sample_text = Text::new(sample.to_string())
+                .set("font-size", format!("{font_size}px"))
+                .set("font-family", FONT_FAMILY)
+                .set("dominant-baseline", "central")
+                .set("text-anchor", text_anchor)
+                .set("transform", format!("translate({text_x} {y})"));
+            sample_axis = sample_axis.add(link(sample_text, options.sample_url.as_deref(), &[("sample", sample)]));
 
-        // Draw the sample text label
-        let sample_text = Text::new(sample.to_string())
-            .set("font-size", format!("{font_size}px"))
-            .set("font-family", FONT_FAMILY)
-            .set("dominant-baseline", "central")
-            .set("text-anchor", "end")
-            .set("transform", format!("translate({x} {y})"));
-        sample_axis = sample_axis.add(sample_text);
+            // Draw the horizontal tick that connects to the mutation box row
+            let tick_coords = Data::new().move_to((0, y)).line_by((tick_length, 0));
+            let tick = Path::new().set("stroke", "black").set("stroke-width", stroke).set("d",tick_coords);
+            sample_axis = sample_axis.add(tick);       
+        }
+        sample_axes = sample_axes.add(sample_axis);
+    }
+
+    // ------------------------------------------------------------------------
+    // Y Axis: Sample Groups
 
-        // Draw the horizontal tick that connects to the mutation box row
-        let tick_x = x + padding;
-        let tick_coords = Data::new().move_to((tick_x, y)).line_by((tick_length, 0));        
-        let tick = Path::new().set("stroke", "black").set("stroke-width", stroke).set("d",tick_coords);
-        sample_axis = sample_axis.add(tick);       
+    let mut group_axis = Group::new();
+    if !group_blocks.is_empty() {
+        log::debug!("Drawing sample groups.");
+        let row_y = |row: usize| mutation_boxes_y + row as u32 * (unit + padding);
+        // The bracket is beyond the longest sample label, and the group label beyond the bracket
+        let (bracket_x, text_x, text_anchor) = match options.sample_labels {
+            Side::Right => {
+                let x = mutation_boxes_x + mutation_boxes_w + sample_axis_w + coverage_w + padding;
+                (x, x + stroke as u32 + padding, "start")
+            },
+            _ => {
+                let x = mutation_boxes_x - sample_axis_w - coverage_w - padding;
+                (x, x - padding, "end")
+            },
+        };
+        for (i, (group, start, end)) in group_blocks.iter().enumerate() {
+            let (top_y, bottom_y) = (row_y(*start), row_y(*end) - padding);
+            let bracket = Data::new().move_to((bracket_x, top_y)).line_to((bracket_x, bottom_y));
+            group_axis = group_axis.add(Path::new().set("stroke", "black").set("stroke-width", stroke).set("d", bracket));
+            let text = Text::new(group.to_string())
+                .set("font-size", format!("{font_size}px"))
+                .set("font-family", FONT_FAMILY)
+                .set("dominant-baseline", "central")
+                .set("text-anchor", text_anchor)
+                .set("transform", format!("translate({text_x} {})", (top_y + bottom_y) / 2));
+            group_axis = group_axis.add(text);
+            // Separate each block from the next, across the boxes
+            if i + 1 < group_blocks.len() {
+                let y         = bottom_y + padding / 2;
+                let separator = Data::new().move_to((mutation_boxes_x, y)).line_by((mutation_boxes_w, 0));
+                group_axis = group_axis.add(Path::new().set("stroke", "grey").set("stroke-width", stroke).set("stroke-dasharray", format!("{unit} {padding}")).set("d", separator));
+            }
+        }
+    }
+
+    // ------------------------------------------------------------------------
+    // Y Axis: Sample Coverage
+
+    // A bar of each sample's genome coverage, so that its calls (and absences) can be
+    // judged in context. Samples without a coverage have no bar.
+    let mut coverage_axis = Group::new();
+    if !tracks.coverage.is_empty() {
+        log::debug!("Drawing sample coverage.");
+        let bar_w = unit * 2;
+        let bar_x = match options.sample_labels {
+            Side::Right => mutation_boxes_x + mutation_boxes_w + sample_axis_w + padding,
+            _           => mutation_boxes_x - sample_axis_w - padding - bar_w,
+        };
+        let mut y = mutation_boxes_y + unit / 4;
+        for (i, sample) in samples.iter().enumerate() {
+            if i > 0 { y += unit + padding; }
+            let Some(coverage) = tracks.coverage.get(sample) else { continue };
+            let width   = (coverage.clamp(0.0, 1.0) * bar_w as f64).round() as u32;
+            let outline = Data::new().move_to((0, 0)).line_by((0, unit / 2)).line_by((bar_w, 0)).line_by((0, -((unit / 2) as i32))).close();
+            let fill    = Data::new().move_to((0, 0)).line_by((0, unit / 2)).line_by((width, 0)).line_by((0, -((unit / 2) as i32))).close();
+            let bar = Group::new()
+                .set("transform", format!("translate({bar_x} {y})"))
+                .add(Title::new(format!("{sample}\nCoverage: {}", options.number.format(*coverage))))
+                .add(Path::new().set("fill", scale_color(*coverage)).set("d", fill))
+                .add(Path::new().set("fill", "none").set("stroke", "black").set("stroke-width", stroke).set("d", outline));
+            coverage_axis = coverage_axis.add(bar);
+        }
     }
 
     // ------------------------------------------------------------------------
@@ -131,81 +662,312 @@ where
 
     log::debug!("Drawing mutation labels.");
 
-    let mutation_axis_x   = sample_axis_x + padding + tick_length;
-    let mutation_axis_y   = top_y + unit;
-    let mut mutation_axis = Group::new().set("transform", format!("translate({mutation_axis_x} {mutation_axis_y})"));
+    let mut mutation_axes = Group::new();
+    for side in &mutation_sides {
+        // Labels on top read upwards from the boxes, labels on the bottom read down towards them.
+        let (mutation_axis_y, text_y, text_anchor, baseline) = match (side, options.rotation) {
+            (Side::Bottom, 0) => (mutation_boxes_y + mutation_boxes_h + band_offset(side), (tick_length + padding) as i32, "middle", "hanging"),
+            (Side::Bottom, _) => (mutation_boxes_y + mutation_boxes_h + band_offset(side), (tick_length + padding) as i32, "end",    "central"),
+            (_, 0)            => (mutation_boxes_y - tick_length - band_offset(side),      -(padding as i32),              "middle", "auto"),
+            _                 => (mutation_boxes_y - tick_length - band_offset(side),      -(padding as i32),              "start",  "central"),
+        };
+        let mut mutation_axis = Group::new().set("transform", format!("translate({mutation_boxes_x} {mutation_axis_y})"));
 
-    // let coord_axis_h = coord_height + padding + tick_length;
-    // let coord_axis_w = (coords.len() as u32 * unit ) + ((coords.len() as u32 - 1) * unit);
+        let mut x = unit / 2;
+        for (i, mutation) in mutations.iter().enumerate() {
+            if i > 0 { x += unit + padding; }
 
-    let mut x = unit / 2;
-    for (i, mutation) in mutations.iter().enumerate() {
-        if i > 0 { x += unit + padding; }
+            // Draw the mutation Label
+            let mutation_text = Text::new(mutation.to_string())
+                .set("font-size", format!("{mutation_font_size}px"))
+                .set("font-family", FONT_FAMILY)
+                .set("dominant-baseline", baseline)
+                .set("text-anchor", text_anchor)
+                .set("transform", format!("translate({x} {text_y}) rotate(-{})", options.rotation));
+            mutation_axis = mutation_axis.add(link(mutation_text, options.mutation_url.as_deref(), &[("mutation", mutation)]));
 
-        // Draw the mutation Label
-        let y = mutation_height;
-        let mutation_text = Text::new(mutation.to_string())
-            .set("font-size", format!("{mutation_font_size}px"))
-            .set("font-family", FONT_FAMILY)
-            .set("dominant-baseline", "central")
-            .set("text-anchor", "start")
-            .set("transform", format!("translate({x} {y}) rotate(-90)"));
-        mutation_axis = mutation_axis.add(mutation_text); 
+            // Draw the vertical tick that connects to the mutation box column
+            let tick_coords = Data::new().move_to((x, 0)).line_by((0, tick_length));
+            let tick = Path::new().set("stroke", "black").set("stroke-width", stroke).set("d",tick_coords);
+            mutation_axis = mutation_axis.add(tick);       
+        }
+        mutation_axes = mutation_axes.add(mutation_axis);
+    }
+
+    // ------------------------------------------------------------------------
+    // X Axis: Gene Product Band
+
+    // Neighbouring mutations in the same product (ex. nsp12) share one labelled block,
+    // so a cluster of mutations in one product stands out.
+    let mut product_band = Group::new();
+    if !tracks.products.is_empty() {
+        log::debug!("Drawing gene product band.");
+        let band_y   = match band_side { Side::Top => mutation_boxes_y - band_h, _ => mutation_boxes_y + mutation_boxes_h + padding };
+        let products = mutations.iter().map(|m| crate::gene::product_of(m, &tracks.products).map(|p| p.name.as_str())).collect::<Vec<_>>();
+        let mut start = 0;
+        for (i, product) in products.iter().enumerate() {
+            // A block ends at the last mutation, or before a mutation in a different product
+            if i + 1 < products.len() && products[i + 1] == *product { continue }
+            if let Some(name) = product {
+                let x      = mutation_boxes_x + start as u32 * (unit + padding);
+                let width  = (i - start + 1) as u32 * (unit + padding) - padding;
+                let coords = Data::new().move_to((x, band_y)).line_by((0, unit)).line_by((width, 0)).line_by((0, -(unit as i32))).close();
+                let block  = Path::new().set("fill", "lightgrey").set("stroke", "black").set("stroke-width", stroke).set("d", coords).add(Title::new(name.to_string()));
+                product_band = product_band.add(block);
+                // Blocks too narrow for their label only have the tooltip
+                let (text_width, _) = largest_text(&[name], FONT_FAMILY, font_size, &opt)?;
+                if text_width <= width {
+                    let text = Text::new(name.to_string())
+                        .set("font-size", format!("{font_size}px"))
+                        .set("font-family", FONT_FAMILY)
+                        .set("dominant-baseline", "central")
+                        .set("text-anchor", "middle")
+                        .set("transform", format!("translate({} {})", x + width / 2, band_y + unit / 2));
+                    product_band = product_band.add(text);
+                }
+            }
+            start = i + 1;
+        }
+    }
 
-        // Draw the vertical tick that connects to the mutation box column
-        let y = mutation_height + padding;
-        let tick_coords = Data::new().move_to((x, y)).line_by((0, tick_length));        
-        let tick = Path::new().set("stroke", "black").set("stroke-width", stroke).set("d",tick_coords);
-        mutation_axis = mutation_axis.add(tick);       
+    // ------------------------------------------------------------------------
+    // Y Axis: Sparklines
+
+    // A tiny histogram of each sample's mutation positions, right of its row, as a
+    // fingerprint of how the mutations are spread along the genome.
+    let bin_w       = (unit / 4).max(1);
+    let sparkline_w = match tracks.sparklines.is_empty() { true => 0, false => SPARKLINE_BINS as u32 * bin_w + unit };
+    let mut sparklines = Group::new();
+    if !tracks.sparklines.is_empty() {
+        log::debug!("Drawing sparklines.");
+        let sparkline_x = left + mutation_boxes_w + right;
+        let max_count   = tracks.sparklines.values().flatten().copied().max().unwrap_or(0).max(1);
+        let mut y = mutation_boxes_y;
+        for (i, sample) in samples.iter().enumerate() {
+            if i > 0 { y += unit + padding; }
+            let Some(bins) = tracks.sparklines.get(sample) else { continue };
+            let baseline  = Data::new().move_to((0, unit)).line_by((SPARKLINE_BINS as u32 * bin_w, 0));
+            let mut sparkline = Group::new()
+                .set("transform", format!("translate({sparkline_x} {y})"))
+                .add(Title::new(format!("{sample}\n{} mutations along the genome", bins.iter().sum::<u32>())))
+                .add(Path::new().set("stroke", "lightgrey").set("stroke-width", stroke).set("d", baseline));
+            for (bin, count) in bins.iter().enumerate().filter(|(_, count)| **count > 0) {
+                let height = (*count as f32 / max_count as f32 * unit as f32).ceil() as u32;
+                let coords = Data::new().move_to((bin as u32 * bin_w, unit)).line_by((0, -(height as i32))).line_by((bin_w as i32, 0)).line_by((0, height as i32)).close();
+                sparkline = sparkline.add(Path::new().set("fill", "purple").set("d", coords));
+            }
+            sparklines = sparklines.add(sparkline);
+        }
     }
 
+    // ------------------------------------------------------------------------
+    // Render
+
+    log::debug!("Rendering document.");
+
+    let text  = samples.iter().chain(mutations).chain(group_blocks.iter().map(|(group, _, _)| group)).map(|t| t.as_str());
+    let style = Style::new(font_css(options.embed_font, text.chain(tracks.products.iter().map(|p| p.name.as_str())))?);
+
+    let document_width  = left + mutation_boxes_w + right + sparkline_w;
+    let document_height = top + mutation_boxes_h + bottom;
+
+    let background_coords = Data::new().move_to((0, 0)).line_by((0, document_height)).line_by((document_width, 0)).line_by((0, -(document_height as i32))).close();
+    let background        = Path::new().set("fill", "white").set("stroke", "white").set("d", background_coords);
+
+    let document = Document::new()
+        .set("viewBox", (0, 0, document_width, document_height))
+        .add(background)
+        .add(style)
+        .add(sample_axes)
+        .add(coverage_axis)
+        .add(group_axis)
+        .add(mutation_axes)
+        .add(product_band)
+        .add(sparklines);
+    let document = add_metadata(document, tracks.metadata.as_deref());
+
     // ------------------------------------------------------------------------
     // X an Y Axis: Mutation Boxes
 
     log::debug!("Drawing mutation boxes.");
 
-    let mutation_boxes_x   = mutation_axis_x;
-    let mutation_boxes_y   = sample_axis_y;
-
-    let mut mutation_boxes  = Group::new().set("transform", format!("translate({mutation_boxes_x} {mutation_boxes_y})"));
+    // The boxes are the bulk of a large heatmap, so each column (mutation) is written
+    // to the svg as soon as it's drawn, rather than holding every box in the document.
     let mutation_box_coords = Data::new().move_to((0, 0)).line_by((0, unit)).line_by((unit, 0)).line_by((0, -(unit as i32))).close();
     let mutation_box        = Path::new().set("fill", "purple").set("stroke", "black").set("stroke-width", stroke).set("d", mutation_box_coords);
 
-    let mut x = 0;
-    // Iterate through mutations ( Moving Left -> Right along the X-Axis)
-    for (i, _mutation) in mutations.iter().enumerate() {
-        let mut y = 0;
-        if i > 0 { x += unit + padding; }
-        // Iterate through samples ( Moving Top -> Down along the Y-Axis)
-        for (i_s, _) in samples.iter().enumerate() {
-            if i_s > 0 { y += unit + padding; }
-            // random color 
-            let num = rand::thread_rng().gen_range(0..100);
-            let fill = match num > 50 {
-                true => "purple",
-                false => "white",
-            };
-            let sample_mutation_box = mutation_box
-                .clone()
-                .set("fill", fill)
-                .set("transform", format!("translate({x} {y})") );
-            mutation_boxes = mutation_boxes.add(sample_mutation_box);
+    let mut mutation_boxes = |emit: &mut dyn FnMut(&dyn Node) -> Result<(), Report>| -> Result<(), Report> {
+        let mut x = mutation_boxes_x;
+        // Iterate through mutations ( Moving Left -> Right along the X-Axis)
+        for (i, mutation) in mutations.iter().enumerate() {
+            let mut y = 0;
+            if i > 0 { x += unit + padding; }
+            let mut column = Group::new().set("transform", format!("translate({x} {mutation_boxes_y})"));
+            // Iterate through samples ( Moving Top -> Down along the Y-Axis)
+            for (i_s, sample) in samples.iter().enumerate() {
+                if i_s > 0 { y += unit + padding; }
+                let mut tooltip = match inserted_sequence(mutation) {
+                    Some(inserted) => format!("{sample}\n{mutation}\nInserted: {inserted} ({} residues)", inserted.len()),
+                    None           => format!("{sample}\n{mutation}"),
+                };
+                if let Some(detail) = detail { tooltip = format!("{tooltip}\n{}", detail(sample, mutation)) }
+                let sample_mutation_box = mutation_box
+                    .clone()
+                    .set("fill", fill(sample, mutation))
+                    .set("transform", format!("translate(0 {y})") )
+                    .add(Title::new(tooltip));
+                column = column.add(link(sample_mutation_box, options.cell_url.as_deref(), &[("sample", sample), ("mutation", mutation)]));
+            }
+            emit(&column)?;
         }
+        Ok(())
+    };
+
+    let hash = stream(&document, &mut mutation_boxes, prefix)?;
+
+    // The cells are generated again for the png, rather than read back from the written svg
+    match options.png {
+        true  => rasterize_stream(&document, &mut mutation_boxes, &hash, document_width, document_height, prefix, &opt, options.cache.as_deref())?,
+        false => remove_png(prefix)?,
     }
 
-    let mutation_boxes_w = (mutations.len() as u32 * unit) + ((mutations.len() - 1) as u32 * padding);
-    let mutation_boxes_h = (samples.len() as u32 * unit) + ((samples.len() - 1) as u32 * padding);
+    Ok(())
+}
+
+/// Render a compact "barcode" heatmap, for matrices with thousands of samples.
+///
+/// Each sample is a 1-2 pixel row without a label. Rows are sorted into bands by
+/// their group (ex. lineage), and each band is labelled with its group and number of
+/// samples. The sample in each row is written to an index file (`{prefix}.index.tsv`).
+pub fn barcode(samples: &[String], mutations: &[String], fill: &dyn Fn(&str, &str) -> String, groups: Option<&HashMap<String, String>>, metadata: Option<&str>, prefix: &str, options: &PlotOptions) -> Result<(), Report> {
+
+    if samples.is_empty() || mutations.is_empty() {
+        return Err(eyre!("No samples or columns were found to plot."))
+    }
+
+    let font_size   = 12.0;
+
+    let mut opt = usvg::Options::default();
+    opt.fontdb_mut().load_font_data(FONT.to_vec());
+    opt.font_family = FONT_FAMILY.to_string();
+    opt.font_size   = font_size;
+
+    // ------------------------------------------------------------------------
+    // Bands
+
+    log::debug!("Grouping samples into bands.");
+
+    // Samples are sorted by group, then name, so that each group is a contiguous band
+    let group = |sample: &str| groups.and_then(|g| g.get(sample)).cloned().unwrap_or_else(|| "ungrouped".to_string());
+    let mut rows = samples.iter().map(|s| (group(s), s.clone())).collect::<Vec<_>>();
+    rows.sort();
 
+    let mut bands: Vec<(String, usize, usize)> = Vec::new();
+    for (i, (group, _)) in rows.iter().enumerate() {
+        match bands.last_mut() {
+            Some((last, _, end)) if *last == *group => *end = i + 1,
+            _ => bands.push((group.clone(), i, i + 1)),
+        }
+    }
+    let band_labels = bands.iter().map(|(group, start, end)| format!("{group} ({})", end - start)).collect::<Vec<_>>();
+
+    // ------------------------------------------------------------------------
+    // Layout
+
+    let row_height   = if rows.len() > 2000 { 1 } else { 2 };
+    let (label_width, label_height) = largest_text(&band_labels, FONT_FAMILY, font_size, &opt)?;
+    let (mutation_height, column_width) = largest_text(mutations, FONT_FAMILY, font_size, &opt)?;
+    let padding      = label_height / 2;
+    let column_width = column_width + 2;
+
+    let boxes_x = padding + label_width + padding;
+    let boxes_y = padding + mutation_height + padding;
+    let boxes_w = mutations.len() as u32 * column_width;
+    let boxes_h = rows.len() as u32 * row_height;
+
+    // ------------------------------------------------------------------------
+    // Mutation Labels
+
+    log::debug!("Drawing mutation labels.");
+
+    let mut mutation_axis = Group::new().set("transform", format!("translate({boxes_x} {})", boxes_y - padding));
+    for (i, mutation) in mutations.iter().enumerate() {
+        let x = i as u32 * column_width + column_width / 2;
+        let text = Text::new(mutation.to_string())
+            .set("font-size", format!("{font_size}px"))
+            .set("font-family", FONT_FAMILY)
+            .set("dominant-baseline", "central")
+            .set("text-anchor", "start")
+            .set("transform", format!("translate({x} 0) rotate(-90)"));
+        mutation_axis = mutation_axis.add(text);
+    }
+
+    // ------------------------------------------------------------------------
+    // Band Labels
+
+    log::debug!("Drawing band labels.");
+
+    // Alternating band shading, so that neighbouring groups can be told apart
+    let mut band_axis = Group::new().set("transform", format!("translate(0 {boxes_y})"));
+    for (i, ((_, start, end), label)) in bands.iter().zip(&band_labels).enumerate() {
+        let y      = *start as u32 * row_height;
+        let height = (end - start) as u32 * row_height;
+        let shade  = if i % 2 == 0 { "#eeeeee" } else { "#dddddd" };
+        let coords = Data::new().move_to((0, y)).line_by((0, height)).line_by((boxes_x + boxes_w, 0)).line_by((0, -(height as i32))).close();
+        band_axis  = band_axis.add(Path::new().set("fill", shade).set("d", coords));
+        // Bands shorter than the text are still shaded, but not labelled
+        if height >= label_height {
+            let text = Text::new(label.to_string())
+                .set("font-size", format!("{font_size}px"))
+                .set("font-family", FONT_FAMILY)
+                .set("dominant-baseline", "central")
+                .set("text-anchor", "end")
+                .set("transform", format!("translate({} {})", boxes_x - padding, y + height / 2));
+            band_axis = band_axis.add(text);
+        } else {
+            log::debug!("Band {label} is too short to label.");
+        }
+    }
+
+    // ------------------------------------------------------------------------
+    // Cells
+
+    log::debug!("Drawing barcode cells.");
+
+    // Only the colored cells are drawn over a white background, which keeps the svg small
+    let mut cells = Group::new().set("transform", format!("translate({boxes_x} {boxes_y})"));
+    let background_coords = Data::new().move_to((0, 0)).line_by((0, boxes_h)).line_by((boxes_w, 0)).line_by((0, -(boxes_h as i32))).close();
+    cells = cells.add(Path::new().set("fill", "white").set("d", background_coords));
+    for (r, (_, sample)) in rows.iter().enumerate() {
+        let y = r as u32 * row_height;
+        for (c, mutation) in mutations.iter().enumerate() {
+            let color = fill(sample, mutation);
+            if color == "white" { continue }
+            let x      = c as u32 * column_width;
+            let coords = Data::new().move_to((x, y)).line_by((0, row_height)).line_by((column_width, 0)).line_by((0, -(row_height as i32))).close();
+            cells = cells.add(Path::new().set("fill", color).set("d", coords));
+        }
+    }
+
+    // ------------------------------------------------------------------------
+    // Index
+
+    let index = format!("{prefix}.index.tsv");
+    log::info!("Writing barcode row index: {index}");
+    let mut writer = BufWriter::new(File::create(&index)?);
+    writeln!(writer, "row\tsample\tgroup")?;
+    for (r, (group, sample)) in rows.iter().enumerate() {
+        writeln!(writer, "{}\t{sample}\t{group}", r + 1)?;
+    }
+    writer.flush()?;
 
     // ------------------------------------------------------------------------
     // Render
 
     log::debug!("Rendering document.");
 
-    let style = Style::new(font_css);
-
-    let document_width = mutation_boxes_x + mutation_boxes_w + unit;
-    let document_height = mutation_boxes_y + mutation_boxes_h + unit;
+    let document_width  = boxes_x + boxes_w + padding;
+    let document_height = boxes_y + boxes_h + padding;
 
     let background_coords = Data::new().move_to((0, 0)).line_by((0, document_height)).line_by((document_width, 0)).line_by((0, -(document_height as i32))).close();
     let background        = Path::new().set("fill", "white").set("stroke", "white").set("d", background_coords);
@@ -213,45 +975,574 @@ where
     let document = Document::new()
         .set("viewBox", (0, 0, document_width, document_height))
         .add(background)
-        .add(style)
-        .add(sample_axis)
+        .add(Style::new(font_css(options.embed_font, mutations.iter().chain(&band_labels).map(|t| t.as_str()))?))
+        .add(band_axis)
         .add(mutation_axis)
-        .add(mutation_boxes);
+        .add(cells);
+    let document = add_metadata(document, metadata);
+
+    match options.png {
+        true  => save(&document, document_width, document_height, prefix, &opt, options.cache.as_deref())?,
+        false => {
+            std::fs::write(format!("{prefix}.svg"), document.to_string())?;
+            remove_png(prefix)?;
+        },
+    }
+
+    Ok(())
+}
+
+/// The cell fill color of a mutation status.
+///
+/// Consensus (or present) mutations are solid, low-frequency variants are a lighter shade,
+/// and conflicts (minor variants missing from the consensus) stand out in orange.
+pub fn status_color(status: &str) -> &'static str {
+    match status {
+        "present" | "consensus" => "purple",
+        "low-frequency"         => "plum",
+        "conflict"              => "darkorange",
+        _                       => "white",
+    }
+}
+
+/// The cell fill color of a change in a mutation between two runs.
+pub fn change_color(change: &str) -> &'static str {
+    match change {
+        "gained"  => "forestgreen",
+        "lost"    => "crimson",
+        "present" => "purple",
+        _         => "white",
+    }
+}
+
+/// The cell fill color of a value between 0 and 1, from white (0) to purple (1).
+pub fn scale_color(value: f64) -> String {
+    let value = value.clamp(0.0, 1.0);
+    // purple is rgb(128, 0, 128)
+    let r = (255.0 - 127.0 * value).round() as u8;
+    let g = (255.0 - 255.0 * value).round() as u8;
+    format!("rgb({r},{g},{r})")
+}
+
+/// Read the samples, mutations, and status of each sample mutation from an annotated table.
+///
+/// The mutations table produced by `extract` (tsv, parquet, or sqlite) can be read too, in
+/// which case every mutation of a sample is `present`.
+///
+/// Mutations are identified by their label, which is rendered from the `label` template
+/// when one is given, with the mutation formatted in the `notation` style. Mutations
+/// with the same label are drawn as one column, and the mutation of each label is returned
+/// last (the first one read, when several share a label).
+async fn read_statuses<P>(annotated: &P, label: Option<&str>, notation: &crate::notation::LabelStyle) -> Result<(Vec<String>, Vec<String>, HashMap<(String, String), String>, HashMap<String, String>), Report>
+where
+    P: AsRef<std::path::Path> + std::fmt::Debug,
+{
+    log::info!("Reading annotated table: {annotated:?}");
+    let ctx = SessionContext::new();
+    let ctx = crate::register_mutations(annotated, ctx, "annotated").await?;
+
+    // The mutations table produced by `extract` has no status, all of its mutations are present
+    let schema = ctx.table("annotated").await?.schema().as_arrow().clone();
+    let ctx = match schema.column_with_name("status") {
+        Some(_) => ctx,
+        None    => {
+            let missing = mutation_core::missing_columns(&schema, &mutation_core::mutations_schema());
+            if !missing.is_empty() {
+                return Err(eyre!("Table is neither an annotated table (missing the status column) nor a mutations table (missing the columns {missing:?}): {annotated:?}"))
+            }
+            log::info!("Table has no status column, plotting all mutations as present.");
+            let query = "SELECT *, CASE WHEN mutation IS NULL THEN 'absent' ELSE 'present' END as status FROM annotated";
+            crate::replace_table(ctx, "annotated", query).await?
+        },
+    };
+
+    let schema  = ctx.table("annotated").await?.schema().as_arrow().clone();
+    let missing = mutation_core::missing_columns(&schema, &mutation_core::annotated_schema());
+    if !missing.is_empty() {
+        return Err(eyre!("Annotated table is missing the required columns {missing:?}: {annotated:?}"))
+    }
+    let columns = schema.fields().iter().map(|f| f.name().to_string()).collect::<Vec<_>>();
+    if let Some(label) = label {
+        let missing = template_fields(label).into_iter().filter(|f| !columns.contains(f)).collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(eyre!("Label template {label:?} uses columns {missing:?} that are not in the annotated table: {annotated:?}"))
+        }
+    }
+
+    let mut samples   = BTreeSet::new();
+    let mut mutations = BTreeSet::new();
+    let mut statuses  = HashMap::new();
+    let mut raw       = HashMap::new();
+
+    let batches = ctx.sql("SELECT * FROM annotated").await?.collect().await?;
+    for batch in &batches {
+        let sample   = crate::string_column(batch, "sample")?;
+        let mutation = crate::string_column(batch, "mutation")?;
+        let status   = crate::string_column(batch, "status")?;
+        let values   = columns.iter().map(|c| Ok((c.as_str(), crate::string_column(batch, c)?))).collect::<Result<Vec<_>, Report>>()?;
+        for i in 0..batch.num_rows() {
+            let original  = mutation.value(i);
+            let formatted = crate::notation::format_label(original, notation);
+            let mutation  = match label {
+                Some(label) => render_label(label, &values.iter().map(|(c, v)| match *c {
+                    "mutation" => (*c, formatted.as_str()),
+                    _          => (*c, if v.is_valid(i) { v.value(i) } else { "" }),
+                }).collect()),
+                None        => formatted.clone(),
+            };
+            let sample = sample.value(i).to_string();
+            samples.insert(sample.clone());
+            mutations.insert(mutation.clone());
+            raw.entry(mutation.clone()).or_insert_with(|| original.to_string());
+            // A mutation observed in any form takes precedence over an absence
+            let entry = statuses.entry((sample, mutation)).or_insert_with(|| status.value(i).to_string());
+            if *entry == "absent" { *entry = status.value(i).to_string() }
+        }
+    }
+
+    Ok((samples.into_iter().collect(), mutations.into_iter().collect(), statuses, raw))
+}
+
+/// Wrap an element in a link, when a url template is given.
+///
+/// The template fields are filled in with the percent-encoded values.
+pub fn link<T>(element: T, template: Option<&str>, values: &[(&str, &str)]) -> Box<dyn Node>
+where
+    T: Node + 'static,
+{
+    match template {
+        Some(template) => {
+            let values = values.iter().map(|(field, value)| (*field, percent_encode(value))).collect::<Vec<_>>();
+            let href   = render_label(template, &values.iter().map(|(field, value)| (*field, value.as_str())).collect());
+            Box::new(Anchor::new().set("href", href).add(element))
+        },
+        None => Box::new(element),
+    }
+}
+
+/// The inserted sequence of an insertion in nextclade notation (ex. S:214:EPE -> EPE, 28933:T -> T).
+pub fn inserted_sequence(mutation: &str) -> Option<&str> {
+    let (position, inserted) = mutation.rsplit_once(':')?;
+    let position = position.rsplit(':').next()?;
+    let is_position = !position.is_empty() && position.chars().all(|c| c.is_ascii_digit());
+    let is_sequence = !inserted.is_empty() && inserted.chars().all(|c| c.is_ascii_alphabetic() || c == '*');
+    (is_position && is_sequence).then_some(inserted)
+}
+
+/// Percent-encode all but the unreserved characters of a url component.
+pub fn percent_encode(value: &str) -> String {
+    value.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+        _ => format!("%{b:02X}"),
+    }).collect()
+}
+
+/// The column names used in a label template (ex. `{mutation} | {drug}` -> mutation, drug).
+pub fn template_fields(template: &str) -> Vec<String> {
+    template.split('{').skip(1).filter_map(|s| s.split_once('}')).map(|(field, _)| field.to_string()).collect()
+}
+
+/// Fill in a label template with the values of a row.
+pub fn render_label(template: &str, values: &HashMap<&str, &str>) -> String {
+    template_fields(template).iter().fold(template.to_string(), |label, field| {
+        label.replace(&format!("{{{field}}}"), values.get(field.as_str()).copied().unwrap_or_default())
+    })
+}
+
+/// A small demonstration dataset, with random mutation presence.
+fn demo_statuses() -> (Vec<String>, Vec<String>, HashMap<(String, String), String>, HashMap<String, String>) {
+    let samples = vec![
+        "Sample1", 
+        "Sample2", 
+        "SampleAB", 
+        "UnÌcödé Characters",
+        "Reeeeeeeeeeeeeeeeeeeeeeeeeeaally Long Label",
+    ];
+
+    // stress testing
+    // let samples: Vec<_> = (0..100).map(|i| format!("Sample{i}")).collect();
+
+    let mutations = vec![
+        "OPG057:H238Q | Tecovirimat",
+        "OPG057:A295E | Tecovirimat",
+        "OPG071:L108F | Brincidofovir",
+        "OPG048:F49F  | Hydroxyurea",
+        "OPG180:A50R  | Mitoxantrone",
+    ];
+
+    // stress testing
+    // let mutations: Vec<_> = (0..100).map(|i| format!("Mutation{i}")).collect();
 
+    let mut statuses = HashMap::new();
+    for sample in &samples {
+        for mutation in &mutations {
+            // random status
+            let num    = rand::thread_rng().gen_range(0..100);
+            let status = if num > 50 { "present" } else { "absent" };
+            statuses.insert((sample.to_string(), mutation.to_string()), status.to_string());
+        }
+    }
+
+    let samples   = samples.into_iter().map(String::from).collect();
+    let mutations = mutations.into_iter().map(String::from).collect();
+    (samples, mutations, statuses, HashMap::new())
+}
+
+/// Write the final row and column order of a heatmap, and the group of each row, as a sidecar json.
+///
+/// The sidecar is written whenever the rows are grouped or the columns are sorted, so the same
+/// order can be reused across figures (ex. from week to week). It has the ordered `samples` and
+/// `mutations`, how each was ordered (`sample_order`, `mutation_order`), and the sample `groups`.
+pub fn write_order<P>(path: P, samples: &[String], mutations: &[String], sample_groups: &HashMap<String, String>, options: &PlotOptions) -> Result<(), Report>
+where
+    P: AsRef<std::path::Path> + std::fmt::Debug,
+{
+    log::info!("Writing the heatmap order: {path:?}");
+    let groups = samples.iter().filter_map(|s| Some((s.as_str(), sample_groups.get(s)?.as_str()))).collect::<BTreeMap<_, _>>();
+    let order  = serde_json::json!({
+        "samples":        samples,
+        "mutations":      mutations,
+        "sample_order":   if sample_groups.is_empty() { "input" } else { "group" },
+        "mutation_order": if options.sort_by_score { "score" } else { "input" },
+        "groups":         groups,
+    });
+    let mut writer = BufWriter::new(File::create(&path)?);
+    serde_json::to_writer_pretty(&mut writer, &order)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Number of bins in the sparkline of each sample's mutation positions.
+pub const SPARKLINE_BINS: usize = 30;
+
+/// A histogram of each sample's mutation positions, when requested (otherwise empty).
+///
+/// The genome is split into [`SPARKLINE_BINS`] bins, up to the end of the last gene (or the last
+/// mutation). Mutations without a genome position (see [`crate::gene::genome_position`]) are skipped.
+pub fn sparklines(options: &PlotOptions, genes: &[crate::gene::Gene], samples: &[String], mutations: &[String], observed: &dyn Fn(&str, &str) -> bool) -> HashMap<String, Vec<u32>> {
+    if !options.sparkline { return HashMap::new() }
+    log::debug!("Binning the mutation positions of each sample.");
+
+    let positions = mutations.iter().filter_map(|m| Some((m.as_str(), crate::gene::genome_position(m, genes)?))).collect::<Vec<_>>();
+    let skipped   = mutations.len() - positions.len();
+    if skipped > 0 { log::warn!("Skipping {skipped} mutations without a genome position in the sparklines.") }
+    let length = genes.iter().map(|g| g.end).chain(positions.iter().map(|(_, p)| *p)).max().unwrap_or(1) as usize;
+
+    samples.iter().map(|sample| {
+        let mut bins = vec![0; SPARKLINE_BINS];
+        for (_, position) in positions.iter().filter(|(mutation, _)| observed(sample, mutation)) {
+            let bin = (position.saturating_sub(1) as usize * SPARKLINE_BINS / length).min(SPARKLINE_BINS - 1);
+            bins[bin] += 1;
+        }
+        (sample.clone(), bins)
+    }).collect()
+}
+
+/// Render a document to vector graphics (svg) and pixels (png).
+///
+/// Rasterizing is the slow part of rendering. With a `cache` directory, the png is
+/// stored under the SHA-256 of the svg document (which covers both the data and the style),
+/// and copied from the cache when an identical document is rendered again.
+pub fn save(document: &Document, width: u32, height: u32, prefix: &str, opt: &usvg::Options, cache: Option<&std::path::Path>) -> Result<(), Report> {
     // Render to vector graphics (svg)
-    svg::save(format!("{}.svg", prefix), &document)?;
+    let svg = document.to_string();
+    std::fs::write(format!("{}.svg", prefix), &svg)?;
+
+    rasterize(&svg, width, height, prefix, opt, cache)
+}
+
+/// The default most cells of a heatmap that are rendered to a png.
+///
+/// The png is drawn in memory, at 4 bytes per pixel, before it's written.
+pub const MAX_PNG_CELLS: usize = 250_000;
+
+/// Svg elements, generated one at a time and passed to an `emit` function.
+pub type Elements<'a> = dyn FnMut(&mut dyn FnMut(&dyn Node) -> Result<(), Report>) -> Result<(), Report> + 'a;
+
+/// Render a document to vector graphics (svg), writing extra elements as they're generated.
+///
+/// The `elements` are written after the document's own children, so that large drawings
+/// (ex. the cells of a 5000 x 500 heatmap) are written to the svg without holding all of
+/// their nodes in memory. The document must have at least one child, and the written svg
+/// is the same as [`save`] would write with the elements added to the document.
+///
+/// Returns the SHA-256 of the written svg, which keys its cached png (see [`rasterize_stream`]).
+pub fn stream(document: &Document, elements: &mut Elements, prefix: &str) -> Result<String, Report> {
+    use sha2::Digest;
+
+    let path     = format!("{}.svg", prefix);
+    let document = document.to_string();
+    let head     = document.strip_suffix("</svg>").ok_or(eyre!("Failed to find the end of the svg document: {path}"))?;
+
+    let mut writer = HashWriter { writer: BufWriter::new(File::create(&path)?), hasher: sha2::Sha256::new() };
+    write!(writer, "{head}")?;
+    elements(&mut |element| { writeln!(writer, "{element}")?; Ok(()) })?;
+    write!(writer, "</svg>")?;
+    writer.flush()?;
+
+    Ok(writer.hasher.finalize().iter().map(|b| format!("{b:02x}")).collect())
+}
+
+/// A writer that hashes everything written through it.
+struct HashWriter<W: Write> {
+    writer: W,
+    hasher: sha2::Sha256,
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        use sha2::Digest;
+        let written = self.writer.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Render an svg to pixels (`{prefix}.png`), using the cached render when there is one.
+fn rasterize(svg: &str, width: u32, height: u32, prefix: &str, opt: &usvg::Options, cache: Option<&std::path::Path>) -> Result<(), Report> {
+    render_png(&crate::sha256(svg.as_bytes()), width, height, prefix, cache, &mut |pixmap| {
+        let tree = usvg::Tree::from_str(svg, opt)?;
+        resvg::render(&tree, tiny_skia_path::Transform::default(), &mut pixmap.as_mut());
+        Ok(())
+    })
+}
+
+/// Render a streamed document (see [`stream`]) to pixels (`{prefix}.png`), an element at a time.
+///
+/// The document is drawn first, then each element is parsed and drawn over it on its own,
+/// so that the svg is never held in memory as a whole. The `hash` of the streamed svg keys
+/// the cached render.
+#[allow(clippy::too_many_arguments)]
+fn rasterize_stream(document: &Document, elements: &mut Elements, hash: &str, width: u32, height: u32, prefix: &str, opt: &usvg::Options, cache: Option<&std::path::Path>) -> Result<(), Report> {
+    let head = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {width} {height}\">");
+    render_png(hash, width, height, prefix, cache, &mut |pixmap| {
+        let transform = tiny_skia_path::Transform::default();
+        let tree      = usvg::Tree::from_str(&document.to_string(), opt)?;
+        resvg::render(&tree, transform, &mut pixmap.as_mut());
+        elements(&mut |element| {
+            let tree = usvg::Tree::from_str(&format!("{head}{element}</svg>"), opt)?;
+            resvg::render(&tree, transform, &mut pixmap.as_mut());
+            Ok(())
+        })
+    })
+}
+
+/// Draw pixels and write them to `{prefix}.png`, or copy the cached png of the same `hash`.
+fn render_png(hash: &str, width: u32, height: u32, prefix: &str, cache: Option<&std::path::Path>, draw: &mut dyn FnMut(&mut Pixmap) -> Result<(), Report>) -> Result<(), Report> {
+    let png    = format!("{}.png", prefix);
+    let cached = cache.map(|dir| dir.join(format!("{hash}.png")));
+    if let Some(cached) = cached.as_ref().filter(|c| c.exists()) {
+        log::debug!("Using cached render: {cached:?}");
+        std::fs::copy(cached, &png)?;
+        return Ok(())
+    }
+
     // Render to pixels (png)
-    let tree = usvg::Tree::from_str(&document.to_string(), &opt)?;
-    let transform = tiny_skia_path::Transform::default();
-    let mut pixmap = Pixmap::new(document_width, document_height).ok_or(eyre!("Failed to create png pixel map: {document_width}x{document_height}"))?;
-    resvg::render(&tree, transform, &mut pixmap.as_mut());
-    pixmap.save_png(format!("{}.png", prefix))?;
+    let mut pixmap = Pixmap::new(width, height).ok_or(eyre!("Failed to create png pixel map: {width}x{height}"))?;
+    draw(&mut pixmap)?;
+    pixmap.save_png(&png)?;
 
+    if let Some(cached) = cached {
+        log::debug!("Caching render: {cached:?}");
+        if let Some(dir) = cached.parent() { std::fs::create_dir_all(dir)? }
+        std::fs::copy(&png, cached)?;
+    }
+
+    Ok(())
+}
+
+/// Remove the png of a previous render (`{prefix}.png`), so it isn't mistaken for the current svg.
+fn remove_png(prefix: &str) -> Result<(), Report> {
+    let png = format!("{}.png", prefix);
+    if std::path::Path::new(&png).exists() {
+        log::debug!("Removing previous png: {png}");
+        std::fs::remove_file(&png)?;
+    }
+    Ok(())
+}
+
+/// Encode the plotted data as a `<metadata>` payload, when requested.
+///
+/// The payload is gzipped, base64-encoded JSON of the rows (samples, or groups when aggregated),
+/// mutations, the matrix of drawn cell values (one row per row), the plot options, and the SHA-256
+/// of each input file. It can be decoded with `base64 -d | gunzip`.
+pub fn embed_data(
+    options:   &PlotOptions,
+    samples:   &[String],
+    mutations: &[String],
+    value:     &dyn Fn(&str, &str) -> serde_json::Value,
+    inputs:    &[std::path::PathBuf],
+    label:     Option<&str>,
+    groups:    Option<&HashMap<String, String>>,
+) -> Result<Option<String>, Report> {
+    if !options.embed_data { return Ok(None) }
+    log::debug!("Embedding plot data in the svg metadata.");
+
+    let matrix = samples.iter().map(|sample| mutations.iter().map(|mutation| value(sample, mutation)).collect::<Vec<_>>()).collect::<Vec<_>>();
+    let mut provenance = Vec::new();
+    for input in inputs {
+        provenance.push(serde_json::json!({ "path": input, "sha256": crate::sha256(&std::fs::read(input)?) }));
+    }
+    let data = serde_json::json!({
+        "version":    env!("CARGO_PKG_VERSION"),
+        "samples":    samples,
+        "mutations":  mutations,
+        "matrix":     matrix,
+        "options":    {
+            "label":                  label,
+            "rotation":               options.rotation,
+            "mutation_labels":        format!("{:?}", options.mutation_labels).to_lowercase(),
+            "sample_labels":          format!("{:?}", options.sample_labels).to_lowercase(),
+            "repeat_mutation_labels": options.repeat_mutation_labels,
+            "repeat_sample_labels":   options.repeat_sample_labels,
+            "barcode":                options.barcode,
+            "aggregate":              options.aggregate,
+            "three_letter":           options.notation.three_letter,
+            "del_notation":           options.notation.del,
+            "groups":                 groups,
+        },
+        "provenance": provenance,
+    });
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data.to_string().as_bytes())?;
+    let metadata = BASE64_STANDARD.encode(encoder.finish()?);
+
+    Ok(Some(metadata))
+}
+
+/// Add the encoded plot data (see [`embed_data`]) to the document as a `<metadata>` element.
+fn add_metadata(document: Document, metadata: Option<&str>) -> Document {
+    match metadata {
+        Some(metadata) => {
+            let mut element = Element::new("metadata");
+            element.assign("id", "mutation-heatmap-data");
+            element.assign("data-encoding", "gzip+base64");
+            element.append(svg::node::Text::new(metadata));
+            document.add(element)
+        },
+        None => document,
+    }
+}
+
+/// Render per-position values (ex. entropy) as a genome-position heat track.
+///
+/// Positions are binned to the track width, and each bin is shaded by its maximum value,
+/// from white (0) to purple (the maximum across the track).
+pub fn track(values: &[(u32, f64)], genome_length: u32, prefix: &str) -> Result<(), Report> {
+
+    log::info!("Rendering heat track: {prefix}");
+
+    let width  = 1000;
+    let height = 50;
+
+    // Bin the positions, keeping the maximum value per bin
+    let mut bins = vec![0.0_f64; width as usize];
+    for (position, value) in values {
+        let bin = ((*position as f64 / genome_length as f64) * width as f64) as usize;
+        let bin = bin.min(width as usize - 1);
+        if *value > bins[bin] { bins[bin] = *value }
+    }
+    let max = bins.iter().cloned().fold(0.0, f64::max);
+
+    let background_coords = Data::new().move_to((0, 0)).line_by((0, height)).line_by((width, 0)).line_by((0, -(height as i32))).close();
+    let background        = Path::new().set("fill", "white").set("stroke", "black").set("stroke-width", 2).set("d", background_coords);
+    let mut cells         = Group::new();
+
+    for (x, value) in bins.iter().enumerate().filter(|(_, v)| **v > 0.0) {
+        let opacity = if max > 0.0 { value / max } else { 0.0 };
+        let cell_coords = Data::new().move_to((x, 0)).line_by((0, height)).line_by((1, 0)).line_by((0, -(height as i32))).close();
+        let cell = Path::new().set("fill", "purple").set("fill-opacity", format!("{opacity:.3}")).set("d", cell_coords);
+        cells = cells.add(cell);
+    }
+
+    let document = Document::new()
+        .set("viewBox", (0, 0, width, height))
+        .add(background)
+        .add(cells);
+
+    save(&document, width, height, prefix, &usvg::Options::default(), None)?;
 
     Ok(())
 }
 
 /// Given a list of strings, calculate the maximum width and height needed to accomodate them.
+///
+/// Each unique label is measured once, and all of them are laid out in a single svg
+/// document, rather than parsing a separate document per label.
 pub fn largest_text<T>(labels: &[T], font_family: &str, font_size: f32, opt: &usvg::Options) -> Result<(u32, u32), Report> 
 where
     T: AsRef<str> + std::fmt::Display
 {
+    let unique = labels.iter().map(|label| label.as_ref()).collect::<BTreeSet<_>>();
+    if unique.is_empty() { return Ok((0, 0)) }
+
+    let texts = unique.iter().enumerate().map(|(i, label)| {
+        format!("<text id='text{i}' alignment-baseline='hanging' font-family='{font_family}' font-size='{font_size}px'>{}</text>", escape_xml(label))
+    }).collect::<String>();
+    let tree  = usvg::Tree::from_str(&format!("<svg xmlns='http://www.w3.org/2000/svg'>{texts}</svg>"), opt)?;
 
     let mut max_width: u32 = 0;
     let mut max_height: u32 = 0;
 
-    labels.iter().map(|label| {
-        let text_template = format!("<svg xmlns='http://www.w3.org/2000/svg'><text id='text' alignment-baseline='hanging' font-family='{font_family}' font-size='{font_size}px'>");
-        let text          = format!("{}{}{}", text_template, label, "</text></svg>");
-        let tree          = usvg::Tree::from_str(&text, opt)?;
-        let node          = tree.node_by_id("text").ok_or(eyre!("Failed constructing svg node for text: {label}"))?;
+    for (i, label) in unique.iter().enumerate() {
+        // Labels without any visible glyphs (ex. whitespace) have no node
+        let Some(node)    = tree.node_by_id(&format!("text{i}")) else { log::debug!("No text was rendered for label: {label:?}"); continue };
         let bounding_box  = node.abs_bounding_box();
         let width         = (bounding_box.right()  - bounding_box.left()).ceil() as u32;
         let height        = (bounding_box.bottom() - bounding_box.top()).ceil() as u32;
         if width  > max_width  { max_width = width }
         if height > max_height { max_height = height }
-        Ok(())
-    }).collect::<Result<(), Report>>()?;
+    }
 
     Ok((max_width, max_height))
-}
\ No newline at end of file
+}
+
+/// Escape text for an svg document.
+pub fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('\'', "&apos;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn label_scores_match_raw_mutations() {
+        let scores = HashMap::from([("S:E484K".to_string(), 0.8), ("S:N501Y".to_string(), 0.2)]);
+        let raw    = HashMap::from([("S:Glu484Lys | escape".to_string(), "S:E484K".to_string())]);
+        let scores = label_scores(&scores, &raw);
+        assert_eq!(scores, HashMap::from([("S:Glu484Lys | escape".to_string(), 0.8)]));
+    }
+
+    #[test]
+    fn stream_matches_save() -> Result<(), Report> {
+        let dir = tempfile::tempdir()?;
+        let background = Path::new().set("fill", "white").set("d", Data::new().move_to((0, 0)).line_by((0, 10)).line_by((10, 0)).close());
+        let cell       = Path::new().set("fill", "purple").set("d", Data::new().move_to((2, 2)).line_by((0, 4)).line_by((4, 0)).close());
+        let document   = Document::new().set("viewBox", (0, 0, 10, 10)).add(background);
+
+        let saved = dir.path().join("saved");
+        save(&document.clone().add(cell.clone()), 10, 10, saved.to_str().unwrap(), &usvg::Options::default(), None)?;
+        let streamed = dir.path().join("streamed");
+        let mut cells = |emit: &mut dyn FnMut(&dyn Node) -> Result<(), Report>| -> Result<(), Report> { emit(&cell) };
+        let hash = stream(&document, &mut cells, streamed.to_str().unwrap())?;
+        rasterize_stream(&document, &mut cells, &hash, 10, 10, streamed.to_str().unwrap(), &usvg::Options::default(), None)?;
+
+        let expected = std::fs::read_to_string(saved.with_extension("svg"))?;
+        let observed = std::fs::read_to_string(streamed.with_extension("svg"))?;
+        assert_eq!(observed, expected);
+        assert_eq!(hash, crate::sha256(expected.as_bytes()));
+        assert_eq!(std::fs::read(streamed.with_extension("png"))?, std::fs::read(saved.with_extension("png"))?);
+        Ok(())
+    }
+
+    #[test]
+    fn label_scores_empty() {
+        assert!(label_scores(&HashMap::new(), &HashMap::new()).is_empty());
+    }
+}
@@ -0,0 +1,148 @@
+use color_eyre::eyre::{Report, Result};
+use datafusion::datasource::MemTable;
+use datafusion::prelude::*;
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Mutations within this many bases of a primer's 3' end are the most likely to cause amplicon dropout.
+pub const THREE_PRIME_BASES: u32 = 5;
+
+/// A sample mutation inside a primer binding site.
+#[derive(Clone, Debug)]
+pub struct PrimerMutation {
+    pub sample: String,
+    pub mutation: String,
+    /// Start coordinate of the mutation.
+    pub position: u32,
+    /// The primer name (ex. SARS-CoV-2_72_RIGHT).
+    pub primer: String,
+    /// Distance of the mutation from the primer's 3' end, 0 is the last base.
+    pub three_prime_distance: u32,
+}
+
+/// Flag the nucleotide mutations of each sample that fall inside primer binding sites.
+///
+/// Mutations near the 3' end of a primer (see [`THREE_PRIME_BASES`]) can prevent it from
+/// binding, and cause its amplicon to drop out. The 3' end is the end of `LEFT` primers,
+/// and the start of `RIGHT` primers, following the ARTIC primer naming.
+///
+/// # Arguments
+///
+///   - `mutations`: A file path to the mutations table produced by `extract` (tsv or parquet).
+///   - `bed`      : A file path to an ARTIC-style primer scheme BED.
+///   - `output`   : A file path to write the table of sample, mutation, primer, and 3' distance to.
+///   - `prefix`   : An optional output prefix to render a sample by mutation heatmap of the primer mutations.
+///
+pub async fn primers<P>(mutations: P, bed: P, output: P, prefix: Option<&str>) -> Result<Vec<PrimerMutation>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Beginning primer impact report.");
+
+    let ctx = SessionContext::new();
+    let ctx = crate::register_mutations(&mutations, ctx, "mutations").await?;
+    let ctx = crate::register_bed(&bed, ctx, "primers").await?;
+
+    // ------------------------------------------------------------------------
+    // Primer Mutations
+
+    let query = "
+        SELECT
+            M.sample,
+            M.mutation,
+            M.nuc_start as position,
+            P.name as primer,
+            CASE
+                WHEN P.name LIKE '%RIGHT%' AND M.nuc_start > P.start THEN M.nuc_start - P.start
+                WHEN P.name NOT LIKE '%RIGHT%' AND P.end > M.nuc_end THEN P.end - M.nuc_end
+                ELSE arrow_cast(0, 'UInt32')
+                END as three_prime_distance
+        FROM mutations M
+        JOIN primers P ON M.nuc_start <= P.end AND M.nuc_end >= P.start
+        WHERE M.type = 'nucleotide' AND M.nuc_start IS NOT NULL
+        ORDER BY M.sample, M.nuc_start, P.name";
+    // Collect the table once, for both the output and the results
+    let df      = ctx.sql(query).await?;
+    let schema  = Arc::new(df.schema().as_arrow().clone());
+    let batches = df.collect().await?;
+
+    log::info!("Writing primer mutations: {output:?}");
+    crate::write_table(ctx.read_table(Arc::new(MemTable::try_new(schema, vec![batches.clone()])?))?, &output).await?;
+
+    let mut results = Vec::new();
+    for batch in batches {
+        let sample   = crate::string_column(&batch, "sample")?;
+        let mutation = crate::string_column(&batch, "mutation")?;
+        let position = crate::string_column(&batch, "position")?;
+        let primer   = crate::string_column(&batch, "primer")?;
+        let distance = crate::string_column(&batch, "three_prime_distance")?;
+        for i in 0..batch.num_rows() {
+            results.push(PrimerMutation {
+                sample:               sample.value(i).to_string(),
+                mutation:             mutation.value(i).to_string(),
+                position:             position.value(i).parse()?,
+                primer:               primer.value(i).to_string(),
+                three_prime_distance: distance.value(i).parse()?,
+            });
+        }
+    }
+
+    let samples = results.iter().map(|r| r.sample.as_str()).collect::<BTreeSet<_>>();
+    let near    = results.iter().filter(|r| r.three_prime_distance < THREE_PRIME_BASES).count();
+    log::info!("Found {} primer mutations in {} samples, {near} within {THREE_PRIME_BASES} bases of the 3' end.", results.len(), samples.len());
+
+    // ------------------------------------------------------------------------
+    // Heatmap
+
+    #[cfg(feature = "plot")]
+    if let Some(prefix) = prefix {
+        log::info!("Rendering primer mutations heatmap: {prefix}");
+        let samples = samples.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let mutations = results.iter().map(|r| (r.position, r.mutation.clone())).collect::<BTreeSet<_>>().into_iter().map(|(_, m)| m).collect::<Vec<_>>();
+        let cells  = results.iter().map(|r| ((r.sample.clone(), r.mutation.clone()), r)).collect::<std::collections::HashMap<_, _>>();
+        let cell   = |sample: &str, mutation: &str| cells.get(&(sample.to_string(), mutation.to_string()));
+        let fill   = |sample: &str, mutation: &str| match cell(sample, mutation) {
+            Some(r) if r.three_prime_distance < THREE_PRIME_BASES => "crimson",
+            Some(_)                                                => "orange",
+            None                                                   => "white",
+        }.to_string();
+        let detail = |sample: &str, mutation: &str| match cell(sample, mutation) {
+            Some(r) => format!("Primer: {} ({} bases from 3' end)", r.primer, r.three_prime_distance),
+            None    => "No primer mutation".to_string(),
+        };
+        crate::plot::heatmap(&samples, &mutations, &fill, Some(&detail), prefix, &crate::plot::PlotOptions::default(), &crate::plot::Tracks::default())?;
+    }
+    #[cfg(not(feature = "plot"))]
+    crate::warn_without_plot(prefix, "primer mutations heatmap");
+
+    log::info!("Finished primer impact report.");
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn three_prime_distance_by_primer_direction() -> Result<(), Report> {
+        let dir  = tempfile::tempdir()?;
+        let path = |name: &str| dir.path().join(name);
+        std::fs::write(path("primers.bed"), "ref\t30\t54\tSARS-CoV-2_1_LEFT\t1\t+\nref\t385\t410\tSARS-CoV-2_1_RIGHT\t1\t-\n")?;
+        let mutations = [
+            "sample\tmutation\ttype\tnuc_start\tnuc_end",
+            "s1\tC52T\tnucleotide\t52\t52",
+            "s1\tC390T\tnucleotide\t390\t390",
+            "s2\tC241T\tnucleotide\t241\t241",
+        ];
+        std::fs::write(path("mutations.tsv"), mutations.join("\n") + "\n")?;
+
+        let results = primers(path("mutations.tsv"), path("primers.bed"), path("primer_mutations.tsv"), None).await?;
+        let results = results.iter().map(|r| (r.sample.as_str(), r.mutation.as_str(), r.primer.as_str(), r.three_prime_distance)).collect::<Vec<_>>();
+        // The 3' end is the end of LEFT primers (54), and the start of RIGHT primers (386)
+        assert_eq!(results, [("s1", "C52T", "SARS-CoV-2_1_LEFT", 2), ("s1", "C390T", "SARS-CoV-2_1_RIGHT", 4)]);
+        assert!(path("primer_mutations.tsv").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+use color_eyre::eyre::{Report, Result};
+use datafusion::prelude::*;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Extraction statistics, for debugging a pipeline run.
+///
+/// This is separate from the scientific outputs, and only describes how
+/// the input tables were transformed into the mutations table.
+#[derive(Clone, Debug, Default)]
+pub struct Qc {
+    /// Name, unit and number of rows after each stage, in order.
+    pub stages: Vec<(String, String, usize)>,
+    /// Samples in the input that have no mutations in the output.
+    pub dropped_samples: Vec<String>,
+    /// Number of mutations per nextclade column.
+    pub mutations: Vec<(String, usize)>,
+    /// Genes named by mutations that were not found in the gene annotations.
+    pub unmatched_genes: Vec<String>,
+    /// Warnings raised while reading and checking the inputs.
+    pub warnings: Vec<String>,
+    /// Display format of the fraction of rows kept by each stage, relative to
+    /// the previous stage counted in the same unit.
+    pub number: crate::number::NumberFormat,
+}
+
+impl Qc {
+    /// Record the number of rows returned by a query, as a named stage.
+    ///
+    /// The `unit` names what a row counts (e.g. records or mutations), so
+    /// that stages are only compared with stages of the same unit.
+    pub async fn stage(&mut self, ctx: &SessionContext, name: &str, unit: &str, query: &str) -> Result<(), Report> {
+        let batches = ctx.sql(&format!("SELECT COUNT(*) as count FROM ({query})")).await?.collect().await?;
+        let count   = match batches.first() {
+            Some(batch) => crate::string_column(batch, "count")?.value(0).parse()?,
+            None        => 0,
+        };
+        self.stages.push((name.to_string(), unit.to_string(), count));
+        Ok(())
+    }
+
+    /// Log a warning, and keep it for the report.
+    pub fn warn(&mut self, message: String) {
+        log::warn!("{message}");
+        self.warnings.push(message);
+    }
+
+    /// Fraction of rows kept by a stage, relative to the previous stage of the
+    /// same unit. Empty for the first stage of each unit.
+    fn of_previous(&self, i: usize) -> String {
+        let (_, unit, rows) = &self.stages[i];
+        match self.stages[..i].iter().rev().find(|(_, u, _)| u == unit) {
+            Some((_, _, 0))        => String::new(),
+            Some((_, _, previous)) => self.number.format(*rows as f64 / *previous as f64),
+            None                   => String::new(),
+        }
+    }
+
+    /// Write the statistics as a static html page.
+    pub fn write_html<P>(&self, path: P) -> Result<(), Report>
+    where
+        P: AsRef<Path> + std::fmt::Debug,
+    {
+        log::info!("Writing QC report: {path:?}");
+        let mut writer = BufWriter::new(File::create(&path)?);
+
+        writeln!(writer, "<!DOCTYPE html>")?;
+        writeln!(writer, "<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Extraction QC</title>")?;
+        writeln!(writer, "<style>body {{ font-family: sans-serif; }} table {{ border-collapse: collapse; }} th, td {{ border: 1px solid #ccc; padding: 2px 8px; text-align: left; }}</style>")?;
+        writeln!(writer, "</head>\n<body>\n<h1>Extraction QC</h1>")?;
+
+        writeln!(writer, "<h2>Rows per stage</h2>")?;
+        table(&mut writer, &["stage", "unit", "rows", "of previous"], self.stages.iter().enumerate().map(|(i, (s, u, n))| {
+            vec![s.clone(), u.clone(), n.to_string(), self.of_previous(i)]
+        }))?;
+
+        writeln!(writer, "<h2>Mutations per column</h2>")?;
+        table(&mut writer, &["column", "mutations"], self.mutations.iter().map(|(c, n)| vec![c.clone(), n.to_string()]))?;
+
+        writeln!(writer, "<h2>Samples without mutations ({})</h2>", self.dropped_samples.len())?;
+        list(&mut writer, &self.dropped_samples)?;
+
+        writeln!(writer, "<h2>Genes without annotations ({})</h2>", self.unmatched_genes.len())?;
+        list(&mut writer, &self.unmatched_genes)?;
+
+        writeln!(writer, "<h2>Warnings ({})</h2>", self.warnings.len())?;
+        list(&mut writer, &self.warnings)?;
+
+        writeln!(writer, "</body>\n</html>")?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Escape text for html.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn table<W: Write>(writer: &mut W, header: &[&str], rows: impl Iterator<Item = Vec<String>>) -> Result<(), Report> {
+    writeln!(writer, "<table>")?;
+    writeln!(writer, "<tr>{}</tr>", header.iter().map(|h| format!("<th>{}</th>", escape(h))).collect::<String>())?;
+    for row in rows {
+        writeln!(writer, "<tr>{}</tr>", row.iter().map(|v| format!("<td>{}</td>", escape(v))).collect::<String>())?;
+    }
+    writeln!(writer, "</table>")?;
+    Ok(())
+}
+
+fn list<W: Write>(writer: &mut W, items: &[String]) -> Result<(), Report> {
+    match items.is_empty() {
+        true  => writeln!(writer, "<p>None.</p>")?,
+        false => {
+            writeln!(writer, "<ul>")?;
+            for item in items { writeln!(writer, "<li>{}</li>", escape(item))? }
+            writeln!(writer, "</ul>")?;
+        },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn of_previous_same_unit() {
+        let qc = Qc {
+            stages: vec![
+                ("nextclade records".to_string(), "records".to_string(),   10),
+                ("mutations".to_string(),         "mutations".to_string(), 200),
+                ("joined to gff".to_string(),     "mutations".to_string(), 150),
+                ("final table".to_string(),       "mutations".to_string(), 0),
+                ("empty".to_string(),             "mutations".to_string(), 0),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(qc.of_previous(0), "");
+        assert_eq!(qc.of_previous(1), "");
+        assert_eq!(qc.of_previous(2), qc.number.format(0.75));
+        assert_eq!(qc.of_previous(3), qc.number.format(0.0));
+        assert_eq!(qc.of_previous(4), "");
+    }
+}
@@ -0,0 +1,96 @@
+use color_eyre::eyre::{eyre, Report, Result};
+use datafusion::execution::context::SQLOptions;
+use datafusion::prelude::*;
+use std::path::Path;
+
+/// Select columns from a mutations table, without reading the others.
+///
+/// The projection is pushed down into the table scan, so for parquet only the
+/// requested column chunks are read (ex. a sample list doesn't touch the mutation strings).
+///
+/// # Arguments
+///
+///   - `mutations`: A file path to the mutations table produced by `extract` or `annotate` (tsv or parquet).
+///   - `columns`  : The columns to select, or all columns if empty.
+///   - `distinct` : Remove duplicate rows from the selection.
+///
+pub async fn select<P>(mutations: P, columns: &[String], distinct: bool) -> Result<DataFrame, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let ctx = SessionContext::new();
+    let ctx = crate::register_mutations(&mutations, ctx, "mutations").await?;
+    let df  = ctx.table("mutations").await?;
+
+    let available = crate::column_names(&df);
+    if let Some(column) = columns.iter().find(|c| !available.contains(c)) {
+        return Err(eyre!("Column '{column}' was not found in the mutations table, the available columns are: {}", available.join(", ")))
+    }
+
+    let df = match columns.is_empty() {
+        true  => df,
+        false => df.select_columns(&columns.iter().map(String::as_str).collect::<Vec<_>>())?,
+    };
+    let df = match distinct {
+        true  => df.distinct()?,
+        false => df,
+    };
+
+    Ok(df)
+}
+
+/// Run a read-only SQL query against a mutations table, registered as `mutations`.
+///
+/// Only queries are allowed, statements that create, modify, or drop tables
+/// (DDL and DML), or change session settings, are rejected before planning.
+/// No other tables are registered, except those of a catalog (see [`crate::catalog`]),
+/// so a query can't read any other file.
+///
+/// # Arguments
+///
+///   - `mutations`: A file path to the mutations table produced by `extract` or `annotate` (tsv or parquet).
+///   - `sql`      : The SQL query.
+///   - `catalog`  : An optional file path to a catalog of named tables to join against (ex. gene aliases).
+///
+pub async fn sql<P>(mutations: P, sql: &str, catalog: Option<&Path>) -> Result<DataFrame, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let ctx     = SessionContext::new();
+    let ctx     = crate::register_mutations(&mutations, ctx, "mutations").await?;
+    let ctx     = match catalog {
+        Some(catalog) => crate::catalog::register(&catalog, ctx).await?,
+        None          => ctx,
+    };
+    let options = SQLOptions::new().with_allow_ddl(false).with_allow_dml(false).with_allow_statements(false);
+    ctx.sql_with_options(sql, options).await.map_err(|e| eyre!("Only read-only queries of the mutations table are allowed: {e}"))
+}
+
+/// Select columns from a mutations table, or run a read-only query, and write the results to a file.
+///
+/// # Arguments
+///
+///   - `mutations`: A file path to the mutations table produced by `extract` or `annotate` (tsv or parquet).
+///   - `columns`  : The columns to select, or all columns if empty.
+///   - `distinct` : Remove duplicate rows from the selection.
+///   - `query`    : An optional read-only SQL query to run instead of the selection (see [`sql`]).
+///   - `catalog`  : An optional file path to a catalog of named tables that the query can join against.
+///   - `output`   : A file path to write the selection to (tsv or parquet).
+///
+pub async fn query<P>(mutations: P, columns: &[String], distinct: bool, query: Option<&str>, catalog: Option<&Path>, output: P) -> Result<(), Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Beginning query.");
+
+    let df = match query {
+        Some(query) => sql(&mutations, query, catalog).await?,
+        None        => select(&mutations, columns, distinct).await?,
+    };
+    log::info!("Writing query results: {output:?}");
+    crate::write_table(df, &output).await?;
+
+    log::info!("Finished query.");
+
+    Ok(())
+}
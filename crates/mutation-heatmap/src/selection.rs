@@ -0,0 +1,193 @@
+use arrow::array::{Float64Array, StringArray, UInt32Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use color_eyre::eyre::{Report, Result};
+use datafusion::prelude::*;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::codon::translate_codon;
+use crate::gene::Gene;
+
+/// Nonsynonymous and synonymous substitutions of a gene across a sample set.
+#[derive(Clone, Debug, Default)]
+pub struct GeneSelection {
+    pub gene: String,
+    /// Number of distinct nonsynonymous substitutions observed in the gene.
+    pub nonsynonymous: u32,
+    /// Number of distinct synonymous substitutions observed in the gene.
+    pub synonymous: u32,
+    /// Expected number of nonsynonymous sites, from the reference codons.
+    pub nonsynonymous_sites: f64,
+    /// Expected number of synonymous sites, from the reference codons.
+    pub synonymous_sites: f64,
+}
+
+impl GeneSelection {
+    /// Nonsynonymous substitutions per nonsynonymous site.
+    pub fn pn(&self) -> f64 {
+        self.nonsynonymous as f64 / self.nonsynonymous_sites.max(f64::MIN_POSITIVE)
+    }
+
+    /// Synonymous substitutions per synonymous site.
+    pub fn ps(&self) -> f64 {
+        self.synonymous as f64 / self.synonymous_sites.max(f64::MIN_POSITIVE)
+    }
+
+    /// The ratio of pN to pS, which is undefined without synonymous substitutions.
+    ///
+    /// Values above 1 suggest positive selection, and below 1 purifying selection.
+    pub fn ratio(&self) -> Option<f64> {
+        (self.synonymous > 0).then(|| self.pn() / self.ps())
+    }
+}
+
+/// Count the synonymous and nonsynonymous sites of a gene (Nei-Gojobori).
+///
+/// Each coding position contributes the fraction of its three possible substitutions that
+/// are synonymous, and the remainder as nonsynonymous. Changes to a stop codon are
+/// nonsynonymous, and codons that are ambiguous or already a stop are skipped.
+pub fn sites(gene: &Gene, reference: &[u8]) -> (f64, f64) {
+    let base = |position: &u32| reference.get(*position as usize - 1).map(|b| b.to_ascii_uppercase()).unwrap_or(b'N');
+    let (mut nonsynonymous, mut synonymous) = (0.0, 0.0);
+    for codon_positions in gene.positions().chunks_exact(3) {
+        let mut ref_codon = codon_positions.iter().map(base).collect::<Vec<_>>();
+        if gene.strand == "-" { ref_codon = ref_codon.into_iter().map(crate::mnv::complement).collect() }
+        let ref_aa = translate_codon(&ref_codon);
+        if ref_aa == 'X' || ref_aa == '*' { continue }
+        for (offset, ref_base) in ref_codon.iter().enumerate() {
+            for alt in [b'A', b'C', b'G', b'T'].into_iter().filter(|b| b != ref_base) {
+                let mut alt_codon = ref_codon.clone();
+                alt_codon[offset] = alt;
+                match translate_codon(&alt_codon) == ref_aa {
+                    true  => synonymous    += 1.0 / 3.0,
+                    false => nonsynonymous += 1.0 / 3.0,
+                }
+            }
+        }
+    }
+    (nonsynonymous, synonymous)
+}
+
+/// Summarize the nonsynonymous and synonymous substitutions of each gene, as a dN/dS-style ratio.
+///
+/// Substitutions are counted once per gene, no matter how many samples carry them, and
+/// normalized by the number of nonsynonymous and synonymous sites in the reference gene.
+/// The `effect` column written by `extract --reference` is used when present, otherwise
+/// the substitutions are classified here.
+///
+/// # Arguments
+///
+///   - `mutations`: A file path to the mutations table produced by `extract` (tsv or parquet).
+///   - `gff`      : A file path to the gene annotations (GFF3, GTF, or GenBank).
+///   - `reference`: A file path to the reference genome fasta.
+///   - `output`   : A file path to write the per-gene summary table to.
+///   - `cache`    : An optional directory to cache parsed gene models in (see [`crate::gene::genes_cached`]).
+///
+pub async fn selection<P>(mutations: P, gff: P, reference: P, output: P, cache: Option<&Path>) -> Result<Vec<GeneSelection>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Beginning selection summary.");
+
+    let genes     = crate::gene::read_genes(&gff, None, cache)?;
+    let reference = crate::read_fasta(&reference)?.swap_remove(0).1;
+
+    let ctx = SessionContext::new();
+    let ctx = crate::register_mutations(&mutations, ctx, "mutations").await?;
+    let ctx = match crate::column_names(&ctx.table("mutations").await?).iter().any(|c| c == "effect") {
+        true  => ctx,
+        false => crate::mnv::classify_substitutions(ctx, "mutations", &reference, &genes).await?,
+    };
+
+    // ------------------------------------------------------------------------
+    // Expected Sites
+
+    let mut summary: BTreeMap<String, GeneSelection> = BTreeMap::new();
+    for gene in &genes {
+        let (nonsynonymous_sites, synonymous_sites) = sites(gene, &reference);
+        let entry = summary.entry(gene.name.clone()).or_insert_with(|| GeneSelection { gene: gene.name.clone(), ..Default::default() });
+        entry.nonsynonymous_sites += nonsynonymous_sites;
+        entry.synonymous_sites    += synonymous_sites;
+    }
+
+    // ------------------------------------------------------------------------
+    // Observed Substitutions
+
+    let query = "
+        SELECT gene, effect, COUNT(DISTINCT mutation) as count
+        FROM mutations
+        WHERE column = 'substitutions' AND gene IS NOT NULL AND effect IN ('synonymous', 'nonsynonymous')
+        GROUP BY gene, effect";
+    let batches = ctx.sql(query).await?.collect().await?;
+    for batch in &batches {
+        let gene   = crate::string_column(batch, "gene")?;
+        let effect = crate::string_column(batch, "effect")?;
+        let count  = crate::string_column(batch, "count")?;
+        for i in 0..batch.num_rows() {
+            let Some(entry) = summary.get_mut(gene.value(i)) else { continue };
+            match effect.value(i) {
+                "synonymous" => entry.synonymous    = count.value(i).parse()?,
+                _            => entry.nonsynonymous = count.value(i).parse()?,
+            }
+        }
+    }
+    let summary = summary.into_values().collect::<Vec<_>>();
+
+    // ------------------------------------------------------------------------
+    // Write Table
+
+    log::info!("Writing selection summary: {output:?}");
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("gene",                DataType::Utf8,    false),
+        Field::new("nonsynonymous",       DataType::UInt32,  false),
+        Field::new("synonymous",          DataType::UInt32,  false),
+        Field::new("nonsynonymous_sites", DataType::Float64, false),
+        Field::new("synonymous_sites",    DataType::Float64, false),
+        Field::new("pn",                  DataType::Float64, false),
+        Field::new("ps",                  DataType::Float64, false),
+        Field::new("pn_ps",               DataType::Float64, true),
+    ]));
+    let records = RecordBatch::try_new(
+        schema,
+        vec![
+            Arc::new(StringArray::from(summary.iter().map(|s| s.gene.clone()).collect::<Vec<_>>())),
+            Arc::new(UInt32Array::from(summary.iter().map(|s| s.nonsynonymous).collect::<Vec<_>>())),
+            Arc::new(UInt32Array::from(summary.iter().map(|s| s.synonymous).collect::<Vec<_>>())),
+            Arc::new(Float64Array::from(summary.iter().map(|s| s.nonsynonymous_sites).collect::<Vec<_>>())),
+            Arc::new(Float64Array::from(summary.iter().map(|s| s.synonymous_sites).collect::<Vec<_>>())),
+            Arc::new(Float64Array::from(summary.iter().map(|s| s.pn()).collect::<Vec<_>>())),
+            Arc::new(Float64Array::from(summary.iter().map(|s| s.ps()).collect::<Vec<_>>())),
+            Arc::new(Float64Array::from(summary.iter().map(|s| s.ratio()).collect::<Vec<_>>())),
+        ],
+    )?;
+
+    crate::write_table(ctx.read_batch(records)?, &output).await?;
+
+    log::info!("Finished selection summary.");
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nei_gojobori_sites() {
+        let gene  = |strand: &str, end| Gene { name: "g".to_string(), strand: strand.to_string(), start: 1, end, segments: vec![(1, end)] };
+        let close = |(n, s): (f64, f64), (en, es): (f64, f64)| (n - en).abs() < 1e-9 && (s - es).abs() < 1e-9;
+
+        // Every change to ATG is nonsynonymous, CTG has 4/3 synonymous sites, and the stop is skipped
+        assert!(close(sites(&gene("+", 3), b"ATG"), (3.0, 0.0)));
+        assert!(close(sites(&gene("+", 3), b"CTG"), (5.0 / 3.0, 4.0 / 3.0)));
+        assert!(close(sites(&gene("+", 9), b"ATGCTGTAA"), (14.0 / 3.0, 4.0 / 3.0)));
+        // The minus strand reads CAT as ATG
+        assert!(close(sites(&gene("-", 3), b"CAT"), (3.0, 0.0)));
+        // Ambiguous codons, and codons past the end of the reference, have no sites
+        assert!(close(sites(&gene("+", 3), b"ANG"), (0.0, 0.0)));
+        assert!(close(sites(&gene("+", 6), b""),    (0.0, 0.0)));
+    }
+}
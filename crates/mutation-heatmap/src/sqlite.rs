@@ -0,0 +1,181 @@
+use arrow::array::{Array, ArrayRef, AsArray, Float64Array, Int64Array, StringArray};
+use arrow::datatypes::{DataType, Field, Float64Type, Int64Type, Schema};
+use arrow::record_batch::RecordBatch;
+use color_eyre::eyre::{eyre, Report, Result};
+use datafusion::datasource::MemTable;
+use datafusion::prelude::*;
+use rusqlite::types::Value;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The table of a SQLite database that holds a file's data, named by the file stem (ex. `mutations.sqlite` -> `mutations`).
+pub fn table_name<P>(path: P) -> Result<String, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let stem = path.as_ref().file_stem().and_then(|s| s.to_str()).ok_or(eyre!("Failed to parse the table name from the SQLite path: {path:?}"))?;
+    Ok(stem.to_string())
+}
+
+/// Register the table of a SQLite database for SQL queries.
+///
+/// The table is named by the file stem (see [`table_name`]). Columns declared as INTEGER or
+/// REAL keep their type, and all others are read as text. A value that doesn't fit the
+/// declared type of its column (ex. text in an INTEGER column) is an error.
+///
+/// # Arguments
+///
+///   - `path` : A file path to the SQLite database.
+///   - `ctx`  : The session to register the table in.
+///   - `name` : The name to register the table as.
+///
+pub async fn register<P,N>(path: &P, ctx: SessionContext, name: N) -> Result<SessionContext, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+    N: ToString,
+{
+    let table = table_name(path)?;
+    log::info!("Reading SQLite table {table}: {path:?}");
+
+    let connection = rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+    // The declared column types, from the table definition
+    let mut statement = connection.prepare(&format!("PRAGMA table_info({})", crate::quote_identifier(&table)))?;
+    let columns = statement
+        .query_map([], |row| Ok((row.get::<_, String>(1)?, row.get::<_, String>(2)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+    if columns.is_empty() {
+        return Err(eyre!("SQLite database has no table {table}: {path:?}"))
+    }
+    let types = columns.iter().map(|(_, ty)| match ty.to_uppercase().as_str() {
+        ty if ty.contains("INT")                                                => DataType::Int64,
+        ty if ty.contains("REAL") || ty.contains("FLOA") || ty.contains("DOUB") => DataType::Float64,
+        _                                                                       => DataType::Utf8,
+    }).collect::<Vec<_>>();
+
+    // Read all rows as SQLite values, which are converted to the arrow types per column
+    let mut statement = connection.prepare(&format!("SELECT * FROM {}", crate::quote_identifier(&table)))?;
+    let rows = statement
+        .query_map([], |row| (0..columns.len()).map(|i| row.get::<_, Value>(i)).collect::<Result<Vec<_>, _>>())?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // SQLite columns can hold any type of value, so a mismatch is reported rather than read as NULL
+    let mismatch = |column: &str, row: usize, value: &Value| eyre!(
+        "SQLite column {column} of table {table} has a {:?} value in row {}, which doesn't match its declared type: {path:?}",
+        value.data_type(), row + 1
+    );
+    let arrays = types.iter().zip(&columns).enumerate().map(|(i, (ty, (column, _)))| -> Result<ArrayRef, Report> {
+        let values = rows.iter().map(|row| &row[i]).enumerate();
+        let array: ArrayRef = match ty {
+            DataType::Int64   => Arc::new(values.map(|(r, v)| match v {
+                Value::Null       => Ok(None),
+                Value::Integer(v) => Ok(Some(*v)),
+                v                 => Err(mismatch(column, r, v)),
+            }).collect::<Result<Int64Array, Report>>()?),
+            DataType::Float64 => Arc::new(values.map(|(r, v)| match v {
+                Value::Null       => Ok(None),
+                Value::Real(v)    => Ok(Some(*v)),
+                Value::Integer(v) => Ok(Some(*v as f64)),
+                v                 => Err(mismatch(column, r, v)),
+            }).collect::<Result<Float64Array, Report>>()?),
+            _ => Arc::new(values.map(|(_, v)| match v {
+                Value::Null       => None,
+                Value::Integer(v) => Some(v.to_string()),
+                Value::Real(v)    => Some(v.to_string()),
+                Value::Text(v)    => Some(v.clone()),
+                Value::Blob(v)    => Some(String::from_utf8_lossy(v).to_string()),
+            }).collect::<StringArray>()),
+        };
+        Ok(array)
+    }).collect::<Result<Vec<_>, Report>>()?;
+
+    let fields  = columns.iter().zip(&types).map(|((name, _), ty)| Field::new(name, ty.clone(), true)).collect::<Vec<_>>();
+    let schema  = Arc::new(Schema::new(fields));
+    let records = RecordBatch::try_new(schema.clone(), arrays)?;
+    ctx.register_table(&name.to_string(), Arc::new(MemTable::try_new(schema, vec![vec![records]])?))?;
+
+    Ok(ctx)
+}
+
+/// Write a dataframe to a table of a SQLite database, replacing the table if it exists.
+///
+/// The table is named by the file stem (see [`table_name`]), which is also the table that
+/// [`register`] reads back. Integers and floats are stored as INTEGER and REAL, and all
+/// other columns as TEXT.
+pub async fn write<P>(df: DataFrame, path: &P) -> Result<(), Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let table   = table_name(path)?;
+    let schema  = df.schema().as_arrow().clone();
+    let batches = df.collect().await?;
+
+    let columns = schema.fields().iter().map(|f| {
+        let ty = match f.data_type() {
+            ty if ty.is_integer()  => "INTEGER",
+            ty if ty.is_floating() => "REAL",
+            _                      => "TEXT",
+        };
+        format!("{} {ty}", crate::quote_identifier(f.name()))
+    }).collect::<Vec<_>>();
+
+    let mut connection = rusqlite::Connection::open(path)?;
+    let transaction    = connection.transaction()?;
+    let quoted         = crate::quote_identifier(&table);
+    transaction.execute(&format!("DROP TABLE IF EXISTS {quoted}"), [])?;
+    transaction.execute(&format!("CREATE TABLE {quoted} ({})", columns.join(", ")), [])?;
+    {
+        let placeholders  = vec!["?"; columns.len()].join(", ");
+        let mut statement = transaction.prepare(&format!("INSERT INTO {quoted} VALUES ({placeholders})"))?;
+        for batch in &batches {
+            // Integers and floats are cast to 64-bit, and everything else to text
+            let arrays = batch.columns().iter().map(|c| -> Result<ArrayRef, Report> {
+                let ty = match c.data_type() {
+                    ty if ty.is_integer()  => DataType::Int64,
+                    ty if ty.is_floating() => DataType::Float64,
+                    _                      => DataType::Utf8,
+                };
+                Ok(arrow::compute::cast(c, &ty)?)
+            }).collect::<Result<Vec<_>, _>>()?;
+            for i in 0..batch.num_rows() {
+                let values = arrays.iter().map(|array| match array.is_null(i) {
+                    true  => Value::Null,
+                    false => match array.data_type() {
+                        DataType::Int64   => Value::Integer(array.as_primitive::<Int64Type>().value(i)),
+                        DataType::Float64 => Value::Real(array.as_primitive::<Float64Type>().value(i)),
+                        _                 => Value::Text(array.as_string::<i32>().value(i).to_string()),
+                    },
+                }).collect::<Vec<_>>();
+                statement.execute(rusqlite::params_from_iter(values))?;
+            }
+        }
+    }
+    transaction.commit()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn register_rejects_mismatched_values() -> Result<(), Report> {
+        let dir  = tempfile::tempdir()?;
+        let path = dir.path().join("mutations.sqlite");
+        let connection = rusqlite::Connection::open(&path)?;
+        connection.execute("CREATE TABLE mutations (sample TEXT, depth INTEGER, frequency REAL)", [])?;
+        connection.execute("INSERT INTO mutations VALUES ('s1', 10, 0.5), ('s2', NULL, 1)", [])?;
+
+        let ctx     = register(&path, SessionContext::new(), "mutations").await?;
+        let batches = ctx.sql("SELECT * FROM mutations ORDER BY sample").await?.collect().await?;
+        assert_eq!(batches[0].num_rows(), 2);
+        assert!(batches[0].column(1).is_null(1));
+        assert_eq!(batches[0].column(2).as_primitive::<Float64Type>().value(1), 1.0);
+
+        connection.execute("INSERT INTO mutations VALUES ('s3', 'deep', 0.5)", [])?;
+        let error = register(&path, SessionContext::new(), "mutations").await.unwrap_err();
+        assert!(error.to_string().contains("column depth"));
+        Ok(())
+    }
+}
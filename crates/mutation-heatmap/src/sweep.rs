@@ -0,0 +1,157 @@
+use arrow::array::{Float64Array, StringArray, UInt32Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use color_eyre::eyre::{eyre, Report, Result};
+use datafusion::prelude::*;
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Default allele frequency thresholds, from the lowest that amplicon sequencing can resolve to consensus.
+pub const THRESHOLDS: &[f64] = &[0.01, 0.02, 0.03, 0.05, 0.1, 0.15, 0.2, 0.25, 0.5, 0.75];
+
+/// The number of annotated mutations a sample would have at an allele frequency threshold.
+#[derive(Clone, Debug)]
+pub struct ThresholdCount {
+    pub sample: String,
+    pub threshold: f64,
+    /// Number of distinct annotated mutations with a frequency of at least the threshold.
+    pub present: u32,
+}
+
+/// Count the annotated mutations called present in each sample across allele frequency thresholds.
+///
+/// Helps to choose (and justify) a minor variant reporting cutoff, by showing how quickly
+/// the calls of each sample fall away as the threshold rises. Mutations without a
+/// frequency (ex. from consensus sequences) count as present at every threshold.
+///
+/// Variants below the `--min-freq` of `annotate` were already dropped, so annotate with a
+/// `--min-freq` at or below the lowest threshold.
+///
+/// # Arguments
+///
+///   - `annotated` : A file path to the annotated table produced by `annotate` from ivar variants.
+///   - `thresholds`: The allele frequency thresholds to count the present mutations at.
+///   - `output`    : A file path to write the table of sample, threshold, and present mutations to.
+///   - `prefix`    : An optional output prefix to render a sample by threshold heatmap, shaded by the count.
+///
+pub async fn sweep<P>(annotated: P, thresholds: &[f64], output: P, prefix: Option<&str>) -> Result<Vec<ThresholdCount>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Beginning threshold sweep.");
+
+    let ctx = SessionContext::new();
+    let ctx = crate::register_mutations(&annotated, ctx, "annotated").await?;
+    let columns = crate::column_names(&ctx.table("annotated").await?);
+    for column in ["sample", "mutation", "status", "frequency"] {
+        if !columns.iter().any(|c| c == column) {
+            return Err(eyre!("Annotated table is missing the column {column}, annotate ivar variants to sweep thresholds: {annotated:?}"))
+        }
+    }
+
+    let mut thresholds = thresholds.to_vec();
+    thresholds.sort_by(|a, b| a.total_cmp(b));
+    thresholds.dedup();
+
+    // ------------------------------------------------------------------------
+    // Present Mutations
+
+    let mut counts: HashMap<(String, u64), u32> = HashMap::new();
+    let mut samples = BTreeSet::new();
+    for threshold in &thresholds {
+        let query = format!("
+            SELECT
+                sample,
+                COUNT(DISTINCT CASE
+                    WHEN status != 'absent' AND COALESCE(arrow_cast(frequency, 'Float64'), 1.0) >= {threshold} THEN mutation
+                    END) as present
+            FROM annotated
+            GROUP BY sample");
+        let batches = ctx.sql(&query).await?.collect().await?;
+        for batch in &batches {
+            let sample  = crate::string_column(batch, "sample")?;
+            let present = crate::string_column(batch, "present")?;
+            for i in 0..batch.num_rows() {
+                samples.insert(sample.value(i).to_string());
+                counts.insert((sample.value(i).to_string(), threshold.to_bits()), present.value(i).parse()?);
+            }
+        }
+    }
+
+    let mut results = Vec::new();
+    for sample in &samples {
+        for threshold in &thresholds {
+            let present = counts.get(&(sample.clone(), threshold.to_bits())).copied().unwrap_or(0);
+            results.push(ThresholdCount { sample: sample.clone(), threshold: *threshold, present });
+        }
+    }
+    log::info!("Counted the present mutations of {} samples at {} thresholds.", samples.len(), thresholds.len());
+
+    // ------------------------------------------------------------------------
+    // Write Table
+
+    log::info!("Writing threshold sweep: {output:?}");
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("sample",    DataType::Utf8,    false),
+        Field::new("threshold", DataType::Float64, false),
+        Field::new("present",   DataType::UInt32,  false),
+    ]));
+    let records = RecordBatch::try_new(
+        schema,
+        vec![
+            Arc::new(StringArray::from(results.iter().map(|r| r.sample.clone()).collect::<Vec<_>>())),
+            Arc::new(Float64Array::from(results.iter().map(|r| r.threshold).collect::<Vec<_>>())),
+            Arc::new(UInt32Array::from(results.iter().map(|r| r.present).collect::<Vec<_>>())),
+        ],
+    )?;
+    crate::write_table(ctx.read_batch(records)?, &output).await?;
+
+    // ------------------------------------------------------------------------
+    // Heatmap
+
+    #[cfg(feature = "plot")]
+    if let Some(prefix) = prefix {
+        log::info!("Rendering threshold sweep heatmap: {prefix}");
+        let names   = samples.iter().cloned().collect::<Vec<_>>();
+        let columns = thresholds.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        let cells   = results.iter().map(|r| ((r.sample.clone(), r.threshold.to_string()), r.present)).collect::<HashMap<_, _>>();
+        let max     = results.iter().map(|r| r.present).max().unwrap_or(0).max(1);
+        let present = |sample: &str, threshold: &str| cells.get(&(sample.to_string(), threshold.to_string())).copied().unwrap_or(0);
+        let fill    = |sample: &str, threshold: &str| crate::plot::scale_color(present(sample, threshold) as f64 / max as f64);
+        let detail  = |sample: &str, threshold: &str| format!("Present at {threshold}: {}", present(sample, threshold));
+        crate::plot::heatmap(&names, &columns, &fill, Some(&detail), prefix, &crate::plot::PlotOptions::default(), &crate::plot::Tracks::default())?;
+    }
+    #[cfg(not(feature = "plot"))]
+    crate::warn_without_plot(prefix, "threshold sweep heatmap");
+
+    log::info!("Finished threshold sweep.");
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn present_mutations_by_threshold() -> Result<(), Report> {
+        let dir  = tempfile::tempdir()?;
+        let path = |name: &str| dir.path().join(name);
+        let annotated = [
+            "sample\tmutation\tstatus\tfrequency",
+            "s1\tC241T\tpresent\t0.8",
+            "s1\tA23403G\tlow-frequency\t0.04",
+            "s1\tG28881A\tabsent\t",
+            "s2\tC241T\tpresent\t",
+        ];
+        std::fs::write(path("annotated.tsv"), annotated.join("\n") + "\n")?;
+
+        let results = sweep(path("annotated.tsv"), &[0.5, 0.03, 0.03], path("sweep.tsv"), None).await?;
+        let results = results.iter().map(|r| (r.sample.as_str(), r.threshold, r.present)).collect::<Vec<_>>();
+        // Mutations without a frequency (consensus) are present at every threshold
+        assert_eq!(results, [("s1", 0.03, 2), ("s1", 0.5, 1), ("s2", 0.03, 1), ("s2", 0.5, 1)]);
+        Ok(())
+    }
+}
@@ -0,0 +1,438 @@
+use arrow::array::{BooleanArray, StringArray, UInt64Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use color_eyre::eyre::{eyre, Report, Result};
+use datafusion::datasource::MemTable;
+use datafusion::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::Arc;
+
+/// A node of a phylogenetic tree.
+#[derive(Clone, Debug, Default)]
+pub struct Node {
+    /// Tip name, or a generated name for unlabelled internal nodes.
+    pub name: String,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    /// Branch length to the parent.
+    pub length: Option<f64>,
+}
+
+/// A rooted phylogenetic tree, stored as a flat list of nodes.
+#[derive(Clone, Debug, Default)]
+pub struct Tree {
+    pub nodes: Vec<Node>,
+    pub root: usize,
+}
+
+/// A mutation event reconstructed on a branch of the tree.
+#[derive(Clone, Debug)]
+pub struct BranchMutation {
+    /// The child node of the branch.
+    pub branch: String,
+    pub parent: String,
+    pub mutation: String,
+    /// 'gain' or 'loss' (reversion to the reference).
+    pub event: String,
+    /// Number of tips descending from the branch.
+    pub tips: usize,
+    /// The mutation is gained on more than one branch.
+    pub homoplasy: bool,
+}
+
+impl Tree {
+    /// Parse a tree from a Newick string.
+    pub fn from_newick(newick: &str) -> Result<Self, Report> {
+        let newick = newick.trim().trim_end_matches(';');
+        let mut tree  = Tree::default();
+        let mut stack: Vec<usize> = Vec::new();
+        let mut chars = newick.chars().peekable();
+        // The node currently being labelled
+        let mut current = tree.add_node(None);
+        tree.root = current;
+
+        while let Some(c) = chars.next() {
+            match c {
+                '(' => {
+                    stack.push(current);
+                    current = tree.add_node(Some(current));
+                },
+                ',' => {
+                    let parent = *stack.last().ok_or(eyre!("Unbalanced ',' in Newick tree."))?;
+                    current = tree.add_node(Some(parent));
+                },
+                ')' => {
+                    current = stack.pop().ok_or(eyre!("Unbalanced ')' in Newick tree."))?;
+                },
+                ':' => {
+                    let mut length = String::new();
+                    while let Some(c) = chars.peek().filter(|c| !matches!(**c, ',' | ')' | '(' | ';')) {
+                        length.push(*c);
+                        chars.next();
+                    }
+                    tree.nodes[current].length = Some(length.trim().parse().map_err(|_| eyre!("Failed to parse branch length: {length}"))?);
+                },
+                '\'' | '"' => {
+                    let name = chars.by_ref().take_while(|q| *q != c).collect::<String>();
+                    tree.nodes[current].name.push_str(&name);
+                },
+                c if c.is_whitespace() => (),
+                c => tree.nodes[current].name.push(c),
+            }
+        }
+        if !stack.is_empty() {
+            return Err(eyre!("Unbalanced '(' in Newick tree."))
+        }
+
+        // Name unlabelled internal nodes, so every branch can be reported
+        for (i, node) in tree.nodes.iter_mut().enumerate() {
+            if node.name.is_empty() { node.name = format!("node_{i}") }
+        }
+
+        Ok(tree)
+    }
+
+    /// Read a tree from a Newick file.
+    pub fn read<P>(path: P) -> Result<Self, Report>
+    where
+        P: AsRef<Path> + std::fmt::Debug,
+    {
+        log::info!("Reading tree file: {path:?}");
+        Tree::from_newick(&std::fs::read_to_string(&path)?)
+    }
+
+    fn add_node(&mut self, parent: Option<usize>) -> usize {
+        let i = self.nodes.len();
+        self.nodes.push(Node { parent, ..Default::default() });
+        if let Some(parent) = parent { self.nodes[parent].children.push(i) }
+        i
+    }
+
+    /// Node indices in post-order (children before parents).
+    pub fn postorder(&self) -> Vec<usize> {
+        let mut order = Vec::new();
+        let mut stack = vec![(self.root, false)];
+        while let Some((i, visited)) = stack.pop() {
+            match visited {
+                true  => order.push(i),
+                false => {
+                    stack.push((i, true));
+                    self.nodes[i].children.iter().rev().for_each(|c| stack.push((*c, false)));
+                },
+            }
+        }
+        order
+    }
+
+    /// Tip node indices.
+    pub fn tips(&self) -> Vec<usize> {
+        (0..self.nodes.len()).filter(|i| self.nodes[*i].children.is_empty()).collect()
+    }
+
+    /// Reconstruct the state (present/absent) of a binary character at every node, with Fitch parsimony.
+    ///
+    /// The root prefers the absent (reference) state when ambiguous.
+    pub fn fitch(&self, present: &BTreeSet<usize>) -> Vec<bool> {
+        let postorder = self.postorder();
+
+        // Bottom-up: candidate state sets, as (absent, present)
+        let mut sets = vec![(false, false); self.nodes.len()];
+        for i in &postorder {
+            let node = &self.nodes[*i];
+            sets[*i] = match node.children.is_empty() {
+                true  => if present.contains(i) { (false, true) } else { (true, false) },
+                false => {
+                    let child_sets = node.children.iter().map(|c| sets[*c]).collect::<Vec<_>>();
+                    let intersection = child_sets.iter().fold((true, true), |acc, s| (acc.0 && s.0, acc.1 && s.1));
+                    match intersection {
+                        (false, false) => child_sets.iter().fold((false, false), |acc, s| (acc.0 || s.0, acc.1 || s.1)),
+                        intersection   => intersection,
+                    }
+                },
+            };
+        }
+
+        // Top-down: keep the parent state when possible
+        let mut states = vec![false; self.nodes.len()];
+        for i in postorder.iter().rev() {
+            let (absent, has_present) = sets[*i];
+            states[*i] = match self.nodes[*i].parent {
+                None         => !absent && has_present,
+                Some(parent) => {
+                    let parent_state = states[parent];
+                    if (parent_state && has_present) || (!parent_state && absent) { parent_state } else { has_present }
+                },
+            };
+        }
+
+        states
+    }
+}
+
+/// Reconstruct which mutations arose on which branches of a tree.
+///
+/// Each mutation is treated as a binary character (present/absent) across the tips,
+/// and reconstructed with Fitch parsimony. Mutations gained on more than one branch
+/// are flagged as homoplasies, rather than clade-defining changes.
+///
+/// # Arguments
+///
+///   - `mutations`: A file path to the mutations table produced by `extract` (tsv or parquet).
+///   - `tree`     : A file path to a Newick tree whose tip names match the samples.
+///   - `output`   : A file path to write the branch mutations table to.
+///
+pub async fn branches<P>(mutations: P, tree: P, output: P) -> Result<Vec<BranchMutation>, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    log::info!("Beginning branch reconstruction.");
+
+    let tree = Tree::read(&tree)?;
+    let tips = tree.tips().into_iter().map(|i| (tree.nodes[i].name.clone(), i)).collect::<BTreeMap<_, _>>();
+
+    let ctx = SessionContext::new();
+    let ctx = crate::register_mutations(&mutations, ctx, "mutations").await?;
+
+    // ------------------------------------------------------------------------
+    // Tip States
+
+    let mut present: BTreeMap<String, BTreeSet<usize>> = BTreeMap::new();
+    let mut unknown: BTreeSet<String> = BTreeSet::new();
+    let batches = ctx.sql("SELECT DISTINCT sample, mutation FROM mutations").await?.collect().await?;
+    for batch in &batches {
+        let sample   = crate::string_column(batch, "sample")?;
+        let mutation = crate::string_column(batch, "mutation")?;
+        for i in 0..batch.num_rows() {
+            match tips.get(sample.value(i)) {
+                Some(tip) => { present.entry(mutation.value(i).to_string()).or_default().insert(*tip); },
+                None      => { unknown.insert(sample.value(i).to_string()); },
+            }
+        }
+    }
+    if !unknown.is_empty() {
+        log::warn!("{} samples were not found in the tree and will be ignored: {unknown:?}", unknown.len());
+    }
+
+    // ------------------------------------------------------------------------
+    // Reconstruction
+
+    log::info!("Reconstructing {} mutations on {} nodes.", present.len(), tree.nodes.len());
+
+    // Number of tips below each node
+    let mut tip_counts = vec![0; tree.nodes.len()];
+    for i in tree.postorder() {
+        tip_counts[i] = match tree.nodes[i].children.is_empty() {
+            true  => 1,
+            false => tree.nodes[i].children.iter().map(|c| tip_counts[*c]).sum(),
+        };
+    }
+
+    let mut events = Vec::new();
+    for (mutation, tips) in &present {
+        let states = tree.fitch(tips);
+        let mut mutation_events = Vec::new();
+        for (i, node) in tree.nodes.iter().enumerate() {
+            let parent_state = node.parent.map(|p| states[p]).unwrap_or(false);
+            if states[i] == parent_state { continue }
+            mutation_events.push(BranchMutation {
+                branch:    node.name.clone(),
+                parent:    node.parent.map(|p| tree.nodes[p].name.clone()).unwrap_or_default(),
+                mutation:  mutation.clone(),
+                event:     if states[i] { "gain".to_string() } else { "loss".to_string() },
+                tips:      tip_counts[i],
+                homoplasy: false,
+            });
+        }
+        let homoplasy = mutation_events.iter().filter(|e| e.event == "gain").count() > 1;
+        mutation_events.iter_mut().for_each(|e| e.homoplasy = homoplasy);
+        events.extend(mutation_events);
+    }
+
+    // ------------------------------------------------------------------------
+    // Write Table
+
+    log::info!("Writing branch mutations: {output:?}");
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("branch",    DataType::Utf8,    false),
+        Field::new("parent",    DataType::Utf8,    false),
+        Field::new("mutation",  DataType::Utf8,    false),
+        Field::new("event",     DataType::Utf8,    false),
+        Field::new("tips",      DataType::UInt64,  false),
+        Field::new("homoplasy", DataType::Boolean, false),
+    ]));
+    let records = RecordBatch::try_new(
+        schema,
+        vec![
+            Arc::new(StringArray::from(events.iter().map(|e| e.branch.clone()).collect::<Vec<_>>())),
+            Arc::new(StringArray::from(events.iter().map(|e| e.parent.clone()).collect::<Vec<_>>())),
+            Arc::new(StringArray::from(events.iter().map(|e| e.mutation.clone()).collect::<Vec<_>>())),
+            Arc::new(StringArray::from(events.iter().map(|e| e.event.clone()).collect::<Vec<_>>())),
+            Arc::new(UInt64Array::from(events.iter().map(|e| e.tips as u64).collect::<Vec<_>>())),
+            Arc::new(BooleanArray::from(events.iter().map(|e| e.homoplasy).collect::<Vec<_>>())),
+        ],
+    )?;
+    crate::write_table(SessionContext::new().read_batch(records)?, &output).await?;
+
+    log::info!("Finished branch reconstruction.");
+
+    Ok(events)
+}
+
+/// Register the mutations of each tip of an Auspice v2 JSON tree (ex. a Nextstrain build) as a long table.
+///
+/// The branch mutations (`branch_attrs.mutations`) are accumulated from the root to each tip,
+/// so a later mutation at the same position replaces an earlier one, and reversions to the
+/// reference are dropped. The table has the columns sample, mutation, column, and type, with
+/// nucleotide (`nuc`) changes as substitutions (C241T) or deletions (21765), and amino acid
+/// changes as aaSubstitutions (S:D614G) or aaDeletions (S:H69-).
+pub async fn register_auspice<N, P>(path: P, ctx: SessionContext, name: N) -> Result<SessionContext, Report>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+    N: ToString,
+{
+    log::info!("Reading auspice json: {path:?}");
+
+    let json: serde_json::Value = serde_json::from_reader(crate::open(&path)?)?;
+    let root = json.get("tree").ok_or(eyre!("Auspice json has no tree: {path:?}"))?;
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("sample",   DataType::Utf8, false),
+        Field::new("mutation", DataType::Utf8, false),
+        Field::new("column",   DataType::Utf8, false),
+        Field::new("type",     DataType::Utf8, false),
+    ]));
+
+    let mut samples:   Vec<String>       = Vec::new();
+    let mut mutations: Vec<String>       = Vec::new();
+    let mut columns:   Vec<&'static str> = Vec::new();
+    let mut types:     Vec<&'static str> = Vec::new();
+
+    // The reference and current state of each (gene, position) along the path from the root,
+    // where nucleotide changes have the gene "nuc"
+    type States = BTreeMap<(String, u32), (char, char)>;
+    let mut stack: Vec<(&serde_json::Value, States)> = vec![(root, States::new())];
+
+    while let Some((node, mut states)) = stack.pop() {
+        let changes = node.pointer("/branch_attrs/mutations").and_then(|m| m.as_object());
+        for (gene, changes) in changes.into_iter().flatten() {
+            for change in changes.as_array().into_iter().flatten().filter_map(|c| c.as_str()) {
+                let (Some(reference), Some(alternate)) = (change.chars().next(), change.chars().last()) else { continue };
+                let Some(Ok(position)) = change.get(1..change.len().saturating_sub(1)).map(str::parse::<u32>) else {
+                    log::warn!("Skipping unsupported auspice mutation: {gene}:{change}");
+                    continue
+                };
+                states.entry((gene.clone(), position)).and_modify(|(_, current)| *current = alternate).or_insert((reference, alternate));
+            }
+        }
+
+        let children = node.get("children").and_then(|c| c.as_array()).filter(|c| !c.is_empty());
+        let Some(children) = children else {
+            let sample = node.get("name").and_then(|n| n.as_str()).ok_or(eyre!("Auspice tip has no name: {path:?}"))?;
+            for ((gene, position), (reference, alternate)) in states.into_iter().filter(|(_, (r, a))| r != a) {
+                let (mutation, column, ty) = match (gene.as_str(), alternate) {
+                    ("nuc", '-') => (position.to_string(),                              "deletions",       "nucleotide"),
+                    ("nuc", _)   => (format!("{reference}{position}{alternate}"),        "substitutions",   "nucleotide"),
+                    (_, '-')     => (format!("{gene}:{reference}{position}-"),           "aaDeletions",     "amino-acid"),
+                    _            => (format!("{gene}:{reference}{position}{alternate}"), "aaSubstitutions", "amino-acid"),
+                };
+                samples.push(sample.to_string());
+                mutations.push(mutation);
+                columns.push(column);
+                types.push(ty);
+            }
+            continue
+        };
+        for child in children { stack.push((child, states.clone())) }
+    }
+
+    let records = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(StringArray::from(samples)),
+            Arc::new(StringArray::from(mutations)),
+            Arc::new(StringArray::from(columns)),
+            Arc::new(StringArray::from(types)),
+        ],
+    )?;
+
+    let provider = MemTable::try_new(schema, vec![vec![records]])?;
+    ctx.register_table(&name.to_string(), Arc::new(provider))?;
+
+    Ok(ctx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The tip indices of a tree, by name.
+    fn tip(tree: &Tree, name: &str) -> usize {
+        tree.nodes.iter().position(|n| n.name == name).unwrap()
+    }
+
+    #[test]
+    fn newick() -> Result<(), Report> {
+        let tree = Tree::from_newick("(('s 1':0.1,B:0.2),C)root;")?;
+        assert_eq!(tree.nodes[tree.root].name, "root");
+        assert_eq!(tree.tips().len(), 3);
+        assert_eq!(tree.nodes[tip(&tree, "s 1")].length, Some(0.1));
+        // Unlabelled internal nodes are named by their index, and children come before parents
+        assert_eq!(tree.nodes[1].name, "node_1");
+        assert_eq!(tree.postorder(), vec![2, 3, 1, 4, 0]);
+
+        assert!(Tree::from_newick("((A,B);").is_err());
+        assert!(Tree::from_newick("(A,B));").is_err());
+        assert!(Tree::from_newick("(A:x,B);").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn fitch_parsimony() -> Result<(), Report> {
+        let tree  = Tree::from_newick("((A,B),(C,D));")?;
+        let tips  = |names: &[&str]| names.iter().map(|n| tip(&tree, n)).collect::<BTreeSet<_>>();
+        let nodes = |states: Vec<bool>| (0..states.len()).filter(|i| states[*i]).map(|i| tree.nodes[i].name.clone()).collect::<Vec<_>>();
+
+        // A single gain on the branch to the (A,B) clade
+        assert_eq!(nodes(tree.fitch(&tips(&["A", "B"]))), ["node_1", "A", "B"]);
+        // Two independent gains, with the ambiguous root and internal nodes absent
+        assert_eq!(nodes(tree.fitch(&tips(&["A", "C"]))), ["A", "C"]);
+        // Present everywhere, including the root, or absent everywhere
+        assert_eq!(nodes(tree.fitch(&tips(&["A", "B", "C", "D"]))).len(), tree.nodes.len());
+        assert!(nodes(tree.fitch(&BTreeSet::new())).is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn auspice_tip_mutations() -> Result<(), Report> {
+        let dir  = tempfile::tempdir()?;
+        let path = dir.path().join("auspice.json");
+        std::fs::write(&path, r#"{"tree": {"name": "root", "branch_attrs": {"mutations": {"nuc": ["C241T"]}}, "children": [
+            {"name": "s1", "branch_attrs": {"mutations": {"nuc": ["T241C", "A23403G"], "S": ["D614G"]}}},
+            {"name": "s2", "branch_attrs": {"mutations": {"nuc": ["G21765-"], "S": ["H69-"]}}},
+            {"name": "node", "children": [{"name": "s3", "branch_attrs": {"mutations": {"nuc": ["X"]}}}]}
+        ]}}"#)?;
+
+        let ctx     = register_auspice(&path, SessionContext::new(), "auspice").await?;
+        let batches = ctx.sql("SELECT sample, mutation, column FROM auspice ORDER BY sample, mutation").await?.collect().await?;
+        let mut rows = Vec::new();
+        for batch in &batches {
+            let (sample, mutation, column) = (crate::string_column(batch, "sample")?, crate::string_column(batch, "mutation")?, crate::string_column(batch, "column")?);
+            for i in 0..batch.num_rows() { rows.push(format!("{} {} {}", sample.value(i), mutation.value(i), column.value(i))) }
+        }
+        // The reversion of C241T in s1 is dropped, and the unparseable X is skipped
+        assert_eq!(rows, [
+            "s1 A23403G substitutions",
+            "s1 S:D614G aaSubstitutions",
+            "s2 21765 deletions",
+            "s2 C241T substitutions",
+            "s2 S:H69- aaDeletions",
+            "s3 C241T substitutions",
+        ]);
+
+        std::fs::write(&path, r#"{"meta": {}}"#)?;
+        assert!(register_auspice(&path, SessionContext::new(), "auspice").await.is_err());
+        Ok(())
+    }
+}
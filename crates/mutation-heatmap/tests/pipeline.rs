@@ -0,0 +1,104 @@
+use color_eyre::eyre::{Report, Result};
+use datafusion::prelude::*;
+use mutation_core::{TEST_ANNOTATIONS, TEST_GFF, TEST_NEXTCLADE};
+use mutation_heatmap::{annotate, extract};
+use mutation_heatmap::annotate::AnnotateOptions;
+use mutation_heatmap::extract::{ExtractOptions, Input};
+use std::collections::BTreeMap;
+
+/// Run the golden dataset through extract -> annotate -> plot, and check each stage's output.
+#[tokio::test]
+async fn extract_annotate_plot() -> Result<(), Report> {
+    // extract writes its outputs to the working directory
+    let dir = tempfile::tempdir()?;
+    std::env::set_current_dir(&dir)?;
+
+    // ------------------------------------------------------------------------
+    // Extract
+
+    extract(Input::Nextclade(vec![TEST_NEXTCLADE]), TEST_GFF, None, &ExtractOptions::default()).await?;
+
+    let ctx = SessionContext::new();
+    ctx.register_parquet("mutations", "mutations.parquet", ParquetReadOptions::default()).await?;
+    let schema = ctx.table("mutations").await?.schema().as_arrow().clone();
+    assert_eq!(mutation_core::missing_columns(&schema, &mutation_core::mutations_schema()), Vec::<String>::new());
+
+    let query   = "SELECT sample, mutation, column, gene FROM mutations WHERE mutation IS NOT NULL ORDER BY sample, column, mutation";
+    let batches = ctx.sql(query).await?.collect().await?;
+    let mut mutations = Vec::new();
+    for batch in &batches {
+        let sample   = mutation_heatmap::string_column(batch, "sample")?;
+        let mutation = mutation_heatmap::string_column(batch, "mutation")?;
+        let gene     = mutation_heatmap::string_column(batch, "gene")?;
+        for i in 0..batch.num_rows() {
+            let gene = gene.is_valid(i).then(|| gene.value(i).to_string());
+            mutations.push((sample.value(i).to_string(), mutation.value(i).to_string(), gene));
+        }
+    }
+    let gene = |g: &str| Some(g.to_string());
+    let expected = vec![
+        ("sample1", "ORF1a:S2083-", gene("ORF1a")),
+        ("sample1", "E:T9I",        gene("E")),
+        ("sample1", "ORF1a:S3001I", gene("ORF1a")),
+        ("sample1", "S:A67V",       gene("S")),
+        ("sample1", "6513-6515",    gene("ORF1a")),
+        ("sample1", "28933:T",      gene("N")),
+        ("sample1", "C21762T",      gene("S")),
+        ("sample1", "C241T",        None),
+        ("sample1", "C26270T",      gene("E")),
+        ("sample1", "G9267T",       gene("ORF1a")),
+        ("sample2", "C241T",        None),
+    ];
+    let expected = expected.into_iter().map(|(s, m, g)| (s.to_string(), m.to_string(), g)).collect::<Vec<_>>();
+    assert_eq!(mutations, expected);
+
+    // ------------------------------------------------------------------------
+    // Annotate
+
+    annotate(Some("mutations.tsv"), &[], TEST_ANNOTATIONS, Some(TEST_GFF), None, "annotated.tsv", &AnnotateOptions::default()).await?;
+
+    let ctx = SessionContext::new();
+    ctx.register_csv("annotated", "annotated.tsv", CsvReadOptions::new().file_extension("tsv").delimiter(b'\t')).await?;
+    let batches = ctx.sql("SELECT sample, mutation, status FROM annotated").await?.collect().await?;
+    let mut statuses = BTreeMap::new();
+    for batch in &batches {
+        let sample   = mutation_heatmap::string_column(batch, "sample")?;
+        let mutation = mutation_heatmap::string_column(batch, "mutation")?;
+        let status   = mutation_heatmap::string_column(batch, "status")?;
+        for i in 0..batch.num_rows() {
+            statuses.insert((sample.value(i).to_string(), mutation.value(i).to_string()), status.value(i).to_string());
+        }
+    }
+    let expected = [
+        ("sample1", "C241T",     "present"),
+        ("sample1", "G9267T",    "present"),
+        ("sample1", "E:T9I",     "present"),
+        ("sample1", "6513-6515", "present"),
+        ("sample1", "S:A67V",    "present"),
+        ("sample2", "C241T",     "present"),
+        ("sample2", "G9267T",    "absent"),
+        ("sample2", "E:T9I",     "absent"),
+        ("sample2", "6513-6515", "absent"),
+    ];
+    let expected = expected.into_iter().map(|(s, m, st)| ((s.to_string(), m.to_string()), st.to_string())).collect::<BTreeMap<_, _>>();
+    assert_eq!(statuses, expected);
+
+    // ------------------------------------------------------------------------
+    // Plot
+
+    #[cfg(feature = "plot")]
+    {
+        mutation_heatmap::plot(Some("annotated.tsv"), "heatmap", None, &mutation_heatmap::plot::PlotOptions::default(), &mutation_heatmap::plot::PlotData::default()).await?;
+        let svg = std::fs::read_to_string("heatmap.svg")?;
+        assert!(svg.contains("sample1") && svg.contains("sample2"));
+        assert!(svg.contains("E:T9I"));
+        assert!(std::fs::metadata("heatmap.png")?.len() > 0);
+
+        // The extract output can be plotted directly, without annotations
+        mutation_heatmap::plot(Some("mutations.parquet"), "mutations", None, &mutation_heatmap::plot::PlotOptions::default(), &mutation_heatmap::plot::PlotData::default()).await?;
+        let svg = std::fs::read_to_string("mutations.svg")?;
+        assert!(svg.contains("sample1") && svg.contains("sample2"));
+    }
+
+    Ok(())
+}